@@ -0,0 +1,32 @@
+use std::process::Command;
+
+// Build metadata shown on /about; each falls back to "unknown" outside a git
+// checkout or when the tool isn't around
+fn main() {
+    let commit = run("git", &["rev-parse", "--short=10", "HEAD"]);
+    let dirty = run("git", &["status", "--porcelain", "--untracked-files=no"]);
+    let commit = match (commit, dirty) {
+        (Some(c), Some(d)) if !d.is_empty() => format!("{}-dirty", c),
+        (Some(c), _) => c,
+        (None, _) => "unknown".to_string(),
+    };
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc = run(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    let built = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    println!("cargo:rustc-env=BSDL_BUILD_COMMIT={}", commit);
+    println!("cargo:rustc-env=BSDL_BUILD_RUSTC={}", rustc);
+    println!("cargo:rustc-env=BSDL_BUILD_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+    println!("cargo:rustc-env=BSDL_BUILD_TIME={}", built);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=CHANGELOG.md");
+}
+
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    out.status.success().then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
@@ -1 +1,3 @@
 curl -L -o yt-dlp_linux https://github.com/yt-dlp/yt-dlp/releases/download/2025.11.12/yt-dlp_linux && chmod +x yt-dlp_linux
+# Optional nightly build, for sites pinned to it at /system/ytdlp:
+# curl -L -o yt-dlp_nightly https://github.com/yt-dlp/yt-dlp-nightly-builds/releases/latest/download/yt-dlp_linux && chmod +x yt-dlp_nightly
@@ -0,0 +1,126 @@
+use axum::{extract::State, response::Response};
+use askama::Template;
+
+use crate::clock::Stamp;
+use crate::diagnostics::{self, Check};
+use crate::error::render;
+use crate::update::CURRENT_VERSION;
+use crate::AppState;
+
+const CHANGELOG: &str = include_str!("../CHANGELOG.md");
+const LOCKFILE: &str = include_str!("../Cargo.lock");
+
+/// Components compiled into the binary: crate, what it does here, license, source
+const COMPONENTS: &[(&str, &str, &str, &str)] = &[
+    ("axum", "web framework", "MIT", "https://github.com/tokio-rs/axum"),
+    ("tokio", "async runtime", "MIT", "https://github.com/tokio-rs/tokio"),
+    ("hyper", "HTTP server", "MIT", "https://github.com/hyperium/hyper"),
+    ("tower-http", "static files", "MIT", "https://github.com/tower-rs/tower-http"),
+    ("askama", "templates", "MIT OR Apache-2.0", "https://github.com/djc/askama"),
+    ("serde", "JSON", "MIT OR Apache-2.0", "https://github.com/serde-rs/serde"),
+    ("rustls", "HTTPS", "Apache-2.0 OR ISC OR MIT", "https://github.com/rustls/rustls"),
+    ("ring", "HTTPS cryptography", "Apache-2.0 AND ISC", "https://github.com/briansmith/ring"),
+    ("rusqlite", "database", "MIT", "https://github.com/rusqlite/rusqlite"),
+    ("libsqlite3-sys", "bundled SQLite (public domain)", "MIT", "https://sqlite.org/copyright.html"),
+    #[cfg(feature = "postgres")]
+    ("tokio-postgres", "PostgreSQL database", "MIT OR Apache-2.0", "https://github.com/sfackler/rust-postgres"),
+    ("chacha20poly1305", "secrets encryption", "Apache-2.0 OR MIT", "https://github.com/RustCrypto/AEADs"),
+    ("sha1", "torrent piece hashes", "MIT OR Apache-2.0", "https://github.com/RustCrypto/hashes"),
+    ("sha2", "password and duplicate hashes", "MIT OR Apache-2.0", "https://github.com/RustCrypto/hashes"),
+    ("pbkdf2", "password hashing", "MIT OR Apache-2.0", "https://github.com/RustCrypto/password-hashes"),
+    ("subtle", "constant-time comparisons", "BSD-3-Clause", "https://github.com/dalek-cryptography/subtle"),
+    ("hmac", "password hashing", "MIT OR Apache-2.0", "https://github.com/RustCrypto/MACs"),
+    ("icu_collator", "library sorting", "Unicode-3.0", "https://github.com/unicode-org/icu4x"),
+    ("chrono-tz", "time zones", "MIT OR Apache-2.0", "https://github.com/chronotope/chrono-tz"),
+    ("tar", "backups", "MIT OR Apache-2.0", "https://github.com/composefs/tar-rs"),
+    ("flate2", "backups", "MIT OR Apache-2.0", "https://github.com/rust-lang/flate2-rs"),
+    ("zip", "support bundles", "MIT", "https://github.com/zip-rs/zip2"),
+];
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub name: &'static str,
+    pub version: String,
+    pub purpose: &'static str,
+    pub license: &'static str,
+    pub url: &'static str,
+}
+
+/// One `## ` section of CHANGELOG.md.
+#[derive(Debug, Clone)]
+pub struct ChangelogEntry {
+    pub version: String,
+    pub changes: Vec<String>,
+}
+
+// --- Build metadata ---
+
+// The version Cargo.lock pinned for `name`, the first one if there are several
+fn locked_version(name: &str) -> String {
+    let wanted = format!("name = \"{}\"", name);
+    let mut lines = LOCKFILE.lines();
+    while let Some(line) = lines.next() {
+        if line == wanted {
+            if let Some(v) = lines.next().and_then(|l| l.strip_prefix("version = ")) {
+                return v.trim_matches('"').to_string();
+            }
+        }
+    }
+    "?".to_string()
+}
+
+fn components() -> Vec<Component> {
+    COMPONENTS
+        .iter()
+        .map(|&(name, purpose, license, url)| Component { name, version: locked_version(name), purpose, license, url })
+        .collect()
+}
+
+fn changelog() -> Vec<ChangelogEntry> {
+    let mut entries: Vec<ChangelogEntry> = Vec::new();
+    for line in CHANGELOG.lines() {
+        if let Some(version) = line.strip_prefix("## ") {
+            entries.push(ChangelogEntry { version: version.trim().to_string(), changes: Vec::new() });
+        } else if let (Some(change), Some(entry)) = (line.strip_prefix("- "), entries.last_mut()) {
+            entry.changes.push(change.trim().to_string());
+        }
+    }
+    entries
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "about.html")]
+struct AboutTemplate {
+    version: &'static str,
+    commit: &'static str,
+    built: Stamp,
+    target: &'static str,
+    rustc: &'static str,
+    sqlite: &'static str,
+    tools: Vec<Check>,
+    changelog: Vec<ChangelogEntry>,
+    components: Vec<Component>,
+}
+
+pub async fn show_about(State(state): State<AppState>) -> Response {
+    let (ytdlp, nightly, ffmpeg) =
+        tokio::join!(diagnostics::ytdlp(&state), diagnostics::ytdlp_nightly(&state), diagnostics::ffmpeg(&state));
+    let mut tools = vec![ytdlp];
+    tools.extend(nightly);
+    tools.push(ffmpeg);
+    render(AboutTemplate {
+        version: CURRENT_VERSION,
+        commit: env!("BSDL_BUILD_COMMIT"),
+        built: Stamp(env!("BSDL_BUILD_TIME").parse().unwrap_or(0)),
+        target: env!("BSDL_BUILD_TARGET"),
+        rustc: env!("BSDL_BUILD_RUSTC"),
+        sqlite: rusqlite::version(),
+        tools,
+        changelog: changelog(),
+        components: components(),
+    })
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::AppError;
+use crate::sanitize;
+use crate::{DisplayFormat, SourceLinks, YtDlpFormat, YtDlpOutput};
+
+// --- Related ---
+
+/// A playlist or channel an analyzed video belongs to, which could be
+/// archived as a whole.
+#[derive(Debug, Clone)]
+pub struct Related {
+    /// "Playlist" or "Channel"
+    pub kind: &'static str,
+    pub name: String,
+    pub url: String,
+}
+
+// The `list=` of a YouTube watch URL. Mixes (RD...) are generated per viewer
+// and never end, so they don't count.
+fn youtube_list(page_url: &str) -> Option<String> {
+    let (_, query) = page_url.split_once('?')?;
+    if !is_youtube(page_url) {
+        return None;
+    }
+    let list = query.split(['&', '#']).find_map(|pair| pair.strip_prefix("list="))?;
+    let valid = !list.is_empty() && list.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    (valid && !list.starts_with("RD")).then(|| format!("https://www.youtube.com/playlist?list={}", list))
+}
+
+pub fn is_youtube(url: &str) -> bool {
+    let host = url.split("://").nth(1).and_then(|rest| rest.split(['/', '?', '#']).next()).unwrap_or_default();
+    host.ends_with("youtube.com") || host == "youtu.be"
+}
+
+fn web_url(url: Option<&String>) -> Option<String> {
+    url.map(|u| u.trim().to_string()).filter(|u| u.starts_with("https://") || u.starts_with("http://"))
+}
+
+/// The playlist (from the metadata, or a YouTube URL's `list=`) and the
+/// channel behind `meta`.
+pub fn related(page_url: &str, meta: &YtDlpOutput, channel: Option<&str>) -> Vec<Related> {
+    let links = &meta.links;
+    let mut related = Vec::new();
+    // A post with several videos names itself as their playlist
+    let playlist = web_url(links.playlist_webpage_url.as_ref()).filter(|u| u != page_url);
+    if let Some(url) = playlist.or_else(|| youtube_list(page_url)) {
+        let name = sanitize::line_opt(links.playlist_title.clone()).unwrap_or_else(|| "This playlist".to_string());
+        related.push(Related { kind: "Playlist", name, url });
+    }
+    if let Some(url) = web_url(links.channel_url.as_ref().or(links.uploader_url.as_ref())) {
+        let name = channel.map(str::to_string).unwrap_or_else(|| "This channel".to_string());
+        related.push(Related { kind: "Channel", name, url });
+    }
+    related
+}
+
+// --- Account formats ---
+
+/// Formats that only showed up because the analysis ran with cookies.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Unlocked {
+    /// Which cookies, e.g. "the stored cookies" or "cookies from firefox"
+    pub account: String,
+    /// Format ids the same analysis without cookies didn't list
+    pub format_ids: Vec<String>,
+    /// Without cookies yt-dlp got nothing at all
+    pub anonymous_failed: bool,
+}
+
+impl Unlocked {
+    pub fn contains(&self, format_id: &str) -> bool {
+        self.format_ids.iter().any(|id| id == format_id)
+    }
+}
+
+/// What `with` (listed with cookies) has over `without`, or everything when
+/// the run without cookies failed. None when the cookies changed nothing.
+pub fn unlocked(account: String, with: &[YtDlpFormat], without: Option<&[YtDlpFormat]>) -> Option<Unlocked> {
+    let format_ids: Vec<String> = with
+        .iter()
+        .filter(|f| without.is_none_or(|w| !w.iter().any(|a| a.format_id == f.format_id)))
+        .map(|f| f.format_id.clone())
+        .collect();
+    (!format_ids.is_empty()).then_some(Unlocked { account, format_ids, anonymous_failed: without.is_none() })
+}
+
+// --- Grouping ---
+
+/// Formats that only differ in CDN or protocol, shown as one row with the
+/// rest folded away.
+#[derive(Debug, Clone)]
+pub struct FormatGroup {
+    pub main: DisplayFormat,
+    pub variants: Vec<DisplayFormat>,
+}
+
+impl FormatGroup {
+    pub fn contains(&self, format_id: &str) -> bool {
+        self.variants.iter().any(|f| f.id == format_id)
+    }
+}
+
+// Plain downloads resume and seek best; segmented ones come after
+fn protocol_rank(protocol: &str) -> u8 {
+    match protocol {
+        "https" | "http" => 0,
+        "http_dash_segments" | "http_dash_segments_generator" => 1,
+        p if p.starts_with("m3u8") => 2,
+        _ => 3,
+    }
+}
+
+/// "HLS" or "DASH" for a format that comes down in fragments rather than as one file.
+pub fn fragmented(protocol: &str) -> Option<&'static str> {
+    // Merged formats list both halves, e.g. "m3u8_native+https"
+    protocol.split('+').find_map(|p| match protocol_rank(p) {
+        1 => Some("DASH"),
+        2 => Some("HLS"),
+        _ => None,
+    })
+}
+
+/// Groups `formats` by their `group` key in the order yt-dlp listed them
+/// (worst to best); the most direct protocol heads each group.
+pub fn group(formats: Vec<DisplayFormat>) -> Vec<FormatGroup> {
+    let mut groups: Vec<Vec<DisplayFormat>> = Vec::new();
+    for f in formats {
+        match groups.iter_mut().find(|g| g[0].group == f.group) {
+            Some(g) => g.push(f),
+            None => groups.push(vec![f]),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|mut members| {
+            // Stable, so yt-dlp's own preference breaks ties
+            members.sort_by_key(|f| protocol_rank(&f.protocol));
+            let main = members.remove(0);
+            FormatGroup { main, variants: members }
+        })
+        .collect()
+}
+
+/// The `n` groups most worth offering on a small screen: complete video+audio
+/// formats first, then the rest, each by height, frame rate and bitrate. The group holding
+/// `selected` stays in, so a pick made earlier is still there.
+pub fn recommended(groups: Vec<FormatGroup>, n: usize, selected: &str) -> Vec<FormatGroup> {
+    let mut ranked: Vec<FormatGroup> = groups.into_iter().rev().collect();
+    // Height alone ranks a 30 fps stream with half the bitrate the same; ties
+    // after all three keep yt-dlp's best-last order, as the sort is stable
+    ranked.sort_by_key(|g| {
+        (g.main.type_label != "Video+Audio", Reverse((g.main.raw_height, g.main.raw_fps, g.main.raw_bitrate)))
+    });
+    let (mut kept, rest): (Vec<_>, Vec<_>) = ranked.into_iter().enumerate().partition(|(i, _)| *i < n);
+    kept.extend(rest.into_iter().filter(|(_, g)| g.main.id == selected || g.contains(selected)));
+    kept.into_iter().map(|(_, g)| g).collect()
+}
+
+// --- Cache ---
+
+/// What yt-dlp said about `url`, if it was asked less than `max_age_minutes` ago.
+pub async fn cached(db: &Db, url: &str, max_age_minutes: u64) -> Result<Option<YtDlpOutput>, AppError> {
+    if max_age_minutes == 0 {
+        return Ok(None);
+    }
+    let url = url.to_string();
+    let since = db::now() - (max_age_minutes * 60) as i64;
+    let row = db
+        .call(move |conn| {
+            conn.query_row(
+                "SELECT title, formats, channel, links, unlocked FROM analyses WHERE url = ?1 AND analyzed_at >= ?2",
+                params![url, since],
+                |r| {
+                    Ok((
+                        r.get::<String>(0)?,
+                        r.get::<String>(1)?,
+                        r.get::<Option<String>>(2)?,
+                        r.get::<Option<String>>(3)?,
+                        r.get::<Option<String>>(4)?,
+                    ))
+                },
+            )
+            .optional()
+        })
+        .await?;
+
+    let Some((title, formats, channel, links, unlocked)) = row else {
+        return Ok(None);
+    };
+    let formats: Vec<YtDlpFormat> = serde_json::from_str(&formats)?;
+    // Rows cached before links were kept have none
+    let links: SourceLinks = links.map(|l| serde_json::from_str(&l)).transpose()?.unwrap_or_default();
+    let unlocked = unlocked.map(|u| serde_json::from_str(&u)).transpose()?;
+    Ok(Some(YtDlpOutput { title, formats, channel, links, unlocked, ..Default::default() }))
+}
+
+/// Remembers an analysis, dropping any that have aged out meanwhile.
+pub async fn store(db: &Db, url: &str, meta: &YtDlpOutput, max_age_minutes: u64) -> Result<(), AppError> {
+    let url = url.to_string();
+    let title = meta.title.clone();
+    let formats = serde_json::to_string(&meta.formats)?;
+    let channel = meta.channel_name();
+    let links = serde_json::to_string(&meta.links)?;
+    let unlocked = meta.unlocked.as_ref().map(serde_json::to_string).transpose()?;
+    let now = db::now();
+    let expired = now - (max_age_minutes * 60) as i64;
+    db.call(move |conn| {
+        conn.execute("DELETE FROM analyses WHERE analyzed_at < ?1", params![expired])?;
+        conn.execute(
+            "INSERT INTO analyses (url, title, formats, analyzed_at, channel, links, unlocked)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (url) DO UPDATE SET title = excluded.title, formats = excluded.formats, analyzed_at = excluded.analyzed_at,
+                 channel = excluded.channel, links = excluded.links, unlocked = excluded.unlocked",
+            params![url, title, formats, now, channel, links, unlocked],
+        )?;
+        Ok(())
+    })
+    .await
+}
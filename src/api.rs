@@ -0,0 +1,383 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json as JsonBody, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::auth;
+use crate::clock::Stamp;
+use crate::error::AppError;
+use crate::gate::{self, Workaround};
+use crate::history;
+use crate::library;
+use crate::profiles;
+use crate::sanitize;
+use crate::server::ClientGone;
+use crate::session::{Choice, SessionId, Selection};
+use crate::validate::{self, Json, Validate};
+use crate::{AnalyzeRequest, AppState, DisplayFormat, FilesQuery, MediaType, Unanswered, YtDlpOutput};
+use crate::{AUDIO_FORMATS, CONTAINERS};
+
+/// What `best` may ask for.
+pub const BEST: &[&str] = &["video", "audio"];
+
+// --- Analyze ---
+
+/// One row of the format table.
+#[derive(Serialize)]
+pub struct Format {
+    id: String,
+    ext: String,
+    resolution: String,
+    height: u32,
+    /// As the table shows it: "12.34 MB", "~12.34 MB" estimated, or "Unknown"
+    filesize: String,
+    #[serde(rename = "type")]
+    type_label: String,
+    codecs: String,
+    language: String,
+    fps: String,
+    /// The audio's or video's own when the format has only one
+    bitrate: String,
+    /// "Stereo", "5.1"...
+    channels: String,
+    dynamic_range: String,
+    protocol: String,
+    /// "Premium" or "Account" when only the cookies got this format
+    unlock: Option<&'static str>,
+}
+
+impl From<DisplayFormat> for Format {
+    fn from(f: DisplayFormat) -> Self {
+        Format {
+            id: f.id,
+            ext: f.ext,
+            resolution: f.resolution,
+            height: f.raw_height,
+            filesize: f.filesize,
+            type_label: f.type_label,
+            codecs: f.codecs,
+            language: f.language,
+            fps: f.fps,
+            bitrate: f.bitrate,
+            channels: f.channels,
+            dynamic_range: f.dynamic_range,
+            protocol: f.protocol,
+            unlock: f.unlock,
+        }
+    }
+}
+
+/// One video of a post that holds several; analyze it again with its `item`,
+/// or, for a playlist's entry, by its own `url`.
+#[derive(Serialize)]
+pub struct Video {
+    item: usize,
+    title: String,
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Analysis {
+    url: String,
+    title: String,
+    channel: Option<String>,
+    duration: Option<f64>,
+    /// A premiere or live stream that hasn't started, with nothing to download yet
+    upcoming: bool,
+    /// A photo post, which has no formats to pick
+    photo: bool,
+    languages: Vec<String>,
+    formats: Vec<Format>,
+    videos: Vec<Video>,
+}
+
+// The cached answer when there is one, otherwise yt-dlp's, recorded in the history
+async fn analysis_of(
+    state: &AppState,
+    owner: Option<&str>,
+    url: &str,
+    gone: &ClientGone,
+    workaround: Option<&Workaround>,
+    item: Option<usize>,
+) -> Result<Vec<YtDlpOutput>, AppError> {
+    if let Some(meta) = crate::cached_analysis(state, url, workaround, item).await {
+        return Ok(vec![meta]);
+    }
+    let asked = crate::ask_ytdlp(state, url, gone, workaround, item).await;
+    let (title, status) = match &asked {
+        Ok(entries) if entries.len() == 1 && !entries[0].flat() => (sanitize::line(&entries[0].title), "Done"),
+        Ok(entries) => (format!("{} videos", entries.len()), "Done"),
+        Err(Unanswered::Abandoned) => (String::new(), "Abandoned"),
+        Err(_) => (String::new(), "Failed"),
+    };
+    history::analyzed(&state.db, owner, url, &title, status, asked.as_ref().err().and_then(Unanswered::error)).await;
+    asked.map_err(|e| match e {
+        // Nobody is left to read it
+        Unanswered::Abandoned => AppError::BadRequest("The request went away".to_string()),
+        Unanswered::Gated(g, detail) => AppError::YtDlpFailed(format!(
+            "{}; store cookies, or pass a browser or client to get past it: {}",
+            g.title(),
+            detail
+        )),
+        Unanswered::Failed(msg) => AppError::YtDlpFailed(msg),
+        Unanswered::App(e) => e,
+    })
+}
+
+fn checked_url(url: &str) -> Result<(), AppError> {
+    validate::url("url", url)?;
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(AppError::Invalid { field: "url", reason: "must be an http:// or https:// URL".to_string() });
+    }
+    Ok(())
+}
+
+/// The format table of a URL as JSON, from the same analysis the wizard runs.
+pub async fn analyze(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Extension(gone): Extension<ClientGone>,
+    Json(req): Json<AnalyzeRequest>,
+) -> Result<Response, AppError> {
+    let url = req.url.trim().to_string();
+    checked_url(&url)?;
+    gate::check_proxy(&state.config, req.proxy)?;
+    let workaround = Workaround::from_form(&req.browser, &req.client, &req.country, req.proxy);
+    let viewer = auth::viewer(&state, &sid);
+    let mut entries = analysis_of(&state, viewer.as_deref(), &url, &gone, workaround.as_ref(), req.item).await?;
+
+    if entries.len() > 1 || entries.iter().any(YtDlpOutput::flat) {
+        let videos = entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| Video { item: i + 1, title: sanitize::line(&e.title), url: e.entry_url() })
+            .collect();
+        return Ok(JsonBody(Analysis {
+            url,
+            title: format!("{} videos", entries.len()),
+            channel: entries[0].channel_name(),
+            duration: None,
+            upcoming: false,
+            photo: false,
+            languages: Vec::new(),
+            formats: Vec::new(),
+            videos,
+        })
+        .into_response());
+    }
+    let meta = entries.remove(0);
+    let photo = meta.photo().is_some();
+    let (title, channel, duration, upcoming) = (sanitize::line(&meta.title), meta.channel_name(), meta.duration, meta.upcoming());
+    let (formats, languages) = if photo { (Vec::new(), Vec::new()) } else { crate::format_table(&url, meta.formats, meta.unlocked.as_ref()) };
+    Ok(JsonBody(Analysis {
+        url,
+        title,
+        channel,
+        duration,
+        upcoming,
+        photo,
+        languages,
+        formats: formats.into_iter().map(Format::from).collect(),
+        videos: Vec::new(),
+    })
+    .into_response())
+}
+
+// --- Download ---
+
+/// What to download and how. Name one of `format` (an id the analysis
+/// listed), `profile` (a device profile's id) or `best`; with none, the best video.
+/// A field it doesn't know is refused with 422.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DownloadRequest {
+    url: String,
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    profile: Option<i64>,
+    /// "video" or "audio"
+    #[serde(default)]
+    best: Option<String>,
+    #[serde(default)]
+    container: Option<String>,
+    #[serde(default)]
+    audio_format: Option<String>,
+    /// Also make an H.264 1080p copy
+    #[serde(default)]
+    compat: bool,
+    #[serde(default)]
+    polite: bool,
+    #[serde(default)]
+    browser: String,
+    #[serde(default)]
+    client: String,
+    #[serde(default)]
+    country: String,
+    #[serde(default)]
+    proxy: bool,
+    #[serde(default)]
+    item: Option<usize>,
+    /// Every video of the playlist or channel the URL is, as one job
+    #[serde(default)]
+    playlist: bool,
+}
+
+impl Validate for DownloadRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        checked_url(self.url.trim())?;
+        if self.item == Some(0) {
+            return Err(AppError::Invalid { field: "item", reason: "videos are counted from 1".to_string() });
+        }
+        gate::validate_fields(&self.browser, &self.client, &self.country)?;
+        if self.playlist && (self.format.is_some() || self.item.is_some()) {
+            return Err(AppError::Invalid {
+                field: "playlist",
+                reason: "a whole playlist takes a profile or best, not one video's format or item".to_string(),
+            });
+        }
+        if let Some(c) = &self.container {
+            validate::one_of("container", c, CONTAINERS)?;
+        }
+        if let Some(a) = &self.audio_format {
+            validate::one_of("audio_format", a, AUDIO_FORMATS)?;
+        }
+        if let Some(id) = &self.format {
+            validate::format_id("format", id)?;
+        }
+        if let Some(b) = &self.best {
+            validate::one_of("best", b, BEST)?;
+        }
+        let named = [self.format.is_some(), self.profile.is_some(), self.best.is_some()].iter().filter(|n| **n).count();
+        if named > 1 {
+            return Err(AppError::Invalid { field: "format", reason: "name only one of format, profile or best".to_string() });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct Queued {
+    /// The chain's id on /jobs
+    job: u64,
+    title: String,
+    /// Server-sent events with its progress
+    progress: String,
+}
+
+/// Queues a download like the wizard's last step does. Answers 202 with the job.
+pub async fn download(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Extension(gone): Extension<ClientGone>,
+    Json(req): Json<DownloadRequest>,
+) -> Result<Response, AppError> {
+    state.jobs.accepting()?;
+    let url = req.url.trim().to_string();
+    gate::check_proxy(&state.config, req.proxy)?;
+    let workaround = Workaround::from_form(&req.browser, &req.client, &req.country, req.proxy);
+
+    let (choice, title) = match (req.format, req.profile, req.best.as_deref()) {
+        // Only what the URL offers, as in the wizard
+        (Some(id), _, _) => {
+            let entries = analysis_of(&state, auth::viewer(&state, &sid).as_deref(), &url, &gone, workaround.as_ref(), req.item).await?;
+            let [meta] = <[YtDlpOutput; 1]>::try_from(entries).map_err(|entries| AppError::Invalid {
+                field: "item",
+                reason: format!("this URL holds {} videos; pick one with item", entries.len()),
+            })?;
+            let title = sanitize::line(&meta.title);
+            let (formats, _) = crate::format_table(&url, meta.formats, meta.unlocked.as_ref());
+            let format = formats.into_iter().find(|f| f.id == id).ok_or_else(|| AppError::Invalid {
+                field: "format",
+                reason: format!("format {} was not offered for this URL", id),
+            })?;
+            (Choice::Format(Box::new(format)), title)
+        }
+        (None, Some(id), _) => match profiles::get(&state.db, id).await? {
+            Some(p) => (Choice::Profile(p), url.clone()),
+            None => return Err(AppError::NotFound("Device profile".to_string())),
+        },
+        (None, None, Some("audio")) => (Choice::BestAudio, url.clone()),
+        (None, None, _) => (Choice::BestVideo, url.clone()),
+    };
+    // A profile brings its own container
+    let container = match &choice {
+        Choice::Profile(p) => p.container.clone(),
+        _ => req.container.unwrap_or_else(|| CONTAINERS[0].to_string()),
+    };
+    let selection = Selection {
+        choice,
+        container,
+        audio_format: req.audio_format.unwrap_or_else(|| AUDIO_FORMATS[0].to_string()),
+        compat: req.compat,
+        workaround,
+        item: req.item,
+        date_after: None,
+        playlist: req.playlist,
+    };
+    let steps = crate::download_steps(url, selection, req.polite);
+    let job = state.jobs.submit_as(&title, auth::viewer(&state, &sid).as_deref(), steps)?;
+    let queued = Queued { job, title, progress: format!("{}/jobs/{}/progress", state.config.base_path, job) };
+    Ok((StatusCode::ACCEPTED, JsonBody(queued)).into_response())
+}
+
+// --- Files ---
+
+#[derive(Serialize)]
+pub struct File {
+    name: String,
+    /// "video", "audio", "image" or "other"
+    media_type: &'static str,
+    mime_type: String,
+    size: u64,
+    modified: Option<Stamp>,
+    title: Option<String>,
+    channel: Option<String>,
+    duration: Option<f64>,
+    /// The page it was downloaded from
+    source: Option<String>,
+    /// Where to fetch the file itself
+    url: String,
+}
+
+/// The library's files, `?channel=` for one channel's.
+pub async fn files(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Query(query): Query<FilesQuery>,
+) -> Result<Response, AppError> {
+    let channel = query.channel.filter(|c| !c.is_empty());
+    let dir = &state.config.downloads_dir;
+    let names = library::list_names(dir)?;
+    let viewer = auth::viewer(&state, &sid);
+    let files: Vec<File> = crate::shown_files(dir, &names, viewer.as_deref(), channel.as_deref())
+        .into_iter()
+        .map(|(name, info)| {
+            let path = std::path::Path::new(dir).join(&name);
+            let mime = mime_guess::from_path(&path).first_or_octet_stream();
+            let meta = std::fs::metadata(&path).ok();
+            let info = info.unwrap_or_default();
+            File {
+                media_type: match library::media_type_of(&mime) {
+                    MediaType::Video => "video",
+                    MediaType::Audio => "audio",
+                    MediaType::Image => "image",
+                    MediaType::Other => "other",
+                },
+                mime_type: mime.to_string(),
+                size: meta.as_ref().map(|m| m.len()).unwrap_or(0),
+                modified: meta.as_ref().and_then(Stamp::modified),
+                title: info.title,
+                channel: info.channel.or(info.uploader),
+                duration: info.duration,
+                source: info.webpage_url,
+                url: format!("{}/content/{}", state.config.base_path, sanitize::url_path(&name)),
+                name,
+            }
+        })
+        .collect();
+    Ok(JsonBody(files).into_response())
+}
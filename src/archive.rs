@@ -0,0 +1,119 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::clock::Stamp;
+use crate::collate;
+use crate::error::AppError;
+use crate::library::{self, INDEX_HTML, INDEX_JSON};
+use crate::page::{self, FlashKind};
+use crate::sanitize::filters;
+use crate::session::SessionId;
+use crate::AppState;
+
+// --- Data Structures ---
+
+/// One library file as the static index describes it. Every path is relative
+/// to the downloads directory, so the index works wherever it's copied or shared.
+#[derive(Debug, Serialize)]
+pub struct IndexEntry {
+    pub file: String,
+    pub title: String,
+    /// video, audio, image or other
+    pub media_type: &'static str,
+    pub size_bytes: u64,
+    pub thumbnail: Option<String>,
+    pub channel: Option<String>,
+    pub duration: Option<String>,
+    /// YYYYMMDD, as yt-dlp has it
+    pub upload_date: Option<String>,
+    pub source_url: Option<String>,
+}
+
+impl IndexEntry {
+    pub fn size(&self) -> String {
+        format!("{:.2} MB", self.size_bytes as f64 / 1024.0 / 1024.0)
+    }
+}
+
+#[derive(Serialize)]
+struct IndexJson<'a> {
+    generated_at: String,
+    items: &'a [IndexEntry],
+}
+
+#[derive(Template)]
+#[template(path = "archive_index.html")]
+struct ArchiveTemplate<'a> {
+    items: &'a [IndexEntry],
+    generated: Stamp,
+}
+
+// --- Export ---
+
+fn entries(dir: &str, locale: &str) -> std::io::Result<Vec<IndexEntry>> {
+    let names = library::list_names(dir)?;
+    let mut items = Vec::new();
+    for name in &names {
+        if name.starts_with('.') || library::is_sidecar(name, &names) {
+            continue;
+        }
+        let path = Path::new(dir).join(name);
+        let mime = mime_guess::from_path(&path).first_or_octet_stream();
+        let meta = library::load_meta(dir, name).unwrap_or_default();
+        items.push(IndexEntry {
+            file: name.clone(),
+            title: meta.title.clone().unwrap_or_else(|| name.clone()),
+            media_type: crate::reconcile::media_type_label(library::media_type_of(&mime)),
+            size_bytes: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+            thumbnail: library::thumbnail_for(name, &names),
+            channel: meta.channel.clone().or_else(|| meta.uploader.clone()),
+            duration: meta.duration.is_some().then(|| meta.duration_label()),
+            upload_date: meta.upload_date,
+            source_url: meta.webpage_url,
+        });
+    }
+    collate::sort_by(&mut items, locale, |e| &e.title);
+    Ok(items)
+}
+
+// Written next to the final name and moved over it, so a share never sees half a file
+fn write(dir: &str, name: &str, contents: &str) -> std::io::Result<()> {
+    let tmp = Path::new(dir).join(format!(".{}.tmp", name));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, Path::new(dir).join(name))
+}
+
+/// Writes index.html and index.json into the downloads directory `dir`.
+/// Returns how many files they list.
+pub fn export(dir: &str, locale: &str) -> Result<usize, AppError> {
+    let items = entries(dir, locale)?;
+    let generated = Stamp::now();
+    let html = ArchiveTemplate { items: &items, generated }.render().map_err(std::io::Error::other)?;
+    let json = serde_json::to_string_pretty(&IndexJson { generated_at: generated.iso(), items: &items })?;
+    write(dir, INDEX_JSON, &json)?;
+    write(dir, INDEX_HTML, &html)?;
+    Ok(items.len())
+}
+
+// --- Handlers ---
+
+pub async fn export_index(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let (dir, locale) = (state.config.downloads_dir.clone(), state.config.sort_locale.clone());
+    let count = tokio::task::spawn_blocking(move || export(&dir, &locale))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+    page::flash(
+        &state,
+        &sid,
+        FlashKind::Info,
+        format!("Wrote {} and {} listing {} file(s) to {}/.", INDEX_HTML, INDEX_JSON, count, state.config.downloads_dir),
+    );
+    Ok(Redirect::to("/files").into_response())
+}
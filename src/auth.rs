@@ -0,0 +1,453 @@
+use askama::Template;
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, Method},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+use std::fmt;
+use std::path::Path as FsPath;
+use std::sync::{Arc, Mutex};
+
+use crate::clock::Stamp;
+use crate::config::Config;
+use crate::error::{self, render, AppError};
+use crate::page::{self, FlashKind};
+use crate::ratelimit;
+use crate::sanitize::{self, filters};
+use crate::secrets::{self, Secrets};
+use crate::session::{self, SessionId};
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+/// Tokens made on /system/tokens are kept in the secret store under this prefix.
+pub const TOKEN_PREFIX: &str = "token-";
+
+// Generated tokens are this many letters and digits, about 238 bits
+const TOKEN_LEN: usize = 40;
+// Shorter tokens in the file are ignored rather than trusted
+const MIN_FILE_TOKEN_LEN: usize = 16;
+
+// Open to anyone, so a browser can still sign in and look right while it does,
+// and container probes don't need a token
+const OPEN_PATHS: &[&str] = &["/login", "/logout", "/favicon.svg", "/healthz", "/readyz"];
+
+// Under /system but not only for admins: everyone manages their own tokens
+const OWN_PATHS: &[&str] = &["/system/tokens"];
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone)]
+struct Token {
+    name: String,
+    value: String,
+    /// Read from BSDL_API_TOKENS_FILE rather than made on the tokens page
+    from_file: bool,
+    created: Option<Stamp>,
+    /// The user who made it, whose folder it sees; `None` for the file's and
+    /// those an admin made, which see the whole library
+    owner: Option<String>,
+}
+
+/// A token as its page lists it; the value itself is never shown again.
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    pub name: String,
+    pub from_file: bool,
+    pub created: Option<Stamp>,
+    pub owner: Option<String>,
+}
+
+/// What a browser signed in at /login with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignedIn {
+    /// A user's name and password
+    User(String),
+    /// An API token, by its name
+    Token(String),
+}
+
+impl fmt::Display for SignedIn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignedIn::User(name) => write!(f, "{}", name),
+            SignedIn::Token(name) => write!(f, "{} (token)", name),
+        }
+    }
+}
+
+/// The tokens that may call the app. With none at all, nothing is asked for.
+#[derive(Clone, Default)]
+pub struct Tokens {
+    inner: Arc<Mutex<Vec<Token>>>,
+    /// Read routes (and every page) want a token too, not only the ones that change something
+    reads: bool,
+}
+
+// "name token" or just "token" per line; blank lines and # comments are skipped
+fn parse_file(path: &FsPath, text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (name, value) = match (fields.next(), fields.next()) {
+            (Some(name), Some(value)) => (name.to_string(), value.to_string()),
+            (Some(value), None) => (format!("file-{}", i + 1), value.to_string()),
+            _ => continue,
+        };
+        if value.len() < MIN_FILE_TOKEN_LEN {
+            tracing::warn!("Ignoring the token on line {} of {}: shorter than {} characters", i + 1, path.display(), MIN_FILE_TOKEN_LEN);
+            continue;
+        }
+        tokens.push(Token { name: sanitize::line(&name), value, from_file: true, created: None, owner: None });
+    }
+    tokens
+}
+
+impl Tokens {
+    /// The configured file's tokens and the stored ones.
+    pub async fn load(config: &Config, secrets: &Secrets) -> Result<Tokens, AppError> {
+        let tokens = Tokens { inner: Arc::default(), reads: config.auth_reads };
+        tokens.reload(config, secrets).await?;
+        Ok(tokens)
+    }
+
+    async fn reload(&self, config: &Config, secrets: &Secrets) -> Result<(), AppError> {
+        let mut loaded = match &config.api_tokens_file {
+            Some(path) => parse_file(path, &std::fs::read_to_string(path)?),
+            None => Vec::new(),
+        };
+        for info in secrets.list().await? {
+            let Some(name) = info.name.strip_prefix(TOKEN_PREFIX) else {
+                continue;
+            };
+            // "token" or "token owner"
+            if let Some(stored) = secrets.get(&info.name).await? {
+                let (value, owner) = match stored.split_once(' ') {
+                    Some((value, owner)) => (value.to_string(), Some(owner.to_string())),
+                    None => (stored, None),
+                };
+                loaded.push(Token { name: name.to_string(), value, from_file: false, created: Some(info.updated), owner });
+            }
+        }
+        *self.inner.lock().unwrap() = loaded;
+        Ok(())
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.inner.lock().unwrap().is_empty()
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    /// Name of the token `presented` is, if it is one.
+    fn check(&self, presented: &str) -> Option<String> {
+        let tokens = self.inner.lock().unwrap();
+        // All of them, not stopping at the first match, and each in constant time
+        tokens.iter().fold(None, |found, t| {
+            if t.value.as_bytes().ct_eq(presented.as_bytes()).into() { Some(t.name.clone()) } else { found }
+        })
+    }
+
+    fn knows(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().iter().any(|t| t.name == name)
+    }
+
+    /// The user a token was made by, if it's theirs alone.
+    fn owner(&self, name: &str) -> Option<String> {
+        self.inner.lock().unwrap().iter().find(|t| t.name == name).and_then(|t| t.owner.clone())
+    }
+
+    pub fn list(&self) -> Vec<TokenInfo> {
+        let tokens = self.inner.lock().unwrap();
+        tokens
+            .iter()
+            .map(|t| TokenInfo { name: t.name.clone(), from_file: t.from_file, created: t.created, owner: t.owner.clone() })
+            .collect()
+    }
+}
+
+// --- Middleware ---
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Whether anything asks who's there: once there is a token or a user.
+pub fn enabled(state: &AppState) -> bool {
+    state.tokens.enabled() || state.users.enabled()
+}
+
+// Whether `req` goes through without signing in. The admin pages under
+// /system never do; other reads only while there are no users and
+// BSDL_AUTH_READS isn't set, and reads that run yt-dlp count as changes.
+fn open(state: &AppState, req: &Request) -> bool {
+    let path = req.uri().path();
+    if OPEN_PATHS.contains(&path) || path.starts_with("/assets/") {
+        return true;
+    }
+    if path.starts_with("/system/") || state.tokens.reads || state.users.enabled() {
+        return false;
+    }
+    matches!(*req.method(), Method::GET | Method::HEAD) && !ratelimit::spawns(req)
+}
+
+/// Who the session signed in as, if that user or token is still there.
+pub fn signed_in(state: &AppState, sid: &SessionId) -> Option<SignedIn> {
+    state.sessions.get(sid).signed_in.filter(|who| match who {
+        SignedIn::User(name) => state.users.knows(name),
+        SignedIn::Token(name) => state.tokens.knows(name),
+    })
+}
+
+/// The user the browser signed in as, whose folder of the library it sees.
+/// For an API token, the user who made it. `None` without users, or for a
+/// token from the file or made by an admin, which sees all of it.
+pub fn viewer(state: &AppState, sid: &SessionId) -> Option<String> {
+    match signed_in(state, sid)? {
+        SignedIn::User(name) => Some(name),
+        SignedIn::Token(name) => state.tokens.owner(&name),
+    }
+}
+
+/// Whether `who` may use the /system pages: an admin, or a token that isn't
+/// one user's (or whose user is an admin).
+fn is_admin(state: &AppState, who: &SignedIn) -> bool {
+    match who {
+        SignedIn::User(name) => state.users.is_admin(name),
+        SignedIn::Token(name) => state.tokens.owner(name).is_none_or(|owner| state.users.is_admin(&owner)),
+    }
+}
+
+/// Whether the session may use the /system pages; anyone may while nothing asks who's there.
+pub fn admin(state: &AppState, sid: &SessionId) -> bool {
+    !enabled(state) || signed_in(state, sid).is_some_and(|who| is_admin(state, &who))
+}
+
+// The /system pages, less the ones everyone may use
+fn admin_only(path: &str) -> bool {
+    path.starts_with("/system/") && !OWN_PATHS.iter().any(|p| path == *p || path.strip_prefix(p).is_some_and(|rest| rest.starts_with('/')))
+}
+
+/// Signs the browser in as `who` under a new session id, so one someone
+/// planted before sign-in is worth nothing after, and sends it on to `to`.
+pub fn sign_in(state: &AppState, sid: &SessionId, who: SignedIn, to: &str) -> Response {
+    let renewed = state.sessions.renew(sid);
+    state.sessions.update(&renewed, |s| s.signed_in = Some(who));
+    let mut res = Redirect::to(to).into_response();
+    if let Ok(value) = session::set_cookie(state, &renewed).parse() {
+        res.headers_mut().append(header::SET_COOKIE, value);
+    }
+    res
+}
+
+/// Once there is a token or a user, requests that change something need one:
+/// a token as `Authorization: Bearer`, or a session signed in at /login. With
+/// users, every page does. Browsers are sent to sign in; scripts get a 401.
+/// The /system pages want an admin, except for managing one's own tokens.
+pub async fn auth_layer(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    if !enabled(&state) || open(&state, &req) {
+        return next.run(req).await;
+    }
+    let who = if let Some(presented) = bearer(req.headers()) {
+        // A wrong token is refused, even from a browser that's signed in
+        let Some(name) = state.tokens.check(presented) else {
+            return AppError::Unauthorized.into_response();
+        };
+        // A session of its own, so handlers see whose token it is
+        let who = SignedIn::Token(name);
+        req.extensions_mut().insert(state.sessions.issue(who.clone()));
+        who
+    } else {
+        match req.extensions().get::<SessionId>().and_then(|sid| signed_in(&state, sid)) {
+            Some(who) => who,
+            None if error::wants_json(&req) => return AppError::Unauthorized.into_response(),
+            None => {
+                // A form's data is lost on the way, so POSTs come back to the start
+                let back = match *req.method() {
+                    Method::GET => req.uri().path_and_query().map(|p| p.to_string()).unwrap_or_default(),
+                    _ => "/".to_string(),
+                };
+                return Redirect::to(&format!("/login?back={}", sanitize::query_value(&back))).into_response();
+            }
+        }
+    };
+    if admin_only(req.uri().path()) && !is_admin(&state, &who) {
+        return AppError::Forbidden(format!("{} isn't an admin; the /system pages are only for admins", who)).into_response();
+    }
+    next.run(req).await
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    back: String,
+    /// Whether to offer the name and password form
+    users: bool,
+    /// Whether to offer the API token form
+    tokens: bool,
+}
+
+// Only paths on this app, so the form can't send anyone elsewhere
+fn local(back: &str) -> String {
+    if back.starts_with('/') && !back.starts_with("//") && !back.contains(['\\', '\r', '\n']) {
+        back.to_string()
+    } else {
+        "/".to_string()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    #[serde(default)]
+    back: String,
+}
+
+pub async fn show_login(State(state): State<AppState>, Query(query): Query<LoginQuery>) -> Response {
+    render(LoginTemplate { back: local(&query.back), users: state.users.enabled(), tokens: state.tokens.enabled() })
+}
+
+/// Either a user's name and password, or an API token.
+#[derive(Deserialize)]
+pub struct LoginForm {
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    back: String,
+}
+
+impl Validate for LoginForm {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::max_len("username", &self.username, 64)?;
+        validate::max_len("password", &self.password, 256)?;
+        validate::max_len("token", &self.token, 256)
+    }
+}
+
+pub async fn login(State(state): State<AppState>, Extension(sid): Extension<SessionId>, Form(form): Form<LoginForm>) -> Response {
+    let back = local(&form.back);
+    let username = form.username.trim();
+    let who = if username.is_empty() {
+        state.tokens.check(form.token.trim()).map(SignedIn::Token)
+    } else {
+        let known = state.users.check(username, &form.password).await;
+        known.then(|| SignedIn::User(username.to_string()))
+    };
+    let Some(who) = who else {
+        let msg = if username.is_empty() { "That isn't a valid token." } else { "Wrong user name or password." };
+        page::flash(&state, &sid, FlashKind::Error, msg);
+        return Redirect::to(&format!("/login?back={}", sanitize::query_value(&back))).into_response();
+    };
+    tracing::info!("Signed in: {}", who);
+    sign_in(&state, &sid, who, &back)
+}
+
+pub async fn logout(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    state.sessions.update(&sid, |s| s.signed_in = None);
+    Redirect::to(if enabled(&state) { "/login" } else { "/" }).into_response()
+}
+
+#[derive(Template)]
+#[template(path = "tokens.html")]
+struct TokensTemplate {
+    tokens: Vec<TokenInfo>,
+    file: Option<String>,
+    reads: bool,
+}
+
+// The tokens `sid` may see and revoke: all of them for an admin, else its user's own
+fn visible_tokens(state: &AppState, sid: &SessionId) -> Vec<TokenInfo> {
+    let (admin, viewer) = (admin(state, sid), viewer(state, sid));
+    state.tokens.list().into_iter().filter(|t| admin || (t.owner.is_some() && t.owner == viewer)).collect()
+}
+
+pub async fn show_tokens(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    render(TokensTemplate {
+        tokens: visible_tokens(&state, &sid),
+        file: state.config.api_tokens_file.as_ref().map(|p| p.display().to_string()),
+        reads: state.tokens.reads,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct TokenForm {
+    name: String,
+}
+
+impl Validate for TokenForm {
+    fn validate(&self) -> Result<(), AppError> {
+        let name = self.name.trim();
+        if name.is_empty() || !secrets::valid_name(&format!("{}{}", TOKEN_PREFIX, name)) {
+            return Err(AppError::Invalid {
+                field: "name",
+                reason: "token names are up to 58 lowercase letters, digits, '-' or '_'".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Makes a token and shows it once. A user who isn't an admin owns theirs,
+/// and it sees only their folder. The browser that made it is signed in with
+/// it, so turning auth on from here doesn't lock it out.
+pub async fn create_token(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<TokenForm>,
+) -> Result<Response, AppError> {
+    let name = form.name.trim().to_string();
+    if state.tokens.knows(&name) {
+        return Err(AppError::Invalid { field: "name", reason: format!("there is already a token called {}", name) });
+    }
+    let value: String = rand::thread_rng().sample_iter(&Alphanumeric).take(TOKEN_LEN).map(char::from).collect();
+    let stored = match viewer(&state, &sid).filter(|_| !admin(&state, &sid)) {
+        Some(owner) => format!("{} {}", value, owner),
+        None => value.clone(),
+    };
+    state.secrets.set(&format!("{}{}", TOKEN_PREFIX, name), &stored).await?;
+    state.tokens.reload(&state.config, &state.secrets).await?;
+    tracing::info!("Made API token {}", name);
+    let msg = format!("Made token {}: {} (copy it now, it isn't shown again)", name, value);
+    page::flash(&state, &sid, FlashKind::Info, msg);
+    if signed_in(&state, &sid).is_none() {
+        return Ok(sign_in(&state, &sid, SignedIn::Token(name), "/system/tokens"));
+    }
+    Ok(Redirect::to("/system/tokens").into_response())
+}
+
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    // Someone else's token is as good as not there
+    if !visible_tokens(&state, &sid).iter().any(|t| t.name == name && !t.from_file) {
+        return Err(AppError::NotFound(format!("Token \"{}\"", name)));
+    }
+    if !state.secrets.delete(&format!("{}{}", TOKEN_PREFIX, name)).await? {
+        return Err(AppError::NotFound(format!("Token \"{}\"", name)));
+    }
+    state.tokens.reload(&state.config, &state.secrets).await?;
+    tracing::info!("Revoked API token {}", name);
+    page::flash(&state, &sid, FlashKind::Info, format!("Revoked {}; anything using it is turned away now.", name));
+    // Revoking the token this browser signed in with signs it out
+    let back = if signed_in(&state, &sid).is_some() || !enabled(&state) { "/system/tokens" } else { "/login" };
+    Ok(Redirect::to(back).into_response())
+}
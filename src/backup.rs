@@ -0,0 +1,323 @@
+use axum::{
+    extract::{Path, State},
+    Extension,
+    response::{IntoResponse, Redirect, Response},
+};
+use askama::Template;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::fs;
+use std::path::{Path as FsPath, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::clock::Stamp;
+use crate::config::CONFIG_FILE;
+use crate::db;
+use crate::error::{render, AppError};
+use crate::page::{self, FlashKind};
+use crate::sanitize::filters;
+use crate::schedule::Timer;
+use crate::reconcile;
+use crate::session::SessionId;
+use crate::AppState;
+
+pub const BACKUP_DIR: &str = "backups";
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone)]
+pub struct BackupFile {
+    pub name: String,
+    pub size_kb: String,
+    pub created: Stamp,
+}
+
+// --- Snapshots ---
+
+// Archives hold the SQLite file; a PostgreSQL server has its own tools for this
+fn unsupported(state: &AppState) -> Option<String> {
+    let engine = state.db.engine();
+    (engine != "SQLite").then(|| format!("Backups aren't supported on {}: they only cover SQLite; back {} up with its own tools, like pg_dump", engine, engine))
+}
+
+fn check_engine(state: &AppState) -> Result<(), AppError> {
+    match unsupported(state) {
+        Some(reason) => Err(AppError::BadRequest(reason)),
+        None => Ok(()),
+    }
+}
+
+// Claims the first archive name free for `stamp`. A second backup within the
+// same second, like a restore's safety net right after a manual one, gets -2,
+// then -3 and so on.
+fn reserve(stamp: &str, label: &str) -> std::io::Result<(String, fs::File)> {
+    let mut n = 1;
+    loop {
+        let mut name = format!("bplus-backup-{}", stamp);
+        if n > 1 {
+            name.push_str(&format!("-{}", n));
+        }
+        if !label.is_empty() {
+            name.push_str(&format!("-{}", label));
+        }
+        name.push_str(".tar.gz");
+        match fs::OpenOptions::new().write(true).create_new(true).open(FsPath::new(BACKUP_DIR).join(&name)) {
+            Ok(file) => return Ok((name, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Writes `backups/bplus-backup-<timestamp>[-<n>][-<label>].tar.gz` with a consistent
+/// copy of the database, the config file if there is one, and a small manifest.
+pub async fn create(state: &AppState, label: &str) -> Result<PathBuf, AppError> {
+    check_engine(state)?;
+    fs::create_dir_all(BACKUP_DIR)?;
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let (name, file) = reserve(&stamp, label)?;
+    let archive = FsPath::new(BACKUP_DIR).join(&name);
+
+    // Named after the archive, so it's as unique as that is
+    let snapshot = FsPath::new(BACKUP_DIR).join(format!(".{}.db", name.trim_end_matches(".tar.gz")));
+    let snapshot_to = snapshot.clone();
+    if let Err(e) = state.db.call(move |conn| conn.snapshot(&snapshot_to)).await {
+        let _ = fs::remove_file(&snapshot);
+        let _ = fs::remove_file(&archive);
+        return Err(e);
+    }
+
+    let snap = snapshot.clone();
+    // Picked up alongside the database when present, as config.toml whatever it's called
+    let config_file = state.config.config_file.clone();
+    let packed = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+        let mut files = vec!["bplus.db"];
+        tar.append_path_with_name(&snap, "bplus.db")?;
+        if config_file.is_file() {
+            tar.append_path_with_name(&config_file, CONFIG_FILE)?;
+            files.push(CONFIG_FILE);
+        }
+
+        let manifest = serde_json::json!({
+            "created_at": db::now(),
+            "app_version": env!("CARGO_PKG_VERSION"),
+            "files": files,
+        });
+        let body = serde_json::to_vec_pretty(&manifest).map_err(std::io::Error::other)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(body.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(db::now() as u64);
+        header.set_cksum();
+        tar.append_data(&mut header, "manifest.json", body.as_slice())?;
+
+        tar.into_inner()?.finish()?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| AppError::Io(std::io::Error::other(e)))?;
+
+    let _ = fs::remove_file(&snapshot);
+    if let Err(e) = packed {
+        let _ = fs::remove_file(&archive);
+        return Err(e.into());
+    }
+    tracing::info!("Backup written to {}", archive.display());
+
+    prune(state.config.backup_keep);
+    if let Some(upload) = &state.config.backup_upload_cmd {
+        upload_copy(state, upload, &archive).await;
+    }
+    Ok(archive)
+}
+
+// Hands the archive to a user-supplied command (rclone, scp, aws s3 cp, ...)
+// as its last argument. A failed upload is logged; the local copy still counts.
+async fn upload_copy(state: &AppState, command_line: &str, archive: &FsPath) {
+    let mut parts = command_line.split_whitespace();
+    let Some(program) = parts.next() else {
+        return;
+    };
+    let mut cmd = Command::new(program);
+    cmd.args(parts).arg(archive);
+    match state.procs.output("backup upload", cmd).await {
+        Ok(out) if out.status.success() => tracing::info!("Backup {} uploaded", archive.display()),
+        Ok(out) => tracing::warn!(
+            "Backup upload failed ({}): {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ),
+        Err(e) => tracing::warn!("Backup upload could not start: {}", e),
+    }
+}
+
+// Keeps only the newest `keep` archives
+fn prune(keep: usize) {
+    let mut backups = list();
+    if keep == 0 || backups.len() <= keep {
+        return;
+    }
+    for old in backups.split_off(keep) {
+        let path = FsPath::new(BACKUP_DIR).join(&old.name);
+        if let Err(e) = fs::remove_file(&path) {
+            tracing::warn!("Could not prune {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Newest first.
+pub fn list() -> Vec<BackupFile> {
+    let Ok(entries) = fs::read_dir(BACKUP_DIR) else {
+        return Vec::new();
+    };
+    let mut files: Vec<BackupFile> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with("bplus-backup-") || !name.ends_with(".tar.gz") {
+                return None;
+            }
+            let meta = entry.metadata().ok()?;
+            Some(BackupFile {
+                name,
+                size_kb: format!("{:.1} KB", meta.len() as f64 / 1024.0),
+                created: Stamp::modified(&meta).unwrap_or(Stamp(0)),
+            })
+        })
+        .collect();
+    files.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| b.name.cmp(&a.name)));
+    files
+}
+
+// Where an archive is unpacked to; gone when the restore is over, however it ended
+struct Staging(PathBuf);
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Could not remove {}: {}", self.0.display(), e);
+            }
+        }
+    }
+}
+
+/// Replaces the live database with the one in `name`. A config file in the
+/// archive is put back too, but only takes effect after a restart.
+pub async fn restore(state: &AppState, name: &str) -> Result<bool, AppError> {
+    check_engine(state)?;
+    if name.contains(['/', '\\']) || !list().iter().any(|b| b.name == name) {
+        return Err(AppError::NotFound(format!("Backup \"{}\"", name)));
+    }
+    let archive = FsPath::new(BACKUP_DIR).join(name);
+    let staging = Staging(FsPath::new(BACKUP_DIR).join(format!(".restore-{}", db::now())));
+
+    let unpack_to = staging.0.clone();
+    tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        let file = fs::File::open(&archive)?;
+        tar::Archive::new(GzDecoder::new(file)).unpack(&unpack_to)
+    })
+    .await
+    .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+
+    let restored_db = staging.0.join("bplus.db");
+    if !restored_db.is_file() {
+        return Err(AppError::BadRequest(format!("{} has no database in it", name)));
+    }
+
+    // Safety net in case the restore was a mistake
+    create(state, "pre-restore").await?;
+
+    let source = restored_db.clone();
+    state.db.call(move |conn| conn.restore(&source)).await?;
+
+    let config_back = staging.0.join(CONFIG_FILE);
+    let had_config = config_back.is_file();
+    if had_config {
+        fs::copy(&config_back, &state.config.config_file)?;
+    }
+    drop(staging);
+    tracing::info!("Restored state from {}", name);
+
+    // The restored index predates whatever changed on disk since
+    reconcile::run(&state.db, &state.config.downloads_dir, "restore").await?;
+    Ok(had_config)
+}
+
+/// Takes a backup every `hours` in the background. 0 turns the schedule off.
+pub fn spawn_schedule(state: AppState, hours: u64) {
+    if hours == 0 {
+        return;
+    }
+    if let Some(reason) = unsupported(&state) {
+        tracing::info!("Scheduled backups are off. {}", reason);
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(hours * 3600));
+        // Skip the immediate first tick; a restart shouldn't mean a fresh backup
+        tick.tick().await;
+        state.timers.started(Timer::Backup, hours * 3600);
+        loop {
+            tick.tick().await;
+            state.timers.fired(Timer::Backup, hours * 3600);
+            if let Err(e) = create(&state, "").await {
+                tracing::warn!("Scheduled backup failed: {}", e);
+            }
+        }
+    });
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "backups.html")]
+struct BackupsTemplate {
+    backups: Vec<BackupFile>,
+    interval_hours: u64,
+    keep: usize,
+    uploads: bool,
+    /// Why there are none to take or restore here
+    unsupported: Option<String>,
+}
+
+pub async fn show_backups(State(state): State<AppState>) -> Response {
+    render(BackupsTemplate {
+        backups: list(),
+        interval_hours: state.config.backup_hours,
+        keep: state.config.backup_keep,
+        uploads: state.config.backup_upload_cmd.is_some(),
+        unsupported: unsupported(&state),
+    })
+}
+
+/// Takes a backup now and says how that went, here or on /schedule.
+pub async fn create_and_flash(state: &AppState, sid: &SessionId) {
+    match create(state, "").await {
+        Ok(path) => page::flash(state, sid, FlashKind::Info, format!("Created {}.", path.display())),
+        Err(e) => page::flash(state, sid, FlashKind::Error, format!("Backup failed: {}", e)),
+    }
+}
+
+pub async fn create_now(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    check_engine(&state)?;
+    create_and_flash(&state, &sid).await;
+    Ok(Redirect::to("/system/backups").into_response())
+}
+
+pub async fn restore_backup(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let had_config = restore(&state, &name).await?;
+    let mut notice = format!("Restored {}.", name);
+    if had_config {
+        notice.push_str(" The config file was put back too; restart the app for it to apply.");
+    }
+    page::flash(&state, &sid, FlashKind::Info, notice);
+    Ok(Redirect::to("/system/backups").into_response())
+}
+
@@ -0,0 +1,147 @@
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use serde::Deserialize;
+
+use crate::auth;
+use crate::dedupe;
+use crate::error::{render, AppError};
+use crate::page::{self, FlashKind};
+use crate::recipe::{self, Recipe, POSTPROCESS};
+use crate::session::SessionId;
+use crate::subscriptions;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+/// Most URLs one batch may queue
+const MAX_BATCH: usize = 1000;
+/// How far back a playlist or channel is listed when it fills the batch
+const FROM_DEPTH: usize = 200;
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "batch.html")]
+struct BatchTemplate {
+    presets: Vec<(String, String)>,
+    postprocess: &'static [(&'static str, &'static str)],
+    sleep_min: u64,
+    sleep_max: u64,
+    per_hour: usize,
+    pause_minutes: u64,
+    /// Prefilled URLs, one per line
+    urls: String,
+    /// Playlist or channel they were listed from
+    source: Option<String>,
+    /// Videos of it left out for being in the download archive
+    archived: usize,
+    error: Option<String>,
+}
+
+/// `?from=<playlist or channel URL>&name=` fills the batch with its videos
+#[derive(Deserialize)]
+pub struct BatchSource {
+    #[serde(default)]
+    from: String,
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct BatchForm {
+    /// One URL per line
+    urls: String,
+    #[serde(default)]
+    preset: String,
+    #[serde(default)]
+    output_template: String,
+    #[serde(default)]
+    postprocess: String,
+    #[serde(default)]
+    polite: Option<String>,
+}
+
+impl BatchForm {
+    fn urls(&self) -> Vec<&str> {
+        self.urls.lines().map(str::trim).filter(|l| !l.is_empty()).collect()
+    }
+}
+
+impl Validate for BatchForm {
+    fn validate(&self) -> Result<(), AppError> {
+        let urls = self.urls();
+        if urls.is_empty() || urls.len() > MAX_BATCH {
+            return Err(AppError::Invalid { field: "urls", reason: format!("give 1 to {} URLs, one per line", MAX_BATCH) });
+        }
+        for url in urls {
+            validate::url("urls", url)?;
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return Err(AppError::Invalid { field: "urls", reason: format!("{} isn't an http:// or https:// URL", url) });
+            }
+        }
+        recipe::validate_fields(&self.preset, &self.output_template, &self.postprocess)
+    }
+}
+
+pub async fn show_batch(State(state): State<AppState>, Query(source): Query<BatchSource>) -> Result<Response, AppError> {
+    let from = source.from.trim();
+    let (urls, archived, error) = if from.is_empty() {
+        (String::new(), 0, None)
+    } else {
+        let listed = match validate::url("from", from) {
+            Ok(()) if from.starts_with("http://") || from.starts_with("https://") => {
+                subscriptions::list_entries(&state, from, FROM_DEPTH).await
+            }
+            Ok(()) => Err(AppError::Invalid { field: "from", reason: format!("{} isn't an http:// or https:// URL", from) }),
+            Err(e) => Err(e),
+        };
+        match listed {
+            Ok(entries) if entries.is_empty() => (String::new(), 0, Some(format!("{} lists no videos", from))),
+            Ok(entries) => {
+                // Downloaded before, outside the app
+                let had = dedupe::archived(&state.db, entries.iter().map(|e| e.id.clone()).collect()).await?;
+                let urls = entries.iter().filter(|e| !had.contains(&e.id)).map(|e| e.url.as_str()).collect::<Vec<_>>();
+                (urls.join("\n"), had.len(), None)
+            }
+            Err(e) => (String::new(), 0, Some(e.to_string())),
+        }
+    };
+    let source = (!from.is_empty()).then(|| if source.name.trim().is_empty() { from.to_string() } else { source.name.trim().to_string() });
+
+    let polite = &state.config.polite;
+    Ok(render(BatchTemplate {
+        presets: recipe::preset_options(&state.db).await?,
+        postprocess: POSTPROCESS,
+        sleep_min: polite.sleep_min,
+        sleep_max: polite.sleep_max,
+        per_hour: polite.per_hour,
+        pause_minutes: polite.pause_minutes,
+        urls,
+        source,
+        archived,
+        error,
+    }))
+}
+
+pub async fn submit_batch(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<BatchForm>,
+) -> Result<Response, AppError> {
+    let recipe = Recipe::from_form(&form.preset, &form.output_template, &form.postprocess, form.polite.is_some());
+    recipe.check(&state.db).await?;
+    state.jobs.accepting()?;
+
+    let urls = form.urls();
+    let viewer = auth::viewer(&state, &sid);
+    for url in &urls {
+        let steps = recipe.steps(&state, url.to_string()).await?;
+        state.jobs.submit_as(url, viewer.as_deref(), steps)?;
+    }
+    let mode = if recipe.polite { " politely" } else { "" };
+    page::flash(&state, &sid, FlashKind::Info, format!("Queued {} URL(s){}.", urls.len(), mode));
+    Ok(Redirect::to("/jobs").into_response())
+}
@@ -0,0 +1,191 @@
+use chrono::{Datelike, Duration as Days, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+
+use crate::clock::Stamp;
+use crate::db::{self, params};
+use crate::error::AppError;
+use crate::jobs::Hold;
+use crate::notify;
+use crate::AppState;
+
+/// How long a BSDL_BUDGET_CMD answer is trusted, and how often held
+/// downloads look again in case the allowance changed.
+pub const RECHECK_SECS: i64 = 300;
+
+// --- Data Structures ---
+
+/// Transfer so far in the current billing month.
+#[derive(Debug, Clone)]
+pub struct Usage {
+    pub used: u64,
+    pub cap: u64,
+    pub pause_percent: u64,
+    pub resets: Stamp,
+    /// "counted by the app" or "reported by BSDL_BUDGET_CMD"
+    pub source: &'static str,
+}
+
+impl Usage {
+    pub fn percent(&self) -> u64 {
+        self.used.saturating_mul(100) / self.cap.max(1)
+    }
+
+    pub fn over(&self) -> bool {
+        self.percent() >= self.pause_percent
+    }
+
+    /// e.g. "41.2 GB of 100.0 GB (41%)"
+    pub fn summary(&self) -> String {
+        format!("{} of {} ({}%)", gigabytes(self.used), gigabytes(self.cap), self.percent())
+    }
+}
+
+fn gigabytes(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / 1024.0 / 1024.0 / 1024.0)
+}
+
+/// Remembers the last command answer and which month was already announced.
+#[derive(Clone, Default)]
+pub struct Meter {
+    inner: Arc<Mutex<MeterState>>,
+}
+
+#[derive(Default)]
+struct MeterState {
+    // When the command was asked, and what it said
+    reported: Option<(i64, u64)>,
+    // End of the billing month the pause notification went out for
+    notified: Option<i64>,
+}
+
+// --- Billing months ---
+
+// Start and end of the billing month `now` falls in
+fn period(tz: Tz, reset_day: u32, now: i64) -> (i64, i64) {
+    let today = tz.timestamp_opt(now, 0).single().map(|t| t.date_naive()).unwrap_or_default();
+    let (mut year, mut month) = (today.year(), today.month());
+    if today.day() < reset_day {
+        (year, month) = if month == 1 { (year - 1, 12) } else { (year, month - 1) };
+    }
+    let start = NaiveDate::from_ymd_opt(year, month, reset_day).unwrap_or(today);
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    let end = NaiveDate::from_ymd_opt(next_year, next_month, reset_day).unwrap_or(start + Days::days(30));
+    (midnight(tz, start), midnight(tz, end))
+}
+
+// Falls back to UTC midnight when a DST jump skips the local one
+fn midnight(tz: Tz, day: NaiveDate) -> i64 {
+    let naive = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+    tz.from_local_datetime(&naive).earliest().map(|t| t.timestamp()).unwrap_or(naive.and_utc().timestamp())
+}
+
+// --- Metering ---
+
+/// Adds a finished download to this month's count.
+pub async fn record(state: &AppState, bytes: u64) -> Result<(), AppError> {
+    let rules = &state.config.budget;
+    let (start, _) = period(state.config.timezone, rules.reset_day, db::now());
+    state
+        .db
+        .call(move |conn| {
+            conn.execute(
+                "INSERT INTO transfer_usage (period_start, bytes) VALUES (?1, ?2)
+                 ON CONFLICT (period_start) DO UPDATE SET bytes = transfer_usage.bytes + excluded.bytes",
+                params![start, bytes as i64],
+            )
+        })
+        .await?;
+    Ok(())
+}
+
+/// This month's transfer, or None when there's no budget.
+pub async fn usage(state: &AppState) -> Result<Option<Usage>, AppError> {
+    let rules = &state.config.budget;
+    if rules.monthly_bytes == 0 {
+        return Ok(None);
+    }
+    let now = db::now();
+    let (start, end) = period(state.config.timezone, rules.reset_day, now);
+    let (used, source) = match &rules.check_cmd {
+        Some(command_line) => (reported(state, command_line, now).await?, "reported by BSDL_BUDGET_CMD"),
+        None => {
+            let used = state
+                .db
+                .call(move |conn| {
+                    conn.query_row(
+                        "SELECT COALESCE(SUM(bytes), 0) FROM transfer_usage WHERE period_start = ?1",
+                        params![start],
+                        |r| r.get::<i64>(0),
+                    )
+                })
+                .await?;
+            (used.max(0) as u64, "counted by the app")
+        }
+    };
+    Ok(Some(Usage { used, cap: rules.monthly_bytes, pause_percent: rules.pause_percent, resets: Stamp(end), source }))
+}
+
+// Bytes used this month according to BSDL_BUDGET_CMD, whose output has to
+// start with a plain number
+async fn reported(state: &AppState, command_line: &str, now: i64) -> Result<u64, AppError> {
+    if let Some((asked, used)) = state.budget.inner.lock().unwrap().reported {
+        if now - asked < RECHECK_SECS {
+            return Ok(used);
+        }
+    }
+    let mut parts = command_line.split_whitespace();
+    let program = parts.next().unwrap_or_default();
+    let mut cmd = Command::new(program);
+    cmd.args(parts);
+    let out = state.procs.output("budget", cmd).await?;
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let used = match stdout.split_whitespace().next().map(str::parse::<u64>) {
+        Some(Ok(used)) if out.status.success() => used,
+        _ => {
+            let err = String::from_utf8_lossy(&out.stderr);
+            return Err(AppError::BadRequest(format!(
+                "BSDL_BUDGET_CMD exited with {} and no byte count: {}",
+                out.status,
+                err.trim()
+            )));
+        }
+    };
+    state.budget.inner.lock().unwrap().reported = Some((now, used));
+    Ok(used)
+}
+
+/// Holds downloads back once the budget is nearly spent, telling the admin the
+/// first time it happens in a month. A check that fails holds nothing.
+pub async fn hold(state: &AppState) -> Option<Hold> {
+    let usage = match usage(state).await {
+        Ok(usage) => usage?,
+        Err(e) => {
+            tracing::warn!("Could not check the transfer budget: {}", e);
+            return None;
+        }
+    };
+    if !usage.over() {
+        return None;
+    }
+
+    let first = {
+        let mut meter = state.budget.inner.lock().unwrap();
+        let month = usage.resets.0;
+        let first = meter.notified != Some(month);
+        meter.notified = Some(month);
+        first
+    };
+    if first {
+        let message = format!(
+            "Downloads paused: {} of the monthly transfer budget used, {}. They resume {}",
+            usage.summary(),
+            usage.source,
+            usage.resets
+        );
+        notify::send(state, &message).await;
+    }
+    let reason = format!("{} of the monthly transfer budget is used ({})", usage.summary(), usage.source);
+    Some(Hold { what: "Downloads", until: usage.resets, reason })
+}
@@ -0,0 +1,173 @@
+use chrono::{DateTime, Days, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+use crate::db::{self, params, Db};
+use crate::error::AppError;
+use crate::history;
+use crate::page;
+
+// The charts of the stats page, worked out from the history and the library
+// index and drawn here as SVG, so the page needs no script and asks no one
+// else for anything.
+
+/// Days the per-day charts go back, today included.
+pub const DAYS: usize = 30;
+
+/// Sites the top sites chart lists.
+const TOP_SITES: usize = 10;
+
+// Drawing area of a column chart, in viewBox units
+const WIDTH: f64 = 600.0;
+const HEIGHT: f64 = 140.0;
+
+// --- Data Structures ---
+
+/// One column of a chart: a day and its value.
+#[derive(Debug, Clone)]
+pub struct Column {
+    /// "2026-10-14: 3 downloads", shown on hover
+    pub label: String,
+    pub x: f64,
+    pub y: f64,
+    pub height: f64,
+}
+
+/// A column per day, scaled to the largest.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    pub columns: Vec<Column>,
+    pub width: f64,
+    /// What the top of the chart stands for
+    pub top: String,
+    pub first: String,
+    pub last: String,
+}
+
+/// One row of a ranked chart.
+#[derive(Debug, Clone)]
+pub struct Bar {
+    pub label: String,
+    pub value: usize,
+    /// Of the largest one
+    pub percent: f64,
+}
+
+// --- Drawing ---
+
+fn columns(days: &[NaiveDate], values: &[f64], label: impl Fn(f64) -> String) -> Columns {
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    let width = WIDTH / days.len().max(1) as f64;
+    let columns = days
+        .iter()
+        .zip(values)
+        .enumerate()
+        .map(|(i, (day, v))| {
+            let height = if max > 0.0 { v / max * HEIGHT } else { 0.0 };
+            Column { label: format!("{}: {}", day, label(*v)), x: i as f64 * width, y: HEIGHT - height, height }
+        })
+        .collect();
+    let day = |d: Option<&NaiveDate>| d.map(|d| d.format("%b %-d").to_string()).unwrap_or_default();
+    Columns { columns, width, top: label(max), first: day(days.first()), last: day(days.last()) }
+}
+
+// --- Data ---
+
+// The last DAYS days in the viewer's zone, oldest first, with when each ends;
+// the day before them comes first, for when the oldest starts
+fn last_days(tz: Tz) -> Vec<(NaiveDate, i64)> {
+    let today = DateTime::from_timestamp(db::now(), 0).unwrap_or_default().with_timezone(&tz).date_naive();
+    (0..=DAYS as u64)
+        .rev()
+        .filter_map(|back| {
+            let day = today.checked_sub_days(Days::new(back))?;
+            let next = day.checked_add_days(Days::new(1))?.and_hms_opt(0, 0, 0)?;
+            // A day starting in a DST gap begins at the first moment that exists
+            let end = tz.from_local_datetime(&next).earliest().map_or(next.and_utc().timestamp(), |t| t.timestamp());
+            Some((day, end))
+        })
+        .collect()
+}
+
+// Which of the days after the first a moment falls on
+fn day_of(days: &[(NaiveDate, i64)], at: i64) -> Option<usize> {
+    let i = days.partition_point(|(_, end)| *end <= at);
+    (i > 0 && i < days.len()).then(|| i - 1)
+}
+
+fn dates(days: &[(NaiveDate, i64)]) -> Vec<NaiveDate> {
+    days.iter().skip(1).map(|(d, _)| *d).collect()
+}
+
+/// Downloads finished on each of the last DAYS days.
+pub async fn downloads_per_day(db: &Db) -> Result<Columns, AppError> {
+    let days = last_days(page::timezone());
+    let since = days.first().map_or(0, |(_, end)| *end);
+    let finished: Vec<i64> = db
+        .call(move |conn| {
+            conn.query_map(
+                "SELECT finished_at FROM history WHERE kind = 'download' AND status = 'Done' AND finished_at >= ?1",
+                params![since],
+                |r| r.get(0),
+            )
+        })
+        .await?;
+    let mut counts = vec![0.0; DAYS];
+    for at in finished {
+        if let Some(i) = day_of(&days, at) {
+            counts[i] += 1.0;
+        }
+    }
+    Ok(columns(&dates(&days), &counts, |n| format!("{} download(s)", n)))
+}
+
+/// The library's size at the end of each of the last DAYS days, from the
+/// index: files count from when they were added until deleted or missing.
+pub async fn storage_per_day(db: &Db) -> Result<Columns, AppError> {
+    let days = last_days(page::timezone());
+    let items: Vec<(i64, i64, Option<i64>)> = db
+        .call(|conn| {
+            conn.query_map("SELECT added_at, size_bytes, COALESCE(deleted_at, missing_since) FROM items", params![], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })
+        })
+        .await?;
+    let sizes: Vec<f64> = days
+        .iter()
+        .skip(1)
+        .map(|(_, end)| {
+            let bytes: i64 = items
+                .iter()
+                .filter(|(added, _, gone)| *added < *end && gone.is_none_or(|g| g >= *end))
+                .map(|(_, size, _)| size)
+                .sum();
+            bytes as f64 / 1024.0 / 1024.0
+        })
+        .collect();
+    Ok(columns(&dates(&days), &sizes, |mb| match mb {
+        mb if mb >= 1024.0 => format!("{:.1} GB", mb / 1024.0),
+        mb if mb >= 10.0 => format!("{:.0} MB", mb),
+        mb => format!("{:.1} MB", mb),
+    }))
+}
+
+/// The sites most downloaded from, by finished downloads of all time.
+pub async fn top_sites(db: &Db) -> Result<Vec<Bar>, AppError> {
+    let urls: Vec<String> = db
+        .call(|conn| {
+            conn.query_map("SELECT url FROM history WHERE kind = 'download' AND status = 'Done'", params![], |r| r.get(0))
+        })
+        .await?;
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for url in urls {
+        *counts.entry(history::site(&url)).or_default() += 1;
+    }
+    let mut sites: Vec<(String, usize)> = counts.into_iter().collect();
+    sites.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sites.truncate(TOP_SITES);
+    let max = sites.first().map_or(1, |(_, n)| *n).max(1);
+    Ok(sites
+        .into_iter()
+        .map(|(label, value)| Bar { percent: value as f64 * 100.0 / max as f64, label, value })
+        .collect())
+}
@@ -0,0 +1,81 @@
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::fmt;
+
+use crate::db;
+use crate::page;
+use crate::session;
+
+/// Set by the browser (see `templates/_context.html`) to its IANA time zone.
+pub const TZ_COOKIE: &str = "bsdl_tz";
+
+// --- Timestamps ---
+
+/// A moment stored as Unix seconds (always UTC). Displays in the time zone of
+/// whoever is looking at the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Stamp(pub i64);
+
+impl Stamp {
+    pub fn now() -> Stamp {
+        Stamp(db::now())
+    }
+
+    /// When a file was last written, if the filesystem knows.
+    pub fn modified(meta: &std::fs::Metadata) -> Option<Stamp> {
+        let since = meta.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+        Some(Stamp(since.as_secs() as i64))
+    }
+
+    fn utc(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(self.0, 0)
+    }
+
+    /// e.g. "2026-10-14 14:05 CEST"
+    pub fn local(&self) -> String {
+        match self.utc() {
+            Some(t) => t.with_timezone(&page::timezone()).format("%Y-%m-%d %H:%M %Z").to_string(),
+            None => "?".to_string(),
+        }
+    }
+
+    /// For `datetime` attributes and logs
+    pub fn iso(&self) -> String {
+        self.utc().map(|t| t.to_rfc3339()).unwrap_or_default()
+    }
+
+    /// "just now", "5 minutes ago", "in 2 hours", ...
+    pub fn ago(&self) -> String {
+        let secs = db::now() - self.0;
+        let (n, unit) = match secs.abs() {
+            s if s < 60 => return "just now".to_string(),
+            s if s < 3600 => (s / 60, "minute"),
+            s if s < 86400 => (s / 3600, "hour"),
+            s if s < 30 * 86400 => (s / 86400, "day"),
+            s if s < 365 * 86400 => (s / (30 * 86400), "month"),
+            s => (s / (365 * 86400), "year"),
+        };
+        let plural = if n == 1 { "" } else { "s" };
+        if secs >= 0 {
+            format!("{} {}{} ago", n, unit, plural)
+        } else {
+            format!("in {} {}{}", n, unit, plural)
+        }
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.local())
+    }
+}
+
+// --- Time zones ---
+
+/// The browser's own zone if it told us a valid one, otherwise `default`.
+pub fn zone_for(headers: &HeaderMap, default: Tz) -> Tz {
+    session::cookie(headers, TZ_COOKIE).and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
@@ -0,0 +1,78 @@
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+};
+use icu_collator::{Collator, CollatorOptions, Numeric};
+use icu_locid::Locale;
+use serde::Deserialize;
+
+use crate::error::AppError;
+use crate::session;
+use crate::validate::{Form, Validate};
+
+/// Remembers each browser's chosen sort language for a year.
+pub const SORT_COOKIE: &str = "bsdl_sort";
+
+/// Languages offered on the library page, as (BCP 47 tag, label).
+pub const LOCALES: &[(&str, &str)] = &[
+    ("en", "English"),
+    ("de", "Deutsch"),
+    ("es", "Español"),
+    ("fr", "Français"),
+    ("pl", "Polski"),
+    ("sv", "Svenska"),
+    ("ru", "Русский"),
+    ("ja", "日本語"),
+    ("ko", "한국어"),
+    ("zh", "中文"),
+];
+
+// --- Sorting ---
+
+/// Sorts by `key` the way a person reading `locale` expects: case and accents
+/// only break ties, and digit runs compare as numbers ("Episode 2" < "Episode 10").
+pub fn sort_by<T>(items: &mut [T], locale: &str, key: impl Fn(&T) -> &str) {
+    let mut options = CollatorOptions::new();
+    options.numeric = Some(Numeric::On);
+    let tag: Locale = locale.parse().unwrap_or_default();
+
+    match Collator::try_new(&tag.into(), options) {
+        Ok(collator) => items.sort_by(|a, b| collator.compare(key(a), key(b)).then_with(|| key(a).cmp(key(b)))),
+        Err(e) => {
+            tracing::warn!("No collation data for {}: {}; falling back to plain ordering", locale, e);
+            items.sort_by_key(|a| key(a).to_lowercase());
+        }
+    }
+}
+
+/// The browser's saved choice if it's one we offer, otherwise `default`.
+pub fn locale_for(headers: &HeaderMap, default: &str) -> String {
+    session::cookie(headers, SORT_COOKIE)
+        .filter(|v| LOCALES.iter().any(|(tag, _)| tag == v))
+        .unwrap_or_else(|| default.to_string())
+}
+
+// --- Handlers ---
+
+#[derive(Deserialize)]
+pub struct SortRequest {
+    locale: String,
+}
+
+impl Validate for SortRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        if !LOCALES.iter().any(|(tag, _)| *tag == self.locale) {
+            return Err(AppError::Invalid { field: "locale", reason: "not one of the offered languages".to_string() });
+        }
+        Ok(())
+    }
+}
+
+pub async fn set_sort(Form(req): Form<SortRequest>) -> Response {
+    let mut res = Redirect::to("/files").into_response();
+    let cookie = format!("{}={}; Path=/; Max-Age=31536000; SameSite=Lax", SORT_COOKIE, req.locale);
+    if let Ok(value) = cookie.parse() {
+        res.headers_mut().append(header::SET_COOKIE, value);
+    }
+    res
+}
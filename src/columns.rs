@@ -0,0 +1,136 @@
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+
+use crate::error::AppError;
+use crate::session;
+use crate::validate::{self, Form, Validate};
+
+/// Remembers which optional columns each browser shows, for a year.
+pub const COLUMNS_COOKIE: &str = "bsdl_columns";
+
+/// Optional columns of the format table and details of the library cards, as (key, label).
+pub const COLUMNS: &[(&str, &str)] = &[
+    ("codec", "Codec"),
+    ("language", "Language"),
+    ("fps", "Frame rate"),
+    ("bitrate", "Bitrate"),
+    ("channels", "Audio channels"),
+    ("hdr", "Dynamic range"),
+];
+
+// Pages the chooser sits on, and so where it may send the browser back to
+const PAGES: &[&str] = &["/options", "/files"];
+
+/// Which optional columns to render.
+#[derive(Debug, Clone, Copy)]
+pub struct Columns {
+    pub codec: bool,
+    pub language: bool,
+    pub fps: bool,
+    pub bitrate: bool,
+    pub channels: bool,
+    pub hdr: bool,
+}
+
+// What the pages showed before there was a choice
+impl Default for Columns {
+    fn default() -> Self {
+        Columns { codec: true, language: true, fps: false, bitrate: false, channels: false, hdr: false }
+    }
+}
+
+impl Columns {
+    pub fn shows(&self, key: &str) -> bool {
+        match key {
+            "codec" => self.codec,
+            "language" => self.language,
+            "fps" => self.fps,
+            "bitrate" => self.bitrate,
+            "channels" => self.channels,
+            "hdr" => self.hdr,
+            _ => false,
+        }
+    }
+
+    /// Cells in a format table row: the seven fixed ones and the chosen extras.
+    pub fn format_cells(&self) -> usize {
+        7 + [self.language, self.fps, self.bitrate, self.channels, self.hdr].iter().filter(|c| **c).count()
+    }
+
+    // "codec.fps"; "none" when everything is hidden, so it still differs from no cookie
+    fn cookie_value(&self) -> String {
+        let keys: Vec<&str> = COLUMNS.iter().map(|(k, _)| *k).filter(|k| self.shows(k)).collect();
+        if keys.is_empty() {
+            "none".to_string()
+        } else {
+            keys.join(".")
+        }
+    }
+}
+
+/// The browser's saved choice, or the defaults.
+pub fn columns_for(headers: &HeaderMap) -> Columns {
+    let Some(value) = session::cookie(headers, COLUMNS_COOKIE) else {
+        return Columns::default();
+    };
+    let keys: Vec<&str> = value.split('.').collect();
+    Columns {
+        codec: keys.contains(&"codec"),
+        language: keys.contains(&"language"),
+        fps: keys.contains(&"fps"),
+        bitrate: keys.contains(&"bitrate"),
+        channels: keys.contains(&"channels"),
+        hdr: keys.contains(&"hdr"),
+    }
+}
+
+// --- Handlers ---
+
+// Unchecked boxes aren't sent at all
+#[derive(Deserialize)]
+pub struct ColumnsRequest {
+    back: String,
+    #[serde(default)]
+    codec: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    fps: Option<String>,
+    #[serde(default)]
+    bitrate: Option<String>,
+    #[serde(default)]
+    channels: Option<String>,
+    #[serde(default)]
+    hdr: Option<String>,
+}
+
+impl Validate for ColumnsRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        // Or a folder of the library, as the files page links to it
+        let folder = self.back.strip_prefix("/files/").is_some_and(|f| !f.is_empty() && !f.contains("//") && !f.contains(['\\', '\r', '\n']));
+        if folder {
+            return Ok(());
+        }
+        validate::one_of("back", &self.back, PAGES)
+    }
+}
+
+pub async fn set_columns(Form(req): Form<ColumnsRequest>) -> Response {
+    let columns = Columns {
+        codec: req.codec.is_some(),
+        language: req.language.is_some(),
+        fps: req.fps.is_some(),
+        bitrate: req.bitrate.is_some(),
+        channels: req.channels.is_some(),
+        hdr: req.hdr.is_some(),
+    };
+    let mut res = Redirect::to(&req.back).into_response();
+    let cookie = format!("{}={}; Path=/; Max-Age=31536000; SameSite=Lax", COLUMNS_COOKIE, columns.cookie_value());
+    if let Ok(value) = cookie.parse() {
+        res.headers_mut().append(header::SET_COOKIE, value);
+    }
+    res
+}
@@ -0,0 +1,717 @@
+use std::env;
+use chrono_tz::Tz;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::jobs::DEFAULT_TEMPLATE;
+use crate::validate;
+
+/// Extensions /content serves unless BSDL_CONTENT_EXTENSIONS says otherwise.
+const CONTENT_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mkv", "webm", "mov", "avi", "mp3", "m4a", "aac", "opus", "ogg", "oga", "flac", "wav",
+];
+
+// --- Config ---
+
+/// How strictly file names are cleaned, for yt-dlp and for names the app picks itself.
+#[derive(Debug, Clone, Default)]
+pub struct FilenameProfile {
+    /// ASCII letters, digits, `.`, `-` and `_` only (yt-dlp `--restrict-filenames`)
+    pub restrict: bool,
+    /// Nothing Windows or SMB shares would reject (yt-dlp `--windows-filenames`)
+    pub windows: bool,
+    /// Longest stem in characters, 0 for no limit (yt-dlp `--trim-filenames`)
+    pub trim: usize,
+}
+
+impl FilenameProfile {
+    /// The matching yt-dlp flags.
+    pub fn ytdlp_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.restrict {
+            args.push("--restrict-filenames".to_string());
+        }
+        if self.windows {
+            args.push("--windows-filenames".to_string());
+        }
+        if self.trim > 0 {
+            args.push("--trim-filenames".to_string());
+            args.push(self.trim.to_string());
+        }
+        args
+    }
+}
+
+/// How finished downloads get into the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LandingMode {
+    /// yt-dlp writes into the library and renames the finished file there
+    Move,
+    /// Made in the landing folder, copied over and checked against the original
+    Copy,
+    /// Made in the landing folder and cloned over (`cp --reflink`) where the
+    /// filesystem can share blocks between files, else copied as with Copy
+    Reflink,
+}
+
+#[derive(Debug, Clone)]
+pub struct LandingPolicy {
+    pub mode: LandingMode,
+    /// Where downloads are made before landing; unused with Move
+    pub dir: PathBuf,
+    /// Where they land, BSDL_DOWNLOADS_DIR
+    pub library: PathBuf,
+}
+
+/// How the database is opened and looked after.
+#[derive(Debug, Clone)]
+pub struct StoragePolicy {
+    /// PostgreSQL connection string, in place of the SQLite file; it may hold a password
+    pub url: Option<String>,
+    /// Write-ahead log, so readers never wait on a writer; off for filesystems
+    /// that can't share memory maps, such as some network shares
+    pub wal: bool,
+    /// How long a statement waits for a lock held elsewhere before failing
+    pub busy_timeout_ms: u64,
+    /// Minutes between WAL checkpoints, 0 leaves them to SQLite
+    pub checkpoint_minutes: u64,
+    /// Page cache in KiB, None for SQLite's own default
+    pub cache_kib: Option<u32>,
+}
+
+/// How yt-dlp and ffmpeg are kept from starving the rest of the host, which is
+/// often a media server too. Everything is off by default.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessLimits {
+    /// `nice` increment, 0 to leave the priority alone
+    pub nice: i32,
+    /// `idle` or `best-effort` (the lowest best-effort level); Linux only
+    pub ionice: Option<String>,
+    /// CPUs as `taskset` takes them, e.g. `2-3` or `0,2`; Linux only
+    pub cpu_affinity: Option<String>,
+    /// cgroup v2 directory each process joins, with its CPU or memory limits
+    /// set up beforehand; Linux only
+    pub cgroup: Option<PathBuf>,
+}
+
+impl ProcessLimits {
+    /// Wrapper commands that apply the priority and affinity, each exec'ing the
+    /// next, so they hold from the first instruction.
+    pub fn prefix(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.nice != 0 {
+            args.extend(["nice".to_string(), "-n".to_string(), self.nice.to_string()]);
+        }
+        if cfg!(target_os = "linux") {
+            match self.ionice.as_deref() {
+                Some("idle") => args.extend(["ionice", "-c", "3"].map(String::from)),
+                Some("best-effort") => args.extend(["ionice", "-c", "2", "-n", "7"].map(String::from)),
+                _ => {}
+            }
+            if let Some(cpus) = &self.cpu_affinity {
+                args.extend(["taskset".to_string(), "-c".to_string(), cpus.clone()]);
+            }
+        }
+        args
+    }
+}
+
+/// How much memory the app allows itself. BSDL_LOW_MEMORY picks small
+/// defaults for Raspberry Pis and NAS boxes; each can still be set on its own.
+#[derive(Debug, Clone)]
+pub struct MemoryProfile {
+    pub low: bool,
+    /// Runtime worker threads, None for one per core
+    pub worker_threads: Option<usize>,
+    /// ffmpeg `-threads`, 0 lets ffmpeg decide
+    pub ffmpeg_threads: usize,
+    /// Finished job chains kept on /jobs, 0 keeps them all until a restart
+    pub keep_finished_jobs: usize,
+}
+
+impl MemoryProfile {
+    /// Goes right before ffmpeg's output file.
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.ffmpeg_threads > 0 {
+            args.extend(["-threads".to_string(), self.ffmpeg_threads.to_string()]);
+        }
+        if self.low {
+            // Its progress lines would otherwise pile up in the captured stderr
+            args.push("-nostats".to_string());
+        }
+        args
+    }
+}
+
+/// Monthly transfer cap. Downloads pause once `pause_percent` of it is used and
+/// resume when the next billing month starts.
+#[derive(Debug, Clone)]
+pub struct BudgetPolicy {
+    /// 0 turns the budget off
+    pub monthly_bytes: u64,
+    pub pause_percent: u64,
+    /// Day of the month the allowance resets, 1-28, in BSDL_TIMEZONE
+    pub reset_day: u32,
+    /// Command printing the bytes used this month (e.g. asking the router);
+    /// without one the app counts what it downloads itself
+    pub check_cmd: Option<String>,
+}
+
+/// Limits for polite downloads (batches and subscriptions), which archive lots
+/// of videos from one site without getting the address banned.
+#[derive(Debug, Clone)]
+pub struct PolitePolicy {
+    /// yt-dlp waits a random number of seconds in this range before each download
+    pub sleep_min: u64,
+    pub sleep_max: u64,
+    /// Polite downloads started per hour, 0 for no limit
+    pub per_hour: usize,
+    /// How long polite downloads wait after the site answers 429 Too Many Requests
+    pub pause_minutes: u64,
+}
+
+/// Where the `translate` post-processing step sends subtitles.
+#[derive(Debug, Clone)]
+pub struct Translation {
+    /// LibreTranslate, e.g. `http://127.0.0.1:5000`
+    pub url: String,
+    pub api_key: Option<String>,
+    /// Language of the subtitles fetched for translating, as yt-dlp names it
+    pub from: String,
+    pub to: String,
+    /// Also add the translated track to the file itself
+    pub embed: bool,
+}
+
+impl Translation {
+    // Needs both an endpoint and a target language
+    fn from_env(p: &Problems) -> Option<Translation> {
+        let var = |key: &str| env::var(key).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        Some(Translation {
+            url: var("BSDL_TRANSLATE_URL")?,
+            api_key: var("BSDL_TRANSLATE_API_KEY"),
+            from: var("BSDL_TRANSLATE_FROM").unwrap_or_else(|| "en".to_string()),
+            to: var("BSDL_TRANSLATE_TO")?,
+            embed: env_parse(p, "BSDL_TRANSLATE_EMBED", false),
+        })
+    }
+}
+
+impl PolitePolicy {
+    pub fn ytdlp_args(&self) -> Vec<String> {
+        if self.sleep_max == 0 {
+            return Vec::new();
+        }
+        let min = self.sleep_min.min(self.sleep_max);
+        vec![
+            "--sleep-interval".to_string(),
+            min.to_string(),
+            "--max-sleep-interval".to_string(),
+            self.sleep_max.to_string(),
+        ]
+    }
+}
+
+/// Certificate and key HTTPS is served with, both PEM.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    /// The certificate chain, the server's own certificate first
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsFiles {
+    // BSDL_TLS_CERT (and BSDL_TLS_KEY, else the key is in the same file), or a
+    // folder holding them under certbot's names or as cert.pem and key.pem
+    fn from_env() -> Option<TlsFiles> {
+        let var = |key: &str| env::var(key).ok().filter(|v| !v.trim().is_empty()).map(PathBuf::from);
+        if let Some(cert) = var("BSDL_TLS_CERT") {
+            let key = var("BSDL_TLS_KEY").unwrap_or_else(|| cert.clone());
+            return Some(TlsFiles { cert, key });
+        }
+        let dir = var("BSDL_TLS_DIR")?;
+        let (cert, key) = if dir.join("fullchain.pem").exists() { ("fullchain.pem", "privkey.pem") } else { ("cert.pem", "key.pem") };
+        Some(TlsFiles { cert: dir.join(cert), key: dir.join(key) })
+    }
+}
+
+/// Runtime settings, read once at startup from `BSDL_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address and port the server listens on
+    pub host: String,
+    pub port: u16,
+    /// Serves HTTPS instead of HTTP when set
+    pub tls: Option<TlsFiles>,
+    /// Largest single upload accepted by /upload, in MB
+    pub max_upload_mb: u64,
+    /// SQLite database holding the library index
+    pub db_path: PathBuf,
+    /// config.toml, or the file BSDL_CONFIG names; backups take it along and put it back
+    pub config_file: PathBuf,
+    /// Where the library lives: `downloads/` in the app folder, or
+    /// BSDL_DOWNLOADS_DIR, without a trailing slash. Files can sit in folders
+    /// of it, and their names in the library carry the folders in front:
+    /// `Music/Jazz/track.mp3`. A signed-in user's downloads go into `<user>/`.
+    pub downloads_dir: String,
+    /// BSDL_BASE_PATH as `/ytdl`: one leading slash and none at the end, or
+    /// empty when the app is served from the root
+    pub base_path: String,
+    /// BSDL_YTDLP, over the release binary in the app folder or the PATH's
+    pub ytdlp: Option<PathBuf>,
+    /// BSDL_YTDLP_NIGHTLY, over yt-dlp_nightly in the app folder
+    pub ytdlp_nightly: Option<PathBuf>,
+    /// The proxy geo-blocked formats can go through
+    pub geo_proxy: Option<String>,
+    /// Unix socket `ctl` talks to, None to go without
+    pub control_socket: Option<PathBuf>,
+    pub storage: StoragePolicy,
+    pub landing: LandingPolicy,
+    /// Minutes between background library rescans, 0 to disable
+    pub rescan_minutes: u64,
+    /// Hours between automatic backups, 0 to disable
+    pub backup_hours: u64,
+    /// How many backup archives to keep, 0 keeps all
+    pub backup_keep: usize,
+    /// Command run with each new backup's path appended, e.g. `rclone copyto remote:bplus/`
+    pub backup_upload_cmd: Option<String>,
+    /// Where the secrets key lives when BSDL_SECRET_KEY isn't set
+    pub secret_key_file: PathBuf,
+    pub filenames: FilenameProfile,
+    pub polite: PolitePolicy,
+    /// Downloads started per hour with each cookies account, 0 for no limit
+    pub cookie_per_hour: usize,
+    /// Jobs (downloads and the steps after them) running at once; the rest wait their turn
+    pub max_downloads: usize,
+    /// Analyze and download posts a minute from one address, 0 for no limit
+    pub rate_limit: u32,
+    /// Seconds shutting down waits for running jobs to finish, 0 to stop them at once
+    pub shutdown_timeout: u64,
+    /// ffprobe runs at once when looking up format sizes yt-dlp left out, 0 to turn that off
+    pub size_probes: usize,
+    pub budget: BudgetPolicy,
+    pub limits: ProcessLimits,
+    pub memory: MemoryProfile,
+    /// Language the library is sorted in until a browser picks its own
+    pub sort_locale: String,
+    /// Allows /system/update to replace the running binary with the latest release
+    pub self_update: bool,
+    /// GitHub `owner/name` whose releases are checked for updates
+    pub update_repo: String,
+    /// Release asset installed by self-update
+    pub update_asset: String,
+    /// Lowercase extensions, without the dot, that /content will serve
+    pub content_extensions: Vec<String>,
+    /// Minutes an analyzed URL's format list is reused, 0 to always ask yt-dlp
+    pub analysis_cache_minutes: u64,
+    /// Analyses that run with cookies run again without them, to tell which formats the account added
+    pub compare_without_cookies: bool,
+    /// Zone times are shown in until a browser reports its own
+    pub timezone: Tz,
+    /// Hours between subscription checks, 0 to only check on demand
+    pub subscription_hours: u64,
+    /// Format preset for subscriptions that don't set one: `best`, `audio:<format>` or `profile:<name>`
+    pub subscription_preset: String,
+    /// yt-dlp output template for subscriptions that don't set one
+    pub subscription_template: String,
+    /// Steps run after each subscription download (`normalize`, `compat`, `torrent`, `translate`) unless it sets its own
+    pub subscription_postprocess: Vec<String>,
+    /// Files played within this many days are kept when a subscription's window moves on, 0 to delete them anyway
+    pub keep_played_days: u64,
+    /// Command run with each notification's text appended, e.g. `curl -s https://ntfy.sh/mytopic -d`
+    pub notify_cmd: Option<String>,
+    /// Announce URLs written into created torrents, first one first
+    pub torrent_trackers: Vec<String>,
+    /// Public base URL of this app; torrents then web-seed from its /content
+    pub torrent_webseed: Option<String>,
+    /// Kubo (go-ipfs) HTTP API, e.g. `http://127.0.0.1:5001`; downloads get added and pinned there
+    pub ipfs_api: Option<String>,
+    /// API tokens, one per line as `name token` or just `token`
+    pub api_tokens_file: Option<PathBuf>,
+    /// Pages and read routes need a token too, once there is one
+    pub auth_reads: bool,
+    /// Users who sign in with a password, one per line as `name hash`
+    pub users_file: Option<PathBuf>,
+    /// Users who may use the /system pages, besides those marked admin on the users page
+    pub admins: Vec<String>,
+    /// Set when BSDL_TRANSLATE_URL and BSDL_TRANSLATE_TO are
+    pub translation: Option<Translation>,
+}
+
+impl Config {
+    /// Every `BSDL_*` variable, or one line per variable whose value doesn't
+    /// parse: a typo stops startup rather than quietly meaning the default.
+    pub fn from_env() -> Result<Self, String> {
+        let problems = Problems::default();
+        let p = &problems;
+        let low_memory = env_parse(p, "BSDL_LOW_MEMORY", false);
+        // Defaults that differ in low-memory mode
+        let small = |normal: usize, low: usize| if low_memory { low } else { normal };
+        let downloads_dir = downloads_dir();
+        let config = Config {
+            host: env_parse(p, "BSDL_HOST", "0.0.0.0".to_string()),
+            port: env_parse(p, "BSDL_PORT", 3000),
+            tls: TlsFiles::from_env(),
+            max_upload_mb: env_parse(p, "BSDL_MAX_UPLOAD_MB", 2048),
+            db_path: env_parse(p, "BSDL_DB_PATH", PathBuf::from("data/bplus.db")),
+            config_file: file_path(),
+            landing: landing_policy(p, &downloads_dir),
+            downloads_dir,
+            base_path: base_path(p),
+            ytdlp: env::var_os("BSDL_YTDLP").filter(|p| !p.is_empty()).map(PathBuf::from),
+            ytdlp_nightly: env::var_os("BSDL_YTDLP_NIGHTLY").filter(|p| !p.is_empty()).map(PathBuf::from),
+            geo_proxy: env::var("BSDL_GEO_PROXY").ok().map(|p| p.trim().to_string()).filter(|p| !p.is_empty()),
+            control_socket: Some(env_parse(p, "BSDL_CONTROL_SOCKET", PathBuf::from("data/control.sock"))).filter(|p| !p.as_os_str().is_empty()),
+            storage: StoragePolicy {
+                url: env::var("BSDL_DB_URL").ok().filter(|u| !u.trim().is_empty()),
+                wal: env_choice(p, "BSDL_DB_JOURNAL", &["wal", "delete"]).is_none_or(|j| j == "wal"),
+                busy_timeout_ms: env_parse(p, "BSDL_DB_BUSY_TIMEOUT_MS", 5000),
+                checkpoint_minutes: env_parse(p, "BSDL_DB_CHECKPOINT_MINUTES", 5),
+                cache_kib: low_memory.then_some(256),
+            },
+            rescan_minutes: env_parse(p, "BSDL_RESCAN_MINUTES", 30),
+            backup_hours: env_parse(p, "BSDL_BACKUP_HOURS", 24),
+            backup_keep: env_parse(p, "BSDL_BACKUP_KEEP", 7),
+            backup_upload_cmd: env::var("BSDL_BACKUP_UPLOAD_CMD").ok().filter(|c| !c.trim().is_empty()),
+            secret_key_file: env_parse(p, "BSDL_SECRET_KEY_FILE", PathBuf::from("data/secret.key")),
+            filenames: FilenameProfile {
+                restrict: env_parse(p, "BSDL_RESTRICT_FILENAMES", false),
+                windows: env_parse(p, "BSDL_WINDOWS_FILENAMES", false),
+                trim: env_parse(p, "BSDL_TRIM_FILENAMES", 0),
+            },
+            polite: PolitePolicy {
+                sleep_min: env_parse(p, "BSDL_POLITE_SLEEP_MIN", 5),
+                sleep_max: env_parse(p, "BSDL_POLITE_SLEEP_MAX", 30),
+                per_hour: env_parse(p, "BSDL_POLITE_PER_HOUR", 60),
+                pause_minutes: env_parse(p, "BSDL_POLITE_PAUSE_MINUTES", 30),
+            },
+            cookie_per_hour: env_parse(p, "BSDL_COOKIE_PER_HOUR", 0),
+            max_downloads: env_parse(p, "BSDL_MAX_DOWNLOADS", small(2, 1)).max(1),
+            rate_limit: env_parse(p, "BSDL_RATE_LIMIT", 30),
+            shutdown_timeout: env_parse(p, "BSDL_SHUTDOWN_TIMEOUT", 60),
+            size_probes: env_parse(p, "BSDL_SIZE_PROBES", small(4, 1)),
+            budget: BudgetPolicy {
+                monthly_bytes: env_parse::<u64>(p, "BSDL_MONTHLY_BUDGET_GB", 0) * 1024 * 1024 * 1024,
+                pause_percent: env_parse::<u64>(p, "BSDL_BUDGET_PAUSE_PERCENT", 90).clamp(1, 100),
+                reset_day: env_parse::<u32>(p, "BSDL_BUDGET_RESET_DAY", 1).clamp(1, 28),
+                check_cmd: env::var("BSDL_BUDGET_CMD").ok().filter(|c| !c.trim().is_empty()),
+            },
+            limits: ProcessLimits {
+                nice: env_parse::<i32>(p, "BSDL_NICE", 0).clamp(-20, 19),
+                ionice: env_choice(p, "BSDL_IONICE", &["idle", "best-effort"]),
+                cpu_affinity: env_cpus(p, "BSDL_CPU_AFFINITY"),
+                cgroup: env::var("BSDL_CGROUP").ok().filter(|c| !c.trim().is_empty()).map(PathBuf::from),
+            },
+            memory: MemoryProfile {
+                low: low_memory,
+                worker_threads: Some(env_parse(p, "BSDL_WORKER_THREADS", small(0, 2))).filter(|&n| n > 0),
+                ffmpeg_threads: env_parse(p, "BSDL_FFMPEG_THREADS", small(0, 1)),
+                keep_finished_jobs: env_parse(p, "BSDL_KEEP_FINISHED_JOBS", small(0, 20)),
+            },
+            sort_locale: env_parse(p, "BSDL_SORT_LOCALE", "en".to_string()),
+            self_update: env_parse(p, "BSDL_SELF_UPDATE", false),
+            update_repo: env_parse(p, "BSDL_UPDATE_REPO", "mrhappynice/bplus-streamdlrs-gui".to_string()),
+            update_asset: env_parse(p, "BSDL_UPDATE_ASSET", "bplus-streamdlrs-gui".to_string()),
+            content_extensions: env_list("BSDL_CONTENT_EXTENSIONS", CONTENT_EXTENSIONS),
+            analysis_cache_minutes: env_parse(p, "BSDL_ANALYSIS_CACHE_MINUTES", small(30, 0) as u64),
+            compare_without_cookies: env_parse(p, "BSDL_COMPARE_WITHOUT_COOKIES", !low_memory),
+            timezone: env_parse(p, "BSDL_TIMEZONE", Tz::UTC),
+            subscription_hours: env_parse(p, "BSDL_SUBSCRIPTION_HOURS", 6),
+            subscription_preset: env_parse(p, "BSDL_SUBSCRIPTION_PRESET", "best".to_string()),
+            subscription_template: env_template(p, "BSDL_SUBSCRIPTION_TEMPLATE", DEFAULT_TEMPLATE),
+            subscription_postprocess: env_list("BSDL_SUBSCRIPTION_POSTPROCESS", &[]),
+            keep_played_days: env_parse(p, "BSDL_KEEP_PLAYED_DAYS", 90),
+            notify_cmd: env::var("BSDL_NOTIFY_CMD").ok().filter(|c| !c.trim().is_empty()),
+            // Not env_list: tracker URLs can carry case-sensitive passkeys
+            torrent_trackers: env::var("BSDL_TORRENT_TRACKERS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            torrent_webseed: env::var("BSDL_TORRENT_WEBSEED").ok().filter(|u| !u.trim().is_empty()),
+            ipfs_api: env::var("BSDL_IPFS_API").ok().map(|u| u.trim().to_string()).filter(|u| !u.is_empty()),
+            api_tokens_file: env::var("BSDL_API_TOKENS_FILE").ok().filter(|p| !p.trim().is_empty()).map(PathBuf::from),
+            auth_reads: env_parse(p, "BSDL_AUTH_READS", false),
+            users_file: env::var("BSDL_USERS_FILE").ok().filter(|p| !p.trim().is_empty()).map(PathBuf::from),
+            admins: env_list("BSDL_ADMINS", &[]),
+            translation: Translation::from_env(p),
+        };
+        let problems = problems.into_inner();
+        if !problems.is_empty() {
+            return Err(format!("Invalid settings:\n  {}", problems.join("\n  ")));
+        }
+        Ok(config)
+    }
+}
+
+// What from_env found wrong, one line per variable
+type Problems = RefCell<Vec<String>>;
+
+fn problem(p: &Problems, key: &str, raw: &str, why: impl fmt::Display) {
+    p.borrow_mut().push(format!("{}={:?}: {}", key, raw, why));
+}
+
+fn downloads_dir() -> String {
+    let dir = env::var("BSDL_DOWNLOADS_DIR").unwrap_or_default();
+    // Names are cut off it with strip_prefix, which a trailing slash would throw off
+    let dir = dir.trim().trim_end_matches(['/', '\\']);
+    if dir.is_empty() { "downloads" } else { dir }.to_string()
+}
+
+// Only what a link can carry without escaping
+fn base_path(p: &Problems) -> String {
+    let raw = env::var("BSDL_BASE_PATH").unwrap_or_default();
+    let base = raw.trim().trim_matches('/');
+    if base.contains("//") || !base.chars().all(|c| c.is_ascii_alphanumeric() || "/-._~".contains(c)) {
+        problem(p, "BSDL_BASE_PATH", &raw, "expected a path like /ytdl of letters, digits and -._~");
+        return String::new();
+    }
+    if base.is_empty() { String::new() } else { format!("/{}", base) }
+}
+
+// A clone only works within one filesystem, so by default reflinks are made
+// next to the library, in a hidden folder the library doesn't list
+fn landing_policy(p: &Problems, downloads_dir: &str) -> LandingPolicy {
+    let mode = match env_choice(p, "BSDL_LANDING", &["move", "copy", "reflink"]).as_deref() {
+        Some("copy") => LandingMode::Copy,
+        Some("reflink") => LandingMode::Reflink,
+        _ => LandingMode::Move,
+    };
+    let default = match mode {
+        LandingMode::Reflink => PathBuf::from(downloads_dir).join(".landing"),
+        _ => PathBuf::from("data/landing"),
+    };
+    let dir = env_parse(p, "BSDL_LANDING_DIR", default.clone());
+    LandingPolicy { mode, dir: if dir.as_os_str().is_empty() { default } else { dir }, library: PathBuf::from(downloads_dir) }
+}
+
+fn env_template(p: &Problems, key: &str, default: &str) -> String {
+    let value = env_parse(p, key, default.to_string());
+    match validate::output_template("template", &value) {
+        Ok(()) => value,
+        Err(e) => {
+            problem(p, key, &value, e);
+            default.to_string()
+        }
+    }
+}
+
+// One of `allowed`, or None when unset
+fn env_choice(p: &Problems, key: &str, allowed: &[&str]) -> Option<String> {
+    let value = env::var(key).ok()?.trim().to_lowercase();
+    if value.is_empty() {
+        return None;
+    }
+    if !allowed.contains(&value.as_str()) {
+        problem(p, key, &value, format!("expected one of {}", allowed.join(", ")));
+        return None;
+    }
+    Some(value)
+}
+
+// A CPU list such as `0-1,3`, handed to taskset as is
+fn env_cpus(p: &Problems, key: &str) -> Option<String> {
+    let value = env::var(key).ok()?.trim().to_string();
+    if value.is_empty() {
+        return None;
+    }
+    if !value.chars().all(|c| c.is_ascii_digit() || c == '-' || c == ',') {
+        problem(p, key, &value, "expected CPU numbers and ranges like 0-1,3");
+        return None;
+    }
+    Some(value)
+}
+
+// Comma separated, e.g. "mp4, mkv,.webm"; blank entries and leading dots are dropped
+fn env_list(key: &str, default: &[&str]) -> Vec<String> {
+    match env::var(key) {
+        Ok(raw) if !raw.trim().is_empty() => raw
+            .split(',')
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect(),
+        _ => default.iter().map(|e| e.to_string()).collect(),
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(p: &Problems, key: &str, default: T) -> T
+where
+    T::Err: fmt::Display,
+{
+    match env::var(key) {
+        Ok(raw) => match raw.trim().parse() {
+            Ok(v) => v,
+            Err(e) => {
+                problem(p, key, &raw, e);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+// --- config.toml ---
+
+/// Read at startup from the app folder, unless BSDL_CONFIG names another file.
+pub const CONFIG_FILE: &str = "config.toml";
+
+// What a setting's value has to be
+enum Kind {
+    Flag,
+    /// Whole number, 0 or more
+    Count,
+    Range(i64, i64),
+    Text,
+    /// Strings, or one string with commas between them
+    List,
+}
+
+// Every BSDL_* variable the file may set. A key is the variable's name without
+// BSDL_, lowercase, either whole (`max_downloads = 2`) or split on an
+// underscore into a table (`[polite]` then `sleep_min = 5`).
+const SETTINGS: &[(&str, Kind)] = &[
+    ("BSDL_HOST", Kind::Text),
+    ("BSDL_BASE_PATH", Kind::Text),
+    ("BSDL_CONTROL_SOCKET", Kind::Text),
+    ("BSDL_PORT", Kind::Range(0, 65535)),
+    ("BSDL_TLS_CERT", Kind::Text),
+    ("BSDL_TLS_KEY", Kind::Text),
+    ("BSDL_TLS_DIR", Kind::Text),
+    ("BSDL_YTDLP", Kind::Text),
+    ("BSDL_YTDLP_NIGHTLY", Kind::Text),
+    ("BSDL_DOWNLOADS_DIR", Kind::Text),
+    ("BSDL_LANDING", Kind::Text),
+    ("BSDL_LANDING_DIR", Kind::Text),
+    ("BSDL_MAX_UPLOAD_MB", Kind::Count),
+    ("BSDL_MAX_DOWNLOADS", Kind::Count),
+    ("BSDL_RATE_LIMIT", Kind::Count),
+    ("BSDL_SHUTDOWN_TIMEOUT", Kind::Count),
+    ("BSDL_SIZE_PROBES", Kind::Count),
+    ("BSDL_GEO_PROXY", Kind::Text),
+    ("BSDL_COOKIE_PER_HOUR", Kind::Count),
+    ("BSDL_LOW_MEMORY", Kind::Flag),
+    ("BSDL_WORKER_THREADS", Kind::Count),
+    ("BSDL_FFMPEG_THREADS", Kind::Count),
+    ("BSDL_KEEP_FINISHED_JOBS", Kind::Count),
+    ("BSDL_DB_PATH", Kind::Text),
+    ("BSDL_DB_URL", Kind::Text),
+    ("BSDL_DB_JOURNAL", Kind::Text),
+    ("BSDL_DB_BUSY_TIMEOUT_MS", Kind::Count),
+    ("BSDL_DB_CHECKPOINT_MINUTES", Kind::Count),
+    ("BSDL_RESCAN_MINUTES", Kind::Count),
+    ("BSDL_BACKUP_HOURS", Kind::Count),
+    ("BSDL_BACKUP_KEEP", Kind::Count),
+    ("BSDL_BACKUP_UPLOAD_CMD", Kind::Text),
+    ("BSDL_SECRET_KEY_FILE", Kind::Text),
+    ("BSDL_RESTRICT_FILENAMES", Kind::Flag),
+    ("BSDL_WINDOWS_FILENAMES", Kind::Flag),
+    ("BSDL_TRIM_FILENAMES", Kind::Count),
+    ("BSDL_POLITE_SLEEP_MIN", Kind::Count),
+    ("BSDL_POLITE_SLEEP_MAX", Kind::Count),
+    ("BSDL_POLITE_PER_HOUR", Kind::Count),
+    ("BSDL_POLITE_PAUSE_MINUTES", Kind::Count),
+    ("BSDL_MONTHLY_BUDGET_GB", Kind::Count),
+    ("BSDL_BUDGET_PAUSE_PERCENT", Kind::Range(1, 100)),
+    ("BSDL_BUDGET_RESET_DAY", Kind::Range(1, 28)),
+    ("BSDL_BUDGET_CMD", Kind::Text),
+    ("BSDL_NICE", Kind::Range(-20, 19)),
+    ("BSDL_IONICE", Kind::Text),
+    ("BSDL_CPU_AFFINITY", Kind::Text),
+    ("BSDL_CGROUP", Kind::Text),
+    ("BSDL_SORT_LOCALE", Kind::Text),
+    ("BSDL_TIMEZONE", Kind::Text),
+    ("BSDL_SELF_UPDATE", Kind::Flag),
+    ("BSDL_UPDATE_REPO", Kind::Text),
+    ("BSDL_UPDATE_ASSET", Kind::Text),
+    ("BSDL_CONTENT_EXTENSIONS", Kind::List),
+    ("BSDL_ANALYSIS_CACHE_MINUTES", Kind::Count),
+    ("BSDL_COMPARE_WITHOUT_COOKIES", Kind::Flag),
+    ("BSDL_SUBSCRIPTION_HOURS", Kind::Count),
+    ("BSDL_SUBSCRIPTION_PRESET", Kind::Text),
+    ("BSDL_SUBSCRIPTION_TEMPLATE", Kind::Text),
+    ("BSDL_SUBSCRIPTION_POSTPROCESS", Kind::List),
+    ("BSDL_KEEP_PLAYED_DAYS", Kind::Count),
+    ("BSDL_NOTIFY_CMD", Kind::Text),
+    ("BSDL_TORRENT_TRACKERS", Kind::List),
+    ("BSDL_TORRENT_WEBSEED", Kind::Text),
+    ("BSDL_IPFS_API", Kind::Text),
+    ("BSDL_API_TOKENS_FILE", Kind::Text),
+    ("BSDL_AUTH_READS", Kind::Flag),
+    ("BSDL_USERS_FILE", Kind::Text),
+    ("BSDL_ADMINS", Kind::List),
+    ("BSDL_TRANSLATE_URL", Kind::Text),
+    ("BSDL_TRANSLATE_API_KEY", Kind::Text),
+    ("BSDL_TRANSLATE_FROM", Kind::Text),
+    ("BSDL_TRANSLATE_TO", Kind::Text),
+    ("BSDL_TRANSLATE_EMBED", Kind::Flag),
+];
+
+// The value as the variable would hold it
+fn setting_value(kind: &Kind, value: &Value) -> Result<String, String> {
+    match (kind, value) {
+        (Kind::Flag, Value::Bool(b)) => Ok(b.to_string()),
+        (Kind::Flag, _) => Err("expected true or false".to_string()),
+        (Kind::Count, Value::Number(n)) if n.as_u64().is_some() => Ok(n.to_string()),
+        (Kind::Count, _) => Err("expected a whole number, 0 or more".to_string()),
+        (Kind::Range(min, max), Value::Number(n)) if n.as_i64().is_some_and(|n| (*min..=*max).contains(&n)) => Ok(n.to_string()),
+        (Kind::Range(min, max), _) => Err(format!("expected a whole number from {} to {}", min, max)),
+        (Kind::Text | Kind::List, Value::String(s)) => Ok(s.clone()),
+        (Kind::Text, _) => Err("expected a string in quotes".to_string()),
+        (Kind::List, Value::Array(items)) => items
+            .iter()
+            .map(|item| item.as_str().filter(|s| !s.contains(',')).map(str::to_string))
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(","))
+            .ok_or_else(|| "expected a list of strings, without commas in them".to_string()),
+        (Kind::List, _) => Err("expected a list of strings".to_string()),
+    }
+}
+
+// Tables become part of the key: [polite] sleep_min is polite.sleep_min
+fn flatten(prefix: &str, table: &Map<String, Value>, out: &mut Vec<(String, String)>) -> Result<(), String> {
+    for (key, value) in table {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        if let Value::Object(inner) = value {
+            flatten(&path, inner, out)?;
+            continue;
+        }
+        let var = format!("BSDL_{}", path.replace('.', "_").to_uppercase());
+        let Some((_, kind)) = SETTINGS.iter().find(|(v, _)| *v == var) else {
+            return Err(format!("`{}` is not a setting", path));
+        };
+        let value = setting_value(kind, value).map_err(|why| format!("`{}`: {}", path, why))?;
+        out.push((var, value));
+    }
+    Ok(())
+}
+
+/// The settings file: the one BSDL_CONFIG names, or config.toml.
+pub fn file_path() -> PathBuf {
+    env::var_os("BSDL_CONFIG").filter(|p| !p.is_empty()).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(CONFIG_FILE))
+}
+
+/// Sets the BSDL_* variables config.toml (or BSDL_CONFIG) holds, leaving those
+/// already set alone: the environment and the command line win over the file.
+/// Returns the file when there was one; a mistake in it is an error naming the key.
+pub fn load_file() -> Result<Option<PathBuf>, String> {
+    let named = env::var_os("BSDL_CONFIG").filter(|p| !p.is_empty());
+    let path = file_path();
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && named.is_none() => return Ok(None),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+    };
+    let table: Map<String, Value> = basic_toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut settings = Vec::new();
+    flatten("", &table, &mut settings).map_err(|e| format!("{}: {}", path.display(), e))?;
+    for (var, value) in settings {
+        if env::var_os(&var).is_none() {
+            env::set_var(var, value);
+        }
+    }
+    Ok(Some(path))
+}
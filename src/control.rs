@@ -0,0 +1,180 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::maintenance;
+use crate::AppState;
+
+// For when the web UI can't be reached: a Unix socket next to the database
+// that takes one command line per connection and answers in plain lines, the
+// first starting "error: " if the command failed. `bplus-streamdlrs-gui ctl`
+// is the client; anything that writes a line to a socket works too.
+
+const HELP: &str = "\
+status                    running and queued jobs, and maintenance mode
+jobs                      every chain: id, status, title
+cancel ID                 cancels a chain and stops what of it runs
+pause-all | resume-all    pauses or resumes the running downloads
+maintenance on [REASON]   pauses the queue and refuses new submissions
+maintenance off           lets the queue carry on
+rollback on | off         runs the stable yt-dlp for every site, or lets the pins apply again";
+
+// Longer command lines are cut off here
+const MAX_LINE: u64 = 1024;
+
+// --- Commands ---
+
+async fn run(state: &AppState, line: &str) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["status"] => {
+            let q = state.jobs.summary();
+            let mut out = format!("{} running, {} queued", q.running, q.queued);
+            if let (Some(title), Some(p)) = (&q.title, &q.progress) {
+                let _ = write!(out, "\ndownloading {} {}", title, p.percent);
+            }
+            if let Some(m) = state.jobs.maintenance() {
+                let _ = write!(out, "\nmaintenance mode since {}", m.since.iso());
+            }
+            Ok(out)
+        }
+        ["jobs"] => {
+            let chains = state.jobs.list();
+            if chains.is_empty() {
+                return Ok("no jobs".to_string());
+            }
+            let lines: Vec<String> = chains.iter().map(|c| format!("{}\t{}\t{}", c.id, c.status.label(), c.title)).collect();
+            Ok(lines.join("\n"))
+        }
+        ["cancel", id] => {
+            let id: u64 = id.parse().map_err(|_| format!("{} is not a chain id", id))?;
+            if !state.jobs.cancel(id) {
+                return Err(format!("no chain {}", id));
+            }
+            tracing::info!("Cancelled chain {} from the control socket", id);
+            Ok(format!("cancelled chain {}", id))
+        }
+        ["pause-all"] => Ok(format!("paused {} download(s)", state.jobs.pause_all(None))),
+        ["resume-all"] => Ok(format!("resumed {} download(s)", state.jobs.resume_all(None))),
+        ["maintenance", "on", reason @ ..] => {
+            maintenance::turn_on(state, &reason.join(" ")).await;
+            Ok(format!("maintenance mode on, {} job(s) still running", state.jobs.summary().running))
+        }
+        ["maintenance", "off"] => match maintenance::turn_off(state).await {
+            true => Ok("maintenance mode off".to_string()),
+            false => Ok("maintenance mode was already off".to_string()),
+        },
+        ["rollback", switch @ ("on" | "off")] => {
+            let on = *switch == "on";
+            let changed = state.pins.set_rollback(&state.db, on).await.map_err(|e| e.to_string())?;
+            if changed {
+                tracing::info!("yt-dlp rollback turned {} from the control socket", switch);
+            }
+            Ok(format!("yt-dlp rollback {}{}", if changed { "" } else { "was already " }, switch))
+        }
+        ["help"] | [] => Ok(HELP.to_string()),
+        _ => Err(format!("unknown command {:?}; `help` lists them", line)),
+    }
+}
+
+// --- Server ---
+
+/// Answers commands on BSDL_CONTROL_SOCKET until the app stops.
+pub fn spawn(state: AppState) {
+    let Some(socket) = state.config.control_socket.clone() else {
+        return;
+    };
+    tokio::spawn(async move {
+        if let Err(e) = serve(state, &socket).await {
+            tracing::warn!("Control socket {}: {}", socket.display(), e);
+        }
+    });
+}
+
+// Only open to the user the app runs as. The socket is made in a folder
+// only that user can enter and moved into place once it's 0600, so there's
+// no moment another user could connect to it.
+#[cfg(unix)]
+fn bind(socket: &Path) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let parent = socket.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let private = parent.join(format!(".control-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&private);
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let made = private.join("control.sock");
+    let bound = tokio::net::UnixListener::bind(&made).and_then(|listener| {
+        std::fs::set_permissions(&made, std::fs::Permissions::from_mode(0o600))?;
+        // Left over from a run that didn't shut down; the instance lock says none is running
+        std::fs::rename(&made, socket)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&private);
+    bound
+}
+
+#[cfg(unix)]
+async fn serve(state: AppState, socket: &Path) -> std::io::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let listener = bind(socket)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut line = String::new();
+            if BufReader::new(read.take(MAX_LINE)).read_line(&mut line).await.is_err() {
+                return;
+            }
+            let reply = match run(&state, line.trim()).await {
+                Ok(out) => out,
+                Err(msg) => format!("error: {}", msg),
+            };
+            let _ = write.write_all(format!("{}\n", reply).as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve(_state: AppState, _socket: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "control sockets need a Unix-like system"))
+}
+
+// --- Client ---
+
+/// `ctl`: sends `words` as one command and prints the answer. Returns the
+/// exit code, 1 when the command failed or the app couldn't be reached.
+#[cfg(unix)]
+pub fn client(socket: &Path, words: &[String]) -> i32 {
+    use std::io::{Read, Write};
+
+    let mut stream = match std::os::unix::net::UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Could not reach {}: {} (is the app running in this folder?)", socket.display(), e);
+            return 1;
+        }
+    };
+    let line = words.join(" ").replace(['\r', '\n'], " ");
+    let mut reply = String::new();
+    if let Err(e) = writeln!(stream, "{}", line).and_then(|_| stream.read_to_string(&mut reply)) {
+        eprintln!("Control socket: {}", e);
+        return 1;
+    }
+    match reply.strip_prefix("error: ") {
+        Some(msg) => {
+            eprint!("{}", msg);
+            1
+        }
+        None => {
+            print!("{}", reply);
+            0
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn client(_socket: &Path, _words: &[String]) -> i32 {
+    eprintln!("ctl talks to the app over a Unix socket, which this system doesn't have; use the web UI");
+    2
+}
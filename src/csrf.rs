@@ -0,0 +1,84 @@
+use axum::{
+    body::{self, Body},
+    extract::{Request, State},
+    http::{header, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use subtle::ConstantTimeEq;
+
+use crate::error::AppError;
+use crate::secrets;
+use crate::session::SessionId;
+use crate::AppState;
+
+// Every form the templates render carries the session's token (templates/_csrf.html)
+// and posts without it are refused, so another site can't post to the app
+// from a browser that has it open or is signed in. JSON and Authorization
+// headers can't be sent cross-site without the app agreeing to it, so the
+// API and scripts with a token are left alone.
+
+/// The form field, query parameter (for multipart forms, which the layer
+/// doesn't read) or `X-CSRF-Token` header the token comes in.
+pub const FIELD: &str = "csrf";
+const HEADER: &str = "x-csrf-token";
+
+// The largest form any route takes, the secrets and cookie forms'
+const MAX_READ: usize = secrets::MAX_SECRET_BYTES;
+
+// One field of a urlencoded form or query string
+fn field(encoded: &str, name: &str) -> Option<String> {
+    encoded.split('&').filter_map(|pair| pair.split_once('=')).find(|(k, _)| *k == name).map(|(_, v)| {
+        let v = v.replace('+', " ");
+        percent_decode_str(&v).decode_utf8_lossy().into_owned()
+    })
+}
+
+fn exempt(req: &Request) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) || req.headers().contains_key(header::AUTHORIZATION) {
+        return true;
+    }
+    let kind = req.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    kind.starts_with("application/json")
+}
+
+/// Refuses form posts that don't carry this browser's token.
+pub async fn csrf_layer(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if exempt(&req) {
+        return next.run(req).await;
+    }
+    let Some(sid) = req.extensions().get::<SessionId>().cloned() else {
+        return next.run(req).await;
+    };
+    let expected = state.sessions.csrf(&sid);
+
+    let mut presented = req.headers().get(HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+    presented = presented.or_else(|| req.uri().query().and_then(|q| field(q, FIELD)));
+    let urlencoded = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+    let req = if presented.is_none() && urlencoded {
+        // Read here and handed on, so the handler still gets the whole form
+        let (parts, body) = req.into_parts();
+        let Ok(bytes) = body::to_bytes(body, MAX_READ).await else {
+            return AppError::TooLarge("The form is too large".to_string()).into_response();
+        };
+        presented = field(&String::from_utf8_lossy(&bytes), FIELD);
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        req
+    };
+
+    // In constant time, like password::verify
+    if !presented.is_some_and(|p| p.as_bytes().ct_eq(expected.as_bytes()).into()) {
+        tracing::warn!("Refused {} {} without this session's CSRF token", req.method(), req.uri().path());
+        return AppError::Forbidden(
+            "This form is out of date or was sent from another site. Reload the page and try again.".to_string(),
+        )
+        .into_response();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,764 @@
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::Connection;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::StoragePolicy;
+use crate::error::AppError;
+use crate::schedule::{Timer, Timers};
+
+// Where the app keeps its tables: one SQLite file by default, or a
+// PostgreSQL server that several instances share when BSDL_DB_URL names one
+// (a build with `--features postgres`, see pg.rs). Both sit behind `Storage`,
+// and the SQL the modules send is written to run on either: `?1`
+// placeholders, ON CONFLICT rather than INSERT OR ..., RETURNING rather than
+// last_insert_rowid().
+
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS items (
+    id            INTEGER PRIMARY KEY,
+    file_name     TEXT NOT NULL UNIQUE,
+    title         TEXT,
+    source_url    TEXT,
+    media_type    TEXT NOT NULL,
+    size_bytes    INTEGER NOT NULL DEFAULT 0,
+    status        TEXT NOT NULL DEFAULT 'present',
+    added_at      INTEGER NOT NULL,
+    last_seen_at  INTEGER NOT NULL,
+    missing_since INTEGER,
+    channel       TEXT,
+    deleted_at    INTEGER,
+    ipfs_cid      TEXT,
+    -- Seconds, and the tag_list of the file's metadata joined by commas
+    duration      REAL,
+    tags          TEXT,
+    -- SHA-256 of the contents in hex, for finding the same file under two names
+    content_hash  TEXT,
+    -- Times /content served it from the start, and the last of them
+    plays          INTEGER NOT NULL DEFAULT 0,
+    last_played_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS reconcile_runs (
+    id          INTEGER PRIMARY KEY,
+    started_at  INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL,
+    trigger     TEXT NOT NULL,
+    scanned     INTEGER NOT NULL,
+    indexed     INTEGER NOT NULL,
+    missing     INTEGER NOT NULL,
+    restored    INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS device_profiles (
+    id          INTEGER PRIMARY KEY,
+    name        TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    selector    TEXT NOT NULL,
+    container   TEXT NOT NULL
+);
+
+-- Format lists from yt-dlp, so the options step can be checked against them
+CREATE TABLE IF NOT EXISTS analyses (
+    url         TEXT PRIMARY KEY,
+    title       TEXT NOT NULL,
+    formats     TEXT NOT NULL,
+    analyzed_at INTEGER NOT NULL,
+    channel     TEXT,
+    -- Channel and playlist URLs, JSON
+    links       TEXT,
+    -- Formats only the cookies got, JSON
+    unlocked    TEXT
+);
+
+-- Bytes downloaded per billing month, keyed by when the month started
+CREATE TABLE IF NOT EXISTS transfer_usage (
+    period_start INTEGER PRIMARY KEY,
+    bytes        INTEGER NOT NULL DEFAULT 0
+);
+
+-- NULL overrides fall back to the BSDL_SUBSCRIPTION_* settings
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id              INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL,
+    url             TEXT NOT NULL UNIQUE,
+    preset          TEXT,
+    output_template TEXT,
+    postprocess     TEXT,
+    created_at      INTEGER NOT NULL,
+    last_checked_at INTEGER,
+    failures        INTEGER NOT NULL DEFAULT 0,
+    last_error      TEXT,
+    last_success_at INTEGER,
+    -- Only the newest this many uploads are downloaded and kept; NULL keeps all
+    keep_latest     INTEGER,
+    -- YYYY-MM-DD; older uploads aren't downloaded
+    date_after      TEXT
+);
+
+-- Uploads a subscription has already seen, so each is fetched once
+CREATE TABLE IF NOT EXISTS subscription_items (
+    subscription_id INTEGER NOT NULL,
+    video_id        TEXT NOT NULL,
+    seen_at         INTEGER NOT NULL,
+    PRIMARY KEY (subscription_id, video_id)
+);
+
+-- Announced videos downloaded once they go live; NULL preset uses BSDL_SUBSCRIPTION_PRESET
+CREATE TABLE IF NOT EXISTS premieres (
+    id              INTEGER PRIMARY KEY,
+    url             TEXT NOT NULL UNIQUE,
+    title           TEXT NOT NULL,
+    release_at      INTEGER,
+    preset          TEXT,
+    created_at      INTEGER NOT NULL,
+    last_checked_at INTEGER,
+    queued_at       INTEGER,
+    last_error      TEXT
+);
+
+-- Every analysis and download attempt, kept after /jobs forgets them
+CREATE TABLE IF NOT EXISTS history (
+    id          INTEGER PRIMARY KEY,
+    kind        TEXT NOT NULL,
+    url         TEXT NOT NULL,
+    title       TEXT NOT NULL,
+    format      TEXT,
+    file_path   TEXT,
+    status      TEXT NOT NULL,
+    error       TEXT,
+    started_at  INTEGER NOT NULL,
+    finished_at INTEGER,
+    trace       TEXT
+);
+
+-- What happened to each download attempt and when: queued, started,
+-- postprocessing, then how it ended
+CREATE TABLE IF NOT EXISTS history_events (
+    history_id INTEGER NOT NULL REFERENCES history(id),
+    event      TEXT NOT NULL,
+    at         INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS history_events_entry ON history_events (history_id);
+
+-- Chains with steps still to run, so a restart picks them up again. Their
+-- steps are JSON, as the queue keeps them; finished chains are deleted.
+CREATE TABLE IF NOT EXISTS queue (
+    chain_id   INTEGER PRIMARY KEY,
+    title      TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    jobs       TEXT NOT NULL,
+    -- The ID its lines are logged with, see logging.rs
+    trace      TEXT
+);
+
+CREATE TABLE IF NOT EXISTS secrets (
+    name       TEXT PRIMARY KEY,
+    nonce      BLOB NOT NULL,
+    ciphertext BLOB NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- Saved filters of the library index, matched afresh on every visit (see smart.rs)
+CREATE TABLE IF NOT EXISTS smart_folders (
+    id          INTEGER PRIMARY KEY,
+    name        TEXT NOT NULL UNIQUE,
+    media_type  TEXT,
+    min_minutes INTEGER,
+    max_minutes INTEGER,
+    tag         TEXT,
+    words       TEXT,
+    created_at  INTEGER NOT NULL
+);
+
+-- Who may sign in at /login, with a PBKDF2 hash of their password (see password.rs)
+CREATE TABLE IF NOT EXISTS users (
+    name       TEXT PRIMARY KEY,
+    password   TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    admin      INTEGER NOT NULL DEFAULT 0
+);
+
+-- Other names a library file is published under, hard links to the contents
+-- of `source` (see publish.rs)
+CREATE TABLE IF NOT EXISTS publications (
+    name       TEXT PRIMARY KEY,
+    source     TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+-- Which yt-dlp build a site runs, stable or nightly; the site '' is the
+-- default for the rest (see pinning.rs)
+CREATE TABLE IF NOT EXISTS ytdlp_pins (
+    site      TEXT PRIMARY KEY,
+    build     TEXT NOT NULL,
+    pinned_at INTEGER NOT NULL
+);
+
+-- A row while every site is rolled back to the stable build
+CREATE TABLE IF NOT EXISTS ytdlp_rollback (
+    id    INTEGER PRIMARY KEY CHECK (id = 1),
+    since INTEGER NOT NULL
+);
+
+-- Videos downloaded before, outside the app, from yt-dlp download archives
+-- (--download-archive) imported at /library/duplicates
+CREATE TABLE IF NOT EXISTS download_archive (
+    extractor   TEXT NOT NULL,
+    video_id    TEXT NOT NULL,
+    imported_at INTEGER NOT NULL,
+    PRIMARY KEY (extractor, video_id)
+);
+CREATE INDEX IF NOT EXISTS download_archive_video ON download_archive (video_id);
+";
+
+// Columns added after their table first shipped, which CREATE TABLE IF NOT
+// EXISTS won't add to an existing database
+pub const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("items", "channel", "TEXT"),
+    ("analyses", "channel", "TEXT"),
+    ("analyses", "links", "TEXT"),
+    ("analyses", "unlocked", "TEXT"),
+    ("items", "deleted_at", "INTEGER"),
+    ("subscriptions", "failures", "INTEGER NOT NULL DEFAULT 0"),
+    ("subscriptions", "last_error", "TEXT"),
+    ("subscriptions", "last_success_at", "INTEGER"),
+    ("subscriptions", "keep_latest", "INTEGER"),
+    ("subscriptions", "date_after", "TEXT"),
+    ("items", "ipfs_cid", "TEXT"),
+    ("items", "duration", "REAL"),
+    ("items", "tags", "TEXT"),
+    ("items", "content_hash", "TEXT"),
+    ("items", "plays", "INTEGER NOT NULL DEFAULT 0"),
+    ("items", "last_played_at", "INTEGER"),
+    ("queue", "trace", "TEXT"),
+    ("history", "trace", "TEXT"),
+    ("queue", "owner", "TEXT"),
+    ("history", "owner", "TEXT"),
+    ("users", "admin", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+// --- Values ---
+
+/// A parameter or a column, the same whichever database it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "NULL",
+            Value::Integer(_) => "an integer",
+            Value::Real(_) => "a real",
+            Value::Text(_) => "text",
+            Value::Blob(_) => "a blob",
+        }
+    }
+}
+
+/// What can be bound to a placeholder, see `params!`.
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+macro_rules! integer_values {
+    ($($t:ty),*) => {$(
+        impl ToValue for $t {
+            fn to_value(&self) -> Value {
+                Value::Integer(*self as i64)
+            }
+        }
+
+        impl FromValue for $t {
+            fn from_value(value: &Value) -> std::result::Result<Self, String> {
+                match value {
+                    Value::Integer(i) => (*i).try_into().map_err(|_| format!("{} is out of range", i)),
+                    other => Err(format!("expected an integer, found {}", other.kind())),
+                }
+            }
+        }
+    )*};
+}
+integer_values!(i64, i32, u32, u64, usize);
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Integer(*self as i64)
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Value {
+        Value::Real(*self)
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl ToValue for [u8] {
+    fn to_value(&self) -> Value {
+        Value::Blob(self.to_vec())
+    }
+}
+
+impl ToValue for Vec<u8> {
+    fn to_value(&self) -> Value {
+        Value::Blob(self.clone())
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToValue::to_value)
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+/// Placeholder values for `?1`, `?2`, ... in order.
+macro_rules! params {
+    () => {
+        &[] as &[&dyn $crate::db::ToValue]
+    };
+    ($($p:expr),+ $(,)?) => {
+        &[$(&$p as &dyn $crate::db::ToValue),+] as &[&dyn $crate::db::ToValue]
+    };
+}
+pub(crate) use params;
+
+/// What a column can be read as.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> std::result::Result<Self, String>;
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> std::result::Result<Self, String> {
+        i64::from_value(value).map(|i| i != 0)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::Real(f) => Ok(*f),
+            Value::Integer(i) => Ok(*i as f64),
+            other => Err(format!("expected a number, found {}", other.kind())),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::Text(s) => Ok(s.clone()),
+            other => Err(format!("expected text, found {}", other.kind())),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::Blob(b) => Ok(b.clone()),
+            Value::Text(s) => Ok(s.clone().into_bytes()),
+            other => Err(format!("expected a blob, found {}", other.kind())),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+/// One row a query returned.
+#[derive(Debug, Clone)]
+pub struct Row {
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub fn new(values: Vec<Value>) -> Row {
+        Row { values }
+    }
+
+    /// Column `idx`, counting from 0 as the SELECT lists them.
+    pub fn get<T: FromValue>(&self, idx: usize) -> Result<T> {
+        let value = self.values.get(idx).ok_or_else(|| Error::Column(idx, "no such column".to_string()))?;
+        T::from_value(value).map_err(|reason| Error::Column(idx, reason))
+    }
+}
+
+// --- Storage ---
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("query returned no rows")]
+    NoRows,
+    #[error("column {0}: {1}")]
+    Column(usize, String),
+    /// Asked of the database that can't do it, e.g. a file snapshot of PostgreSQL
+    #[error("{0}")]
+    Unsupported(String),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    // tokio-postgres leaves the server's message out of its own
+    #[cfg(feature = "postgres")]
+    #[error("{0}{}", .0.as_db_error().map(|e| format!(": {}", e.message())).unwrap_or_default())]
+    Postgres(#[from] tokio_postgres::Error),
+}
+
+impl Error {
+    /// A UNIQUE, NOT NULL or CHECK constraint refused the change.
+    pub fn is_constraint(&self) -> bool {
+        match self {
+            Error::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => e.code == rusqlite::ErrorCode::ConstraintViolation,
+            // Class 23 is "integrity constraint violation"
+            #[cfg(feature = "postgres")]
+            Error::Postgres(e) => e.code().is_some_and(|c| c.code().starts_with("23")),
+            _ => false,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A connection to the database the tables live in.
+pub trait Storage: Send {
+    /// Runs one statement, returning how many rows it changed.
+    fn execute(&mut self, sql: &str, params: &[&dyn ToValue]) -> Result<usize>;
+    /// Runs one statement and reads every row it returns.
+    fn query(&mut self, sql: &str, params: &[&dyn ToValue]) -> Result<Vec<Row>>;
+    /// Runs statements separated by semicolons, without parameters.
+    fn execute_batch(&mut self, sql: &str) -> Result<()>;
+    /// The journal the database settled on, e.g. "wal".
+    fn journal_mode(&mut self) -> Result<String>;
+    /// Copies the write-ahead log back into the database and empties it.
+    /// Returns false when a reader elsewhere kept it from finishing.
+    fn checkpoint(&mut self) -> Result<bool>;
+    /// "ok", or each thing found wrong with the database.
+    fn integrity(&mut self) -> Result<Vec<String>>;
+    /// Writes a consistent copy of the whole database to the file `to`.
+    fn snapshot(&mut self, to: &Path) -> Result<()>;
+    /// Replaces the whole database with `from`, a file `snapshot` wrote.
+    fn restore(&mut self, from: &Path) -> Result<()>;
+    /// "SQLite" or "PostgreSQL".
+    fn engine(&self) -> &'static str;
+}
+
+impl<'s> dyn Storage + 's {
+    /// The first row `sql` returns, read by `f`; Error::NoRows when there's none.
+    pub fn query_row<T>(&mut self, sql: &str, params: &[&dyn ToValue], f: impl FnOnce(&Row) -> Result<T>) -> Result<T> {
+        self.query(sql, params)?.first().ok_or(Error::NoRows).and_then(f)
+    }
+
+    /// Every row `sql` returns, each read by `f`.
+    pub fn query_map<T>(&mut self, sql: &str, params: &[&dyn ToValue], f: impl FnMut(&Row) -> Result<T>) -> Result<Vec<T>> {
+        self.query(sql, params)?.iter().map(f).collect()
+    }
+
+    pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+        self.execute_batch("BEGIN")?;
+        Ok(Transaction { conn: self, open: true })
+    }
+}
+
+/// Rolled back when dropped before `commit`.
+pub struct Transaction<'a> {
+    conn: &'a mut (dyn Storage + 'a),
+    open: bool,
+}
+
+impl Transaction<'_> {
+    pub fn commit(mut self) -> Result<()> {
+        self.open = false;
+        let res = self.conn.execute_batch("COMMIT");
+        if res.is_err() {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+        res
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+impl<'a> Deref for Transaction<'a> {
+    type Target = dyn Storage + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn
+    }
+}
+
+/// Turns Error::NoRows into None.
+pub trait OptionalExtension<T> {
+    fn optional(self) -> Result<Option<T>>;
+}
+
+impl<T> OptionalExtension<T> for Result<T> {
+    fn optional(self) -> Result<Option<T>> {
+        match self {
+            Ok(v) => Ok(Some(v)),
+            Err(Error::NoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The values behind `params`, for a backend to bind.
+pub fn values(params: &[&dyn ToValue]) -> Vec<Value> {
+    params.iter().map(|p| p.to_value()).collect()
+}
+
+// --- SQLite ---
+
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(match self {
+            Value::Null => ValueRef::Null,
+            Value::Integer(i) => ValueRef::Integer(*i),
+            Value::Real(f) => ValueRef::Real(*f),
+            Value::Text(s) => ValueRef::Text(s.as_bytes()),
+            Value::Blob(b) => ValueRef::Blob(b),
+        }))
+    }
+}
+
+impl From<ValueRef<'_>> for Value {
+    fn from(value: ValueRef<'_>) -> Value {
+        match value {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(i) => Value::Integer(i),
+            ValueRef::Real(f) => Value::Real(f),
+            ValueRef::Text(s) => Value::Text(String::from_utf8_lossy(s).into_owned()),
+            ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+        }
+    }
+}
+
+/// The database in one file, next to the binary unless BSDL_DB_PATH says otherwise.
+pub struct Sqlite {
+    conn: Connection,
+}
+
+impl Sqlite {
+    pub fn open(path: &Path, policy: &StoragePolicy) -> Result<Sqlite> {
+        let conn = Connection::open(path)?;
+        // Backups, restores and the sqlite3 shell can all hold a lock for a moment
+        conn.busy_timeout(Duration::from_millis(policy.busy_timeout_ms))?;
+        if let Some(kib) = policy.cache_kib {
+            // Negative means KiB rather than pages; temp tables and sorts go to disk
+            conn.execute_batch(&format!("PRAGMA cache_size = -{}; PRAGMA temp_store = FILE;", kib))?;
+        }
+        let wanted = if policy.wal { "wal" } else { "delete" };
+        let mode: String = conn.query_row(&format!("PRAGMA journal_mode = {}", wanted), [], |r| r.get(0))?;
+        if mode != wanted {
+            tracing::warn!("Database journal is {} rather than {}; the filesystem may not support it", mode, wanted);
+        } else if policy.wal {
+            // Safe with a WAL: a crash can only lose the last commits, never corrupt
+            conn.execute_batch("PRAGMA synchronous = NORMAL;")?;
+        }
+        conn.execute_batch(SCHEMA)?;
+        add_columns(&conn)?;
+        Ok(Sqlite { conn })
+    }
+}
+
+impl Storage for Sqlite {
+    fn execute(&mut self, sql: &str, params: &[&dyn ToValue]) -> Result<usize> {
+        let values = values(params);
+        Ok(self.conn.prepare_cached(sql)?.execute(rusqlite::params_from_iter(&values))?)
+    }
+
+    fn query(&mut self, sql: &str, params: &[&dyn ToValue]) -> Result<Vec<Row>> {
+        let values = values(params);
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let width = stmt.column_count();
+        let mut rows = stmt.query(rusqlite::params_from_iter(&values))?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let values = (0..width).map(|i| row.get_ref(i).map(Value::from)).collect::<rusqlite::Result<_>>()?;
+            out.push(Row::new(values));
+        }
+        Ok(out)
+    }
+
+    fn execute_batch(&mut self, sql: &str) -> Result<()> {
+        Ok(self.conn.execute_batch(sql)?)
+    }
+
+    fn journal_mode(&mut self) -> Result<String> {
+        Ok(self.conn.query_row("PRAGMA journal_mode", [], |r| r.get(0))?)
+    }
+
+    fn checkpoint(&mut self) -> Result<bool> {
+        let busy: i64 = self.conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |r| r.get(0))?;
+        Ok(busy == 0)
+    }
+
+    fn integrity(&mut self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("PRAGMA quick_check")?;
+        let rows = stmt.query_map([], |r| r.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn snapshot(&mut self, to: &Path) -> Result<()> {
+        // VACUUM INTO gives a consistent copy even while other requests are writing
+        self.conn.execute("VACUUM INTO ?1", [to.to_string_lossy()])?;
+        Ok(())
+    }
+
+    fn restore(&mut self, from: &Path) -> Result<()> {
+        Ok(self.conn.restore(rusqlite::DatabaseName::Main, from, None::<fn(rusqlite::backup::Progress)>)?)
+    }
+
+    fn engine(&self) -> &'static str {
+        "SQLite"
+    }
+}
+
+fn add_columns(conn: &Connection) -> rusqlite::Result<()> {
+    for (table, column, decl) in ADDED_COLUMNS {
+        let exists: bool = conn.query_row(
+            &format!("SELECT COUNT(*) > 0 FROM pragma_table_info('{}') WHERE name = ?1", table),
+            [column],
+            |r| r.get(0),
+        )?;
+        if !exists {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+        }
+    }
+    Ok(())
+}
+
+// --- Database ---
+
+/// Shared handle on the database. Queries run on the blocking pool so they never stall the runtime.
+#[derive(Clone)]
+pub struct Db {
+    conn: Arc<Mutex<Box<dyn Storage>>>,
+    engine: &'static str,
+}
+
+impl Db {
+    /// Opens the server BSDL_DB_URL names, or else the SQLite file at `path`,
+    /// and brings its tables up to date.
+    pub async fn open(path: &Path, policy: &StoragePolicy) -> Result<Db> {
+        let conn: Box<dyn Storage> = match &policy.url {
+            Some(url) => connect(url, policy).await?,
+            None => Box::new(Sqlite::open(path, policy)?),
+        };
+        let engine = conn.engine();
+        Ok(Db { conn: Arc::new(Mutex::new(conn)), engine })
+    }
+
+    pub async fn call<T, F>(&self, f: F) -> std::result::Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn Storage) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut **conn)
+        })
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))?;
+        Ok(res?)
+    }
+
+    /// "SQLite" or "PostgreSQL".
+    pub fn engine(&self) -> &'static str {
+        self.engine
+    }
+
+    /// The journal mode SQLite actually settled on, e.g. "wal".
+    pub async fn journal_mode(&self) -> std::result::Result<String, AppError> {
+        self.call(|conn| conn.journal_mode()).await
+    }
+
+    /// Copies the write-ahead log back into the database and empties it.
+    /// Returns false when a reader elsewhere kept it from finishing.
+    pub async fn checkpoint(&self) -> std::result::Result<bool, AppError> {
+        self.call(|conn| conn.checkpoint()).await
+    }
+}
+
+#[cfg(feature = "postgres")]
+async fn connect(url: &str, policy: &StoragePolicy) -> Result<Box<dyn Storage>> {
+    Ok(Box::new(crate::pg::Postgres::connect(url, policy).await?))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn connect(_url: &str, _policy: &StoragePolicy) -> Result<Box<dyn Storage>> {
+    Err(Error::Unsupported(
+        "BSDL_DB_URL needs a build with PostgreSQL support: cargo build --release --features postgres".to_string(),
+    ))
+}
+
+/// Checkpoints the WAL every `minutes` so it can't grow without bound while
+/// the app is busy. 0 leaves it to SQLite's automatic checkpoints.
+pub fn spawn_checkpointer(db: Db, minutes: u64, timers: Timers) {
+    if minutes == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(minutes * 60));
+        tick.tick().await;
+        timers.started(Timer::Checkpoint, minutes * 60);
+        loop {
+            tick.tick().await;
+            timers.fired(Timer::Checkpoint, minutes * 60);
+            match db.checkpoint().await {
+                Ok(true) => {}
+                Ok(false) => tracing::warn!("WAL checkpoint couldn't finish: another connection is reading"),
+                Err(e) => tracing::warn!("WAL checkpoint failed: {}", e),
+            }
+        }
+    });
+}
+
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
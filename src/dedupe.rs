@@ -0,0 +1,393 @@
+use askama::Template;
+use axum::{
+    extract::{Multipart, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path as FsPath, PathBuf};
+
+use crate::auth;
+use crate::clock::Stamp;
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::{render, AppError};
+use crate::library;
+use crate::notify;
+use crate::page::{self, FlashKind};
+use crate::reconcile;
+use crate::sanitize::{self, filters};
+use crate::session::SessionId;
+use crate::validate::{Form, Validate};
+use crate::AppState;
+
+// Video ids only catch the same upload fetched twice. Mirrors and re-uploads
+// come from other URLs, so finished downloads are also hashed and compared
+// with what the library already holds. Videos fetched before, with yt-dlp on
+// the command line, come in as its download archive (archive.txt): their ids
+// are kept, and subscriptions and channel listings leave them out.
+
+/// The largest archive.txt taken; years of downloads are a few MB.
+pub const MAX_ARCHIVE_BYTES: usize = 32 * 1024 * 1024;
+
+// --- Hashing ---
+
+/// The SHA-256 of a file's contents, in hex.
+pub fn hash_file(path: &FsPath) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut sha = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sha.update(&buf[..n]);
+    }
+    Ok(hex::encode(sha.finalize()))
+}
+
+// Device and inode: two names with the same one are one file already, hard-linked
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn identity(path: &FsPath) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_path: &FsPath) -> Option<FileId> {
+    None
+}
+
+/// Replaces `duplicate` with a hard link to `original`. The link is made under
+/// a hidden name first, so the duplicate is only gone once the link is there.
+fn link(original: &FsPath, duplicate: &FsPath) -> std::io::Result<()> {
+    let hidden = format!(".{}.linking", duplicate.file_name().and_then(|n| n.to_str()).unwrap_or_default());
+    let temp = duplicate.with_file_name(hidden);
+    let _ = fs::remove_file(&temp);
+    fs::hard_link(original, &temp)?;
+    fs::rename(&temp, duplicate).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+// --- Data Structures ---
+
+/// One copy of a file the library holds more than once.
+#[derive(Debug, Clone)]
+pub struct Copy {
+    pub file_name: String,
+    pub title: String,
+    pub added: Stamp,
+    /// A hard link to the group's first copy, taking no space of its own
+    pub linked: bool,
+}
+
+/// Files with the same contents, the one downloaded first leading.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub size_mb: String,
+    pub copies: Vec<Copy>,
+}
+
+// --- Store ---
+
+// Hashes `name` and keeps the hash against its item
+async fn hash_item(state: &AppState, name: &str) -> Result<String, AppError> {
+    let path = FsPath::new(&state.config.downloads_dir).join(name);
+    let hash = tokio::task::spawn_blocking(move || hash_file(&path))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+    let (file, value) = (name.to_string(), hash.clone());
+    state.db.call(move |conn| conn.execute("UPDATE items SET content_hash = ?2 WHERE file_name = ?1", params![file, value])).await?;
+    Ok(hash)
+}
+
+// Present items other than `name` with these contents, oldest first
+async fn copies_of(db: &Db, name: &str, hash: &str) -> Result<Vec<String>, AppError> {
+    let (name, hash) = (name.to_string(), hash.to_string());
+    db.call(move |conn| {
+        conn.query_map(
+            "SELECT file_name FROM items WHERE status = 'present' AND content_hash = ?2 AND file_name != ?1 ORDER BY added_at, id",
+            params![name, hash],
+            |r| r.get(0),
+        )
+    })
+    .await
+}
+
+/// Hashes a finished download and, when the library already had the same
+/// file from another URL, says so.
+pub async fn check(state: &AppState, name: &str) -> Result<(), AppError> {
+    let hash = hash_item(state, name).await?;
+    let dir = FsPath::new(&state.config.downloads_dir);
+    let path = dir.join(name);
+    let same = identity(&path);
+    let copies = copies_of(&state.db, name, &hash).await?;
+    let Some(original) = copies.iter().find(|c| same.is_none() || identity(&dir.join(c)) != same) else {
+        return Ok(());
+    };
+    let message = format!("{} is the same file as {}; link or delete it on /library/duplicates", name, original);
+    notify::send(state, &message).await;
+    Ok(())
+}
+
+/// Hashes every present item that hasn't been yet. Returns how many.
+async fn hash_unhashed(state: &AppState) -> Result<usize, AppError> {
+    let names: Vec<String> = state
+        .db
+        .call(|conn| {
+            conn.query_map("SELECT file_name FROM items WHERE status = 'present' AND content_hash IS NULL", params![], |r| r.get(0))
+        })
+        .await?;
+    let mut hashed = 0;
+    for name in names {
+        match hash_item(state, &name).await {
+            Ok(_) => hashed += 1,
+            // Gone since the last rescan; the next one marks it missing
+            Err(AppError::Io(e)) => tracing::warn!("Could not hash {}: {}", name, e),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hashed)
+}
+
+async fn groups(state: &AppState, viewer: Option<String>) -> Result<Vec<Group>, AppError> {
+    let dir = state.config.downloads_dir.clone();
+    state.db.call(move |conn| {
+        let rows = conn.query_map(
+            "SELECT content_hash, file_name, COALESCE(title, file_name), added_at, size_bytes FROM items
+             WHERE status = 'present' AND content_hash IN (
+                 SELECT content_hash FROM items WHERE status = 'present' AND content_hash IS NOT NULL
+                 GROUP BY content_hash HAVING COUNT(*) > 1)
+             ORDER BY content_hash, added_at, id",
+            params![],
+            |r| Ok((r.get::<String>(0)?, r.get::<String>(1)?, r.get::<String>(2)?, Stamp(r.get(3)?), r.get::<i64>(4)?)),
+        )?;
+        let mut groups: Vec<(String, Option<FileId>, Group)> = Vec::new();
+        for (hash, file_name, title, added, size) in rows {
+            if !library::visible_to(viewer.as_deref(), &file_name) {
+                continue;
+            }
+            let here = identity(&FsPath::new(&dir).join(&file_name));
+            match groups.last_mut() {
+                Some((h, first, group)) if *h == hash => {
+                    let linked = here.is_some() && here == *first;
+                    group.copies.push(Copy { file_name, title, added, linked });
+                }
+                _ => {
+                    let size_mb = format!("{:.2} MB", size as f64 / 1024.0 / 1024.0);
+                    let copies = vec![Copy { file_name, title, added, linked: false }];
+                    groups.push((hash, here, Group { size_mb, copies }));
+                }
+            }
+        }
+        // Only groups with a copy that still takes up space of its own
+        Ok(groups.into_iter().map(|(_, _, g)| g).filter(|g| g.copies.iter().skip(1).any(|c| !c.linked)).collect())
+    })
+    .await
+}
+
+// --- Download archives ---
+
+// "youtube dQw4w9WgXcQ" per line, as yt-dlp writes them; returns those and
+// how many lines weren't
+fn parse_archive(text: &str) -> (Vec<(String, String)>, usize) {
+    let (mut entries, mut unreadable) = (Vec::new(), 0);
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(extractor), Some(id), None) if extractor.len() <= 64 && id.len() <= 256 => {
+                entries.push((extractor.to_lowercase(), id.to_string()))
+            }
+            _ => unreadable += 1,
+        }
+    }
+    (entries, unreadable)
+}
+
+// Keeps `entries`, returning how many weren't there yet
+async fn import_archive(db: &Db, entries: Vec<(String, String)>) -> Result<usize, AppError> {
+    db.call(move |conn| {
+        let mut tx = conn.transaction()?;
+        let mut added = 0;
+        let now = db::now();
+        for (extractor, id) in &entries {
+            added += tx.execute(
+                "INSERT INTO download_archive (extractor, video_id, imported_at) VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING",
+                params![extractor, id, now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(added)
+    })
+    .await
+}
+
+/// Which of `ids` an imported download archive has. By id alone: flat
+/// listings don't say which extractor an entry is for, and ids of two sites
+/// hardly ever meet.
+pub async fn archived(db: &Db, ids: Vec<String>) -> Result<HashSet<String>, AppError> {
+    db.call(move |conn| {
+        let mut found = HashSet::new();
+        for id in ids {
+            if !conn.query("SELECT 1 FROM download_archive WHERE video_id = ?1 LIMIT 1", params![id])?.is_empty() {
+                found.insert(id);
+            }
+        }
+        Ok(found)
+    })
+    .await
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "duplicates.html")]
+struct DuplicatesTemplate {
+    groups: Vec<Group>,
+    unhashed: i64,
+    /// Videos in imported download archives
+    archived: i64,
+}
+
+pub async fn show_duplicates(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let groups = groups(&state, auth::viewer(&state, &sid)).await?;
+    let unhashed = state
+        .db
+        .call(|conn| conn.query_row("SELECT COUNT(*) FROM items WHERE status = 'present' AND content_hash IS NULL", params![], |r| r.get(0)))
+        .await?;
+    let archived = state.db.call(|conn| conn.query_row("SELECT COUNT(*) FROM download_archive", params![], |r| r.get(0))).await?;
+    Ok(render(DuplicatesTemplate { groups, unhashed, archived }))
+}
+
+/// Takes an uploaded yt-dlp download archive, so the videos in it aren't
+/// fetched again.
+pub async fn upload_archive(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    mut multipart: Multipart,
+) -> Result<Response, AppError> {
+    let malformed = |e: axum::extract::multipart::MultipartError| AppError::BadRequest(format!("Upload was malformed: {}", e));
+    let mut text = None;
+    while let Some(field) = multipart.next_field().await.map_err(malformed)? {
+        if field.name() == Some("archive") {
+            let bytes = field.bytes().await.map_err(malformed)?;
+            text = Some(String::from_utf8_lossy(&bytes).into_owned());
+        }
+    }
+    let Some(text) = text.filter(|t| !t.trim().is_empty()) else {
+        return Err(AppError::Invalid { field: "archive", reason: "pick the archive.txt to import".to_string() });
+    };
+    let (entries, unreadable) = parse_archive(&text);
+    if entries.is_empty() {
+        return Err(AppError::Invalid {
+            field: "archive",
+            reason: "no line reads \"<extractor> <video id>\", as yt-dlp's --download-archive writes them".to_string(),
+        });
+    }
+    let listed = entries.len();
+    let added = import_archive(&state.db, entries).await?;
+    tracing::info!("Imported a download archive: {} new of {} video(s)", added, listed);
+    let mut message = format!("Imported {} video(s) from the archive; {} were in already.", added, listed - added);
+    if unreadable > 0 {
+        message.push_str(&format!(" {} line(s) weren't \"<extractor> <video id>\" and were skipped.", unreadable));
+    }
+    page::flash(&state, &sid, FlashKind::Info, message);
+    Ok(Redirect::to("/library/duplicates").into_response())
+}
+
+pub async fn scan_duplicates(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let hashed = hash_unhashed(&state).await?;
+    tracing::info!("Hashed {} library files for duplicates", hashed);
+    page::flash(&state, &sid, FlashKind::Info, format!("Checked {} more file(s).", hashed));
+    Ok(Redirect::to("/library/duplicates").into_response())
+}
+
+#[derive(Deserialize)]
+pub struct DuplicateForm {
+    name: String,
+}
+
+impl Validate for DuplicateForm {
+    fn validate(&self) -> Result<(), AppError> {
+        if !library::plain_name(&self.name) {
+            return Err(AppError::Invalid { field: "name", reason: "not a library file".to_string() });
+        }
+        Ok(())
+    }
+}
+
+/// The copy `name` duplicates and both paths, after hashing the two again so
+/// nothing is thrown away on a stale hash.
+async fn original_of(state: &AppState, sid: &SessionId, name: &str) -> Result<(String, PathBuf, PathBuf), AppError> {
+    let viewer = auth::viewer(state, sid);
+    let shown = sanitize::line(name);
+    if !library::visible_to(viewer.as_deref(), name) {
+        return Err(AppError::NotFound(format!("File \"{}\"", shown)));
+    }
+    let key = name.to_string();
+    let hash: Option<String> = state
+        .db
+        .call(move |conn| {
+            conn.query_row("SELECT content_hash FROM items WHERE file_name = ?1 AND status = 'present'", params![key], |r| r.get(0))
+                .optional()
+                .map(Option::flatten)
+        })
+        .await?;
+    let hash = hash.ok_or_else(|| AppError::NotFound(format!("Checked file \"{}\"", shown)))?;
+    let dir = &state.config.downloads_dir;
+    let duplicate = library::library_file(dir, name)?;
+    let original = copies_of(&state.db, name, &hash)
+        .await?
+        .into_iter()
+        .find(|c| library::visible_to(viewer.as_deref(), c) && FsPath::new(dir).join(c).is_file())
+        .ok_or_else(|| AppError::BadRequest(format!("{} no longer has a copy in the library", shown)))?;
+
+    if hash_item(state, name).await? != hash || hash_item(state, &original).await? != hash {
+        return Err(AppError::BadRequest(format!(
+            "{} or {} changed since they were checked, so they aren't copies any more",
+            shown,
+            sanitize::line(&original)
+        )));
+    }
+    let from = FsPath::new(dir).join(&original);
+    Ok((original, from, duplicate))
+}
+
+/// Replaces a duplicate with a hard link to the first copy, freeing its space
+/// while both names keep working.
+pub async fn link_duplicate(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<DuplicateForm>,
+) -> Result<Response, AppError> {
+    let (original, from, to) = original_of(&state, &sid, &form.name).await?;
+    tokio::task::spawn_blocking(move || link(&from, &to))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+    tracing::info!("Linked {} to {}", form.name, original);
+    let shown = sanitize::line(&form.name);
+    page::flash(&state, &sid, FlashKind::Info, format!("{} is now a hard link to {}; its space is free again.", shown, sanitize::line(&original)));
+    Ok(Redirect::to("/library/duplicates").into_response())
+}
+
+/// Deletes a duplicate and its sidecars and removes it from the history.
+pub async fn delete_duplicate(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<DuplicateForm>,
+) -> Result<Response, AppError> {
+    let (original, _, _) = original_of(&state, &sid, &form.name).await?;
+    let (dir, key) = (state.config.downloads_dir.clone(), form.name.clone());
+    let files = state.db.call(move |conn| reconcile::remove_record(conn, &dir, &key, true)).await??.unwrap_or_default();
+    tracing::info!("Deleted {}, a copy of {}", form.name, original);
+    let shown = sanitize::line(&form.name);
+    page::flash(&state, &sid, FlashKind::Info, format!("Deleted {} ({} file(s)); {} is kept.", shown, files, sanitize::line(&original)));
+    Ok(Redirect::to("/library/duplicates").into_response())
+}
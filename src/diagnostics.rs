@@ -0,0 +1,276 @@
+use axum::{extract::State, response::Response};
+use askama::Template;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::backup::BACKUP_DIR;
+use crate::error::render;
+use crate::landing;
+use crate::pinning;
+use crate::platform;
+use crate::AppState;
+
+// Below these the disk check warns, then fails
+const LOW_SPACE_MB: u64 = 5 * 1024;
+const CRITICAL_SPACE_MB: u64 = 500;
+// Below this much free memory the memory check warns
+const LOW_MEMORY_MB: u64 = 128;
+// More drift than this upsets signed URLs and TLS
+const MAX_SKEW_SECS: i64 = 60;
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Pass => "Pass",
+            Status::Warn => "Warn",
+            Status::Fail => "Fail",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Check { name: name.into(), status, detail: detail.into() }
+    }
+}
+
+// --- Startup ---
+
+/// Creates a directory the app needs, recording the outcome for the diagnostics page.
+pub fn ensure_dir(dir: &Path, checks: &mut Vec<Check>) {
+    let name = format!("Create {}", dir.display());
+    match std::fs::create_dir_all(dir) {
+        Ok(()) => checks.push(Check::new(name, Status::Pass, "exists")),
+        Err(e) => {
+            tracing::warn!("Could not create {}: {}", dir.display(), e);
+            checks.push(Check::new(name, Status::Fail, e.to_string()));
+        }
+    }
+}
+
+// --- Checks ---
+
+pub async fn ytdlp(state: &AppState) -> Check {
+    build(state, "yt-dlp", platform::ytdlp(&state.config)).await
+}
+
+/// The nightly build sites can be pinned to, when there is one.
+pub async fn ytdlp_nightly(state: &AppState) -> Option<Check> {
+    Some(build(state, "yt-dlp nightly", pinning::nightly(&state.config)?).await)
+}
+
+async fn build(state: &AppState, name: &str, program: PathBuf) -> Check {
+    let path = std::fs::canonicalize(&program).unwrap_or_else(|_| program.clone());
+    let mut cmd = Command::new(&program);
+    cmd.arg("--version");
+    match state.procs.output("diagnostics", cmd).await {
+        Ok(out) if out.status.success() => Check::new(
+            name,
+            Status::Pass,
+            format!("{} at {}", String::from_utf8_lossy(&out.stdout).trim(), path.display()),
+        ),
+        Ok(out) => Check::new(name, Status::Fail, format!("{} exited with {}", path.display(), out.status)),
+        Err(e) => Check::new(name, Status::Fail, format!("{}: {} (run get-dlp.sh)", path.display(), e)),
+    }
+}
+
+pub async fn ffmpeg(state: &AppState) -> Check {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-version");
+    match state.procs.output("diagnostics", cmd).await {
+        Ok(out) if out.status.success() => {
+            let text = String::from_utf8_lossy(&out.stdout);
+            Check::new("ffmpeg", Status::Pass, text.lines().next().unwrap_or("found").to_string())
+        }
+        Ok(out) => Check::new("ffmpeg", Status::Warn, format!("exited with {}", out.status)),
+        // Only merging and compatibility copies need it
+        Err(e) => Check::new("ffmpeg", Status::Warn, format!("not usable ({}); merging formats and compatibility copies will fail", e)),
+    }
+}
+
+async fn disk_space(state: &AppState) -> Check {
+    let mut cmd = Command::new("df");
+    cmd.args(["-Pk", &state.config.downloads_dir]);
+    let out = match state.procs.output("diagnostics", cmd).await {
+        Ok(out) if out.status.success() => out,
+        Ok(out) => return Check::new("Disk space", Status::Warn, format!("df exited with {}", out.status)),
+        Err(e) => return Check::new("Disk space", Status::Warn, format!("could not run df: {}", e)),
+    };
+    // Second line, fourth column is the free space in KB
+    let text = String::from_utf8_lossy(&out.stdout);
+    let Some(free_kb) = text.lines().nth(1).and_then(|l| l.split_whitespace().nth(3)).and_then(|v| v.parse::<u64>().ok())
+    else {
+        return Check::new("Disk space", Status::Warn, "could not read df output");
+    };
+    let free_mb = free_kb / 1024;
+    let status = if free_mb < CRITICAL_SPACE_MB {
+        Status::Fail
+    } else if free_mb < LOW_SPACE_MB {
+        Status::Warn
+    } else {
+        Status::Pass
+    };
+    Check::new("Disk space", status, format!("{:.1} GB free for {}/", free_mb as f64 / 1024.0, state.config.downloads_dir))
+}
+
+async fn db_integrity(state: &AppState) -> Check {
+    let res = state.db.call(|conn| conn.integrity()).await;
+    let at = match state.config.storage.url {
+        Some(_) => "PostgreSQL at BSDL_DB_URL".to_string(),
+        None => state.config.db_path.display().to_string(),
+    };
+    match res {
+        Ok(rows) if rows.len() == 1 && rows[0] == "ok" => Check::new("Database integrity", Status::Pass, at),
+        Ok(rows) => Check::new("Database integrity", Status::Fail, rows.join("; ")),
+        Err(e) => Check::new("Database integrity", Status::Fail, e.to_string()),
+    }
+}
+
+// The journal SQLite settled on, against the one asked for
+async fn db_journal(state: &AppState) -> Check {
+    let storage = &state.config.storage;
+    if storage.url.is_some() {
+        let detail = format!("{}, lock timeout {} ms", state.db.engine(), storage.busy_timeout_ms);
+        return Check::new("Database journal", Status::Pass, detail);
+    }
+    let mode = match state.db.journal_mode().await {
+        Ok(mode) => mode,
+        Err(e) => return Check::new("Database journal", Status::Fail, e.to_string()),
+    };
+    let detail = format!("{}, busy timeout {} ms", mode, storage.busy_timeout_ms);
+    if storage.wal && mode != "wal" {
+        return Check::new("Database journal", Status::Warn, format!("{}; WAL was asked for but the filesystem refused it", detail));
+    }
+    match storage.checkpoint_minutes {
+        minutes if storage.wal && minutes > 0 => {
+            Check::new("Database journal", Status::Pass, format!("{}, checkpoint every {} min", detail, minutes))
+        }
+        _ => Check::new("Database journal", Status::Pass, detail),
+    }
+}
+
+pub async fn writable(dir: PathBuf) -> Check {
+    let name = format!("Write {}", dir.display());
+    let probe = dir.join(".bsdl-write-test");
+    match tokio::fs::write(&probe, b"ok").await {
+        Ok(()) => {
+            let _ = tokio::fs::remove_file(&probe).await;
+            Check::new(name, Status::Pass, "writable")
+        }
+        Err(e) => Check::new(name, Status::Fail, e.to_string()),
+    }
+}
+
+// Compares our clock with the Date header of a well-known server
+async fn clock_skew(state: &AppState) -> Check {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-sSI", "-m", "10", "https://api.github.com"]);
+    let out = match state.procs.output("diagnostics", cmd).await {
+        Ok(out) if out.status.success() => out,
+        Ok(_) | Err(_) => return Check::new("Clock", Status::Warn, "no reference server reachable; skew not checked"),
+    };
+    let headers = String::from_utf8_lossy(&out.stdout);
+    let remote = headers
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("date"))
+        .and_then(|(_, v)| chrono::DateTime::parse_from_rfc2822(v.trim()).ok());
+    let Some(remote) = remote else {
+        return Check::new("Clock", Status::Warn, "reference server sent no usable Date header");
+    };
+    let skew = (chrono::Utc::now() - remote.with_timezone(&chrono::Utc)).num_seconds();
+    let status = if skew.abs() > MAX_SKEW_SECS { Status::Fail } else { Status::Pass };
+    Check::new("Clock", status, format!("{:+} s against api.github.com", skew))
+}
+
+// What yt-dlp and ffmpeg run under; the wrappers have to exist for them to start
+fn process_limits(state: &AppState) -> Check {
+    let limits = &state.config.limits;
+    let prefix = limits.prefix();
+    let mut detail = if prefix.is_empty() { "normal priority".to_string() } else { prefix.join(" ") };
+    if let Some(cgroup) = &limits.cgroup {
+        let procs = cgroup.join("cgroup.procs");
+        if !cfg!(target_os = "linux") || !procs.is_file() {
+            return Check::new("Process limits", Status::Warn, format!("{}; no cgroup at {}", detail, cgroup.display()));
+        }
+        detail.push_str(&format!("; cgroup {}", cgroup.display()));
+    }
+    Check::new("Process limits", Status::Pass, detail)
+}
+
+// A `Key:   1234 kB` line from /proc/self/status or /proc/meminfo, in MB
+fn proc_kb(file: &str, key: &str) -> Option<u64> {
+    let text = std::fs::read_to_string(file).ok()?;
+    let line = text.lines().find(|l| l.starts_with(key))?;
+    let kb: u64 = line[key.len()..].trim_start_matches(':').split_whitespace().next()?.parse().ok()?;
+    Some(kb / 1024)
+}
+
+// What the app itself uses, and what the machine has left
+fn memory(state: &AppState) -> Check {
+    let mode = if state.config.memory.low { "low-memory mode" } else { "normal mode" };
+    let (Some(used), Some(free), Some(total)) = (
+        proc_kb("/proc/self/status", "VmRSS"),
+        proc_kb("/proc/meminfo", "MemAvailable"),
+        proc_kb("/proc/meminfo", "MemTotal"),
+    ) else {
+        return Check::new("Memory", Status::Pass, format!("{}; usage isn't reported on this platform", mode));
+    };
+    let status = if free < LOW_MEMORY_MB { Status::Warn } else { Status::Pass };
+    Check::new("Memory", status, format!("{} MB used by the app, {} of {} MB free; {}", used, free, total, mode))
+}
+
+/// Runs every live check. Each one is cheap, so this happens on every page view.
+pub async fn run(state: &AppState) -> Vec<Check> {
+    let data_dir = state.config.db_path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let (ytdlp, nightly, ffmpeg, disk, db, journal, downloads, data, backups, clock) = tokio::join!(
+        ytdlp(state),
+        ytdlp_nightly(state),
+        ffmpeg(state),
+        disk_space(state),
+        db_integrity(state),
+        db_journal(state),
+        writable(PathBuf::from(&state.config.downloads_dir)),
+        writable(data_dir),
+        writable(PathBuf::from(BACKUP_DIR)),
+        clock_skew(state),
+    );
+    let mut checks = vec![ytdlp];
+    checks.extend(nightly);
+    checks.extend([ffmpeg, process_limits(state), memory(state), disk, db, journal, downloads]);
+    checks.extend([landing::check(&state.config.landing), data, backups, clock]);
+    checks
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "diagnostics.html")]
+struct DiagnosticsTemplate {
+    startup: Vec<Check>,
+    live: Vec<Check>,
+    failures: usize,
+}
+
+pub async fn show_diagnostics(State(state): State<AppState>) -> Response {
+    let live = run(&state).await;
+    let startup = state.startup.to_vec();
+    let failures = startup.iter().chain(&live).filter(|c| c.status == Status::Fail).count();
+    render(DiagnosticsTemplate { startup, live, failures })
+}
@@ -0,0 +1,212 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::auth;
+use crate::error::{render, AppError};
+use crate::gate::Workaround;
+use crate::jobs::{self, NewJob, Step};
+use crate::page::{self, FlashKind};
+use crate::recipe::Recipe;
+use crate::sanitize;
+use crate::session::{Choice, Selection, SessionId};
+use crate::validate::{Form, Validate};
+use crate::{AppState, YtDlpOutput};
+
+// --- Data Structures ---
+
+/// One video or photo of a post that holds several.
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// What `--playlist-items` picks it by, counting from 1
+    pub index: usize,
+    /// A flat playlist entry's own page, which it's analyzed and downloaded by
+    /// instead of its position
+    pub url: Option<String>,
+    pub title: String,
+    pub formats: usize,
+    /// "1080p", "photo", or "audio" when nothing has a picture
+    pub best: String,
+    /// Set for a photo rather than a video
+    pub photo: Option<Choice>,
+}
+
+/// A URL (a tweet with several videos, a playlist) whose analysis listed more
+/// than one video. Kept in the session until items are picked.
+#[derive(Debug, Clone)]
+pub struct Post {
+    pub url: String,
+    pub items: Vec<Item>,
+    pub workaround: Option<Workaround>,
+}
+
+impl Post {
+    pub fn new(url: &str, entries: Vec<YtDlpOutput>, workaround: Option<Workaround>) -> Post {
+        let items = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, meta)| {
+                let height = meta.formats.iter().filter_map(|f| f.height).max();
+                let photo = meta.photo();
+                let best = match (&photo, height) {
+                    _ if meta.flat() => "-".to_string(),
+                    (Some(_), _) => "photo".to_string(),
+                    (None, Some(h)) => format!("{}p", h),
+                    (None, None) => "audio".to_string(),
+                };
+                let index = meta.links.playlist_index.unwrap_or(i + 1);
+                let url = meta.entry_url();
+                let title = match sanitize::line(&meta.title) {
+                    t if t.is_empty() => url.clone().unwrap_or_else(|| format!("Item {}", index)),
+                    t => t,
+                };
+                Item {
+                    index,
+                    url,
+                    title,
+                    formats: meta.formats.len(),
+                    best,
+                    photo,
+                }
+            })
+            .collect();
+        Post { url: url.to_string(), items, workaround }
+    }
+
+    /// A playlist or channel listed flat, rather than one post's videos.
+    pub fn playlist(&self) -> bool {
+        self.items.iter().any(|item| item.url.is_some())
+    }
+}
+
+// --- Page ---
+
+#[derive(Template)]
+#[template(path = "entries.html")]
+struct EntriesTemplate {
+    post: Post,
+    browser: String,
+    client: String,
+    country: String,
+    proxy: bool,
+}
+
+/// Shown instead of the format table when `post` holds several videos.
+pub fn page(state: &AppState, sid: &SessionId, post: Post) -> Response {
+    // The item forms go back through /analyze, which wants the workaround spelled out
+    let (browser, client, country) = match &post.workaround {
+        Some(Workaround::Browser(b)) => (b.clone(), String::new(), String::new()),
+        Some(Workaround::Client(c)) => (String::new(), c.clone(), String::new()),
+        Some(Workaround::Country(c)) => (String::new(), String::new(), c.clone()),
+        Some(Workaround::Proxy) | None => (String::new(), String::new(), String::new()),
+    };
+    let proxy = post.workaround == Some(Workaround::Proxy);
+    state.sessions.update(sid, |s| s.post = Some(post.clone()));
+    render(EntriesTemplate { post, browser, client, country, proxy })
+}
+
+// --- Handlers ---
+
+/// The items ticked on the page, each sent as `item=<index>`.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct PickRequest(Vec<(String, String)>);
+
+impl PickRequest {
+    fn indices(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter(|(key, _)| key == "item").map(|(_, value)| value.as_str())
+    }
+}
+
+impl Validate for PickRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        if self.indices().next().is_none() {
+            return Err(AppError::Invalid { field: "item", reason: "tick at least one item to queue".to_string() });
+        }
+        if self.indices().any(|i| i.parse::<usize>().is_err()) {
+            return Err(AppError::Invalid { field: "item", reason: "must be an item's number".to_string() });
+        }
+        Ok(())
+    }
+}
+
+/// Queues the ticked videos of the post in the session the way subscriptions
+/// and batches download (BSDL_SUBSCRIPTION_PRESET and friends), photos as they are.
+pub async fn queue_picked(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(req): Form<PickRequest>,
+) -> Result<Response, AppError> {
+    let Some(post) = state.sessions.get(&sid).post else {
+        return Ok(Redirect::to(crate::start::ANALYZER).into_response());
+    };
+    state.jobs.accepting()?;
+    let picked: HashSet<usize> = req.indices().filter_map(|i| i.parse().ok()).collect();
+    let items: Vec<&Item> = post.items.iter().filter(|item| picked.contains(&item.index)).collect();
+    if items.is_empty() {
+        return Err(AppError::Invalid { field: "item", reason: "none of the ticked items are in this post".to_string() });
+    }
+
+    let recipe = Recipe::default();
+    let viewer = auth::viewer(&state, &sid);
+    for item in &items {
+        // A playlist entry goes by its own URL, a post's video by its position in the post
+        let url = item.url.clone().unwrap_or_else(|| post.url.clone());
+        let position = item.url.is_none().then_some(item.index);
+        let mut steps = match &item.photo {
+            Some(choice) => {
+                let selection = Box::new(Selection::photo(choice.clone()));
+                let template = state.config.subscription_template.clone();
+                vec![NewJob { step: Step::Download { url, selection, template, polite: false }, after: vec![] }]
+            }
+            None => recipe.steps(&state, url).await?,
+        };
+        for job in &mut steps {
+            if let Step::Download { selection, .. } = &mut job.step {
+                selection.item = position;
+                selection.workaround = post.workaround.clone();
+            }
+        }
+        state.jobs.submit_as(&item.title, viewer.as_deref(), steps)?;
+    }
+    state.sessions.update(&sid, |s| s.post = None);
+    let message = format!("Queued {} of the {} items from {}.", items.len(), post.items.len(), post.url);
+    page::flash(&state, &sid, FlashKind::Info, message);
+    Ok(Redirect::to("/jobs").into_response())
+}
+
+/// Queues the whole playlist or channel as one job: yt-dlp walks it in a
+/// single run, numbering the files in a folder of its own, and the job's
+/// progress says which video it's on.
+pub async fn queue_whole(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let Some(post) = state.sessions.get(&sid).post else {
+        return Ok(Redirect::to(crate::start::ANALYZER).into_response());
+    };
+    if !post.playlist() {
+        return Err(AppError::Invalid { field: "url", reason: "only a playlist or channel downloads whole".to_string() });
+    }
+    state.jobs.accepting()?;
+    // The steps after a download work from one file, which this doesn't make
+    let recipe = Recipe {
+        output_template: Some(jobs::PLAYLIST_TEMPLATE.to_string()),
+        postprocess: Some(String::new()),
+        ..Recipe::default()
+    };
+    let mut steps = recipe.steps(&state, post.url.clone()).await?;
+    for job in &mut steps {
+        if let Step::Download { selection, .. } = &mut job.step {
+            selection.playlist = true;
+            selection.workaround = post.workaround.clone();
+        }
+    }
+    state.jobs.submit_as(&post.url, auth::viewer(&state, &sid).as_deref(), steps)?;
+    state.sessions.update(&sid, |s| s.post = None);
+    let message = format!("Queued all {} items from {} as one download.", post.items.len(), post.url);
+    page::flash(&state, &sid, FlashKind::Info, message);
+    Ok(Redirect::to("/jobs").into_response())
+}
@@ -0,0 +1,220 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Json, Response},
+};
+use askama::Template;
+use serde_json::json;
+
+use crate::logging;
+
+// --- Data Structures ---
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("yt-dlp failed: {0}")]
+    YtDlpFailed(String),
+    #[error("could not parse yt-dlp output: {0}")]
+    ParseError(#[from] serde_json::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("database error: {0}")]
+    Db(#[from] crate::db::Error),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("not authorized: send an API token as \"Authorization: Bearer <token>\", or sign in at /login")]
+    Unauthorized,
+    #[error("{0}")]
+    BadRequest(String),
+    // A form post without this browser's CSRF token, or an admin page for someone who isn't one
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    TooLarge(String),
+    #[error("ffmpeg failed: {0}")]
+    FfmpegFailed(String),
+    #[error("secret store: {0}")]
+    Secret(String),
+    #[error("update: {0}")]
+    Update(String),
+    // The IPFS node refused or couldn't be reached
+    #[error("IPFS: {0}")]
+    Ipfs(String),
+    // LibreTranslate refused or couldn't be reached
+    #[error("translation: {0}")]
+    Translate(String),
+    // The site answered 429 Too Many Requests
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    // One address asked for too much too quickly
+    #[error("too many requests: {0}")]
+    TooManyRequests(String),
+    // Maintenance mode is on and the queue takes nothing new
+    #[error("{0}")]
+    Maintenance(String),
+    #[error("{field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            // Upstream trouble, not something the browser did
+            AppError::YtDlpFailed(_)
+            | AppError::ParseError(_)
+            | AppError::Update(_)
+            | AppError::RateLimited(_)
+            | AppError::Ipfs(_)
+            | AppError::Translate(_) => StatusCode::BAD_GATEWAY,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Invalid { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Maintenance(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Io(_) | AppError::Db(_) | AppError::FfmpegFailed(_) | AppError::Secret(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            AppError::YtDlpFailed(_) => "yt_dlp_failed",
+            AppError::ParseError(_) => "parse_error",
+            AppError::Io(_) => "io",
+            AppError::Db(_) => "db",
+            AppError::NotFound(_) => "not_found",
+            AppError::Unauthorized => "unauthorized",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::TooLarge(_) => "too_large",
+            AppError::FfmpegFailed(_) => "ffmpeg_failed",
+            AppError::Secret(_) => "secret",
+            AppError::Update(_) => "update",
+            AppError::Ipfs(_) => "ipfs",
+            AppError::Translate(_) => "translate",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::TooManyRequests(_) => "too_many_requests",
+            AppError::Maintenance(_) => "maintenance",
+            AppError::Invalid { .. } => "invalid",
+        }
+    }
+}
+
+// What the error middleware needs to re-render the body in the right format
+#[derive(Debug, Clone)]
+struct ErrorDetails {
+    status: StatusCode,
+    kind: &'static str,
+    message: String,
+    // Which form field was rejected, for validation errors
+    field: Option<&'static str>,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("Request failed: {}", self);
+        }
+
+        let field = match &self {
+            AppError::Invalid { field, .. } => Some(*field),
+            _ => None,
+        };
+        let details = ErrorDetails { status, kind: self.kind(), message: self.to_string(), field };
+        // Plain text until error_layer picks HTML or JSON for this route
+        let mut res = (status, details.message.clone()).into_response();
+        res.extensions_mut().insert(details);
+        res
+    }
+}
+
+// --- Rendering ---
+
+// Last resort when a template can't be rendered; deliberately template-free
+const MINIMAL_500: &str = "<!DOCTYPE html><html><head><title>500 Internal Server Error</title></head>\
+<body><h1>Something went wrong</h1><p>The page could not be rendered. The details are in the server log.</p>\
+<p><a href=\"/\">Back to the start</a></p></body></html>";
+
+fn minimal_500() -> Response {
+    (StatusCode::INTERNAL_SERVER_ERROR, Html(MINIMAL_500)).into_response()
+}
+
+/// Renders a page without ever panicking: a failed render is logged and the
+/// user gets a bare 500 page instead of a dropped connection.
+pub fn render<T: Template>(page: T) -> Response {
+    match page.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            tracing::warn!("Rendering {} failed: {}", std::any::type_name::<T>(), e);
+            minimal_500()
+        }
+    }
+}
+
+/// `render` with a status other than 200, for pages that stand in for an
+/// error but keep their own form (the start page's failed analysis).
+pub fn render_status<T: Template>(status: StatusCode, page: T) -> Response {
+    let mut res = render(page);
+    if res.status().is_success() {
+        *res.status_mut() = status;
+    }
+    res
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    status: u16,
+    reason: String,
+    message: String,
+}
+
+pub fn wants_json(req: &Request) -> bool {
+    if req.uri().path().starts_with("/api/") {
+        return true;
+    }
+    let accept = req.headers().get(header::ACCEPT).and_then(|v| v.to_str().ok()).unwrap_or("");
+    accept.contains("application/json") && !accept.contains("text/html")
+}
+
+/// Turns an `AppError` response into the error page, or JSON for API-style requests.
+pub async fn error_layer(req: Request, next: Next) -> Response {
+    let json = wants_json(&req);
+    let res = next.run(req).await;
+
+    let Some(details) = res.extensions().get::<ErrorDetails>().cloned() else {
+        return res;
+    };
+
+    if json {
+        let mut body = json!({
+            "error": details.kind,
+            "message": details.message,
+            "status": details.status.as_u16(),
+        });
+        if let Some(field) = details.field {
+            body["field"] = json!(field);
+        }
+        if let Some(id) = logging::trace_id() {
+            body["request_id"] = json!(id);
+        }
+        return (details.status, Json(body)).into_response();
+    }
+
+    let page = ErrorTemplate {
+        status: details.status.as_u16(),
+        reason: details.status.canonical_reason().unwrap_or("Error").to_string(),
+        message: details.message,
+    };
+    let mut page = render(page);
+    if page.status().is_success() {
+        *page.status_mut() = details.status;
+    }
+    page
+}
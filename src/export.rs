@@ -0,0 +1,120 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::sanitize;
+use crate::session::SessionId;
+use crate::{AppState, YtDlpFormat};
+
+// Same order as YtDlpFormat
+const CSV_COLUMNS: &[&str] = &[
+    "format_id",
+    "ext",
+    "width",
+    "height",
+    "acodec",
+    "vcodec",
+    "filesize",
+    "filesize_approx",
+    "language",
+    "format_note",
+    "fps",
+    "tbr",
+    "vbr",
+    "abr",
+    "audio_channels",
+    "dynamic_range",
+    "protocol",
+];
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default, rename = "as")]
+    kind: String,
+}
+
+// Fields stay in this order, which json! wouldn't keep
+#[derive(Serialize)]
+struct FormatTable<'a> {
+    url: &'a str,
+    title: &'a str,
+    formats: &'a [YtDlpFormat],
+}
+
+// --- CSV ---
+
+// Quoted when it has to be. Text from a remote site starting with = + - @
+// would run as a spreadsheet formula, so it gets a leading apostrophe.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn csv(formats: &[YtDlpFormat]) -> String {
+    let text = |v: &Option<String>| v.as_deref().map(csv_field).unwrap_or_default();
+    let number = |v: Option<u64>| v.map(|n| n.to_string()).unwrap_or_default();
+    let mut out = CSV_COLUMNS.join(",");
+    out.push_str("\r\n");
+    for f in formats {
+        let row = [
+            csv_field(&f.format_id),
+            text(&f.ext),
+            number(f.width.map(u64::from)),
+            number(f.height.map(u64::from)),
+            text(&f.acodec),
+            text(&f.vcodec),
+            number(f.filesize),
+            number(f.filesize_approx),
+            text(&f.language),
+            text(&f.format_note),
+            f.fps.map(|fps| fps.to_string()).unwrap_or_default(),
+            f.tbr.map(|tbr| tbr.to_string()).unwrap_or_default(),
+            f.vbr.map(|vbr| vbr.to_string()).unwrap_or_default(),
+            f.abr.map(|abr| abr.to_string()).unwrap_or_default(),
+            number(f.audio_channels.map(u64::from)),
+            text(&f.dynamic_range),
+            text(&f.protocol),
+        ];
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// --- Handlers ---
+
+/// The analyzed URL's whole format table, `?as=json` (with the URL and title)
+/// or `?as=csv`, as a download.
+pub async fn download_formats(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let Some(wizard) = state.sessions.get(&sid).wizard else {
+        return Ok(Redirect::to(crate::start::ANALYZER).into_response());
+    };
+    let (body, content_type, ext) = match query.kind.as_str() {
+        "json" => {
+            let table = FormatTable { url: &wizard.url, title: &wizard.title, formats: &wizard.source_formats };
+            (serde_json::to_string_pretty(&table)?, "application/json", "json")
+        }
+        "csv" => (csv(&wizard.source_formats), "text/csv; charset=utf-8", "csv"),
+        _ => return Err(AppError::Invalid { field: "as", reason: "expected json or csv".to_string() }),
+    };
+
+    // Plain ASCII fallback, then the title for browsers that read filename*
+    let name = format!("{} formats.{}", wizard.title, ext);
+    let disposition =
+        format!("attachment; filename=\"formats.{}\"; filename*=UTF-8''{}", ext, sanitize::query_value(&name));
+    Ok(([(header::CONTENT_TYPE, content_type.to_string()), (header::CONTENT_DISPOSITION, disposition)], body)
+        .into_response())
+}
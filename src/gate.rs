@@ -0,0 +1,224 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::analysis;
+use crate::config::Config;
+use crate::error::{render, AppError};
+use crate::geo;
+use crate::secrets::{self, COOKIES};
+use crate::server::ClientGone;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+/// What `--cookies-from-browser` can read; the browser has to be on the
+/// machine running the app.
+pub const BROWSERS: &[&str] = &["firefox", "chrome", "chromium", "edge", "brave", "opera", "vivaldi", "safari"];
+
+/// YouTube player clients worth trying instead of the default ones
+pub const CLIENTS: &[(&str, &str)] = &[
+    ("web_embedded", "the embedded player, which still plays many age-restricted videos"),
+    ("tv", "the smart TV app"),
+    ("mweb", "the mobile site"),
+];
+
+// --- Detection ---
+
+/// Why yt-dlp couldn't get at a video on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gate {
+    AgeRestricted,
+    LoginRequired,
+}
+
+// Lower-cased pieces of yt-dlp's error messages. The age checks go first:
+// "Sign in to confirm your age" also reads as a login prompt.
+const AGE_SIGNS: &[&str] =
+    &["confirm your age", "age-restricted", "age restricted", "inappropriate for some users", "age verification"];
+const LOGIN_SIGNS: &[&str] = &[
+    "sign in to confirm you",
+    "sign in if you've been granted access",
+    "login required",
+    "requires authentication",
+    "need to log in",
+    "members-only",
+    "join this channel",
+    "use --cookies",
+];
+
+impl Gate {
+    pub fn detect(stderr: &str) -> Option<Gate> {
+        let stderr = stderr.to_lowercase();
+        if AGE_SIGNS.iter().any(|s| stderr.contains(s)) {
+            Some(Gate::AgeRestricted)
+        } else if LOGIN_SIGNS.iter().any(|s| stderr.contains(s)) {
+            Some(Gate::LoginRequired)
+        } else {
+            None
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Gate::AgeRestricted => "This video is age-restricted",
+            Gate::LoginRequired => "This video needs a login",
+        }
+    }
+
+    /// Added to a failed job's error, which has no room for the whole guide
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Gate::AgeRestricted => "age-restricted; analyze the URL again for ways around it",
+            Gate::LoginRequired => "needs a login; analyze the URL again for ways around it",
+        }
+    }
+}
+
+// --- Workarounds ---
+
+/// A way past a gate that the analysis and the download both use.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Workaround {
+    /// Cookies read straight from a browser profile
+    Browser(String),
+    /// A different YouTube player client
+    Client(String),
+    /// An address in another country, by its two-letter code
+    Country(String),
+    /// BSDL_GEO_PROXY
+    Proxy,
+}
+
+impl Workaround {
+    /// From the analyze form's `browser`, `client`, `country` and `proxy`
+    /// fields, once validated
+    pub fn from_form(browser: &str, client: &str, country: &str, proxy: bool) -> Option<Workaround> {
+        if !browser.is_empty() {
+            Some(Workaround::Browser(browser.to_string()))
+        } else if !client.is_empty() {
+            Some(Workaround::Client(client.to_string()))
+        } else if proxy {
+            Some(Workaround::Proxy)
+        } else if !country.is_empty() {
+            Some(Workaround::Country(country.to_ascii_uppercase()))
+        } else {
+            None
+        }
+    }
+
+    pub fn ytdlp_args(&self, config: &Config) -> Vec<String> {
+        match self {
+            Workaround::Browser(b) => vec!["--cookies-from-browser".to_string(), b.clone()],
+            Workaround::Client(c) => vec!["--extractor-args".to_string(), format!("youtube:player_client={}", c)],
+            Workaround::Country(c) => vec!["--xff".to_string(), c.clone()],
+            // Queued before the proxy was taken out of the settings: goes direct
+            Workaround::Proxy => config.geo_proxy.clone().map(|p| vec!["--proxy".to_string(), p]).unwrap_or_default(),
+        }
+    }
+
+    /// Browser cookies stand in for the stored cookies files
+    pub fn replaces_cookies(&self) -> bool {
+        matches!(self, Workaround::Browser(_))
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Workaround::Browser(b) => format!("cookies from {}", b),
+            Workaround::Client(c) => format!("the {} client", c),
+            Workaround::Country(c) => format!("an address in {}", geo::country_name(c)),
+            Workaround::Proxy => "the proxy".to_string(),
+        }
+    }
+}
+
+/// The `browser`, `client` and `country` fields the analyze forms share;
+/// `check_proxy` takes `proxy`, which needs the config.
+pub fn validate_fields(browser: &str, client: &str, country: &str) -> Result<(), AppError> {
+    if !browser.is_empty() {
+        validate::one_of("browser", browser, BROWSERS)?;
+    }
+    if !client.is_empty() && !CLIENTS.iter().any(|(c, _)| *c == client) {
+        let names: Vec<&str> = CLIENTS.iter().map(|(c, _)| *c).collect();
+        return Err(AppError::Invalid { field: "client", reason: format!("must be one of {}", names.join(", ")) });
+    }
+    if !country.is_empty() {
+        geo::validate_country("country", country)?;
+    }
+    Ok(())
+}
+
+/// Refuses the `proxy` field while no proxy is set up (BSDL_GEO_PROXY).
+pub fn check_proxy(config: &Config, proxy: bool) -> Result<(), AppError> {
+    if proxy && config.geo_proxy.is_none() {
+        return Err(AppError::Invalid { field: "proxy", reason: "no proxy is set up (BSDL_GEO_PROXY)".to_string() });
+    }
+    Ok(())
+}
+
+// --- Page ---
+
+#[derive(Template)]
+#[template(path = "gate.html")]
+struct GateTemplate {
+    url: String,
+    gate: Gate,
+    /// yt-dlp's own words
+    detail: String,
+    /// Player clients only mean something to the YouTube extractor
+    youtube: bool,
+    has_cookies: bool,
+    tried: Option<String>,
+    browsers: &'static [&'static str],
+    clients: &'static [(&'static str, &'static str)],
+}
+
+/// The guide shown instead of the plain error when `gate` stopped an analysis.
+pub async fn page(state: &AppState, url: &str, gate: Gate, detail: String, tried: Option<&Workaround>) -> Response {
+    let has_cookies = state.secrets.cookie_accounts().await.map(|a| !a.is_empty()).unwrap_or(false);
+    render(GateTemplate {
+        url: url.to_string(),
+        gate,
+        detail,
+        youtube: analysis::is_youtube(url),
+        has_cookies,
+        tried: tried.map(Workaround::label),
+        browsers: BROWSERS,
+        clients: CLIENTS,
+    })
+}
+
+// --- Handlers ---
+
+#[derive(Deserialize)]
+pub struct CookiesForm {
+    url: String,
+    cookies: String,
+}
+
+impl Validate for CookiesForm {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::url("url", &self.url)?;
+        validate::max_len("cookies", &self.cookies, secrets::MAX_SECRET_BYTES)
+    }
+}
+
+/// Saves a pasted cookies.txt as the `cookies` secret and analyzes the URL again with it.
+pub async fn save_cookies(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Extension(gone): Extension<ClientGone>,
+    Form(form): Form<CookiesForm>,
+) -> Response {
+    if form.cookies.trim().is_empty() {
+        return AppError::Invalid { field: "cookies", reason: "paste the exported cookies.txt".to_string() }.into_response();
+    }
+    if let Err(e) = state.secrets.set(COOKIES, &form.cookies).await {
+        return e.into_response();
+    }
+    crate::analyze(&state, &sid, &gone, form.url.trim().to_string(), None, None).await
+}
@@ -0,0 +1,195 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use futures_util::StreamExt;
+use std::collections::BTreeSet;
+use tokio::process::Command;
+
+use crate::error::AppError;
+use crate::gate::Workaround;
+use crate::page::{self, FlashKind};
+use crate::server::ClientGone;
+use crate::session::{SessionId, Wizard};
+use crate::sizes::{self, Located};
+use crate::AppState;
+
+// Formats the site won't serve to this address, while others of the same
+// video play. yt-dlp notes some as geo-restricted itself; the rest only show
+// when fetched, so the format table can check each format's URL and mark
+// those answered with 403 or 451. Marked rows offer the ways round it inline:
+// asking as if from another country (yt-dlp fakes an X-Forwarded-For address,
+// which some sites go by) or through BSDL_GEO_PROXY. Either is a Workaround,
+// so the download goes the same way.
+
+// Lower-cased pieces of yt-dlp's format notes
+const SIGNS: &[&str] = &[
+    "geo restricted",
+    "geo-restricted",
+    "georestricted",
+    "geo blocked",
+    "geo-blocked",
+    "geoblocked",
+    "not available in your country",
+    "not available in your region",
+];
+
+/// Countries offered to ask from; the API takes any two-letter code.
+pub const COUNTRIES: &[(&str, &str)] = &[
+    ("US", "United States"),
+    ("GB", "United Kingdom"),
+    ("CA", "Canada"),
+    ("IE", "Ireland"),
+    ("DE", "Germany"),
+    ("FR", "France"),
+    ("NL", "Netherlands"),
+    ("SE", "Sweden"),
+    ("JP", "Japan"),
+    ("KR", "South Korea"),
+    ("AU", "Australia"),
+    ("BR", "Brazil"),
+    ("IN", "India"),
+];
+
+// What the check's fetches are answered with when the site keeps a format from here
+const REFUSED: &[u16] = &[403, 451];
+
+// --- Data Structures ---
+
+/// Which formats of the wizard's analysis the site turned away.
+#[derive(Debug, Clone, Default)]
+pub struct Check {
+    /// The analysis it belongs to
+    pub url: String,
+    pub workaround: Option<Workaround>,
+    /// Formats with a URL to fetch
+    pub checked: usize,
+    pub blocked: BTreeSet<String>,
+}
+
+impl Check {
+    pub fn of(&self, w: &Wizard) -> bool {
+        self.url == w.url && self.workaround == w.workaround
+    }
+}
+
+/// Whether yt-dlp's note on a format says it's geo-restricted.
+pub fn noted(format_note: Option<&str>) -> bool {
+    format_note.is_some_and(|note| {
+        let lower = note.to_lowercase();
+        SIGNS.iter().any(|s| lower.contains(s))
+    })
+}
+
+pub fn country_name(code: &str) -> &str {
+    COUNTRIES.iter().find(|(c, _)| *c == code).map_or(code, |(_, name)| *name)
+}
+
+/// A two-letter country code, like US.
+pub fn validate_country(field: &'static str, value: &str) -> Result<(), AppError> {
+    if value.len() != 2 || !value.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(AppError::Invalid { field, reason: "must be a two-letter country code, like US".to_string() });
+    }
+    Ok(())
+}
+
+// --- Checking ---
+
+// The HTTP status of the format's first byte, fetched the way yt-dlp would
+async fn status(state: &AppState, w: &Wizard, located: &Located) -> Option<u16> {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-s", "-o", "/dev/null", "-r", "0-0", "-m", "20", "-w", "%{http_code}"]);
+    if !located.user_agent.is_empty() {
+        cmd.arg("-A").arg(&located.user_agent);
+    }
+    if !located.referer.is_empty() {
+        cmd.arg("-e").arg(&located.referer);
+    }
+    if !located.forwarded_for.is_empty() {
+        cmd.arg("-H").arg(format!("X-Forwarded-For: {}", located.forwarded_for));
+    }
+    if let (Some(Workaround::Proxy), Some(proxy)) = (&w.workaround, &state.config.geo_proxy) {
+        cmd.arg("-x").arg(proxy);
+    }
+    cmd.arg(&located.url);
+    match state.procs.output("geo check", cmd).await {
+        // 000 when there was no answer at all, which says nothing about the country
+        Ok(out) => String::from_utf8_lossy(&out.stdout).trim().parse().ok().filter(|code| *code > 0),
+        Err(e) => {
+            tracing::warn!("Could not run curl for format {}: {}", located.id, e);
+            None
+        }
+    }
+}
+
+async fn check(state: &AppState, w: &Wizard, ids: &[String]) -> Check {
+    let (cmd, _cookies) = sizes::locate(state, w, ids).await;
+    let mut located = Vec::new();
+    let listed = state
+        .procs
+        .lines("geo check", cmd, |line| {
+            if let Some(l) = sizes::parse_located(line).filter(|l| ids.contains(&l.id) && l.url.starts_with("http")) {
+                located.push(l);
+            }
+        })
+        .await;
+    match listed {
+        Ok((status, _)) if !status.success() => tracing::warn!("Checking formats of {}: yt-dlp exited with {}", w.url, status),
+        Err(e) => tracing::warn!("Checking formats of {}: {}", w.url, e),
+        Ok(_) => {}
+    }
+
+    let checked = located.len();
+    let mut fetches = futures_util::stream::iter(located)
+        .map(|located| async move { (status(state, w, &located).await, located.id) })
+        .buffer_unordered(state.config.size_probes.max(1));
+    let mut blocked = BTreeSet::new();
+    while let Some((code, id)) = fetches.next().await {
+        if code.is_some_and(|code| REFUSED.contains(&code)) {
+            blocked.insert(id);
+        }
+    }
+    Check { url: w.url.clone(), workaround: w.workaround.clone(), checked, blocked }
+}
+
+// --- Handlers ---
+
+/// Fetches the first byte of every format from here, marks the ones the site
+/// turns away and goes back to the format table.
+pub async fn check_formats(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Extension(gone): Extension<ClientGone>,
+) -> Result<Response, AppError> {
+    let session = state.sessions.get(&sid);
+    let Some(w) = session.wizard else {
+        return Ok(Redirect::to(crate::start::ANALYZER).into_response());
+    };
+    // Once per analysis
+    if session.geo.as_ref().is_some_and(|c| c.of(&w)) || w.formats.is_empty() {
+        return Ok(Redirect::to("/options").into_response());
+    }
+    let ids: Vec<String> = w.formats.iter().map(|f| f.id.clone()).collect();
+    tracing::info!("Checking {} format(s) of {} for geo-blocking", ids.len(), w.url);
+    let found = tokio::select! {
+        found = check(&state, &w, &ids) => found,
+        _ = gone.cancelled() => {
+            tracing::info!("Format check of {} abandoned by client", w.url);
+            return Ok(Redirect::to("/options").into_response());
+        }
+    };
+    let message = match (found.checked, found.blocked.len()) {
+        (0, _) => "yt-dlp gave no format URLs to check.".to_string(),
+        (n, 0) => format!("All {} format{} checked play from here.", n, if n == 1 { "" } else { "s" }),
+        (n, blocked) => format!(
+            "{} of the {} formats checked {} geo-blocked from here; marked below.",
+            blocked,
+            n,
+            if blocked == 1 { "is" } else { "are" }
+        ),
+    };
+    page::flash(&state, &sid, FlashKind::Info, message);
+    state.sessions.update(&sid, |s| s.geo = Some(found));
+    Ok(Redirect::to("/options").into_response())
+}
@@ -0,0 +1,117 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::db::params;
+use crate::diagnostics::{self, Status};
+use crate::pinning;
+use crate::platform;
+use crate::AppState;
+
+// Probes for Docker, Kubernetes and the like: JSON, no sign-in, and nothing
+// run, so they're cheap enough to ask every few seconds. /healthz checks what
+// downloads need on disk; /readyz the database as well, and turns traffic
+// away while shutting down. Both answer 503 when a check fails.
+
+// --- Data Structures ---
+
+#[derive(Debug, Serialize)]
+pub struct Probe {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    /// "ok", or "fail" when any check did
+    pub status: &'static str,
+    pub checks: Vec<Probe>,
+}
+
+// --- Checks ---
+
+// Where `program` would be run from: itself if it's a path, else the first
+// match on the PATH
+fn find(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return Some(program.to_path_buf());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).map(|dir| dir.join(program)).find(|p| p.is_file())
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &std::fs::Metadata) -> bool {
+    true
+}
+
+fn program(name: &'static str, program: PathBuf) -> Probe {
+    let Some(path) = find(&program) else {
+        return Probe { name, ok: false, detail: format!("{} not found on the PATH", program.display()) };
+    };
+    match std::fs::metadata(&path) {
+        Ok(meta) if meta.is_file() && is_executable(&meta) => Probe { name, ok: true, detail: path.display().to_string() },
+        Ok(_) => Probe { name, ok: false, detail: format!("{} is not an executable file", path.display()) },
+        Err(e) => Probe { name, ok: false, detail: format!("{}: {}", path.display(), e) },
+    }
+}
+
+async fn downloads(config: &Config) -> Probe {
+    let check = diagnostics::writable(PathBuf::from(&config.downloads_dir)).await;
+    Probe { name: "downloads", ok: check.status == Status::Pass, detail: format!("{}: {}", config.downloads_dir, check.detail) }
+}
+
+async fn database(state: &AppState) -> Probe {
+    match state.db.call(|conn| conn.query_row("SELECT 1", params![], |r| r.get::<i64>(0))).await {
+        Ok(_) => Probe { name: "database", ok: true, detail: "answers".to_string() },
+        Err(e) => Probe { name: "database", ok: false, detail: e.to_string() },
+    }
+}
+
+async fn dependencies(config: &Config) -> Vec<Probe> {
+    let ffmpeg = if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" };
+    let mut checks = vec![downloads(config).await, program("yt-dlp", platform::ytdlp(config))];
+    checks.extend(pinning::nightly(config).map(|path| program("yt-dlp nightly", path)));
+    checks.push(program("ffmpeg", PathBuf::from(ffmpeg)));
+    checks
+}
+
+fn queue(state: &AppState) -> Probe {
+    match state.jobs.draining() {
+        true => Probe { name: "queue", ok: false, detail: "shutting down; new jobs are turned away".to_string() },
+        false => Probe { name: "queue", ok: true, detail: "taking jobs".to_string() },
+    }
+}
+
+fn answer(checks: Vec<Probe>) -> Response {
+    let ok = checks.iter().all(|c| c.ok);
+    let code = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(Health { status: if ok { "ok" } else { "fail" }, checks })).into_response()
+}
+
+// --- Handlers ---
+
+/// The downloads folder is writable and yt-dlp and ffmpeg are there to run.
+pub async fn healthz(State(state): State<AppState>) -> Response {
+    answer(dependencies(&state.config).await)
+}
+
+/// As /healthz, and the database answers and the queue takes jobs too.
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let mut checks = dependencies(&state.config).await;
+    checks.push(database(&state).await);
+    checks.push(queue(&state));
+    answer(checks)
+}
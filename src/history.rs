@@ -0,0 +1,495 @@
+use askama::Template;
+use axum::{
+    extract::{Path as UrlPath, Query, State},
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::auth;
+use crate::charts::{self, Bar, Columns};
+use crate::clock::Stamp;
+use crate::db::{self, params, Db, OptionalExtension, Storage};
+use crate::error::{render, AppError};
+use crate::logging;
+use crate::session::{Choice, Selection, SessionId};
+use crate::AppState;
+
+// Rows the page and /api/history show, newest first
+const SHOWN: usize = 500;
+// Finished downloads the stats page is worked out from, newest first
+const MEASURED: usize = 1000;
+
+pub const KINDS: &[&str] = &["analyze", "download"];
+
+/// One analysis or download attempt, as it went.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub id: i64,
+    /// "analyze" or "download"
+    pub kind: String,
+    pub url: String,
+    pub title: String,
+    /// What was asked for: a format id or profile, and the container
+    pub format: Option<String>,
+    pub file_path: Option<String>,
+    /// Running, Done, Failed, Cancelled, Paused, Queued (held back after a 429), Abandoned or Interrupted
+    pub status: String,
+    pub error: Option<String>,
+    pub started: Stamp,
+    pub finished: Option<Stamp>,
+    /// The ID the request or job was logged with
+    #[serde(rename = "request_id")]
+    pub trace: Option<String>,
+}
+
+impl Entry {
+    /// Badge class for the list
+    pub fn css(&self) -> &'static str {
+        match self.status.as_str() {
+            "Done" => "check-pass",
+            "Failed" | "Interrupted" => "check-fail",
+            _ => "check-warn",
+        }
+    }
+}
+
+const COLUMNS: &str = "id, kind, url, title, format, file_path, status, error, started_at, finished_at, trace";
+
+/// One step of a download: queued, started, postprocessing, then how it ended.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    pub at: Stamp,
+}
+
+impl Event {
+    pub fn label(&self) -> String {
+        match self.name.as_str() {
+            "postprocessing" => "Post-processing".to_string(),
+            name => {
+                let mut chars = name.chars();
+                chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Seconds a download spent in each stage, where its events tell.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    /// In the queue before it started
+    pub wait: Option<i64>,
+    /// yt-dlp fetching, up to post-processing or the end
+    pub download: Option<i64>,
+    /// Merging, converting and the rest after the download
+    pub postprocess: Option<i64>,
+    pub total: Option<i64>,
+}
+
+impl Timings {
+    fn from_events(events: &[Event]) -> Timings {
+        let at = |name: &str| events.iter().find(|e| e.name == name).map(|e| e.at.0);
+        let (queued, started, post) = (at("queued"), at("started"), at("postprocessing"));
+        // How it ended comes after it started; a 429 can queue it again
+        let ended = events.iter().skip_while(|e| e.name != "started").skip(1).filter(|e| e.name != "postprocessing").last();
+        let ended = ended.map(|e| e.at.0);
+        let span = |from: Option<i64>, to: Option<i64>| Some(to? - from?);
+        Timings {
+            wait: span(queued, started),
+            download: span(started, post.or(ended)),
+            postprocess: span(post, post.and(ended)),
+            total: span(queued.or(started), ended),
+        }
+    }
+
+    /// Each stage's name and how long it took, for a table.
+    pub fn stages(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Waiting in the queue", took_opt(self.wait)),
+            ("Downloading", took_opt(self.download)),
+            ("Post-processing", took_opt(self.postprocess)),
+            ("Total", took_opt(self.total)),
+        ]
+    }
+}
+
+/// "42s", "3m 05s" or "1h 02m".
+pub fn took(secs: i64) -> String {
+    match secs.max(0) {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m {:02}s", s / 60, s % 60),
+        s => format!("{}h {:02}m", s / 3600, s / 60 % 60),
+    }
+}
+
+fn took_opt(secs: Option<i64>) -> String {
+    secs.map(took).unwrap_or_else(|| "-".to_string())
+}
+
+/// The host a URL is on, without "www." or a port, e.g. "youtube.com".
+pub fn site(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    let host = host.split(':').next().unwrap_or_default().to_lowercase();
+    host.strip_prefix("www.").map(str::to_string).unwrap_or(host)
+}
+
+fn from_row(r: &db::Row) -> db::Result<Entry> {
+    Ok(Entry {
+        id: r.get(0)?,
+        kind: r.get(1)?,
+        url: r.get(2)?,
+        title: r.get(3)?,
+        format: r.get(4)?,
+        file_path: r.get(5)?,
+        status: r.get(6)?,
+        error: r.get(7)?,
+        started: Stamp(r.get(8)?),
+        finished: r.get::<Option<i64>>(9)?.map(Stamp),
+        trace: r.get(10)?,
+    })
+}
+
+/// How a download's selection reads in the history, e.g. "137 as mkv".
+pub fn format_of(sel: &Selection) -> String {
+    let asked = match &sel.choice {
+        Choice::Format(f) => f.id.clone(),
+        Choice::Profile(p) => p.name.clone(),
+        Choice::BestVideo => "best video".to_string(),
+        Choice::BestAudio => "best audio".to_string(),
+        Choice::Image { .. } => return "photo".to_string(),
+    };
+    let into = if sel.audio_only() { &sel.audio_format } else { &sel.container };
+    if into.is_empty() {
+        asked
+    } else {
+        format!("{} as {}", asked, into)
+    }
+}
+
+// --- Recording ---
+
+// The history is a record, never a reason for the work itself to fail
+fn logged<T: Default>(what: &str, result: Result<T, AppError>) -> T {
+    result.unwrap_or_else(|e| {
+        tracing::warn!("Could not record {} in the history: {}", what, e);
+        T::default()
+    })
+}
+
+/// Records a finished analysis: `Done`, `Failed` or `Abandoned`, with what
+/// went wrong if it failed. `owner` is the signed-in user who asked for it.
+pub async fn analyzed(db: &Db, owner: Option<&str>, url: &str, title: &str, status: &'static str, error: Option<String>) {
+    let (url, title, now, trace) = (url.to_string(), title.to_string(), db::now(), logging::trace_id());
+    let owner = owner.map(str::to_string);
+    let result = db
+        .call(move |conn| {
+            conn.execute(
+                "INSERT INTO history (kind, url, title, status, error, started_at, finished_at, trace, owner)
+                 VALUES ('analyze', ?1, ?2, ?3, ?4, ?5, ?5, ?6, ?7)",
+                params![url, title, status, error, now, trace, owner],
+            )
+        })
+        .await;
+    logged("an analysis", result.map(|_| ()));
+}
+
+fn event(conn: &mut dyn Storage, id: i64, name: &str, at: i64) -> db::Result<()> {
+    conn.execute("INSERT INTO history_events (history_id, event, at) VALUES (?1, ?2, ?3)", params![id, name, at]).map(|_| ())
+}
+
+/// Records a download as running, after waiting in the queue since `queued`,
+/// for the user who queued it. Returns its entry for `finished`.
+pub async fn started(db: &Db, url: &str, title: &str, format: &str, owner: Option<&str>, queued: i64) -> Option<i64> {
+    let (url, title, format, now, trace) = (url.to_string(), title.to_string(), format.to_string(), db::now(), logging::trace_id());
+    let owner = owner.map(str::to_string);
+    let result = db
+        .call(move |conn| {
+            let mut tx = conn.transaction()?;
+            let id: i64 = tx.query_row(
+                "INSERT INTO history (kind, url, title, format, status, started_at, trace, owner)
+                 VALUES ('download', ?1, ?2, ?3, 'Running', ?4, ?5, ?6) RETURNING id",
+                params![url, title, format, now, trace, owner],
+                |r| r.get(0),
+            )?;
+            event(&mut *tx, id, "queued", queued.min(now))?;
+            event(&mut *tx, id, "started", now)?;
+            tx.commit()?;
+            Ok(Some(id))
+        })
+        .await;
+    logged("a download", result)
+}
+
+/// Records how a download ended, and when its post-processing began if it got that far.
+pub async fn finished(
+    db: &Db,
+    id: i64,
+    status: &'static str,
+    file: Option<&Path>,
+    error: Option<String>,
+    postprocessing: Option<i64>,
+) {
+    let (file, now) = (file.map(|f| f.to_string_lossy().into_owned()), db::now());
+    let result = db
+        .call(move |conn| {
+            let mut tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE history SET status = ?2, file_path = ?3, error = ?4, finished_at = ?5 WHERE id = ?1",
+                params![id, status, file, error, now],
+            )?;
+            if let Some(at) = postprocessing {
+                event(&mut *tx, id, "postprocessing", at.min(now))?;
+            }
+            event(&mut *tx, id, &status.to_lowercase(), now)?;
+            tx.commit()
+        })
+        .await;
+    logged("a finished download", result);
+}
+
+/// Downloads still marked running were cut off by the last shutdown or crash.
+pub async fn interrupted(db: &Db) -> Result<usize, AppError> {
+    db.call(|conn| {
+        conn.execute("UPDATE history SET status = 'Interrupted', finished_at = started_at WHERE status = 'Running'", params![])
+    })
+    .await
+}
+
+// A signed-in user sees what they asked for; without one (no users, or an
+// API token) everything shows
+const VISIBLE: &str = "(CAST(?2 AS TEXT) IS NULL OR owner = ?2)";
+
+/// The entry, unless `viewer` doesn't get to see it.
+pub async fn get(db: &Db, id: i64, viewer: Option<&str>) -> Result<Option<Entry>, AppError> {
+    let viewer = viewer.map(str::to_string);
+    db.call(move |conn| {
+        conn.query_row(&format!("SELECT {} FROM history WHERE id = ?1 AND {}", COLUMNS, VISIBLE), params![id, viewer], from_row)
+            .optional()
+    })
+    .await
+}
+
+/// The newest SHOWN entries `viewer` gets to see, of one kind or all.
+pub async fn list(db: &Db, kind: Option<String>, viewer: Option<&str>) -> Result<Vec<Entry>, AppError> {
+    let viewer = viewer.map(str::to_string);
+    db.call(move |conn| {
+        conn.query_map(
+            &format!("SELECT {} FROM history WHERE (CAST(?1 AS TEXT) IS NULL OR kind = ?1) AND {} ORDER BY id DESC LIMIT {}", COLUMNS, VISIBLE, SHOWN),
+            params![kind, viewer],
+            from_row,
+        )
+    })
+    .await
+}
+
+pub async fn events(db: &Db, id: i64) -> Result<Vec<Event>, AppError> {
+    db.call(move |conn| {
+        conn.query_map("SELECT event, at FROM history_events WHERE history_id = ?1 ORDER BY at, rowid", params![id], |r| {
+            Ok(Event { name: r.get(0)?, at: Stamp(r.get(1)?) })
+        })
+    })
+    .await
+}
+
+// --- Stats ---
+
+/// How downloads from one site (or all of them) have gone.
+#[derive(Debug, Clone, Default)]
+pub struct SiteStats {
+    pub site: String,
+    pub downloads: usize,
+    /// Failed or cut off by a restart
+    pub failed: usize,
+    waits: Vec<i64>,
+    downloading: Vec<i64>,
+    postprocessing: Vec<i64>,
+}
+
+fn average(secs: &[i64]) -> String {
+    match secs.len() {
+        0 => "-".to_string(),
+        n => took(secs.iter().sum::<i64>() / n as i64),
+    }
+}
+
+impl SiteStats {
+    fn add(&mut self, status: &str, t: Timings) {
+        self.downloads += 1;
+        if matches!(status, "Failed" | "Interrupted") {
+            self.failed += 1;
+        }
+        self.waits.extend(t.wait);
+        // Only finished ones say how long the whole thing takes
+        if status == "Done" {
+            self.downloading.extend(t.download);
+            self.postprocessing.extend(t.postprocess);
+        }
+    }
+
+    pub fn wait(&self) -> String {
+        average(&self.waits)
+    }
+
+    pub fn download(&self) -> String {
+        average(&self.downloading)
+    }
+
+    pub fn postprocess(&self) -> String {
+        average(&self.postprocessing)
+    }
+
+    pub fn slowest(&self) -> String {
+        took_opt(self.downloading.iter().max().copied())
+    }
+}
+
+/// The last MEASURED finished downloads per site, busiest first, and all of them together.
+pub async fn stats(db: &Db) -> Result<(Vec<SiteStats>, SiteStats), AppError> {
+    let rows = db
+        .call(|conn| {
+            let sql = format!(
+                "SELECT h.id, h.url, h.status, e.event, e.at FROM (
+                     SELECT id, url, status FROM history
+                     WHERE kind = 'download' AND finished_at IS NOT NULL ORDER BY id DESC LIMIT {}
+                 ) h LEFT JOIN history_events e ON e.history_id = h.id ORDER BY h.id, e.at, e.rowid",
+                MEASURED
+            );
+            conn.query_map(&sql, params![], |r| {
+                let event = match r.get::<Option<String>>(3)? {
+                    Some(name) => Some(Event { name, at: Stamp(r.get(4)?) }),
+                    None => None,
+                };
+                Ok((r.get::<i64>(0)?, r.get::<String>(1)?, r.get::<String>(2)?, event))
+            })
+        })
+        .await?;
+
+    let mut downloads: Vec<(String, String, Vec<Event>)> = Vec::new();
+    let mut last = None;
+    for (id, url, status, event) in rows {
+        if last != Some(id) {
+            downloads.push((url, status, Vec::new()));
+            last = Some(id);
+        }
+        if let (Some(e), Some(d)) = (event, downloads.last_mut()) {
+            d.2.push(e);
+        }
+    }
+
+    let mut sites: BTreeMap<String, SiteStats> = BTreeMap::new();
+    let mut all = SiteStats { site: "All sites".to_string(), ..SiteStats::default() };
+    for (url, status, events) in downloads {
+        let t = Timings::from_events(&events);
+        let site = site(&url);
+        sites.entry(site.clone()).or_insert_with(|| SiteStats { site, ..SiteStats::default() }).add(&status, t);
+        all.add(&status, t);
+    }
+    let mut sites: Vec<SiteStats> = sites.into_values().collect();
+    sites.sort_by(|a, b| b.downloads.cmp(&a.downloads).then_with(|| a.site.cmp(&b.site)));
+    Ok((sites, all))
+}
+
+// --- Handlers ---
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    kind: Option<String>,
+}
+
+impl HistoryQuery {
+    fn kind(self) -> Result<Option<String>, AppError> {
+        match self.kind.filter(|k| !k.is_empty()) {
+            Some(k) if !KINDS.contains(&k.as_str()) => {
+                Err(AppError::Invalid { field: "kind", reason: format!("must be one of {}", KINDS.join(", ")) })
+            }
+            kind => Ok(kind),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+struct HistoryTemplate {
+    entries: Vec<Entry>,
+    kind: String,
+    shown: usize,
+}
+
+pub async fn show_history(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Response, AppError> {
+    let kind = query.kind()?;
+    let entries = list(&state.db, kind.clone(), auth::viewer(&state, &sid).as_deref()).await?;
+    Ok(render(HistoryTemplate { entries, kind: kind.unwrap_or_default(), shown: SHOWN }))
+}
+
+#[derive(Template)]
+#[template(path = "history_entry.html")]
+struct EntryTemplate {
+    entry: Entry,
+    events: Vec<Event>,
+    timings: Timings,
+    /// How long after the first event each came, e.g. "+42s"
+    offsets: Vec<String>,
+}
+
+/// One attempt and the steps it went through.
+pub async fn show_entry(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    UrlPath(id): UrlPath<i64>,
+) -> Result<Response, AppError> {
+    let Some(entry) = get(&state.db, id, auth::viewer(&state, &sid).as_deref()).await? else {
+        return Err(AppError::NotFound(format!("History entry {}", id)));
+    };
+    let events = events(&state.db, id).await?;
+    let first = events.first().map(|e| e.at.0).unwrap_or_default();
+    let offsets = events.iter().map(|e| format!("+{}", took(e.at.0 - first))).collect();
+    Ok(render(EntryTemplate { timings: Timings::from_events(&events), entry, events, offsets }))
+}
+
+#[derive(Template)]
+#[template(path = "history_stats.html")]
+struct StatsTemplate {
+    sites: Vec<SiteStats>,
+    all: SiteStats,
+    measured: usize,
+    max_downloads: usize,
+    days: usize,
+    per_day: Columns,
+    storage: Columns,
+    top_sites: Vec<Bar>,
+}
+
+/// Download times per site, to tune BSDL_MAX_DOWNLOADS by and spot slow sites,
+/// and charts of what was downloaded lately and from where.
+pub async fn show_stats(State(state): State<AppState>) -> Result<Response, AppError> {
+    let (sites, all) = stats(&state.db).await?;
+    Ok(render(StatsTemplate {
+        sites,
+        all,
+        measured: MEASURED,
+        max_downloads: state.config.max_downloads,
+        days: charts::DAYS,
+        per_day: charts::downloads_per_day(&state.db).await?,
+        storage: charts::storage_per_day(&state.db).await?,
+        top_sites: charts::top_sites(&state.db).await?,
+    }))
+}
+
+/// The same as JSON, newest first.
+pub async fn api_history(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Response, AppError> {
+    let viewer = auth::viewer(&state, &sid);
+    Ok(Json(list(&state.db, query.kind()?, viewer.as_deref()).await?).into_response())
+}
@@ -0,0 +1,109 @@
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// Only for services on this machine or the local network (an IPFS node,
+// LibreTranslate), so plain HTTP/1.1 is enough and there's no TLS
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Bigger answers are cut off; nothing we ask for comes close
+const MAX_RESPONSE: u64 = 16 * 1024 * 1024;
+
+/// A piece of a request body, sent in order.
+pub enum Part<'a> {
+    Bytes(&'a [u8]),
+    /// Streamed from disk rather than read into memory
+    File(&'a Path),
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).trim().to_string()
+    }
+}
+
+// Host (with port) and path of a plain-HTTP URL
+fn split_url(url: &str) -> io::Result<(String, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't an http:// URL", url)))?;
+    let (host, path) = rest.split_once('/').map_or((rest, ""), |(h, p)| (h, p));
+    let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    Ok((host, format!("/{}", path)))
+}
+
+// The body without its chunked framing
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    while let Some(end) = body.windows(2).position(|w| w == b"\r\n") {
+        let size = std::str::from_utf8(&body[..end])
+            .ok()
+            .and_then(|s| usize::from_str_radix(s.split(';').next()?.trim(), 16).ok());
+        let start = end + 2;
+        match size {
+            Some(size) if size > 0 && start + size <= body.len() => {
+                out.extend_from_slice(&body[start..start + size]);
+                body = &body[(start + size + 2).min(body.len())..];
+            }
+            _ => break,
+        }
+    }
+    out
+}
+
+/// POSTs `body` to `url` and reads the whole answer.
+pub async fn post(url: &str, content_type: &str, body: &[Part<'_>]) -> io::Result<Reply> {
+    let (host, path) = split_url(url)?;
+    let mut length = 0;
+    for part in body {
+        length += match part {
+            Part::Bytes(b) => b.len() as u64,
+            Part::File(p) => tokio::fs::metadata(p).await?.len(),
+        };
+    }
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path, host, content_type, length
+    );
+
+    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&host))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("no answer from {}", host)))??;
+    stream.write_all(head.as_bytes()).await?;
+    for part in body {
+        match part {
+            Part::Bytes(b) => stream.write_all(b).await?,
+            Part::File(p) => {
+                tokio::io::copy(&mut tokio::fs::File::open(p).await?, &mut stream).await?;
+            }
+        }
+    }
+
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut response).await?;
+    let garbled = || io::Error::new(io::ErrorKind::InvalidData, format!("garbled answer from {}", host));
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(garbled)?;
+    let headers = String::from_utf8_lossy(&response[..split]).to_lowercase();
+    let status = headers
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(garbled)?;
+    let mut body = response[split + 4..].to_vec();
+    if headers.lines().any(|h| h.starts_with("transfer-encoding:") && h.contains("chunked")) {
+        body = dechunk(&body);
+    }
+    Ok(Reply { status, body })
+}
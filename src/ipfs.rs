@@ -0,0 +1,106 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::path::Path as FsPath;
+
+use crate::db::{params, Db, OptionalExtension};
+use crate::error::AppError;
+use crate::http::{self, Part};
+use crate::library;
+use crate::page::{self, FlashKind};
+use crate::sanitize;
+use crate::session::SessionId;
+use crate::AppState;
+
+const BOUNDARY: &str = "bplus-ipfs-boundary";
+
+// --- Node API ---
+
+#[derive(Deserialize)]
+struct Added {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct Refused {
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+/// Adds `path` to the node behind `api` and pins it. Returns the CID.
+pub async fn add(api: &str, path: &FsPath) -> Result<String, AppError> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file").replace(['"', '\r', '\n'], "_");
+    let head = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{n}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+        b = BOUNDARY,
+        n = name
+    );
+    let tail = format!("\r\n--{}--\r\n", BOUNDARY);
+    let url = format!("{}/api/v0/add?pin=true&cid-version=1", api.trim_end_matches('/'));
+    let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+    let body = [Part::Bytes(head.as_bytes()), Part::File(path), Part::Bytes(tail.as_bytes())];
+    let reply = http::post(&url, &content_type, &body)
+        .await
+        .map_err(|e| AppError::Ipfs(format!("could not reach the node at {}: {}", api, e)))?;
+
+    let text = reply.text();
+    if !reply.ok() {
+        let reason = serde_json::from_str::<Refused>(&text).map(|r| r.message).unwrap_or(text);
+        return Err(AppError::Ipfs(format!("the node refused {}: {}", name, reason)));
+    }
+    // One line per file; ours is the only one
+    text.lines()
+        .rev()
+        .find_map(|l| serde_json::from_str::<Added>(l).ok())
+        .map(|a| a.hash)
+        .ok_or_else(|| AppError::Ipfs(format!("the node didn't say what {} was added as", name)))
+}
+
+// --- Store ---
+
+pub async fn cid(db: &Db, name: &str) -> Result<Option<String>, AppError> {
+    let name = name.to_string();
+    db.call(move |conn| {
+        conn.query_row("SELECT ipfs_cid FROM items WHERE file_name = ?1", params![name], |r| r.get(0))
+            .optional()
+            .map(Option::flatten)
+    })
+    .await
+}
+
+/// Adds the library file `name` to BSDL_IPFS_API and records its CID against
+/// the item, which has to be indexed already.
+pub async fn pin(state: &AppState, name: &str) -> Result<String, AppError> {
+    let api = state.config.ipfs_api.as_deref().ok_or_else(|| AppError::BadRequest("BSDL_IPFS_API isn't set".to_string()))?;
+    let path = library::library_file(&state.config.downloads_dir, name)?;
+    let cid = add(api, &path).await?;
+    let (file, value) = (name.to_string(), cid.clone());
+    let updated = state
+        .db
+        .call(move |conn| conn.execute("UPDATE items SET ipfs_cid = ?2 WHERE file_name = ?1", params![file, value]))
+        .await?;
+    if updated == 0 {
+        tracing::warn!("Pinned {} as {} but it isn't indexed, so the CID wasn't kept", name, cid);
+    }
+    tracing::info!("Pinned {} to IPFS as {}", name, cid);
+    Ok(cid)
+}
+
+// --- Handlers ---
+
+pub async fn pin_file(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    match pin(&state, &name).await {
+        Ok(cid) => page::flash(&state, &sid, FlashKind::Info, format!("Added {} to IPFS as {}.", name, cid)),
+        Err(AppError::Ipfs(e)) => page::flash(&state, &sid, FlashKind::Error, format!("IPFS: {}", e)),
+        Err(e) => return Err(e),
+    }
+    Ok(Redirect::to(&format!("/media/{}", sanitize::url_path(&name))).into_response())
+}
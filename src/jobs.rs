@@ -0,0 +1,1888 @@
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Redirect, Response,
+    },
+    Extension,
+};
+use askama::Template;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::{broadcast, mpsc, Notify, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::auth;
+use crate::budget::{self, Usage};
+use crate::clock::Stamp;
+use crate::config::{Config, PolitePolicy};
+use crate::db::{self, params, Db};
+use crate::dedupe;
+use crate::error::{render, AppError};
+use crate::gate::{Gate, Workaround};
+use crate::history;
+use crate::ipfs;
+use crate::landing;
+use crate::library;
+use crate::logging;
+use crate::notify;
+use crate::page::{self, FlashKind};
+use crate::pinning;
+use crate::reconcile;
+use crate::session::{Choice, SessionId, Selection};
+use crate::subtitles;
+use crate::torrent;
+use crate::transcode;
+use crate::AppState;
+
+/// yt-dlp output template used unless a subscription sets its own. Templates
+/// name a file directly inside the library; `owned_by` puts it in a user's folder.
+pub const DEFAULT_TEMPLATE: &str = "%(title)s.%(ext)s";
+/// Template for a whole playlist or channel downloaded in one job: a folder
+/// named after it, and each video numbered by its place in it.
+pub const PLAYLIST_TEMPLATE: &str = "%(playlist_title,playlist_id|Playlist)s/%(playlist_index)03d - %(title)s.%(ext)s";
+
+// --- Data Structures ---
+
+/// One unit of work. Steps that need an earlier step's file get it through
+/// their dependencies' outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Step {
+    /// Polite downloads sleep between items and respect the hourly limit and 429 pauses
+    Download { url: String, selection: Box<Selection>, template: String, polite: bool },
+    /// Compatibility copy of the file the dependency produced
+    Transcode,
+    /// Evens out the loudness of the dependency's file in place
+    Normalize,
+    /// .torrent for the dependency's file, for passing the archive on
+    Torrent,
+    /// Machine-translated subtitles for the dependency's file
+    Translate,
+}
+
+impl Step {
+    fn label(&self) -> String {
+        match self {
+            Step::Download { selection, polite, .. } => {
+                let kind = match (*polite, selection.playlist) {
+                    (true, true) => "Polite download of the playlist",
+                    (true, false) => "Polite download",
+                    (false, true) => "Download of the playlist",
+                    (false, false) => "Download",
+                };
+                if selection.is_photo() {
+                    format!("{} photo", kind)
+                } else if selection.audio_only() {
+                    format!("{} audio ({})", kind, selection.audio_format)
+                } else {
+                    format!("{} ({})", kind, selection.container)
+                }
+            }
+            Step::Transcode => "Compatibility copy".to_string(),
+            Step::Normalize => "Normalize loudness".to_string(),
+            Step::Torrent => "Create torrent".to_string(),
+            Step::Translate => "Translate subtitles".to_string(),
+        }
+    }
+
+    fn is_polite(&self) -> bool {
+        matches!(self, Step::Download { polite: true, .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JobStatus {
+    /// Queued, or waiting on a dependency
+    Waiting,
+    Running,
+    Done,
+    Failed,
+    /// A dependency failed or was cancelled, so this never ran
+    Skipped,
+    Cancelled,
+    /// A download stopped from the jobs page, its partial files kept for the resume
+    Paused,
+}
+
+impl JobStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            JobStatus::Waiting => "Queued",
+            JobStatus::Running => "Running",
+            JobStatus::Done => "Done",
+            JobStatus::Failed => "Failed",
+            JobStatus::Skipped => "Skipped",
+            JobStatus::Cancelled => "Cancelled",
+            JobStatus::Paused => "Paused",
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        !matches!(self, JobStatus::Waiting | JobStatus::Running | JobStatus::Paused)
+    }
+}
+
+// yt-dlp prints this before each progress line (see `download`)
+const PROGRESS_PREFIX: &str = "bplus-progress ";
+// How often a progress stream looks for changes
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Latest progress yt-dlp reported for a running download, as it formats it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Progress {
+    pub percent: String,
+    pub speed: String,
+    pub eta: String,
+    /// "3 of 10" while a whole playlist downloads, of the video being fetched
+    pub item: String,
+}
+
+impl Progress {
+    // "  42.0%|  2.10MiB/s|00:31", fields yt-dlp couldn't work out show as
+    // "N/A"; a playlist's lines go on with "|3|10" for the item and the count
+    fn parse(line: &str) -> Option<Progress> {
+        let mut parts = line.strip_prefix(PROGRESS_PREFIX)?.split('|').map(str::trim);
+        let mut field =
+            || parts.next().filter(|p| !p.is_empty() && !["N/A", "NA", "Unknown"].contains(p)).unwrap_or("").to_string();
+        let (percent, speed, eta) = (field(), field(), field());
+        let item = match (field(), field()) {
+            (index, count) if !index.is_empty() && !count.is_empty() => format!("{} of {}", index, count),
+            _ => String::new(),
+        };
+        Some(Progress { percent, speed, eta, item })
+    }
+
+    /// The percentage as a number, for progress bars
+    pub fn value(&self) -> Option<f64> {
+        self.percent.trim_end_matches('%').parse::<f64>().ok().map(|p| p.clamp(0.0, 100.0))
+    }
+}
+
+/// Counts for the status strip, page titles, favicon and /api/status.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueSummary {
+    pub running: usize,
+    pub queued: usize,
+    /// Chain title and progress of the download that's running, if any
+    pub title: Option<String>,
+    pub progress: Option<Progress>,
+    pub maintenance: bool,
+}
+
+/// Set while an admin has the queue in maintenance mode.
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    pub since: Stamp,
+    /// What it's for, e.g. "host reboot"; may be empty
+    pub reason: String,
+}
+
+impl Maintenance {
+    /// Shown to anyone whose submission is turned away.
+    pub fn notice(&self) -> String {
+        let why = if self.reason.is_empty() { String::new() } else { format!(" ({})", self.reason) };
+        format!(
+            "The app is in maintenance mode{}, so nothing new can be queued right now. Please try again once it's over.",
+            why
+        )
+    }
+}
+
+struct Job {
+    step: Step,
+    deps: Vec<u64>,
+    status: JobStatus,
+    output: Option<PathBuf>,
+    error: Option<String>,
+    /// When it last went into the queue, for how long it waited
+    queued: i64,
+    started: Option<i64>,
+    /// When yt-dlp moved on from downloading to merging, converting or fixing up
+    postprocessing: Option<i64>,
+    finished: Option<i64>,
+    progress: Option<Progress>,
+    chain: u64,
+    /// Cookies account a download ran with
+    account: Option<String>,
+    /// Tripped to stop the job while it runs
+    stop: Option<CancellationToken>,
+    /// Stopped to be resumed rather than cancelled
+    pausing: bool,
+    /// Partial files of a paused download
+    leftovers: Vec<String>,
+    /// What yt-dlp said it was writing, to tell its partial files from other downloads'
+    writing: Vec<String>,
+}
+
+struct Chain {
+    title: String,
+    jobs: Vec<u64>,
+    created: Stamp,
+    /// The ID its lines are logged with
+    trace: String,
+    /// The signed-in user who queued it; None for the app's own and API tokens'
+    owner: Option<String>,
+}
+
+#[derive(Default)]
+struct Inner {
+    jobs: BTreeMap<u64, Job>,
+    chains: BTreeMap<u64, Chain>,
+    next_id: u64,
+    polite: PoliteState,
+    /// When recent downloads started, per cookies account
+    accounts: HashMap<String, VecDeque<i64>>,
+    maintenance: Option<Maintenance>,
+    /// Finished chains kept around, 0 for all
+    keep_finished: usize,
+    /// Where changes go to be saved, once `restore` has run
+    saving: Option<mpsc::UnboundedSender<Save>>,
+    /// Shutting down once what's running has finished
+    draining: bool,
+    stopping: bool,
+}
+
+// A step as the `queue` table keeps it
+#[derive(Serialize, Deserialize)]
+struct SavedJob {
+    id: u64,
+    step: Step,
+    deps: Vec<u64>,
+    status: JobStatus,
+    output: Option<PathBuf>,
+    error: Option<String>,
+    leftovers: Vec<String>,
+    writing: Vec<String>,
+}
+
+enum Save {
+    Chain { id: u64, title: String, created: Stamp, trace: String, owner: Option<String>, jobs: Vec<SavedJob> },
+    Forget(u64),
+}
+
+#[derive(Default)]
+struct PoliteState {
+    /// When recent polite downloads started, for the hourly limit
+    starts: VecDeque<i64>,
+    /// Set when a site answered 429; polite downloads wait until then
+    paused_until: Option<i64>,
+    pause_reason: String,
+}
+
+// Drops starts older than an hour
+fn last_hour(starts: &mut VecDeque<i64>, now: i64) {
+    while starts.front().is_some_and(|&t| t <= now - 3600) {
+        starts.pop_front();
+    }
+}
+
+impl PoliteState {
+    // Until when polite downloads have to wait, if they do
+    fn hold(&mut self, rules: &PolitePolicy, now: i64) -> Option<i64> {
+        if let Some(until) = self.paused_until {
+            if until > now {
+                return Some(until);
+            }
+            self.paused_until = None;
+        }
+        last_hour(&mut self.starts, now);
+        if rules.per_hour > 0 && self.starts.len() >= rules.per_hour {
+            return self.starts.front().map(|t| t + 3600);
+        }
+        None
+    }
+}
+
+// The cookies account the next download should use: the least recently used
+// one still under its hourly limit. `Err` says when one frees up.
+fn pick_account(
+    usage: &mut HashMap<String, VecDeque<i64>>,
+    accounts: &[String],
+    per_hour: usize,
+    now: i64,
+) -> Result<Option<String>, i64> {
+    if accounts.is_empty() {
+        return Ok(None);
+    }
+    let mut best: Option<(&String, i64)> = None;
+    let mut frees = i64::MAX;
+    for account in accounts {
+        let starts = usage.entry(account.clone()).or_default();
+        last_hour(starts, now);
+        if per_hour > 0 && starts.len() >= per_hour {
+            frees = frees.min(starts.front().map_or(now, |t| t + 3600));
+            continue;
+        }
+        let last = starts.back().copied().unwrap_or(i64::MIN);
+        if best.is_none_or(|(_, b)| last < b) {
+            best = Some((account, last));
+        }
+    }
+    best.map(|(a, _)| Some(a.clone())).ok_or(frees)
+}
+
+/// Why some downloads are waiting, for the jobs page.
+#[derive(Debug, Clone)]
+pub struct Hold {
+    /// Which downloads, e.g. "Polite downloads"
+    pub what: &'static str,
+    pub until: Stamp,
+    pub reason: String,
+}
+
+// What the worker should do next
+enum Next {
+    /// A job to run, the cookies account to run it with and what stops it
+    Run(u64, Step, Vec<PathBuf>, Option<String>, CancellationToken),
+    /// Only held-back downloads are ready; look again then
+    At(i64),
+    Idle,
+}
+
+/// A step to add to a chain. `after` holds indices of earlier steps in the
+/// same submission, so a chain can only point backwards and never loops.
+pub struct NewJob {
+    pub step: Step,
+    pub after: Vec<usize>,
+}
+
+// Puts the downloads of `steps` into `user`'s folder of the library
+fn owned_by(steps: &mut [NewJob], user: &str) {
+    for job in steps {
+        if let Step::Download { template, .. } = &mut job.step {
+            *template = format!("{}/{}", user, template);
+        }
+    }
+}
+
+/// What the jobs page shows for one job.
+#[derive(Debug, Clone)]
+pub struct JobView {
+    pub label: String,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub took: String,
+    pub progress: Option<Progress>,
+    pub account: Option<String>,
+}
+
+/// What /jobs/:id/progress sends each time a chain changes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainProgress {
+    /// Combined status label, as the jobs page shows it
+    pub status: &'static str,
+    /// Position of the running step in the chain
+    pub step: Option<usize>,
+    pub progress: Option<Progress>,
+    /// Nothing more will change
+    pub done: bool,
+}
+
+/// What /ws sends, one JSON message each. Steps are counted by their
+/// position in the chain, as on the jobs page.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent {
+    /// A chain was added or one of its steps changed status
+    Chain { id: u64, title: String, status: &'static str, steps: Vec<StepState> },
+    /// A running download reported progress
+    Progress { chain: u64, step: usize, progress: Progress },
+    /// A line yt-dlp printed, progress lines aside
+    Log { chain: u64, step: usize, line: String },
+    /// A finished chain was cleared or aged off the list
+    Removed { id: u64 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepState {
+    pub label: String,
+    pub status: &'static str,
+    pub error: Option<String>,
+}
+
+// Events a slow socket can fall behind by before it misses some
+const EVENT_BACKLOG: usize = 256;
+
+/// A chain and the combined status of its jobs.
+#[derive(Debug, Clone)]
+pub struct ChainView {
+    pub id: u64,
+    pub title: String,
+    pub created: Stamp,
+    /// The ID its lines are logged with
+    pub trace: String,
+    pub owner: Option<String>,
+    pub status: JobStatus,
+    pub jobs: Vec<JobView>,
+    /// A download is running that can be paused
+    pub pausable: bool,
+    pub paused: bool,
+}
+
+impl ChainView {
+    /// Whether `viewer` gets to see it: a signed-in user sees the chains they
+    /// queued; without one (no users, or an API token) every chain shows.
+    pub fn visible_to(&self, viewer: Option<&str>) -> bool {
+        viewer.is_none_or(|user| self.owner.as_deref() == Some(user))
+    }
+}
+
+// The same for a chain in the queue
+fn visible(inner: &Inner, chain: u64, viewer: Option<&str>) -> bool {
+    inner.chains.get(&chain).is_some_and(|c| viewer.is_none_or(|user| c.owner.as_deref() == Some(user)))
+}
+
+/// Queue of job chains, in memory and saved to the database until they
+/// finish. Up to BSDL_MAX_DOWNLOADS jobs run at once, each as soon as
+/// everything it depends on is done.
+#[derive(Clone)]
+pub struct JobQueue {
+    inner: Arc<Mutex<Inner>>,
+    wake: Arc<Notify>,
+    events: broadcast::Sender<JobEvent>,
+    /// BSDL_DOWNLOADS_DIR, where cancelled downloads leave partial files
+    library: Arc<str>,
+}
+
+// --- Queue ---
+
+impl JobQueue {
+    /// `keep_finished` caps how many finished chains stay listed, 0 for no cap.
+    pub fn new(keep_finished: usize, library: &str) -> JobQueue {
+        JobQueue {
+            inner: Arc::new(Mutex::new(Inner { keep_finished, ..Inner::default() })),
+            wake: Arc::default(),
+            events: broadcast::channel(EVENT_BACKLOG).0,
+            library: Arc::from(library),
+        }
+    }
+
+    /// Every state change, progress tick and log line from here on.
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    // Nobody listening is fine
+    fn publish(&self, event: JobEvent) {
+        let _ = self.events.send(event);
+    }
+
+    // The chain as it stands, for anyone listening and for the database
+    fn changed(&self, inner: &Inner, chain: u64) {
+        save(inner, chain);
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        if let Some(c) = inner.chains.get(&chain) {
+            self.publish(JobEvent::from(view(inner, chain, c)));
+        }
+    }
+
+    fn removed(&self, chains: Vec<u64>) {
+        for id in chains {
+            self.publish(JobEvent::Removed { id });
+        }
+    }
+
+    /// Puts back the chains the last run left unfinished, then saves every
+    /// change from here on. Steps that were running start over; paused ones
+    /// stay paused. Returns how many chains there were.
+    pub async fn restore(&self, db: &Db) -> Result<usize, AppError> {
+        type Row = (u64, String, i64, String, Option<String>, Option<String>);
+        let rows: Vec<Row> = db
+            .call(|conn| {
+                conn.query_map("SELECT chain_id, title, created_at, jobs, trace, owner FROM queue ORDER BY chain_id", params![], |r| {
+                    Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?))
+                })
+            })
+            .await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut inner = self.inner.lock().unwrap();
+        let mut restored = 0;
+        for (chain_id, title, created, jobs, trace, owner) in rows {
+            // Written by a version that queued steps differently
+            let jobs: Vec<SavedJob> = match serde_json::from_str(&jobs) {
+                Ok(jobs) => jobs,
+                Err(e) => {
+                    tracing::warn!("Could not restore queued chain {} ({}): {}", chain_id, title, e);
+                    let _ = tx.send(Save::Forget(chain_id));
+                    continue;
+                }
+            };
+            let mut ids = Vec::with_capacity(jobs.len());
+            for mut saved in jobs {
+                if saved.status == JobStatus::Running {
+                    saved.status = JobStatus::Waiting;
+                    // What it got to is the download's to continue, or to remove if cancelled
+                    if matches!(saved.step, Step::Download { .. }) {
+                        saved.leftovers = partials(&self.library, &HashSet::new(), &saved.writing);
+                    }
+                }
+                inner.next_id = inner.next_id.max(saved.id);
+                inner.jobs.insert(saved.id, Job {
+                    step: saved.step,
+                    deps: saved.deps,
+                    status: saved.status,
+                    output: saved.output,
+                    error: saved.error,
+                    queued: db::now(),
+                    started: None,
+                    postprocessing: None,
+                    finished: None,
+                    progress: None,
+                    chain: chain_id,
+                    account: None,
+                    stop: None,
+                    pausing: false,
+                    leftovers: saved.leftovers,
+                    writing: saved.writing,
+                });
+                ids.push(saved.id);
+            }
+            inner.next_id = inner.next_id.max(chain_id);
+            // Saved before chains had IDs
+            let trace = trace.unwrap_or_else(logging::new_id);
+            inner.chains.insert(chain_id, Chain { title, jobs: ids, created: Stamp(created), trace, owner });
+            restored += 1;
+        }
+        inner.saving = Some(tx);
+        drop(inner);
+
+        tokio::spawn(save_queue(db.clone(), rx));
+        self.wake.notify_one();
+        Ok(restored)
+    }
+
+    /// Starts nothing more and turns new chains away, so shutting down can
+    /// wait for what's running.
+    pub fn drain(&self) {
+        self.inner.lock().unwrap().draining = true;
+    }
+
+    pub fn draining(&self) -> bool {
+        self.inner.lock().unwrap().draining
+    }
+
+    /// Stops saving changes, so what shutting down does to running steps
+    /// isn't kept: they run again at the next start.
+    pub fn shut_down(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.saving = None;
+        inner.stopping = true;
+    }
+
+    fn stopping(&self) -> bool {
+        self.inner.lock().unwrap().stopping
+    }
+
+    /// Adds a chain of steps and returns its id. Turned away in maintenance mode
+    /// and while shutting down.
+    pub fn submit(&self, title: &str, steps: Vec<NewJob>) -> Result<u64, AppError> {
+        self.submit_as(title, None, steps)
+    }
+
+    /// `submit` for a signed-in user: the chain is theirs, and its downloads
+    /// go into their folder of the library.
+    pub fn submit_as(&self, title: &str, owner: Option<&str>, mut steps: Vec<NewJob>) -> Result<u64, AppError> {
+        if let Some(user) = owner {
+            owned_by(&mut steps, user);
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(m) = &inner.maintenance {
+            return Err(AppError::Maintenance(m.notice()));
+        }
+        if inner.draining {
+            return Err(AppError::Maintenance(
+                "The app is shutting down, so nothing new can be queued right now. Please try again once it's back.".to_string(),
+            ));
+        }
+        inner.next_id += 1;
+        let chain_id = inner.next_id;
+
+        let mut ids: Vec<u64> = Vec::with_capacity(steps.len());
+        for (i, new) in steps.into_iter().enumerate() {
+            inner.next_id += 1;
+            let id = inner.next_id;
+            let deps = new.after.iter().filter(|&&d| d < i).map(|&d| ids[d]).collect();
+            inner.jobs.insert(id, Job {
+                step: new.step,
+                deps,
+                status: JobStatus::Waiting,
+                output: None,
+                error: None,
+                queued: db::now(),
+                started: None,
+                postprocessing: None,
+                finished: None,
+                progress: None,
+                chain: chain_id,
+                account: None,
+                stop: None,
+                pausing: false,
+                leftovers: Vec::new(),
+                writing: Vec::new(),
+            });
+            ids.push(id);
+        }
+        // Queued by hand it shares the request's ID; by a subscription or the like, a fresh one
+        let trace = logging::trace_id().unwrap_or_else(logging::new_id);
+        let owner = owner.map(str::to_string);
+        inner.chains.insert(chain_id, Chain { title: title.to_string(), jobs: ids, created: Stamp::now(), trace, owner });
+        self.changed(&inner, chain_id);
+        drop(inner);
+
+        self.wake.notify_one();
+        Ok(chain_id)
+    }
+
+    /// Fails the way `submit` would, for callers with work to do before submitting.
+    pub fn accepting(&self) -> Result<(), AppError> {
+        match self.maintenance() {
+            Some(m) => Err(AppError::Maintenance(m.notice())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn maintenance(&self) -> Option<Maintenance> {
+        self.inner.lock().unwrap().maintenance.clone()
+    }
+
+    /// Turns maintenance mode on (with what it's for) or off. While it's on
+    /// nothing new starts and nothing new is accepted; running jobs finish.
+    pub fn set_maintenance(&self, on: Option<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        match on {
+            Some(reason) => {
+                // Editing the reason doesn't restart the clock
+                let since = inner.maintenance.as_ref().map_or_else(Stamp::now, |m| m.since);
+                inner.maintenance = Some(Maintenance { since, reason });
+            }
+            None => inner.maintenance = None,
+        }
+        drop(inner);
+        self.wake.notify_one();
+    }
+
+    // Oldest waiting job whose dependencies have all finished, marked running,
+    // along with the files those dependencies produced. Downloads are passed
+    // over while polite mode, the cookies accounts' limits or the transfer
+    // budget (`budget`: until when) hold them back.
+    fn next_ready(&self, config: &Config, accounts: &[String], budget: Option<i64>) -> Next {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        if inner.maintenance.is_some() || inner.draining {
+            return Next::Idle;
+        }
+        let now = db::now();
+        let polite_hold = inner.polite.hold(&config.polite, now);
+        let account = pick_account(&mut inner.accounts, accounts, config.cookie_per_hour, now);
+
+        let mut wake_at: Option<i64> = None;
+        let ready = inner.jobs.iter().find_map(|(id, job)| {
+            let deps_done = job.deps.iter().all(|d| inner.jobs.get(d).is_some_and(|j| j.status == JobStatus::Done));
+            if job.status != JobStatus::Waiting || !deps_done {
+                return None;
+            }
+            let hold = match (&job.step, polite_hold, &account) {
+                (Step::Download { .. }, _, _) if budget.is_some() => budget,
+                (Step::Download { polite: true, .. }, Some(until), _) => Some(until),
+                (Step::Download { .. }, _, Err(until)) => Some(*until),
+                _ => None,
+            };
+            if let Some(until) = hold {
+                wake_at = Some(wake_at.map_or(until, |w| w.min(until)));
+                return None;
+            }
+            Some(*id)
+        });
+        let Some(ready) = ready else {
+            return wake_at.map_or(Next::Idle, Next::At);
+        };
+
+        let inputs = inner.jobs[&ready].deps.iter().filter_map(|d| inner.jobs[d].output.clone()).collect();
+        let job = inner.jobs.get_mut(&ready).expect("found above");
+        job.status = JobStatus::Running;
+        job.started = Some(now);
+        job.postprocessing = None;
+        let stop = CancellationToken::new();
+        job.stop = Some(stop.clone());
+        if let Step::Download { polite, .. } = &job.step {
+            if *polite {
+                inner.polite.starts.push_back(now);
+            }
+            job.account = account.ok().flatten();
+            if let Some(a) = &job.account {
+                inner.accounts.entry(a.clone()).or_default().push_back(now);
+            }
+        }
+        let (next, chain) = (Next::Run(ready, job.step.clone(), inputs, job.account.clone(), stop), job.chain);
+        self.changed(inner, chain);
+        next
+    }
+
+    // Puts a rate-limited polite download back in the queue and holds every
+    // polite download for `minutes`. Returns when they resume.
+    fn pause(&self, id: u64, minutes: u64, reason: String) -> Stamp {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.status = JobStatus::Waiting;
+            job.queued = db::now();
+            job.started = None;
+            job.postprocessing = None;
+            job.progress = None;
+            job.stop = None;
+            let chain = job.chain;
+            self.changed(&inner, chain);
+        }
+        let until = db::now() + (minutes * 60) as i64;
+        inner.polite.paused_until = Some(until);
+        inner.polite.pause_reason = reason;
+        Stamp(until)
+    }
+
+    /// What is holding downloads back right now, if anything.
+    pub fn holds(&self, config: &Config, accounts: &[String]) -> Vec<Hold> {
+        let mut guard = self.inner.lock().unwrap();
+        let inner = &mut *guard;
+        let now = db::now();
+        let mut holds = Vec::new();
+        if let Some(until) = inner.polite.hold(&config.polite, now) {
+            let reason = if inner.polite.paused_until.is_some() {
+                format!("the site asked us to slow down ({})", inner.polite.pause_reason)
+            } else {
+                format!("{} polite downloads started in the last hour, the hourly limit", inner.polite.starts.len())
+            };
+            holds.push(Hold { what: "Polite downloads", until: Stamp(until), reason });
+        }
+        if let Err(until) = pick_account(&mut inner.accounts, accounts, config.cookie_per_hour, now) {
+            let reason = format!(
+                "every cookies account has started {} downloads in the last hour, the limit for each",
+                config.cookie_per_hour
+            );
+            holds.push(Hold { what: "Downloads", until: Stamp(until), reason });
+        }
+        holds
+    }
+
+    // Returns where the job ended up
+    fn finish(&self, id: u64, result: Result<Option<PathBuf>, String>) -> Option<JobStatus> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(job) = inner.jobs.get_mut(&id).filter(|j| j.pausing) {
+            // Waits for `resume_chain`, then picks up where it left off
+            job.pausing = false;
+            job.stop = None;
+            job.status = JobStatus::Paused;
+            job.started = None;
+            job.progress = None;
+            let chain = job.chain;
+            self.changed(&inner, chain);
+            return Some(JobStatus::Paused);
+        }
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.finished = Some(db::now());
+            job.progress = None;
+            // Stopped from the jobs page, however the step ended
+            let stopped = job.stop.take().is_some_and(|s| s.is_cancelled());
+            match result {
+                _ if stopped => job.status = JobStatus::Cancelled,
+                Ok(output) => {
+                    job.status = JobStatus::Done;
+                    job.output = output;
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e);
+                }
+            }
+        }
+        skip_orphans(&mut inner);
+        let status = inner.jobs.get(&id).map(|j| j.status);
+        if let Some(chain) = inner.jobs.get(&id).map(|j| j.chain) {
+            self.changed(&inner, chain);
+        }
+        self.removed(forget_finished(&mut inner));
+        status
+    }
+
+    // Who queued the job's chain
+    fn owner_of(&self, id: u64) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        let chain = inner.jobs.get(&id)?.chain;
+        inner.chains.get(&chain)?.owner.clone()
+    }
+
+    /// Whether the chain is there and `viewer` gets to see it, as `ChainView::visible_to`.
+    pub fn visible_to(&self, chain: u64, viewer: Option<&str>) -> bool {
+        visible(&self.inner.lock().unwrap(), chain, viewer)
+    }
+
+    fn queued_at(&self, id: u64) -> i64 {
+        self.inner.lock().unwrap().jobs.get(&id).map(|j| j.queued).unwrap_or_else(db::now)
+    }
+
+    // The first post-processor line counts; the rest are more of the same
+    fn mark_postprocessing(&self, id: u64) {
+        if let Some(job) = self.inner.lock().unwrap().jobs.get_mut(&id) {
+            job.postprocessing.get_or_insert_with(db::now);
+        }
+    }
+
+    fn postprocessing_at(&self, id: u64) -> Option<i64> {
+        self.inner.lock().unwrap().jobs.get(&id).and_then(|j| j.postprocessing)
+    }
+
+    fn title_of(&self, id: u64) -> String {
+        let inner = self.inner.lock().unwrap();
+        inner.jobs.get(&id).and_then(|j| inner.chains.get(&j.chain)).map(|c| c.title.clone()).unwrap_or_default()
+    }
+
+    // The job's chain and the ID it's logged with
+    fn trace_of(&self, id: u64) -> (u64, String) {
+        let inner = self.inner.lock().unwrap();
+        let chain = inner.jobs.get(&id).map_or(0, |j| j.chain);
+        (chain, inner.chains.get(&chain).map(|c| c.trace.clone()).unwrap_or_else(logging::new_id))
+    }
+
+    /// Cancels whatever in the chain hasn't started and stops what is
+    /// running; the worker kills its process and marks it cancelled.
+    pub fn cancel(&self, chain: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(ids) = inner.chains.get(&chain).map(|c| c.jobs.clone()) else {
+            return false;
+        };
+        for id in ids {
+            if let Some(job) = inner.jobs.get_mut(&id) {
+                match job.status {
+                    JobStatus::Waiting | JobStatus::Paused => {
+                        job.status = JobStatus::Cancelled;
+                        job.finished = Some(db::now());
+                        remove_files(&self.library, &std::mem::take(&mut job.leftovers));
+                    }
+                    JobStatus::Running => {
+                        job.pausing = false;
+                        if let Some(stop) = &job.stop {
+                            stop.cancel();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        skip_orphans(&mut inner);
+        self.changed(&inner, chain);
+        self.removed(forget_finished(&mut inner));
+        true
+    }
+
+    /// Stops the chain's running download and keeps what it has so far until
+    /// `resume_chain`. Other steps can't pick up halfway, so they aren't paused.
+    pub fn pause_chain(&self, chain: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(ids) = inner.chains.get(&chain).map(|c| c.jobs.clone()) else {
+            return false;
+        };
+        for id in ids {
+            if let Some(job) = inner.jobs.get_mut(&id) {
+                if job.status == JobStatus::Running && matches!(job.step, Step::Download { .. }) {
+                    job.pausing = true;
+                    if let Some(stop) = &job.stop {
+                        stop.cancel();
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Queues the chain's paused downloads again.
+    pub fn resume_chain(&self, chain: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(ids) = inner.chains.get(&chain).map(|c| c.jobs.clone()) else {
+            return false;
+        };
+        for id in ids {
+            if let Some(job) = inner.jobs.get_mut(&id).filter(|j| j.status == JobStatus::Paused) {
+                job.status = JobStatus::Waiting;
+                job.queued = db::now();
+            }
+        }
+        self.changed(&inner, chain);
+        drop(inner);
+        self.wake.notify_one();
+        true
+    }
+
+    // --- Bulk ---
+    // Each only touches the chains `viewer` gets to see, every one for None
+
+    fn jobs_for<'a>(inner: &'a mut Inner, viewer: Option<&str>) -> impl Iterator<Item = &'a mut Job> {
+        let chains: HashSet<u64> = inner.chains.keys().copied().filter(|c| visible(inner, *c, viewer)).collect();
+        inner.jobs.values_mut().filter(move |j| chains.contains(&j.chain))
+    }
+
+    /// Pauses every running download and holds back every queued one, so
+    /// nothing new downloads until `resume_all`. Returns how many chains.
+    pub fn pause_all(&self, viewer: Option<&str>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let mut chains = BTreeSet::new();
+        for job in Self::jobs_for(&mut inner, viewer).filter(|j| matches!(j.step, Step::Download { .. })) {
+            match job.status {
+                JobStatus::Running if !job.pausing => {
+                    job.pausing = true;
+                    if let Some(stop) = &job.stop {
+                        stop.cancel();
+                    }
+                }
+                JobStatus::Waiting => job.status = JobStatus::Paused,
+                _ => continue,
+            }
+            chains.insert(job.chain);
+        }
+        for chain in &chains {
+            self.changed(&inner, *chain);
+        }
+        chains.len()
+    }
+
+    /// Queues every paused download again. Returns how many chains.
+    pub fn resume_all(&self, viewer: Option<&str>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let mut chains = BTreeSet::new();
+        for job in Self::jobs_for(&mut inner, viewer).filter(|j| j.status == JobStatus::Paused) {
+            job.status = JobStatus::Waiting;
+            job.queued = db::now();
+            chains.insert(job.chain);
+        }
+        for chain in &chains {
+            self.changed(&inner, *chain);
+        }
+        drop(inner);
+        self.wake.notify_one();
+        chains.len()
+    }
+
+    /// Cancels every chain's queued and paused steps; what is running carries on,
+    /// and the steps after it are cancelled too. Returns how many chains.
+    pub fn cancel_queued(&self, viewer: Option<&str>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let now = db::now();
+        let mut chains = BTreeSet::new();
+        for job in Self::jobs_for(&mut inner, viewer).filter(|j| matches!(j.status, JobStatus::Waiting | JobStatus::Paused)) {
+            job.status = JobStatus::Cancelled;
+            job.finished = Some(now);
+            remove_files(&self.library, &std::mem::take(&mut job.leftovers));
+            chains.insert(job.chain);
+        }
+        skip_orphans(&mut inner);
+        for chain in &chains {
+            self.changed(&inner, *chain);
+        }
+        self.removed(forget_finished(&mut inner));
+        chains.len()
+    }
+
+    /// Queues every failed step again, along with the steps that were skipped
+    /// because of it. Returns how many chains.
+    pub fn retry_failed(&self, viewer: Option<&str>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let mut chains = BTreeSet::new();
+        for job in Self::jobs_for(&mut inner, viewer).filter(|j| j.status == JobStatus::Failed) {
+            job.status = JobStatus::Waiting;
+            job.queued = db::now();
+            job.error = None;
+            job.output = None;
+            job.started = None;
+            job.postprocessing = None;
+            job.finished = None;
+            job.progress = None;
+            chains.insert(job.chain);
+        }
+        unskip(&mut inner);
+        for chain in &chains {
+            self.changed(&inner, *chain);
+        }
+        drop(inner);
+        self.wake.notify_one();
+        chains.len()
+    }
+
+    /// Drops chains that finished without a failure (done or cancelled) from
+    /// the list. Failed ones stay to be retried. Returns how many.
+    pub fn clear_finished(&self, viewer: Option<&str>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let cleared: Vec<u64> = inner
+            .chains
+            .iter()
+            .filter(|(id, _)| visible(&inner, **id, viewer))
+            .filter(|(_, chain)| {
+                let jobs = || chain.jobs.iter().filter_map(|id| inner.jobs.get(id));
+                jobs().all(|j| j.status.is_final()) && !jobs().any(|j| j.status == JobStatus::Failed)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &cleared {
+            remove_chain(&mut inner, *id);
+        }
+        let count = cleared.len();
+        self.removed(cleared);
+        count
+    }
+
+    fn pausing(&self, id: u64) -> bool {
+        self.inner.lock().unwrap().jobs.get(&id).is_some_and(|j| j.pausing)
+    }
+
+    fn leftovers(&self, id: u64) -> Vec<String> {
+        self.inner.lock().unwrap().jobs.get(&id).map(|j| j.leftovers.clone()).unwrap_or_default()
+    }
+
+    fn keep_leftovers(&self, id: u64, names: Vec<String>) {
+        if let Some(job) = self.inner.lock().unwrap().jobs.get_mut(&id) {
+            job.leftovers = names;
+        }
+    }
+
+    fn writing(&self, id: u64) -> Vec<String> {
+        self.inner.lock().unwrap().jobs.get(&id).map(|j| j.writing.clone()).unwrap_or_default()
+    }
+
+    fn add_writing(&self, id: u64, name: String) {
+        if let Some(job) = self.inner.lock().unwrap().jobs.get_mut(&id) {
+            if !job.writing.contains(&name) {
+                job.writing.push(name);
+            }
+        }
+    }
+
+    fn set_progress(&self, id: u64, progress: Progress) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.progress = Some(progress.clone());
+        }
+        if let Some((chain, step)) = place(&inner, id) {
+            self.publish(JobEvent::Progress { chain, step, progress });
+        }
+    }
+
+    // Only sent on, not kept
+    fn log(&self, id: u64, line: &str) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        if let Some((chain, step)) = place(&self.inner.lock().unwrap(), id) {
+            self.publish(JobEvent::Log { chain, step, line: line.to_string() });
+        }
+    }
+
+    pub fn summary(&self) -> QueueSummary {
+        self.summary_for(None)
+    }
+
+    /// `summary` of the chains `viewer` gets to see.
+    pub fn summary_for(&self, viewer: Option<&str>) -> QueueSummary {
+        let inner = self.inner.lock().unwrap();
+        let jobs = || inner.jobs.values().filter(|j| visible(&inner, j.chain, viewer));
+        let count = |s: JobStatus| jobs().filter(|j| j.status == s).count();
+        let current = jobs().find(|j| j.status == JobStatus::Running && j.progress.is_some());
+        QueueSummary {
+            running: count(JobStatus::Running),
+            queued: count(JobStatus::Waiting),
+            title: current.and_then(|j| inner.chains.get(&j.chain)).map(|c| c.title.clone()),
+            progress: current.and_then(|j| j.progress.clone()),
+            maintenance: inner.maintenance.is_some(),
+        }
+    }
+
+    /// Newest chain first.
+    pub fn list(&self) -> Vec<ChainView> {
+        let inner = self.inner.lock().unwrap();
+        inner.chains.iter().rev().map(|(id, chain)| view(&inner, *id, chain)).collect()
+    }
+
+    /// Where one chain stands, for its progress stream. `None` once it's gone.
+    pub fn chain_progress(&self, id: u64) -> Option<ChainProgress> {
+        let inner = self.inner.lock().unwrap();
+        let chain = view(&inner, id, inner.chains.get(&id)?);
+        let running = chain.jobs.iter().position(|j| j.status == JobStatus::Running);
+        Some(ChainProgress {
+            status: chain.status.label(),
+            step: running,
+            progress: running.and_then(|i| chain.jobs[i].progress.clone()),
+            done: chain.status.is_final(),
+        })
+    }
+}
+
+fn view(inner: &Inner, id: u64, chain: &Chain) -> ChainView {
+    let jobs: Vec<JobView> = chain
+        .jobs
+        .iter()
+        .filter_map(|j| inner.jobs.get(j))
+        .map(|job| JobView {
+            label: job.step.label(),
+            status: job.status,
+            error: job.error.clone(),
+            progress: job.progress.clone(),
+            account: job.account.clone(),
+            took: match (job.started, job.finished) {
+                (Some(s), Some(f)) => format!("{}s", f - s),
+                (Some(s), None) => format!("{}s so far", db::now() - s),
+                _ => String::new(),
+            },
+        })
+        .collect();
+    let steps = || chain.jobs.iter().filter_map(|j| inner.jobs.get(j));
+    let pausable = steps().any(|j| j.status == JobStatus::Running && matches!(j.step, Step::Download { .. }));
+    let paused = steps().any(|j| j.status == JobStatus::Paused);
+    ChainView {
+        id,
+        title: chain.title.clone(),
+        created: chain.created,
+        trace: chain.trace.clone(),
+        owner: chain.owner.clone(),
+        status: combined(&jobs),
+        jobs,
+        pausable,
+        paused,
+    }
+}
+
+// Which chain a job is in, and where
+fn place(inner: &Inner, id: u64) -> Option<(u64, usize)> {
+    let chain = inner.jobs.get(&id)?.chain;
+    let step = inner.chains.get(&chain)?.jobs.iter().position(|j| *j == id)?;
+    Some((chain, step))
+}
+
+impl From<ChainView> for JobEvent {
+    fn from(chain: ChainView) -> JobEvent {
+        let steps = chain
+            .jobs
+            .into_iter()
+            .map(|j| StepState { label: j.label, status: j.status.label(), error: j.error })
+            .collect();
+        JobEvent::Chain { id: chain.id, title: chain.title, status: chain.status.label(), steps }
+    }
+}
+
+// A job can never run once anything it depends on has failed, been skipped
+// or been cancelled. Repeats until nothing changes so whole branches are skipped.
+fn skip_orphans(inner: &mut Inner) {
+    loop {
+        let doomed: Vec<u64> = inner
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.status == JobStatus::Waiting)
+            .filter(|(_, job)| {
+                job.deps.iter().any(|d| {
+                    inner.jobs.get(d).is_none_or(|j| j.status.is_final() && j.status != JobStatus::Done)
+                })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        if doomed.is_empty() {
+            return;
+        }
+        for id in doomed {
+            if let Some(job) = inner.jobs.get_mut(&id) {
+                job.status = JobStatus::Skipped;
+            }
+        }
+    }
+}
+
+// --- Persistence ---
+
+// Sends the chain off to be saved, or to be deleted once nothing is left to run
+fn save(inner: &Inner, chain_id: u64) {
+    let Some(tx) = &inner.saving else {
+        return;
+    };
+    let unfinished = inner.chains.get(&chain_id).filter(|c| c.jobs.iter().any(|id| inner.jobs.get(id).is_some_and(|j| !j.status.is_final())));
+    let save = match unfinished {
+        Some(chain) => Save::Chain {
+            id: chain_id,
+            title: chain.title.clone(),
+            created: chain.created,
+            trace: chain.trace.clone(),
+            owner: chain.owner.clone(),
+            jobs: chain
+                .jobs
+                .iter()
+                .filter_map(|id| inner.jobs.get(id).map(|j| (id, j)))
+                .map(|(id, j)| SavedJob {
+                    id: *id,
+                    step: j.step.clone(),
+                    deps: j.deps.clone(),
+                    status: j.status,
+                    output: j.output.clone(),
+                    error: j.error.clone(),
+                    leftovers: j.leftovers.clone(),
+                    writing: j.writing.clone(),
+                })
+                .collect(),
+        },
+        None => Save::Forget(chain_id),
+    };
+    let _ = tx.send(save);
+}
+
+// Writes changes in the order they happened, until `shut_down`
+async fn save_queue(db: Db, mut rx: mpsc::UnboundedReceiver<Save>) {
+    while let Some(save) = rx.recv().await {
+        let result = match save {
+            Save::Chain { id, title, created, trace, owner, jobs } => match serde_json::to_string(&jobs) {
+                Ok(jobs) => db
+                    .call(move |conn| {
+                        conn.execute(
+                            "INSERT INTO queue (chain_id, title, created_at, jobs, trace, owner)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                             ON CONFLICT (chain_id) DO UPDATE SET title = excluded.title, created_at = excluded.created_at,
+                                 jobs = excluded.jobs, trace = excluded.trace, owner = excluded.owner",
+                            params![id, title, created.0, jobs, trace, owner],
+                        )
+                    })
+                    .await
+                    .map(|_| ()),
+                Err(e) => Err(e.into()),
+            },
+            Save::Forget(id) => db.call(move |conn| conn.execute("DELETE FROM queue WHERE chain_id = ?1", params![id])).await.map(|_| ()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Could not save the job queue: {}", e);
+        }
+    }
+}
+
+// The reverse of `skip_orphans`, once failed steps are queued again: skipped
+// steps whose dependencies can all still finish wait again.
+fn unskip(inner: &mut Inner) {
+    loop {
+        let revived: Vec<u64> = inner
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.status == JobStatus::Skipped)
+            .filter(|(_, job)| {
+                job.deps.iter().all(|d| inner.jobs.get(d).is_some_and(|j| !j.status.is_final() || j.status == JobStatus::Done))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        if revived.is_empty() {
+            return;
+        }
+        for id in revived {
+            if let Some(job) = inner.jobs.get_mut(&id) {
+                job.status = JobStatus::Waiting;
+                job.queued = db::now();
+            }
+        }
+    }
+}
+
+// Drops the oldest finished chains beyond `keep_finished`. Returns which.
+fn forget_finished(inner: &mut Inner) -> Vec<u64> {
+    if inner.keep_finished == 0 {
+        return Vec::new();
+    }
+    let finished: Vec<u64> = inner
+        .chains
+        .iter()
+        .filter(|(_, chain)| chain.jobs.iter().all(|id| inner.jobs.get(id).is_none_or(|j| j.status.is_final())))
+        .map(|(id, _)| *id)
+        .collect();
+    let excess = finished.len().saturating_sub(inner.keep_finished);
+    for chain_id in &finished[..excess] {
+        remove_chain(inner, *chain_id);
+    }
+    finished[..excess].to_vec()
+}
+
+fn remove_chain(inner: &mut Inner, chain_id: u64) {
+    if let Some(chain) = inner.chains.remove(&chain_id) {
+        for id in chain.jobs {
+            inner.jobs.remove(&id);
+        }
+    }
+}
+
+// Running beats paused beats queued beats failed beats cancelled; done only when everything is
+fn combined(jobs: &[JobView]) -> JobStatus {
+    let any = |s: JobStatus| jobs.iter().any(|j| j.status == s);
+    if any(JobStatus::Running) {
+        JobStatus::Running
+    } else if any(JobStatus::Paused) {
+        JobStatus::Paused
+    } else if any(JobStatus::Waiting) {
+        JobStatus::Waiting
+    } else if any(JobStatus::Failed) {
+        JobStatus::Failed
+    } else if any(JobStatus::Cancelled) || any(JobStatus::Skipped) {
+        JobStatus::Cancelled
+    } else {
+        JobStatus::Done
+    }
+}
+
+// --- Worker ---
+
+/// Runs queued jobs in the background for the life of the process, up to
+/// BSDL_MAX_DOWNLOADS of them at once.
+pub fn spawn_worker(state: AppState) {
+    let slots = Arc::new(Semaphore::new(state.config.max_downloads));
+    tokio::spawn(async move {
+        loop {
+            // A free slot first, so nothing is marked running before it can start
+            let slot = slots.clone().acquire_owned().await.expect("the semaphore is never closed");
+            // Secrets can change at any time, so the accounts are looked up afresh
+            let accounts = state.secrets.cookie_accounts().await.unwrap_or_else(|e| {
+                tracing::warn!("Could not list cookies accounts: {}", e);
+                Vec::new()
+            });
+            // Looked at again every few minutes in case the allowance changed
+            let budget = budget::hold(&state).await.map(|h| h.until.0.min(db::now() + budget::RECHECK_SECS));
+            match state.jobs.next_ready(&state.config, &accounts, budget) {
+                Next::Run(id, step, inputs, account, stop) => {
+                    let state = state.clone();
+                    let (chain, trace) = state.jobs.trace_of(id);
+                    tokio::spawn(async move {
+                        logging::in_job(chain, trace, run_job(&state, id, step, inputs, account, stop)).await;
+                        drop(slot);
+                        // Its dependents, or the next in line, can start now
+                        state.jobs.wake.notify_one();
+                    });
+                }
+                Next::At(when) => {
+                    drop(slot);
+                    let wait = Duration::from_secs((when - db::now()).max(1) as u64);
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        _ = state.jobs.wake.notified() => {}
+                    }
+                }
+                Next::Idle => {
+                    drop(slot);
+                    state.jobs.wake.notified().await;
+                }
+            }
+        }
+    });
+}
+
+async fn run_job(state: &AppState, id: u64, step: Step, inputs: Vec<PathBuf>, account: Option<String>, stop: CancellationToken) {
+    // Every download attempt goes into the history
+    let entry = match &step {
+        Step::Download { url, selection, .. } => {
+            let (title, format) = (state.jobs.title_of(id), history::format_of(selection));
+            let owner = state.jobs.owner_of(id);
+            history::started(&state.db, url, &title, &format, owner.as_deref(), state.jobs.queued_at(id)).await
+        }
+        _ => None,
+    };
+    let result = stoppable(state, id, &step, inputs, account, stop).await;
+    // Cut off by shutting down: it runs again at the next start, and the
+    // history marks this attempt interrupted then
+    if state.jobs.stopping() {
+        return;
+    }
+    let (file, error) = match &result {
+        Ok(file) => (file.clone(), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let status = match result {
+        // Retried once the pause is over rather than failed
+        Err(AppError::RateLimited(reason)) if step.is_polite() => {
+            let until = state.jobs.pause(id, state.config.polite.pause_minutes, reason.clone());
+            notify::send(state, &format!("Polite downloads paused until {} after a 429: {}", until, reason)).await;
+            Some(JobStatus::Waiting)
+        }
+        result => {
+            if let Err(e) = &result {
+                tracing::warn!("Job {} ({}) failed: {}", id, step.label(), e);
+            }
+            let status = state.jobs.finish(id, result.map_err(|e| e.to_string()));
+            if state.jobs.maintenance().is_some() && state.jobs.summary().running == 0 {
+                notify::send(state, "Maintenance mode: running jobs have drained").await;
+            }
+            status
+        }
+    };
+    if let Some(entry) = entry {
+        let status = status.unwrap_or(JobStatus::Cancelled).label();
+        history::finished(&state.db, entry, status, file.as_deref(), error, state.jobs.postprocessing_at(id)).await;
+    }
+}
+
+// Dropping the step kills whatever it spawned (children are killed on drop).
+// A cancelled download's leftovers go with it; a paused one's are kept.
+async fn stoppable(
+    state: &AppState,
+    id: u64,
+    step: &Step,
+    inputs: Vec<PathBuf>,
+    account: Option<String>,
+    stop: CancellationToken,
+) -> Result<Option<PathBuf>, AppError> {
+    // A resumed download's own partial files count as new
+    let dir = &state.config.downloads_dir;
+    let mut before = library_names(dir);
+    for name in state.jobs.leftovers(id) {
+        before.remove(&name);
+    }
+    tokio::select! {
+        result = run_step(state, id, step, inputs, account) => result,
+        _ = stop.cancelled() => {
+            let partial = match step {
+                Step::Download { .. } => partials(dir, &before, &state.jobs.writing(id)),
+                _ => Vec::new(),
+            };
+            if state.jobs.pausing(id) {
+                tracing::info!("Job {} ({}) paused", id, step.label());
+                state.jobs.keep_leftovers(id, partial);
+            } else {
+                tracing::info!("Job {} ({}) cancelled", id, step.label());
+                remove_files(dir, &partial);
+                if let Step::Download { .. } = step {
+                    landing::discard(&state.config.landing, id);
+                }
+            }
+            Ok(None)
+        }
+    }
+}
+
+fn library_names(dir: &str) -> HashSet<String> {
+    library::list_names(dir).unwrap_or_default().into_iter().collect()
+}
+
+// What yt-dlp leaves of an unfinished download: .part files and their
+// fragments, its .ytdl state, and single formats still waiting to be merged
+fn is_partial(name: &str) -> bool {
+    let format_file = name.split('.').any(|p| p.len() > 1 && p.starts_with('f') && p[1..].bytes().all(|b| b.is_ascii_digit()));
+    name.ends_with(".part") || name.contains(".part-Frag") || name.ends_with(".ytdl") || name.contains(".temp.") || format_file
+}
+
+// Partial files that appeared since the download started and belong to what
+// it was `writing`: that file's .part and fragments, or the merge's .temp file.
+// Other downloads running at the same time have their own.
+fn partials(dir: &str, before: &HashSet<String>, writing: &[String]) -> Vec<String> {
+    let ours = |name: &str| {
+        writing.iter().any(|w| name.starts_with(w.as_str()) || name.starts_with(&format!("{}.temp.", library::stem(w))))
+    };
+    library_names(dir).difference(before).filter(|n| is_partial(n) && ours(n)).cloned().collect()
+}
+
+// The file a yt-dlp line says it's about to write: a format's download,
+// the merge of several, or an extracted audio track
+fn written_by(dir: &str, line: &str) -> Option<String> {
+    let path = match line.split_once("Destination: ") {
+        Some((_, path)) => path,
+        None => line.split_once("Merging formats into ")?.1,
+    };
+    let path = std::path::Path::new(path.trim().trim_matches('"'));
+    library::name_of(dir, path).or_else(|| path.file_name().and_then(|n| n.to_str()).map(str::to_string))
+}
+
+// yt-dlp's post-processors tag their lines with their name: merging,
+// extracting audio, fixing up HLS streams, embedding...
+const POSTPROCESSORS: &[&str] = &[
+    "[Merger]",
+    "[ExtractAudio]",
+    "[VideoRemuxer]",
+    "[VideoConvertor]",
+    "[FixupM3u8]",
+    "[FixupM4a]",
+    "[FixupStretched]",
+    "[FixupDuration]",
+    "[FixupTimestamp]",
+    "[FixupDuplicateMoov]",
+    "[EmbedSubtitle]",
+    "[EmbedThumbnail]",
+    "[Metadata]",
+    "[ModifyChapters]",
+    "[SponsorBlock]",
+    "[ThumbnailsConvertor]",
+    "[SubtitlesConvertor]",
+];
+
+fn postprocessor_line(line: &str) -> bool {
+    let line = line.trim_start();
+    POSTPROCESSORS.iter().any(|tag| line.starts_with(tag))
+}
+
+fn remove_files(dir: &str, names: &[String]) {
+    for name in names {
+        match fs::remove_file(std::path::Path::new(dir).join(name)) {
+            Ok(()) => tracing::info!("Removed partial download {}", name),
+            Err(e) => tracing::warn!("Could not remove partial download {}: {}", name, e),
+        }
+    }
+}
+
+async fn run_step(
+    state: &AppState,
+    id: u64,
+    step: &Step,
+    inputs: Vec<PathBuf>,
+    account: Option<String>,
+) -> Result<Option<PathBuf>, AppError> {
+    match step {
+        Step::Download { url, selection, template, polite } => {
+            let (file, names) = download(state, id, url, selection, template, *polite, account.as_deref()).await?;
+            if let Some(size) = file.as_ref().and_then(|f| fs::metadata(f).ok()).map(|m| m.len()) {
+                budget::record(state, size).await?;
+            }
+            let dir = &state.config.downloads_dir;
+            if names.is_empty() {
+                // A picture that only came as the thumbnail is reported as no file at all
+                reconcile::run(&state.db, dir, "download").await?;
+            } else {
+                reconcile::refresh(&state.db, dir, names).await?;
+            }
+            let name = file.as_deref().and_then(|f| library::name_of(dir, f));
+            if let Some(name) = &name {
+                // Like pinning, a failed check doesn't fail the download; /library/duplicates checks again
+                if let Err(e) = dedupe::check(state, name).await {
+                    tracing::warn!("Could not check {} for duplicates: {}", name, e);
+                }
+            }
+            if let (Some(_), Some(name)) = (&state.config.ipfs_api, &name) {
+                // A node that's down doesn't fail the download; it can be added from the media page later
+                if let Err(e) = ipfs::pin(state, name).await {
+                    tracing::warn!("Could not add {} to IPFS: {}", name, e);
+                }
+            }
+            Ok(file)
+        }
+        Step::Transcode => {
+            let Some(source) = inputs.first() else {
+                return Err(AppError::BadRequest("Nothing to transcode: the previous step produced no file".to_string()));
+            };
+            let copy = transcode::compat_copy(state, source).await?;
+            refresh(state, &[source, &copy]).await?;
+            Ok(Some(copy))
+        }
+        Step::Normalize => {
+            let Some(source) = inputs.first() else {
+                return Err(AppError::BadRequest("Nothing to normalize: the previous step produced no file".to_string()));
+            };
+            transcode::normalize(state, source).await?;
+            refresh(state, &[source]).await?;
+            Ok(Some(source.clone()))
+        }
+        Step::Torrent => {
+            let Some(source) = inputs.first() else {
+                return Err(AppError::BadRequest("Nothing to share: the previous step produced no file".to_string()));
+            };
+            Ok(Some(torrent::create(state, source).await?))
+        }
+        Step::Translate => {
+            let Some(source) = inputs.first() else {
+                return Err(AppError::BadRequest("Nothing to translate: the previous step produced no file".to_string()));
+            };
+            subtitles::translate_file(state, source).await?;
+            refresh(state, &[source]).await?;
+            Ok(Some(source.clone()))
+        }
+    }
+}
+
+// Reindexes just the files a step changed
+async fn refresh(state: &AppState, files: &[&PathBuf]) -> Result<(), AppError> {
+    let dir = &state.config.downloads_dir;
+    let names = files.iter().filter_map(|f| library::name_of(dir, f)).collect();
+    reconcile::refresh(&state.db, dir, names).await
+}
+
+// Returns the finished file, as reported by yt-dlp, and the library names of
+// every file the download finished, a playlist's earlier entries too
+async fn download(
+    state: &AppState,
+    id: u64,
+    url: &str,
+    sel: &Selection,
+    template: &str,
+    polite: bool,
+    account: Option<&str>,
+) -> Result<(Option<PathBuf>, Vec<String>), AppError> {
+    let mut cmd = Command::new(pinning::ytdlp(state, url));
+    // Sidecars feed the library's titles, thumbnails and detail pages
+    cmd.arg("--write-info-json").arg("--write-thumbnail");
+    cmd.args(state.config.filenames.ytdlp_args());
+    let _cookies = match account {
+        Some(_) if sel.workaround.as_ref().is_some_and(Workaround::replaces_cookies) => None,
+        Some(account) => state.secrets.attach_account(&mut cmd, account).await?,
+        None => None,
+    };
+    if let Some(w) = &sel.workaround {
+        cmd.args(w.ytdlp_args(&state.config));
+    }
+    if let Some(i) = sel.item {
+        cmd.arg("--playlist-items").arg(i.to_string());
+    }
+    if let Some(date) = &sel.date_after {
+        cmd.arg("--dateafter").arg(date);
+    }
+    if sel.playlist {
+        cmd.arg("--yes-playlist");
+    }
+    if polite {
+        cmd.args(state.config.polite.ytdlp_args());
+    }
+
+    // yt-dlp reports where the finished file ended up, which later steps need
+    let tag: String = rand::thread_rng().sample_iter(&Alphanumeric).take(12).map(char::from).collect();
+    let path_log = std::env::temp_dir().join(format!("bplus-path-{}.txt", tag));
+    cmd.arg("--print-to-file").arg("after_move:filepath").arg(&path_log);
+    // One machine-readable line per progress update, picked out below
+    let item = if sel.playlist { "|%(info.playlist_index)s|%(info.n_entries)s" } else { "" };
+    cmd.arg("--newline").arg("--progress-template").arg(format!(
+        "download:{}%(progress._percent_str)s|%(progress._speed_str)s|%(progress._eta_str)s{}",
+        PROGRESS_PREFIX, item
+    ));
+    // A resumed download reuses its .part files and fragments
+    cmd.arg("--continue");
+
+    let landing = landing::folder(&state.config.landing, id);
+    let output = match &landing {
+        Some(dir) => format!("{}/{}", dir.display(), template),
+        None => format!("{}/{}", state.config.downloads_dir, template),
+    };
+
+    // Logic: If Audio Only, extract to the chosen audio format. If Video, merge to the chosen container.
+    if let Choice::Image { thumbnail } = sel.choice {
+        // A picture only listed as the thumbnail comes down through --write-thumbnail
+        // above, under the output name; yt-dlp reports no file then
+        if thumbnail {
+            cmd.arg("--ignore-no-formats-error").arg("--skip-download");
+        } else {
+            cmd.arg("-f").arg(sel.selector());
+        }
+        cmd.arg("-o").arg(&output);
+    } else if sel.audio_only() {
+        cmd.arg("-f")
+           .arg(sel.selector())
+           .args(sel.sort_args())
+           .arg("-x")                  // Extract audio
+           .arg("--audio-format")      // Convert to...
+           .arg(&sel.audio_format)
+           .arg("-o")
+           .arg(&output);
+    } else {
+        // Video logic
+        cmd.arg("-f")
+           .arg(sel.selector())
+           .args(sel.sort_args())
+           .arg("--merge-output-format")
+           .arg(&sel.container)
+           .arg("-o")
+           .arg(&output);
+    }
+
+    cmd.arg("--").arg(url);
+
+    let status = state
+        .procs
+        .lines("download", cmd, |line| match Progress::parse(line) {
+            Some(p) => state.jobs.set_progress(id, p),
+            None => {
+                tracing::info!("{}", line);
+                if let Some(name) = written_by(&state.config.downloads_dir, line) {
+                    state.jobs.add_writing(id, name);
+                }
+                if postprocessor_line(line) {
+                    state.jobs.mark_postprocessing(id);
+                }
+                state.jobs.log(id, line);
+            }
+        })
+        .await;
+    let printed = fs::read_to_string(&path_log).unwrap_or_default();
+    let _ = fs::remove_file(&path_log);
+    let (status, stderr) = status?;
+    if !status.success() {
+        landing::discard(&state.config.landing, id);
+        let reason = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no details").trim().to_string();
+        if stderr.contains("HTTP Error 429") || stderr.contains("Too Many Requests") {
+            return Err(AppError::RateLimited(reason));
+        }
+        if let Some(gate) = Gate::detect(&stderr) {
+            return Err(AppError::YtDlpFailed(format!("download exited with {}: {} ({})", status, reason, gate.hint())));
+        }
+        return Err(AppError::YtDlpFailed(format!("download exited with {}: {}", status, reason)));
+    }
+    let files: Vec<PathBuf> = printed.lines().map(str::trim).filter(|l| !l.is_empty()).map(PathBuf::from).collect();
+    // A playlist's last file is the step's output and is counted with it; the ones before are counted here
+    let earlier: u64 = files.iter().rev().skip(1).filter_map(|f| fs::metadata(f).ok()).map(|m| m.len()).sum();
+    let files = landing::land(state, id, files, |p| state.jobs.set_progress(id, p)).await?;
+    if earlier > 0 {
+        budget::record(state, earlier).await?;
+    }
+    let names = files.iter().filter_map(|f| library::name_of(&state.config.downloads_dir, f)).collect();
+    Ok((files.last().cloned(), names))
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "jobs.html")]
+struct JobsTemplate {
+    chains: Vec<ChainView>,
+    active: bool,
+    holds: Vec<Hold>,
+    budget: Option<Usage>,
+}
+
+pub async fn show_jobs(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let viewer = auth::viewer(&state, &sid);
+    let mut chains = state.jobs.list();
+    chains.retain(|c| c.visible_to(viewer.as_deref()));
+    let active = chains.iter().any(|c| !c.status.is_final());
+    let accounts = state.secrets.cookie_accounts().await.unwrap_or_default();
+    let mut holds = state.jobs.holds(&state.config, &accounts);
+    holds.extend(budget::hold(&state).await);
+    let budget = budget::usage(&state).await.unwrap_or_default();
+    render(JobsTemplate { chains, active, holds, budget })
+}
+
+/// Queue counts and the running download's progress, for pages polling in the background.
+pub async fn status(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Json<QueueSummary> {
+    Json(state.jobs.summary_for(auth::viewer(&state, &sid).as_deref()))
+}
+
+/// The app icon, with a badge counting active jobs and a bar for the running download.
+pub async fn favicon(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let queue = state.jobs.summary_for(auth::viewer(&state, &sid).as_deref());
+    let active = queue.running + queue.queued;
+    let mut svg = String::from(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 64 64\">\
+         <rect width=\"64\" height=\"64\" rx=\"12\" fill=\"#1e1e1e\"/>\
+         <path d=\"M32 12v26M20 28l12 12 12-12M18 50h28\" stroke=\"#e0e0e0\" stroke-width=\"6\" \
+         stroke-linecap=\"round\" stroke-linejoin=\"round\" fill=\"none\"/>",
+    );
+    if let Some(percent) = queue.progress.as_ref().and_then(Progress::value) {
+        svg.push_str(&format!(
+            "<rect x=\"0\" y=\"58\" width=\"{:.1}\" height=\"6\" fill=\"#03dac6\"/>",
+            percent * 0.64
+        ));
+    }
+    if active > 0 {
+        let label = if active > 9 { "9+".to_string() } else { active.to_string() };
+        svg.push_str(&format!(
+            "<circle cx=\"48\" cy=\"16\" r=\"15\" fill=\"#cf6679\"/>\
+             <text x=\"48\" y=\"22\" font-size=\"18\" font-family=\"sans-serif\" font-weight=\"bold\" \
+             text-anchor=\"middle\" fill=\"#fff\">{}</text>",
+            label
+        ));
+    }
+    svg.push_str("</svg>");
+    ([(header::CONTENT_TYPE, "image/svg+xml"), (header::CACHE_CONTROL, "no-store")], svg).into_response()
+}
+
+/// Server-sent events with the chain's status and its running download's
+/// progress, sent whenever either changes. The stream ends once the chain is done.
+pub async fn progress(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<u64>,
+) -> Result<Response, AppError> {
+    if !state.jobs.visible_to(id, auth::viewer(&state, &sid).as_deref()) {
+        return Err(AppError::NotFound(format!("Job {}", id)));
+    }
+    let events = futures_util::stream::unfold((state, None), move |(state, last): (AppState, Option<ChainProgress>)| async move {
+        if last.as_ref().is_some_and(|l| l.done) {
+            return None;
+        }
+        loop {
+            // Forgotten chains end the stream too
+            let now = state.jobs.chain_progress(id)?;
+            if last.as_ref() != Some(&now) {
+                let event = Event::default().json_data(&now).map_err(axum::Error::new);
+                return Some((event, (state, Some(now))));
+            }
+            tokio::time::sleep(PROGRESS_INTERVAL).await;
+        }
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
+}
+
+pub async fn pause_chain(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<u64>,
+) -> Result<Response, AppError> {
+    // Someone else's chain is as good as not there
+    if !state.jobs.visible_to(id, auth::viewer(&state, &sid).as_deref()) || !state.jobs.pause_chain(id) {
+        return Err(AppError::NotFound(format!("Job {}", id)));
+    }
+    Ok(Redirect::to("/jobs").into_response())
+}
+
+pub async fn resume_chain(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<u64>,
+) -> Result<Response, AppError> {
+    // Someone else's chain is as good as not there
+    if !state.jobs.visible_to(id, auth::viewer(&state, &sid).as_deref()) || !state.jobs.resume_chain(id) {
+        return Err(AppError::NotFound(format!("Job {}", id)));
+    }
+    Ok(Redirect::to("/jobs").into_response())
+}
+
+pub async fn cancel_chain(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<u64>,
+) -> Result<Response, AppError> {
+    // Someone else's chain is as good as not there
+    if !state.jobs.visible_to(id, auth::viewer(&state, &sid).as_deref()) || !state.jobs.cancel(id) {
+        return Err(AppError::NotFound(format!("Job {}", id)));
+    }
+    Ok(Redirect::to("/jobs").into_response())
+}
+
+// --- Bulk handlers ---
+
+// Says what a bulk action touched, or that it had nothing to do
+fn bulk_done(state: &AppState, sid: &SessionId, count: usize, did: &str, nothing: &str) -> Response {
+    let message = match count {
+        0 => nothing.to_string(),
+        1 => format!("{} 1 chain.", did),
+        n => format!("{} {} chains.", did, n),
+    };
+    page::flash(state, sid, FlashKind::Info, message);
+    Redirect::to("/jobs").into_response()
+}
+
+pub async fn pause_all(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let count = state.jobs.pause_all(auth::viewer(&state, &sid).as_deref());
+    bulk_done(&state, &sid, count, "Paused", "No downloads to pause.")
+}
+
+pub async fn resume_all(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let count = state.jobs.resume_all(auth::viewer(&state, &sid).as_deref());
+    bulk_done(&state, &sid, count, "Resumed", "Nothing is paused.")
+}
+
+pub async fn cancel_queued(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let count = state.jobs.cancel_queued(auth::viewer(&state, &sid).as_deref());
+    bulk_done(&state, &sid, count, "Cancelled the queued steps of", "Nothing is queued.")
+}
+
+pub async fn retry_failed(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let count = state.jobs.retry_failed(auth::viewer(&state, &sid).as_deref());
+    bulk_done(&state, &sid, count, "Queued again", "Nothing has failed.")
+}
+
+pub async fn clear_finished(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let count = state.jobs.clear_finished(auth::viewer(&state, &sid).as_deref());
+    bulk_done(&state, &sid, count, "Cleared", "Nothing has finished yet.")
+}
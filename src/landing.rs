@@ -0,0 +1,290 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use crate::config::{LandingMode, LandingPolicy};
+use crate::dedupe;
+use crate::diagnostics::{Check, Status};
+use crate::error::AppError;
+use crate::jobs::Progress;
+use crate::logging;
+use crate::AppState;
+
+// How finished downloads get into the library. By default yt-dlp writes
+// straight into it and renames the finished file in place, which is quickest.
+// With BSDL_LANDING=copy or reflink each download is made in a folder of its
+// own under BSDL_LANDING_DIR first, then brought over file by file: a library
+// on a network share only ever sees whole files, and a copy is read back and
+// compared with the original before that goes. A library found on a network
+// share lands that way by default too, moved where the landing folder is on
+// the same filesystem and otherwise copied, flushed and renamed into place.
+
+// Filesystem types of network shares, as /proc/mounts names them
+const NETWORK_FS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+    "fuse.glusterfs",
+    "fuse.davfs2",
+];
+
+// Copies report how far they got this often
+const REPORT_EVERY: Duration = Duration::from_millis(500);
+const CHUNK: usize = 1 << 20;
+
+// --- Folders ---
+
+// /proc/mounts writes spaces and the like in mount points as octal escapes
+fn unescape(field: &str) -> String {
+    field.replace("\\040", " ").replace("\\011", "\t").replace("\\012", "\n").replace("\\134", "\\")
+}
+
+// The type of the filesystem `path` is on, by its deepest mount point
+#[cfg(target_os = "linux")]
+fn mount_type(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let point = unescape(fields.nth(1)?);
+            let kind = fields.next()?.to_string();
+            path.starts_with(&point).then_some((point.len(), kind))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, kind)| kind)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount_type(_path: &Path) -> Option<String> {
+    None
+}
+
+/// The filesystem the library is on, when that's a network share. Looked up
+/// once; a share mounted over the folder later isn't noticed until a restart.
+pub fn network_share(policy: &LandingPolicy) -> Option<&'static str> {
+    static SHARE: OnceLock<Option<String>> = OnceLock::new();
+    SHARE
+        .get_or_init(|| mount_type(&policy.library).filter(|kind| NETWORK_FS.contains(&kind.as_str())))
+        .as_deref()
+}
+
+/// The folder job `id` downloads into, None when it downloads straight into
+/// the library. Named after the job's chain ID too, so a job number reused
+/// after a restart never picks up another download's leftovers.
+pub fn folder(policy: &LandingPolicy, id: u64) -> Option<PathBuf> {
+    if policy.mode == LandingMode::Move && network_share(policy).is_none() {
+        return None;
+    }
+    let name = match logging::trace_id() {
+        Some(trace) => format!("job-{}-{}", id, trace),
+        None => format!("job-{}", id),
+    };
+    Some(policy.dir.join(name))
+}
+
+/// Drops a failed or cancelled download's landing folder.
+pub fn discard(policy: &LandingPolicy, id: u64) {
+    let Some(dir) = folder(policy, id).filter(|d| d.exists()) else {
+        return;
+    };
+    match fs::remove_dir_all(&dir) {
+        Ok(()) => tracing::info!("Removed landing folder {}", dir.display()),
+        Err(e) => tracing::warn!("Could not remove landing folder {}: {}", dir.display(), e),
+    }
+}
+
+// Every file below `dir`, depth first
+fn files_in(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files_in(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+// --- Landing ---
+
+fn copy_progress(copied: u64, total: u64, took: Duration) -> Progress {
+    let rate = copied as f64 / took.as_secs_f64().max(0.001);
+    let percent = if total == 0 { 100.0 } else { copied as f64 * 100.0 / total as f64 };
+    let left = (total.saturating_sub(copied) as f64 / rate.max(1.0)) as u64;
+    Progress {
+        percent: format!("{:.1}%", percent),
+        speed: format!("{:.2}MiB/s", rate / 1024.0 / 1024.0),
+        eta: format!("{:02}:{:02}", left / 60, left % 60),
+        item: String::new(),
+    }
+}
+
+// Copies `from` to `to` a chunk at a time, telling `report` how far it got,
+// and flushes it to disk
+async fn copy_reporting(from: &Path, to: &Path, report: &impl Fn(Progress)) -> io::Result<()> {
+    let mut src = tokio::fs::File::open(from).await?;
+    let total = src.metadata().await?.len();
+    let mut dst = tokio::fs::File::create(to).await?;
+    let mut buf = vec![0u8; CHUNK];
+    let (started, mut reported, mut copied) = (Instant::now(), Instant::now(), 0u64);
+    loop {
+        let n = src.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&buf[..n]).await?;
+        copied += n as u64;
+        if reported.elapsed() >= REPORT_EVERY {
+            reported = Instant::now();
+            report(copy_progress(copied, total, started.elapsed()));
+        }
+    }
+    dst.sync_all().await?;
+    report(copy_progress(copied, total, started.elapsed()));
+    Ok(())
+}
+
+// Makes sure the copy reads back the same as the original before that's removed
+async fn verify(from: &Path, partial: &Path) -> io::Result<()> {
+    let (from, partial) = (from.to_path_buf(), partial.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        if dedupe::hash_file(&from)? != dedupe::hash_file(&partial)? {
+            let _ = fs::remove_file(&partial);
+            return Err(io::Error::other("the copy reads back different from the original"));
+        }
+        Ok(())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+// A copy-on-write clone, which only btrfs, XFS, APFS and the like can make
+async fn reflink(state: &AppState, from: &Path, partial: &Path) -> bool {
+    let mut cmd = Command::new("cp");
+    cmd.arg("--reflink=always").arg("--").arg(from).arg(partial);
+    match state.procs.output("landing", cmd).await {
+        Ok(out) if out.status.success() => true,
+        Ok(out) => {
+            tracing::info!(
+                "Could not clone {}, copying it instead: {}",
+                from.display(),
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            let _ = fs::remove_file(partial);
+            false
+        }
+        Err(e) => {
+            tracing::info!("Could not run cp to clone {}, copying it instead: {}", from.display(), e);
+            false
+        }
+    }
+}
+
+async fn land_file(state: &AppState, from: &Path, to: &Path, report: &impl Fn(Progress)) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mode = state.config.landing.mode;
+    if mode == LandingMode::Move {
+        // Only within one filesystem; across two it's copied like the rest
+        match fs::rename(from, to) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+            moved => return moved,
+        }
+    }
+    let name = to.file_name().and_then(|n| n.to_str()).unwrap_or("download");
+    // Hidden while it's written, so the library never lists half a file
+    let partial = to.with_file_name(format!(".{}.landing", name));
+    let cloned = mode == LandingMode::Reflink && reflink(state, from, &partial).await;
+    if !cloned {
+        if let Err(e) = copy_reporting(from, &partial, report).await {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        if mode != LandingMode::Move {
+            verify(from, &partial).await?;
+        }
+    }
+    fs::rename(&partial, to)?;
+    // The rename reaches the disk with its folder; not every share can flush one
+    if let Some(parent) = to.parent() {
+        let _ = fs::File::open(parent).and_then(|dir| dir.sync_all());
+    }
+    fs::remove_file(from)
+}
+
+/// Brings everything job `id` downloaded into the library, into the same
+/// folders it would have written them to, and returns where `file` landed.
+/// Copies tell `report` how far they got. Whatever couldn't land stays in
+/// the landing folder. Returns where `finished`, the files yt-dlp reported,
+/// are now.
+pub async fn land(
+    state: &AppState,
+    id: u64,
+    finished: Vec<PathBuf>,
+    report: impl Fn(Progress),
+) -> Result<Vec<PathBuf>, AppError> {
+    let Some(dir) = folder(&state.config.landing, id).filter(|d| d.exists()) else {
+        return Ok(finished);
+    };
+    let mut files = Vec::new();
+    files_in(&dir, &mut files)?;
+    for from in &files {
+        let Ok(rel) = from.strip_prefix(&dir) else { continue };
+        let to = state.config.landing.library.join(rel);
+        if let Err(e) = land_file(state, from, &to, &report).await {
+            return Err(AppError::Io(io::Error::new(
+                e.kind(),
+                format!("landing {} in the library: {} (kept in {})", rel.display(), e, dir.display()),
+            )));
+        }
+    }
+    tracing::info!("Landed {} file(s) from {}", files.len(), dir.display());
+    let _ = fs::remove_dir_all(&dir);
+    // yt-dlp may report the file by its absolute path
+    let absolute = std::path::absolute(&dir).unwrap_or_else(|_| dir.clone());
+    Ok(finished
+        .into_iter()
+        .map(|f| match f.strip_prefix(&dir).or_else(|_| f.strip_prefix(&absolute)) {
+            Ok(rel) => state.config.landing.library.join(rel),
+            Err(_) => f,
+        })
+        .collect())
+}
+
+// --- Diagnostics ---
+
+/// How downloads land, and whether the library is on a network share.
+pub fn check(policy: &LandingPolicy) -> Check {
+    let detail = match (policy.mode, network_share(policy)) {
+        (LandingMode::Move, None) => "yt-dlp writes straight into the library".to_string(),
+        (LandingMode::Move, Some(kind)) => format!(
+            "the library is on a network share ({}); downloads are made in {} and moved or copied over whole",
+            kind,
+            policy.dir.display()
+        ),
+        (mode, share) => format!(
+            "downloads are made in {} and {} over{}",
+            policy.dir.display(),
+            if mode == LandingMode::Reflink { "cloned or copied" } else { "copied and checked" },
+            share.map(|kind| format!("; the library is on a network share ({})", kind)).unwrap_or_default()
+        ),
+    };
+    Check::new("Landing", Status::Pass, detail)
+}
@@ -0,0 +1,647 @@
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, Method},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use askama::Template;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path as FsPath, PathBuf};
+use tokio::process::Command;
+use tower::ServiceExt;
+use tower_http::services::ServeDir;
+
+use crate::auth;
+use crate::clock::Stamp;
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::{render, AppError};
+use crate::ipfs;
+use crate::pinning;
+use crate::publish;
+use crate::reconcile;
+use crate::torrent;
+use crate::transcode;
+use crate::sanitize::{self, filters};
+use crate::session::SessionId;
+use crate::subtitles;
+use crate::validate::{self, Form, Validate};
+use crate::{AppState, MediaType};
+
+/// Folders nested deeper than this aren't listed
+pub const MAX_DEPTH: usize = 8;
+/// The static index /files/index writes for browsing the directory without the app
+pub const INDEX_HTML: &str = "index.html";
+pub const INDEX_JSON: &str = "index.json";
+
+// Thumbnail extensions yt-dlp writes with --write-thumbnail
+const THUMB_EXTS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+// --- Data Structures ---
+
+/// The subset of yt-dlp's `.info.json` sidecar shown on detail pages.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MediaMeta {
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    pub channel: Option<String>,
+    pub duration: Option<f64>,
+    pub upload_date: Option<String>,
+    pub webpage_url: Option<String>,
+    pub extractor_key: Option<String>,
+    pub description: Option<String>,
+    // Of the format that was downloaded
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub language: Option<String>,
+    pub fps: Option<f64>,
+    /// kbit/s
+    pub tbr: Option<f64>,
+    pub audio_channels: Option<u32>,
+    pub dynamic_range: Option<String>,
+    // The site's own labels; yt-dlp writes null when there are none
+    pub tags: Option<Vec<String>>,
+    pub categories: Option<Vec<String>>,
+}
+
+/// "1:02:03" or "4:05" for a length in seconds, "Unknown" without one.
+pub fn length_label(duration: Option<f64>) -> String {
+    match duration {
+        Some(d) if d > 0.0 => {
+            let secs = d as u64;
+            if secs >= 3600 {
+                format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+            } else {
+                format!("{}:{:02}", secs / 60, secs % 60)
+            }
+        }
+        _ => "Unknown".to_string(),
+    }
+}
+
+/// "Stereo", "5.1"... for a count of audio channels.
+pub fn channel_layout(channels: u32) -> String {
+    match channels {
+        1 => "Mono".to_string(),
+        2 => "Stereo".to_string(),
+        6 => "5.1".to_string(),
+        8 => "7.1".to_string(),
+        n => format!("{} ch", n),
+    }
+}
+
+impl MediaMeta {
+    // Site-supplied text, minus anything invisible or control
+    fn cleaned(self) -> MediaMeta {
+        MediaMeta {
+            title: sanitize::line_opt(self.title),
+            uploader: sanitize::line_opt(self.uploader),
+            channel: sanitize::line_opt(self.channel),
+            upload_date: sanitize::line_opt(self.upload_date),
+            webpage_url: sanitize::line_opt(self.webpage_url),
+            extractor_key: sanitize::line_opt(self.extractor_key),
+            description: self.description.map(|d| sanitize::text(&d)),
+            vcodec: sanitize::line_opt(self.vcodec),
+            acodec: sanitize::line_opt(self.acodec),
+            language: sanitize::line_opt(self.language),
+            dynamic_range: sanitize::line_opt(self.dynamic_range),
+            tags: self.tags.map(|t| t.iter().map(|t| sanitize::line(t)).collect()),
+            categories: self.categories.map(|c| c.iter().map(|c| sanitize::line(c)).collect()),
+            ..self
+        }
+    }
+
+    /// Tags and categories together, lowercased and without repeats, as the
+    /// index keeps them for smart folders.
+    pub fn tag_list(&self) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.tags.iter().chain(&self.categories).flatten() {
+            // Commas separate them in the index
+            let tag = tag.to_lowercase().replace(',', " ");
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        tags
+    }
+
+    pub fn duration_label(&self) -> String {
+        length_label(self.duration)
+    }
+
+    /// "avc1.64001f/mp4a.40.2", leaving out a missing half.
+    pub fn codec_label(&self) -> Option<String> {
+        let present = |c: &Option<String>| c.clone().filter(|c| c != "none");
+        match (present(&self.vcodec), present(&self.acodec)) {
+            (Some(v), Some(a)) => Some(format!("{}/{}", v, a)),
+            (v, a) => v.or(a),
+        }
+    }
+
+    pub fn fps_label(&self) -> Option<String> {
+        self.fps.filter(|fps| *fps > 0.0).map(|fps| format!("{} fps", fps.round() as u32))
+    }
+
+    pub fn bitrate_label(&self) -> Option<String> {
+        self.tbr.filter(|tbr| *tbr > 0.0).map(|tbr| format!("{:.0} kbit/s", tbr))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    url: String,
+}
+
+impl Validate for ImportRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::url("url", &self.url)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "media.html")]
+struct MediaTemplate {
+    name: String,
+    media_type: MediaType,
+    mime_type: String,
+    size_mb: String,
+    thumbnail: Option<String>,
+    compat: Option<String>,
+    torrent: Option<String>,
+    subtitles: Vec<String>,
+    ipfs_cid: Option<String>,
+    /// BSDL_IPFS_API is set
+    ipfs: bool,
+    meta: Option<MediaMeta>,
+    error: Option<String>,
+    in_history: bool,
+    /// Other names it's published under in the library
+    published: Vec<String>,
+    plays: i64,
+    last_played: Option<Stamp>,
+}
+
+// --- Sidecars ---
+
+/// `name` without its extension, folders kept: `Music/track.mp3` is `Music/track`,
+/// so sidecars are only ever found in their file's own folder.
+pub fn stem(name: &str) -> &str {
+    let base = name.rfind('/').map_or(0, |i| i + 1);
+    match name[base..].rfind('.') {
+        Some(dot) if dot > 0 => &name[..base + dot],
+        _ => name,
+    }
+}
+
+// `sub` is `<stem>.<lang>.srt`
+fn subtitle_of(sub: &str, stem: &str) -> bool {
+    sub.strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .and_then(|rest| rest.strip_suffix(&format!(".{}", subtitles::SUBTITLE_EXT)))
+        .is_some_and(|lang| !lang.is_empty() && !lang.contains('.'))
+}
+
+/// True for files that only exist alongside another file (info.json, thumbnails,
+/// compatibility copies, torrents, subtitles).
+/// `names` is the full directory listing, needed to tell a thumbnail from a standalone image.
+pub fn is_sidecar(name: &str, names: &[String]) -> bool {
+    if name.ends_with(".info.json") {
+        return true;
+    }
+    let ext = FsPath::new(name).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    if ext == torrent::TORRENT_EXT {
+        let own_stem = stem(name);
+        return names.iter().any(|other| other != name && stem(other) == own_stem && !other.ends_with(".info.json"));
+    }
+    if ext == subtitles::SUBTITLE_EXT {
+        return names.iter().any(|other| other != name && !other.ends_with(".info.json") && subtitle_of(name, stem(other)));
+    }
+    // A compatibility copy belongs to its original's detail page
+    if let Some(orig_stem) = name.strip_suffix(transcode::COMPAT_SUFFIX) {
+        if names.iter().any(|other| other != name && stem(other) == orig_stem && !other.ends_with(".info.json")) {
+            return true;
+        }
+    }
+    if !THUMB_EXTS.contains(&ext.as_str()) {
+        return false;
+    }
+    let own_stem = stem(name);
+    names.iter().any(|other| other != name && stem(other) == own_stem && !other.ends_with(".info.json"))
+}
+
+/// Compatibility copy made for `name`, if there is one.
+pub fn compat_for(name: &str, names: &[String]) -> Option<String> {
+    let compat = transcode::compat_name(name);
+    (compat != name && names.contains(&compat)).then_some(compat)
+}
+
+/// Thumbnail file sitting next to `name`, if yt-dlp wrote one.
+pub fn thumbnail_for(name: &str, names: &[String]) -> Option<String> {
+    let own_stem = stem(name);
+    names
+        .iter()
+        .find(|other| {
+            other.as_str() != name
+                && stem(other) == own_stem
+                && FsPath::new(other.as_str())
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| THUMB_EXTS.contains(&e.to_lowercase().as_str()))
+        })
+        .cloned()
+}
+
+/// Torrent made for `name`, if there is one.
+pub fn torrent_for(name: &str, names: &[String]) -> Option<String> {
+    let torrent = torrent::torrent_name(name);
+    (torrent != name && names.contains(&torrent)).then_some(torrent)
+}
+
+/// Subtitles fetched or translated for `name`, in listing order.
+pub fn subtitles_for(name: &str, names: &[String]) -> Vec<String> {
+    names.iter().filter(|other| other.as_str() != name && subtitle_of(other, stem(name))).cloned().collect()
+}
+
+/// `name` and whatever sits next to it: metadata, thumbnail, compatibility copy,
+/// torrent, subtitles. Only files that are actually there.
+pub fn with_sidecars(name: &str, names: &[String]) -> Vec<String> {
+    let info = format!("{}.info.json", stem(name));
+    [Some(name.to_string()), Some(info), thumbnail_for(name, names), compat_for(name, names), torrent_for(name, names)]
+        .into_iter()
+        .flatten()
+        .chain(subtitles_for(name, names))
+        .filter(|n| names.contains(n))
+        .collect()
+}
+
+/// Parsed `.info.json` sidecar for `name`, if there is one.
+pub fn load_meta(dir: &str, name: &str) -> Option<MediaMeta> {
+    let path = FsPath::new(dir).join(format!("{}.info.json", stem(name)));
+    let raw = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<MediaMeta>(&raw) {
+        Ok(meta) => Some(meta.cleaned()),
+        Err(e) => {
+            tracing::warn!("Ignoring unreadable metadata for {}: {}", name, e);
+            None
+        }
+    }
+}
+
+// --- Removal ---
+
+/// Files moved aside under hidden names, so a removal can still be undone
+/// until whatever it depends on (the history record) has been committed.
+#[derive(Debug, Default)]
+pub struct Staged(Vec<(PathBuf, PathBuf)>);
+
+impl Staged {
+    /// Moves `name` and its sidecars out of sight; nothing moves if any one fails.
+    pub fn remove(dir: &str, name: &str) -> std::io::Result<Staged> {
+        let names = list_names(dir)?;
+        let mut staged = Staged::default();
+        for file in with_sidecars(name, &names) {
+            let from = FsPath::new(dir).join(&file);
+            let hidden = format!(".{}.removing", from.file_name().and_then(|n| n.to_str()).unwrap_or_default());
+            let to = from.with_file_name(hidden);
+            if let Err(e) = fs::rename(&from, &to) {
+                staged.restore();
+                return Err(e);
+            }
+            staged.0.push((from, to));
+        }
+        Ok(staged)
+    }
+
+    /// Puts everything back where it was.
+    pub fn restore(self) {
+        for (from, to) in self.0.iter().rev() {
+            if let Err(e) = fs::rename(to, from) {
+                tracing::warn!("Could not put back {}: {}", from.display(), e);
+            }
+        }
+    }
+
+    /// Deletes the staged files for good; returns how many there were.
+    pub fn finish(self) -> usize {
+        for (from, to) in &self.0 {
+            // Hidden names are never listed or served, so a leftover does no harm
+            if let Err(e) = fs::remove_file(to) {
+                tracing::warn!("Could not delete {} (staged as {}): {}", from.display(), to.display(), e);
+            }
+        }
+        self.0.len()
+    }
+}
+
+// Files in `dir` and the folders below it, their names led by `prefix`
+fn list_files(dir: &FsPath, prefix: &str, depth: usize, names: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        match entry.file_name().into_string() {
+            // The static index describes the library rather than being part of it
+            Ok(name) if prefix.is_empty() && (name == INDEX_HTML || name == INDEX_JSON) => {}
+            Ok(name) if kind.is_file() => names.push(format!("{}{}", prefix, name)),
+            // Hidden folders stay out, like hidden files do from the list
+            Ok(name) if kind.is_dir() && !name.starts_with('.') && depth < MAX_DEPTH => {
+                list_files(&entry.path(), &format!("{}{}/", prefix, name), depth + 1, names)?;
+            }
+            Ok(_) => {}
+            // Links need a real string, so these can't be listed
+            Err(raw) => tracing::warn!("Skipping non UTF-8 file name in {}: {:?}", dir.display(), raw),
+        }
+    }
+    Ok(())
+}
+
+/// Every file of the library, in folders too: `video.mp4`, `alice/video.mp4`.
+pub fn list_names(dir: &str) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    list_files(FsPath::new(dir), "", 0, &mut names)?;
+    Ok(names)
+}
+
+/// The files in the folder of `name`, itself included if it's there, named
+/// as `list_names` names them. Enough for `is_sidecar` to decide about `name`.
+pub fn siblings(dir: &str, name: &str) -> std::io::Result<Vec<String>> {
+    let folder = name.rsplit_once('/').map(|(folder, _)| format!("{}/", folder)).unwrap_or_default();
+    let entries = match fs::read_dir(FsPath::new(dir).join(&folder)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        match entry.file_name().into_string() {
+            Ok(file) if folder.is_empty() && (file == INDEX_HTML || file == INDEX_JSON) => {}
+            Ok(file) if entry.file_type()?.is_file() => names.push(format!("{}{}", folder, file)),
+            _ => {}
+        }
+    }
+    Ok(names)
+}
+
+/// A visible name inside the library, of a file or a folder: `Music/Jazz`,
+/// with no hidden or empty parts.
+pub fn plain_name(name: &str) -> bool {
+    name.split('/').all(|part| !(part.is_empty() || part.starts_with('.') || part.contains('\\')))
+}
+
+/// The library name of a file yt-dlp or a step wrote under the library `dir`,
+/// whether its path is relative or, as yt-dlp prints them, absolute.
+pub fn name_of(dir: &str, path: &FsPath) -> Option<String> {
+    let inside = match path.strip_prefix(dir) {
+        Ok(inside) => inside,
+        Err(_) => path.strip_prefix(std::fs::canonicalize(dir).ok()?).ok()?,
+    };
+    let name = inside.to_str()?.replace('\\', "/");
+    plain_name(&name).then_some(name)
+}
+
+/// The user whose folder `name` is in, if it's in one.
+pub fn owner_of(name: &str) -> Option<&str> {
+    name.split_once('/').map(|(user, _)| user)
+}
+
+/// Whether `viewer` gets to see `name`. A signed-in user sees their own
+/// folder only; without one (no users, or an admin's API token) the whole library shows.
+pub fn visible_to(viewer: Option<&str>, name: &str) -> bool {
+    viewer.is_none_or(|user| owner_of(name) == Some(user))
+}
+
+/// Rejects anything that isn't a visible file of the library, in whichever
+/// folder: `video.mp4` or `alice/Music/a.mp3`, with no hidden or empty parts.
+/// By then `owner_layer` has kept a signed-in user to their own folder.
+pub fn library_file(dir: &str, name: &str) -> Result<PathBuf, AppError> {
+    if !plain_name(name) {
+        return Err(AppError::BadRequest("Invalid file name".to_string()));
+    }
+    let path = FsPath::new(dir).join(name);
+    if !path.is_file() {
+        return Err(AppError::NotFound(format!("File \"{}\"", name)));
+    }
+    Ok(path)
+}
+
+/// Whether /content may hand out `name`: a visible file in any folder, which
+/// `owner_layer` narrows to the viewer's own, with an allowed extension. Of the
+/// sidecars, thumbnails, torrents and subtitles are always allowed since the
+/// library pages link them; metadata sidecars and partial downloads never are.
+pub fn servable(name: &str, allowed: &[String]) -> bool {
+    if !plain_name(name) {
+        return false;
+    }
+    let lower = name.to_lowercase();
+    if lower.ends_with(".info.json") || lower.ends_with(".part") || lower.ends_with(".ytdl") {
+        return false;
+    }
+    let ext = FsPath::new(&lower).extension().and_then(|e| e.to_str()).unwrap_or("");
+    THUMB_EXTS.contains(&ext)
+        || ext == torrent::TORRENT_EXT
+        || ext == subtitles::SUBTITLE_EXT
+        || allowed.iter().any(|a| a == ext)
+}
+
+pub fn media_type_of(mime: &mime_guess::Mime) -> MediaType {
+    if mime.type_() == "video" {
+        MediaType::Video
+    } else if mime.type_() == "audio" {
+        MediaType::Audio
+    } else if mime.type_() == "image" {
+        MediaType::Image
+    } else {
+        MediaType::Other
+    }
+}
+
+// --- History ---
+
+/// What the index already holds from one channel.
+#[derive(Debug, Clone)]
+pub struct ChannelStats {
+    pub count: i64,
+    pub bytes: i64,
+}
+
+impl ChannelStats {
+    /// e.g. "1.42 GB", or MB below a gigabyte
+    pub fn size(&self) -> String {
+        let mb = self.bytes as f64 / 1024.0 / 1024.0;
+        if mb >= 1024.0 {
+            format!("{:.2} GB", mb / 1024.0)
+        } else {
+            format!("{:.0} MB", mb)
+        }
+    }
+}
+
+/// Files from `channel` still present in the library; None if there are none.
+pub async fn channel_stats(db: &Db, channel: &str) -> Result<Option<ChannelStats>, AppError> {
+    let channel = channel.to_string();
+    let (count, bytes) = db
+        .call(move |conn| {
+            conn.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(size_bytes), 0) FROM items WHERE status = 'present' AND channel = ?1",
+                params![channel],
+                |r| Ok((r.get::<i64>(0)?, r.get::<i64>(1)?)),
+            )
+        })
+        .await?;
+    Ok((count > 0).then_some(ChannelStats { count, bytes }))
+}
+
+// --- Middleware ---
+
+// The library file a request is about, from its path
+fn requested_file(path: &str) -> Option<String> {
+    let rest = ["/content/", "/media/", "/library/items/"].iter().find_map(|p| path.strip_prefix(p))?;
+    // /content keeps a user's folder as a path; the others have one segment
+    let raw = if path.starts_with("/content/") { rest } else { rest.split('/').next()? };
+    Some(percent_encoding::percent_decode_str(raw).decode_utf8_lossy().into_owned())
+}
+
+/// Keeps signed-in users to their own folder of the library: anyone else's
+/// files are not found, on /content, /media and /library/items alike.
+pub async fn owner_layer(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let viewer = req.extensions().get::<SessionId>().and_then(|sid| auth::viewer(&state, sid));
+    if let (Some(name), Some(viewer)) = (requested_file(req.uri().path()), viewer.as_deref()) {
+        if !visible_to(Some(viewer), &name) {
+            return AppError::NotFound(format!("File \"{}\"", name)).into_response();
+        }
+    }
+    next.run(req).await
+}
+
+// --- Handlers ---
+
+/// Serves library files under /content, after `servable` has had its say.
+/// Fetching one from the start counts as a play of it.
+pub async fn serve_content(State(state): State<AppState>, req: Request) -> Response {
+    let raw = req.uri().path().trim_start_matches('/');
+    let name = percent_encoding::percent_decode_str(raw).decode_utf8_lossy().into_owned();
+    if !servable(&name, &state.config.content_extensions) {
+        return AppError::NotFound("File".to_string()).into_response();
+    }
+    let from_start = req.method() == Method::GET
+        && req.headers().get(header::RANGE).is_none_or(|r| r.to_str().is_ok_and(|r| r.starts_with("bytes=0-")));
+    let res = match ServeDir::new(&state.config.downloads_dir).oneshot(req).await {
+        Ok(res) => res.into_response(),
+        Err(never) => match never {},
+    };
+    if from_start && res.status().is_success() {
+        // Players seek with further ranges, which aren't counted again
+        let (db, now) = (state.db.clone(), db::now());
+        tokio::spawn(async move {
+            let counted = db
+                .call(move |conn| {
+                    conn.execute(
+                        "UPDATE items SET plays = plays + 1, last_played_at = ?2 WHERE file_name = ?1",
+                        params![name, now],
+                    )
+                })
+                .await;
+            if let Err(e) = counted {
+                tracing::warn!("Could not count a play: {}", e);
+            }
+        });
+    }
+    res
+}
+
+/// How often `name` was played, and when last.
+pub async fn plays(db: &Db, name: &str) -> Result<(i64, Option<Stamp>), AppError> {
+    let name = name.to_string();
+    db.call(move |conn| {
+        conn.query_row("SELECT plays, last_played_at FROM items WHERE file_name = ?1", params![name], |r| {
+            Ok((r.get(0)?, r.get::<Option<i64>>(1)?.map(Stamp)))
+        })
+        .optional()
+        .map(Option::unwrap_or_default)
+    })
+    .await
+}
+
+/// Plays and the last of them for each file played at all.
+pub async fn played(db: &Db) -> Result<HashMap<String, (i64, Stamp)>, AppError> {
+    db.call(|conn| {
+        let rows = conn.query_map(
+            "SELECT file_name, plays, last_played_at FROM items WHERE plays > 0 AND last_played_at IS NOT NULL",
+            params![],
+            |r| Ok((r.get(0)?, (r.get(1)?, Stamp(r.get(2)?)))),
+        )?;
+        Ok(rows.into_iter().collect())
+    })
+    .await
+}
+
+pub async fn show_media(State(state): State<AppState>, Path(name): Path<String>) -> Result<Response, AppError> {
+    media_page(&state, name, None).await
+}
+
+async fn media_page(state: &AppState, name: String, error: Option<String>) -> Result<Response, AppError> {
+    let dir = &state.config.downloads_dir;
+    let path = library_file(dir, &name)?;
+    let in_history = reconcile::in_history(&state.db, &name).await?;
+    let names = list_names(dir)?;
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    let (plays, last_played) = plays(&state.db, &name).await?;
+
+    Ok(render(MediaTemplate {
+        media_type: media_type_of(&mime),
+        mime_type: mime.to_string(),
+        size_mb: format!("{:.2} MB", size as f64 / 1024.0 / 1024.0),
+        thumbnail: thumbnail_for(&name, &names),
+        compat: compat_for(&name, &names),
+        torrent: torrent_for(&name, &names),
+        subtitles: subtitles_for(&name, &names),
+        ipfs_cid: ipfs::cid(&state.db, &name).await?,
+        ipfs: state.config.ipfs_api.is_some(),
+        meta: load_meta(dir, &name),
+        error,
+        in_history,
+        published: publish::published(state, &name).await?,
+        plays,
+        last_played,
+        name,
+    }))
+}
+
+/// Fetches metadata and thumbnail for a file that arrived without them,
+/// without downloading the media again.
+pub async fn import_metadata(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Form(req): Form<ImportRequest>,
+) -> Result<Response, AppError> {
+    library_file(&state.config.downloads_dir, &name)?;
+    let url = req.url.trim().to_string();
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return media_page(&state, name, Some("Please enter an http:// or https:// URL".to_string())).await;
+    }
+
+    // Same stem as the media file so the sidecars line up with it.
+    // '%' is yt-dlp's template character and has to be doubled.
+    let template = format!("{}/{}.%(ext)s", state.config.downloads_dir, stem(&name).replace('%', "%%"));
+    let mut cmd = Command::new(pinning::ytdlp(&state, &url));
+    cmd.arg("--skip-download")
+        .arg("--write-info-json")
+        .arg("--write-thumbnail")
+        .arg("--no-playlist")
+        .arg("-o")
+        .arg(&template);
+    let _cookies = state.secrets.attach_cookies(&mut cmd).await?;
+    cmd.arg("--").arg(&url);
+
+    let output = state.procs.output("import", cmd).await?;
+    if !output.status.success() {
+        let err = sanitize::text(&String::from_utf8_lossy(&output.stderr));
+        return media_page(&state, name, Some(format!("yt-dlp error: {}", err))).await;
+    }
+
+    // Picks up the new title and source URL
+    reconcile::refresh(&state.db, &state.config.downloads_dir, vec![name.clone()]).await?;
+    Ok(Redirect::to(&format!("/media/{}", sanitize::url_path(&name))).into_response())
+}
@@ -0,0 +1,113 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+
+use crate::error::AppError;
+use crate::session;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+/// Remembers a browser's own choice of lite or full pages for a year;
+/// without it, lite mode follows the client hints.
+pub const LITE_COOKIE: &str = "bsdl_lite";
+
+/// Client hints asked of every browser, so phones can be told apart on the next request.
+pub const ACCEPT_CH: &str = "Sec-CH-UA-Mobile, Sec-CH-Viewport-Width, Viewport-Width, ECT, Save-Data";
+
+/// Format groups the quality step shows in lite mode.
+pub const RECOMMENDED: usize = 3;
+
+// Narrower than this (CSS pixels) counts as a phone
+const PHONE_WIDTH: u32 = 600;
+// Effective connection types of a cellular or otherwise slow link
+const SLOW_LINKS: &[&str] = &["slow-2g", "2g", "3g"];
+
+const MODES: &[&str] = &["on", "off", "auto"];
+
+/// How trimmed the pages of one request are.
+#[derive(Debug, Clone, Copy)]
+pub struct Lite {
+    pub on: bool,
+    /// Picked with the toggle rather than guessed from the hints
+    pub chosen: bool,
+    /// Posters and thumbnails are sent; not in lite mode on a slow or metered link
+    pub thumbnails: bool,
+}
+
+impl Default for Lite {
+    fn default() -> Self {
+        Lite { on: false, chosen: false, thumbnails: true }
+    }
+}
+
+fn hint<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim)
+}
+
+fn phone(headers: &HeaderMap) -> bool {
+    let width = hint(headers, "sec-ch-viewport-width").or_else(|| hint(headers, "viewport-width")).and_then(|w| w.parse::<u32>().ok());
+    match width {
+        Some(w) => w < PHONE_WIDTH,
+        None => hint(headers, "sec-ch-ua-mobile") == Some("?1"),
+    }
+}
+
+fn slow_link(headers: &HeaderMap) -> bool {
+    hint(headers, "save-data").is_some_and(|v| v.eq_ignore_ascii_case("on"))
+        || hint(headers, "ect").is_some_and(|v| SLOW_LINKS.contains(&v))
+}
+
+/// The browser's saved choice, otherwise lite on phones.
+pub fn lite_for(headers: &HeaderMap) -> Lite {
+    let (on, chosen) = match session::cookie(headers, LITE_COOKIE).as_deref() {
+        Some("on") => (true, true),
+        Some("off") => (false, true),
+        _ => (phone(headers), false),
+    };
+    Lite { on, chosen, thumbnails: !on || !slow_link(headers) }
+}
+
+// The page the toggle was pressed on, if the browser says and it's one of ours
+fn back(headers: &HeaderMap, base_path: &str) -> String {
+    let referer = hint(headers, "referer").unwrap_or_default();
+    let path = referer.splitn(4, '/').nth(3).map(|p| format!("/{}", p)).unwrap_or_default();
+    // Redirects get the base path back on the way out
+    let path = match path.strip_prefix(base_path) {
+        Some(rest) if rest.starts_with('/') => rest.to_string(),
+        _ => path,
+    };
+    if path.starts_with('/') && !path.starts_with("//") && !path.contains(['\r', '\n']) {
+        path
+    } else {
+        "/".to_string()
+    }
+}
+
+// --- Handlers ---
+
+#[derive(Deserialize)]
+pub struct LiteRequest {
+    mode: String,
+}
+
+impl Validate for LiteRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::one_of("mode", &self.mode, MODES)
+    }
+}
+
+pub async fn set_lite(State(state): State<AppState>, headers: HeaderMap, Form(req): Form<LiteRequest>) -> Response {
+    let mut res = Redirect::to(&back(&headers, &state.config.base_path)).into_response();
+    // "auto" forgets the choice
+    let cookie = match req.mode.as_str() {
+        "auto" => format!("{}=; Path=/; Max-Age=0; SameSite=Lax", LITE_COOKIE),
+        mode => format!("{}={}; Path=/; Max-Age=31536000; SameSite=Lax", LITE_COOKIE, mode),
+    };
+    if let Ok(value) = cookie.parse() {
+        res.headers_mut().append(header::SET_COOKIE, value);
+    }
+    res
+}
@@ -0,0 +1,171 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::Instrument;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::prelude::*;
+
+// Everything the app logs goes through `tracing`, one line per event on
+// stdout. RUST_LOG picks what shows, as `info` (the default), `debug`, or per
+// target like `info,bplus::http=warn`; the targets are `bplus::http` for
+// requests, `bplus::procs` for the commands run and module paths such as
+// `bplus_streamdlrs_gui::jobs` for the rest.
+//
+// Every request gets an ID, and so does every chain of jobs: the ID of the
+// request that queued it, or a fresh one for subscriptions and the like.
+// Lines logged while handling either carry it (`request{id=...}` or
+// `job{chain=... id=...}`), and so do error pages, the history, notifications
+// and the support bundle, so a complaint quoting it leads to the exact
+// yt-dlp run.
+//
+// The last lines are also kept in memory, for the support bundle: stdout
+// usually ends up in a journal or a terminal the bundle can't read.
+
+const DEFAULT_FILTER: &str = "info";
+
+// Polled every few seconds by every open tab or a container probe, so only logged at debug
+const QUIET_PATHS: &[&str] = &["/api/status", "/favicon.svg", "/healthz", "/readyz"];
+
+/// Sent back on every response, and taken from a proxy in front that sets it.
+pub static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+const ID_LEN: usize = 12;
+// Longest ID taken from a proxy
+const MAX_ID_LEN: usize = 64;
+
+// How many of the latest lines are kept for the support bundle
+const RECENT_LINES: usize = 2000;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+tokio::task_local! {
+    static TRACE: String;
+}
+
+/// Installs the subscriber. Call once, before anything is logged.
+pub fn init() {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(raw) if !raw.trim().is_empty() => raw.trim().parse::<Targets>().unwrap_or_else(|e| {
+            eprintln!("Ignoring RUST_LOG={:?}: {}", raw, e);
+            DEFAULT_FILTER.parse().expect("default filter parses")
+        }),
+        _ => DEFAULT_FILTER.parse().expect("default filter parses"),
+    };
+    // Colours only for a terminal, not a service's log file
+    let ansi = std::io::stdout().is_terminal();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_ansi(ansi))
+        .with(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(Recent))
+        .with(filter)
+        .init();
+}
+
+// --- Recent lines ---
+
+// Hands the formatter a buffer per event, which goes into RECENT when it's done
+#[derive(Clone, Copy)]
+struct Recent;
+
+struct RecentLine(Vec<u8>);
+
+impl<'a> MakeWriter<'a> for Recent {
+    type Writer = RecentLine;
+
+    fn make_writer(&'a self) -> RecentLine {
+        RecentLine(Vec::new())
+    }
+}
+
+impl io::Write for RecentLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecentLine {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.0);
+        let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+        for line in text.lines() {
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line.to_string());
+        }
+    }
+}
+
+/// The latest lines logged, oldest first.
+pub fn recent() -> Vec<String> {
+    RECENT.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+}
+
+// --- Correlation IDs ---
+
+pub fn new_id() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(ID_LEN).map(|c| char::from(c).to_ascii_lowercase()).collect()
+}
+
+/// The ID of the request or job chain being handled, if any.
+pub fn trace_id() -> Option<String> {
+    TRACE.try_with(String::clone).ok()
+}
+
+/// Runs a job chain's step under its ID.
+pub fn in_job<F: Future>(chain: u64, id: String, work: F) -> impl Future<Output = F::Output> {
+    let span = tracing::info_span!("job", chain, id = %id);
+    TRACE.scope(id, work.instrument(span))
+}
+
+// A proxy's ID, if it's short and plain enough to log and show
+fn forwarded_id(req: &Request) -> Option<String> {
+    let id = req.headers().get(&REQUEST_ID)?.to_str().ok()?.trim();
+    let plain = !id.is_empty() && id.len() <= MAX_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    plain.then(|| id.to_string())
+}
+
+// --- Middleware ---
+
+/// Logs each request with its method, path, status and how long it took, and
+/// gives it the ID everything logged while handling it carries.
+pub async fn request_layer(req: Request, next: Next) -> Response {
+    let id = forwarded_id(&req).unwrap_or_else(new_id);
+    let span = tracing::info_span!("request", id = %id);
+    let header = HeaderValue::from_str(&id).ok();
+    let mut res = TRACE.scope(id, logged(req, next).instrument(span)).await;
+    if let Some(value) = header {
+        res.headers_mut().insert(REQUEST_ID.clone(), value);
+    }
+    res
+}
+
+async fn logged(req: Request, next: Next) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let started = Instant::now();
+    let res = next.run(req).await;
+    let status = res.status().as_u16();
+    let ms = started.elapsed().as_millis() as u64;
+    if QUIET_PATHS.contains(&path.as_str()) || path.starts_with("/assets/") {
+        tracing::debug!(target: "bplus::http", %method, %path, status, ms, "request");
+    } else if res.status().is_server_error() {
+        tracing::warn!(target: "bplus::http", %method, %path, status, ms, "request");
+    } else {
+        tracing::info!(target: "bplus::http", %method, %path, status, ms, "request");
+    }
+    res
+}
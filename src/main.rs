@@ -1,14 +1,111 @@
 use axum::{
-    extract::{Form},
-    response::{Html, IntoResponse, Redirect},
+    extract::{DefaultBodyLimit, Query, State},
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Redirect, Response},
     routing::{get, post},
-    Router,
+    Extension, Router,
 };
 use askama::Template;
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use tokio::process::Command;
+use tower::Layer;
 use tower_http::services::ServeDir;
+use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+mod about;
+mod analysis;
+mod api;
+mod auth;
+mod archive;
+mod backup;
+mod batch;
+mod budget;
+mod charts;
+mod clock;
+mod collate;
+mod columns;
+mod config;
+mod control;
+mod csrf;
+mod db;
+mod dedupe;
+mod diagnostics;
+mod entries;
+mod error;
+mod export;
+mod gate;
+mod geo;
+mod health;
+mod history;
+mod http;
+mod ipfs;
+mod jobs;
+mod landing;
+mod library;
+mod lite;
+mod logging;
+mod maintenance;
+mod notify;
+mod openapi;
+mod page;
+mod password;
+#[cfg(feature = "postgres")]
+mod pg;
+mod platform;
+mod prefix;
+mod premieres;
+mod procs;
+mod profiles;
+mod pinning;
+mod publish;
+mod ratelimit;
+mod recipe;
+mod reconcile;
+mod sanitize;
+mod schedule;
+mod search;
+mod secrets;
+mod server;
+mod service;
+mod session;
+mod sites;
+mod smart;
+mod sizes;
+mod start;
+mod subscriptions;
+mod subtitles;
+mod support;
+mod torrent;
+mod transcode;
+mod update;
+mod upload;
+mod users;
+mod validate;
+mod ws;
+
+use config::Config;
+use db::Db;
+use diagnostics::{Check, Status};
+use error::{render, render_status, AppError};
+use jobs::{JobQueue, NewJob, Step};
+use page::FlashKind;
+use procs::ProcessRegistry;
+use sanitize::filters;
+use secrets::Secrets;
+use server::ClientGone;
+use profiles::DeviceProfile;
+use session::{Choice, Selection, SessionId, SessionStore, Wizard};
+use validate::{Form, Validate};
+
+// Output options offered on the quality step
+const CONTAINERS: &[&str] = &["mp4", "mkv", "webm"];
+const AUDIO_FORMATS: &[&str] = &["mp3", "m4a", "opus", "flac"];
+// What the formats of a photo post are
+const IMAGE_EXTS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "avif", "heic"];
 
 // --- Data Structures ---
 
@@ -24,51 +121,249 @@ struct YtDlpFormat {
     filesize_approx: Option<u64>,
     language: Option<String>,
     format_note: Option<String>,
+    #[serde(default)]
+    fps: Option<f64>,
+    /// Total bitrate in kbit/s
+    #[serde(default)]
+    tbr: Option<f64>,
+    /// Video and audio bitrates in kbit/s, when yt-dlp knows them apart
+    #[serde(default)]
+    vbr: Option<f64>,
+    #[serde(default)]
+    abr: Option<f64>,
+    #[serde(default)]
+    audio_channels: Option<u32>,
+    /// SDR, HDR10, HLG...
+    #[serde(default)]
+    dynamic_range: Option<String>,
+    /// https, m3u8_native, http_dash_segments...
+    #[serde(default)]
+    protocol: Option<String>,
+}
+
+/// Where a video belongs, as far as yt-dlp's metadata says.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct SourceLinks {
+    #[serde(default)]
+    channel_url: Option<String>,
+    #[serde(default)]
+    uploader_url: Option<String>,
+    #[serde(default)]
+    playlist_title: Option<String>,
+    #[serde(default)]
+    playlist_webpage_url: Option<String>,
+    /// Position in a post or playlist with several videos, from 1
+    #[serde(default)]
+    playlist_index: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct YtDlpThumbnail {
+    url: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 struct YtDlpOutput {
+    // Flat playlist entries can come without one
+    #[serde(default, deserialize_with = "null_as_default")]
     title: String,
+    // Empty for photo posts, which only come with thumbnails
+    #[serde(default)]
     formats: Vec<YtDlpFormat>,
+    #[serde(default)]
+    thumbnails: Vec<YtDlpThumbnail>,
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(default)]
+    channel: Option<String>,
+    #[serde(default)]
+    uploader: Option<String>,
+    // "is_upcoming" for a premiere or a live stream that hasn't started
+    #[serde(default)]
+    live_status: Option<String>,
+    #[serde(default)]
+    release_timestamp: Option<i64>,
+    #[serde(flatten)]
+    links: SourceLinks,
+    // "url" for a playlist entry --flat-playlist listed without looking at it,
+    // whose page is then `url`
+    #[serde(default, rename = "_type")]
+    kind: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    // Not from yt-dlp: what the cookies added, filled in by run_analysis
+    #[serde(skip)]
+    unlocked: Option<analysis::Unlocked>,
+}
+
+// yt-dlp writes null for what it doesn't know
+fn null_as_default<'de, D: serde::Deserializer<'de>, T: Default + Deserialize<'de>>(d: D) -> Result<T, D::Error> {
+    Ok(Option::<T>::deserialize(d)?.unwrap_or_default())
+}
+
+impl YtDlpOutput {
+    // Not every extractor knows a channel; the uploader is the next best thing
+    fn channel_name(&self) -> Option<String> {
+        sanitize::line_opt(self.channel.clone().or_else(|| self.uploader.clone()))
+    }
+
+    fn upcoming(&self) -> bool {
+        self.live_status.as_deref() == Some("is_upcoming")
+    }
+
+    fn flat(&self) -> bool {
+        matches!(self.kind.as_deref(), Some("url" | "url_transparent"))
+    }
+
+    // A flat entry's own page, to analyze and download it by
+    fn entry_url(&self) -> Option<String> {
+        self.url.clone().filter(|u| self.flat() && (u.starts_with("http://") || u.starts_with("https://")))
+    }
+
+    // A photo post: every format is a picture, or there are no formats and no
+    // running time and the picture is the thumbnail
+    fn photo(&self) -> Option<Choice> {
+        let is_image = |ext: Option<&str>| ext.is_some_and(|e| IMAGE_EXTS.contains(&e.to_lowercase().as_str()));
+        if self.flat() {
+            return None;
+        }
+        if self.formats.is_empty() {
+            let pictured = !self.upcoming() && self.duration.is_none() && !self.thumbnails.is_empty();
+            return pictured.then_some(Choice::Image { thumbnail: true });
+        }
+        self.formats.iter().all(|f| is_image(f.ext.as_deref())).then_some(Choice::Image { thumbnail: false })
+    }
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
     error: Option<String>,
+    url: String,
 }
 
 #[derive(Template)]
 #[template(path = "analyze.html")]
 struct AnalyzeTemplate {
-    url: String,
     title: String,
-    formats: Vec<DisplayFormat>,
+    profiles: Vec<DeviceProfile>,
+    groups: Vec<analysis::FormatGroup>,
     languages: Vec<String>,
+    error: Option<String>,
+    selected: String,
+    selected_profile: i64,
+    compat: bool,
+    container: String,
+    audio_format: String,
+    containers: &'static [&'static str],
+    audio_formats: &'static [&'static str],
+    channel: Option<String>,
+    history: Option<library::ChannelStats>,
+    related: Vec<analysis::Related>,
+    unlocked: Option<analysis::Unlocked>,
+    /// Cookies accounts downloads rotate between
+    accounts: usize,
+    columns: columns::Columns,
+    choices: &'static [(&'static str, &'static str)],
+    lite: lite::Lite,
+    /// Formats left out in lite mode
+    hidden: usize,
+    /// Sizes yt-dlp left out are being looked up
+    probing: bool,
+    /// Some are missing and haven't been looked up yet
+    can_probe: bool,
+    url: String,
+    item: Option<usize>,
+    /// Formats marked geo-blocked, each with a retry form of its own
+    geo_ids: Vec<String>,
+    /// The format check already ran for this analysis
+    geo_checked: bool,
+    countries: &'static [(&'static str, &'static str)],
+    has_proxy: bool,
+    /// The workaround the analysis was asked with
+    tried: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Template)]
+#[template(path = "confirm.html")]
+struct ConfirmTemplate {
+    url: String,
+    title: String,
+    selection: Selection,
+    /// A format of the same quality without fragments, when the pick has them
+    direct: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum MediaType {
     Video,
     Audio,
+    Image,
     Other,
 }
 
 #[derive(Debug)]
 struct FileInfo {
     name: String,
+    /// The name inside the folder shown
+    label: String,
     media_type: MediaType,
     mime_type: String,
     size_mb: String,
+    title: Option<String>,
+    thumbnail: Option<String>,
+    added: Option<clock::Stamp>,
+    /// How often /content served it, and when last
+    played: Option<(i64, clock::Stamp)>,
+    /// The chosen optional columns that are known for this file
+    details: Vec<String>,
+}
+
+/// A folder of the one shown, as its card links to it.
+#[derive(Debug)]
+struct FolderInfo {
+    name: String,
+    href: String,
+    files: usize,
+}
+
+/// One step of the way from the top of the library to the folder shown.
+#[derive(Debug)]
+struct Crumb {
+    label: String,
+    href: String,
 }
 
 #[derive(Template)]
 #[template(path = "file_list.html")]
 struct FileListTemplate {
     files: Vec<FileInfo>,
+    folders: Vec<FolderInfo>,
+    /// Listed in the navigation
+    smart: Vec<smart::SmartFolder>,
+    crumbs: Vec<Crumb>,
+    /// This folder's /files link, for the forms to come back to
+    here: String,
+    locale: String,
+    locales: &'static [(&'static str, &'static str)],
+    channel: Option<String>,
+    columns: columns::Columns,
+    choices: &'static [(&'static str, &'static str)],
+    lite: lite::Lite,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Deserialize)]
+struct OptionsQuery {
+    // Every format, even in lite mode
+    all: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FilesQuery {
+    channel: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct DisplayFormat {
     id: String,
     ext: String,
@@ -76,192 +371,1301 @@ struct DisplayFormat {
     filesize: String,
     codecs: String,
     language: String,
+    fps: String,
+    /// The stream's own bitrate: the audio's or video's alone when it has only one
+    bitrate: String,
+    // Not in queues saved before there were these columns
+    #[serde(default)]
+    channels: String,
+    #[serde(default)]
+    dynamic_range: String,
     type_label: String,
     raw_height: u32,
+    #[serde(default)]
+    raw_fps: u32,
+    /// kbit/s
+    #[serde(default)]
+    raw_bitrate: u32,
+    /// "Premium" or "Account" when only the cookies got this format
+    #[serde(skip)]
+    unlock: Option<&'static str>,
+    /// The site won't serve it here: yt-dlp noted so, or the format check was turned away
+    #[serde(skip)]
+    geo: bool,
+    protocol: String,
+    /// Formats with the same key only differ in how they're delivered
+    group: String,
 }
 
+// A misspelled field is refused rather than quietly left at its default
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct AnalyzeRequest {
     url: String,
+    // The form's token, which csrf::protect has already checked; JSON bodies go without
+    #[serde(default, rename = "csrf")]
+    _csrf: Option<String>,
+    // Set by the age-gate and login guide
+    #[serde(default)]
+    browser: String,
+    #[serde(default)]
+    client: String,
+    // Set by the geo-blocked rows of the format table
+    #[serde(default)]
+    country: String,
+    #[serde(default)]
+    proxy: bool,
+    // One video of a post that holds several
+    #[serde(default)]
+    item: Option<usize>,
 }
 
-// Updated struct to accept file_type
+impl Validate for AnalyzeRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::url("url", &self.url)?;
+        if self.item == Some(0) {
+            return Err(AppError::Invalid { field: "item", reason: "videos are counted from 1".to_string() });
+        }
+        gate::validate_fields(&self.browser, &self.client, &self.country)
+    }
+}
+
+// Step 2 of the wizard: which format, and how to package it
 #[derive(Deserialize)]
-struct DownloadRequest {
-    url: String,
-    format_id: String,
-    file_type: String, 
+struct OptionsRequest {
+    // "p:<profile id>" or "f:<format id>"
+    #[serde(default)]
+    pick: String,
+    container: String,
+    audio_format: String,
+    #[serde(default)]
+    compat: Option<String>,
+}
+
+impl Validate for OptionsRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        // The selects only offer these, so anything else is a hand-made request
+        validate::one_of("container", &self.container, CONTAINERS)?;
+        validate::one_of("audio_format", &self.audio_format, AUDIO_FORMATS)?;
+        // Nothing picked is fine here; the handler asks again
+        match self.pick.split_once(':') {
+            None if self.pick.is_empty() => Ok(()),
+            Some(("f", id)) => validate::format_id("pick", id),
+            Some(("p", id)) if id.parse::<i64>().is_ok() => Ok(()),
+            _ => Err(AppError::Invalid { field: "pick", reason: "expected p:<profile id> or f:<format id>".to_string() }),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Arc<Config>,
+    db: Db,
+    sessions: SessionStore,
+    procs: ProcessRegistry,
+    secrets: Secrets,
+    tokens: auth::Tokens,
+    users: users::Users,
+    jobs: JobQueue,
+    budget: budget::Meter,
+    limiter: ratelimit::Limiter,
+    pins: pinning::Pins,
+    timers: schedule::Timers,
+    // What happened while starting up, shown on the diagnostics page
+    startup: Arc<Vec<Check>>,
 }
 
 // --- Main ---
 
-#[tokio::main]
-async fn main() {
-    let _ = fs::create_dir_all("downloads");
-    let _ = fs::create_dir_all("assets");
+fn main() {
+    let args = match platform::parse_args() {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(2);
+        }
+    };
+    if args.hash_password {
+        let mut line = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut line) {
+            eprintln!("Could not read the password: {}", e);
+            std::process::exit(1);
+        }
+        println!("{}", password::hash(line.trim_end_matches(['\r', '\n'])));
+        return;
+    }
+    if let Err(e) = platform::enter_home(&args) {
+        eprintln!("Could not switch to the app folder: {}", e);
+        std::process::exit(1);
+    }
+    platform::apply_overrides(&args);
+    if args.install_service || args.uninstall_service {
+        let done = if args.install_service { service::install() } else { service::uninstall() };
+        if let Err(e) = done {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // After installing a service, which reads the file itself when it starts
+    let settings = match config::load_file() {
+        Ok(path) => path,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(2);
+        }
+    };
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(2);
+        }
+    };
+    if let Some(words) = &args.ctl {
+        let Some(socket) = config.control_socket else {
+            eprintln!("BSDL_CONTROL_SOCKET is off, so there is nothing to talk to");
+            std::process::exit(1);
+        };
+        std::process::exit(control::client(&socket, words));
+    }
+    // ctl's output is only the answer, so it doesn't log
+    logging::init();
+    if let Some(path) = settings {
+        tracing::info!("Read settings from {}", path.display());
+    }
+
+    // The config was read before the runtime exists, since it decides how many threads that gets
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = config.memory.worker_threads {
+        runtime.worker_threads(threads);
+    }
+    if config.memory.low {
+        // Each blocking thread holds its own stack
+        runtime.max_blocking_threads(8);
+    }
+    match runtime.build() {
+        Ok(runtime) => runtime.block_on(run(config)),
+        Err(e) => {
+            tracing::error!("Could not start the async runtime: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run(config: Config) {
+    let mut startup = Vec::new();
+    for dir in [config.downloads_dir.as_str(), "assets", backup::BACKUP_DIR] {
+        diagnostics::ensure_dir(Path::new(dir), &mut startup);
+    }
+
+    // Multipart framing adds a little on top of the file itself
+    let upload_limit = (config.max_upload_mb as usize + 1) * 1024 * 1024;
+
+    if let Some(dir) = config.db_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        diagnostics::ensure_dir(dir, &mut startup);
+    }
+    // Held until the process exits
+    let _instance = match platform::lock_instance(&config.db_path) {
+        Ok(lock) => lock,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let db = match Db::open(&config.db_path, &config.storage).await {
+        Ok(db) => db,
+        Err(e) if config.storage.url.is_some() => {
+            tracing::error!("Could not open the PostgreSQL database in BSDL_DB_URL: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            tracing::error!("Could not open database {}: {}", config.db_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    // Shared with the timers started before the rest of the state is
+    let timers = schedule::Timers::default();
+    reconcile::spawn_schedule(db.clone(), config.downloads_dir.clone(), config.rescan_minutes, timers.clone());
+    if config.storage.wal && config.storage.url.is_none() {
+        db::spawn_checkpointer(db.clone(), config.storage.checkpoint_minutes, timers.clone());
+    }
+    if let Err(e) = profiles::seed(&db).await {
+        tracing::warn!("Could not set up device profiles: {}", e);
+    }
+    match history::interrupted(&db).await {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("{} download(s) were cut off by the last shutdown", n),
+        Err(e) => tracing::warn!("Could not mark interrupted downloads in the history: {}", e),
+    }
+
+    let secrets = match Secrets::open(db.clone(), &config.secret_key_file) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Could not load the secret key: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Without its tokens the app would be open to anyone, so it doesn't start
+    let tokens = match auth::Tokens::load(&config, &secrets).await {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!("Could not load the API tokens: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let users = match users::Users::load(&config, &db).await {
+        Ok(u) => u,
+        Err(e) => {
+            tracing::error!("Could not load the users: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if tokens.enabled() {
+        let wanted = if config.auth_reads || users.enabled() { "every page" } else { "changes and /system" };
+        tracing::info!("API tokens: {}, wanted for {}", tokens.count(), wanted);
+    }
+    if users.enabled() {
+        tracing::info!("Users: {}, every page wants a sign-in", users.count());
+    }
+    let pins = match pinning::Pins::load(&db).await {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::error!("Could not load the yt-dlp pins: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(since) = pins.choices().rolled_back {
+        tracing::warn!("yt-dlp rolled back to the stable build since {}", since.iso());
+    }
+
+    let listener = match tokio::net::TcpListener::bind((config.host.as_str(), config.port)).await {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::error!("Could not listen on {} port {}: {}", config.host, config.port, e);
+            std::process::exit(1);
+        }
+    };
+    let bound = listener.local_addr().map(|a| a.to_string()).unwrap_or_else(|e| e.to_string());
+    startup.push(Check::new("Listen", Status::Pass, bound));
+    let tls = match config.tls.as_ref().map(server::tls_acceptor).transpose() {
+        Ok(tls) => tls,
+        Err(e) => {
+            tracing::error!("Could not set up HTTPS: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(files) = &config.tls {
+        startup.push(Check::new("HTTPS", Status::Pass, files.cert.display().to_string()));
+    }
+
+    let procs = ProcessRegistry::new(config.limits.clone());
+    let jobs = JobQueue::new(config.memory.keep_finished_jobs, &config.downloads_dir);
+    let state = AppState {
+        config: Arc::new(config),
+        db,
+        sessions: SessionStore::default(),
+        procs,
+        secrets,
+        tokens,
+        users,
+        jobs,
+        budget: budget::Meter::default(),
+        limiter: ratelimit::Limiter::default(),
+        pins,
+        timers,
+        startup: Arc::new(startup),
+    };
+    match state.jobs.restore(&state.db).await {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Picked up {} unfinished job chain(s) from the last run", n),
+        Err(e) => tracing::warn!("Could not restore the job queue, it won't be saved either: {}", e),
+    }
+    jobs::spawn_worker(state.clone());
+    backup::spawn_schedule(state.clone(), state.config.backup_hours);
+    subscriptions::spawn_schedule(state.clone(), state.config.subscription_hours);
+    premieres::spawn_watcher(state.clone());
+    control::spawn(state.clone());
 
     let app = Router::new()
-        .route("/", get(show_index))
-        .route("/analyze", post(analyze_url))
+        .route("/", get(start::show_start))
+        .route("/analyze", get(show_index).post(analyze_url))
+        .route("/search", get(search::show_search))
+        .route("/options", get(show_options).post(choose_options))
+        .route("/options/formats", get(export::download_formats))
+        .route("/options/sizes", get(sizes::sizes_fragment).post(sizes::probe_sizes))
+        .route("/options/geo", post(geo::check_formats))
+        .route("/confirm", get(show_confirm))
         .route("/download", post(download_format))
+        .route("/jobs", get(jobs::show_jobs))
+        .route("/batch", get(batch::show_batch).post(batch::submit_batch))
+        .route("/api/status", get(jobs::status))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/history", get(history::show_history))
+        .route("/history/stats", get(history::show_stats))
+        .route("/history/sites", get(sites::show_board))
+        .route("/history/:id", get(history::show_entry))
+        .route("/api/history", get(history::api_history))
+        .route("/api/v1/analyze", post(api::analyze))
+        .route("/api/v1/download", post(api::download))
+        .route("/api/v1/files", get(api::files))
+        .route("/api/openapi.json", get(openapi::openapi_json))
+        .route("/api/docs", get(openapi::show_docs))
+        .route("/favicon.svg", get(jobs::favicon))
+        .route("/jobs/pause-all", post(jobs::pause_all))
+        .route("/jobs/resume-all", post(jobs::resume_all))
+        .route("/jobs/cancel-queued", post(jobs::cancel_queued))
+        .route("/jobs/retry-failed", post(jobs::retry_failed))
+        .route("/jobs/clear-finished", post(jobs::clear_finished))
+        .route("/jobs/:id/progress", get(jobs::progress))
+        .route("/jobs/:id/pause", post(jobs::pause_chain))
+        .route("/jobs/:id/resume", post(jobs::resume_chain))
+        .route("/jobs/:id/cancel", post(jobs::cancel_chain))
+        .route("/ws", get(ws::socket))
         .route("/files", get(show_files))
+        .route("/files/*folder", get(show_folder))
+        .route("/library/smart", get(smart::show_smart_folders).post(smart::save_smart_folder))
+        .route("/library/smart/:id", get(smart::show_smart_folder))
+        .route("/library/smart/:id/delete", post(smart::delete_smart_folder))
+        .route("/files/sort", post(collate::set_sort))
+        .route("/settings/columns", post(columns::set_columns))
+        .route("/settings/lite", post(lite::set_lite))
+        .route("/settings", get(start::show_settings))
+        .route("/settings/start", post(start::set_start))
+        .route("/files/index", post(archive::export_index))
+        .route("/media/:name", get(library::show_media))
+        .route("/media/:name/import", post(library::import_metadata))
+        .route("/media/:name/ipfs", post(ipfs::pin_file))
+        .route("/library/reconcile", get(reconcile::show_report).post(reconcile::run_now))
+        .route("/library/items/:name/delete", post(reconcile::remove_item))
+        .route("/library/items/:name/restore", post(reconcile::restore_item))
+        .route("/library/items/:name/publish", post(publish::publish_file))
+        .route("/library/duplicates", get(dedupe::show_duplicates).post(dedupe::scan_duplicates))
+        .route("/library/duplicates/link", post(dedupe::link_duplicate))
+        .route(
+            "/library/duplicates/archive",
+            post(dedupe::upload_archive).layer(DefaultBodyLimit::max(dedupe::MAX_ARCHIVE_BYTES + 64 * 1024)),
+        )
+        .route("/library/duplicates/delete", post(dedupe::delete_duplicate))
+        .route(
+            "/upload",
+            get(upload::show_upload)
+                .post(upload::upload_files)
+                .layer(DefaultBodyLimit::max(upload_limit)),
+        )
+        .route("/settings/profiles", get(profiles::show_profiles).post(profiles::save_profile))
+        .route("/settings/profiles/:id/delete", post(profiles::delete_profile))
+        .route("/subscriptions", get(subscriptions::show_subscriptions).post(subscriptions::save_subscription))
+        .route("/subscriptions/:id/check", post(subscriptions::check_now))
+        .route("/subscriptions/:id/delete", post(subscriptions::delete_subscription))
+        .route("/premieres", get(premieres::show_premieres).post(premieres::watch))
+        .route("/premieres/:id/check", post(premieres::check_now))
+        .route("/premieres/:id/delete", post(premieres::delete_premiere))
+        .route("/schedule", get(schedule::show_schedule))
+        .route("/schedule/run", post(schedule::run_now))
+        .route("/system/backups", get(backup::show_backups).post(backup::create_now))
+        .route("/system/backups/:name/restore", post(backup::restore_backup))
+        .route(
+            "/system/secrets",
+            get(secrets::show_secrets)
+                .post(secrets::save_secret)
+                .layer(DefaultBodyLimit::max(secrets::MAX_SECRET_BYTES)),
+        )
+        .route("/analyze/entries", post(entries::queue_picked))
+        .route("/analyze/entries/whole", post(entries::queue_whole))
+        .route("/analyze/cookies", post(gate::save_cookies).layer(DefaultBodyLimit::max(secrets::MAX_SECRET_BYTES)))
+        .route("/system/secrets/:name/delete", post(secrets::delete_secret))
+        .route("/system/maintenance", get(maintenance::show_maintenance).post(maintenance::start))
+        .route("/system/maintenance/off", post(maintenance::stop))
+        .route("/about", get(about::show_about))
+        .route("/login", get(auth::show_login).post(auth::login))
+        .route("/logout", post(auth::logout))
+        .route("/system/tokens", get(auth::show_tokens).post(auth::create_token))
+        .route("/system/tokens/:name/revoke", post(auth::revoke_token))
+        .route("/system/users", get(users::show_users).post(users::save_user))
+        .route("/system/users/:name/remove", post(users::remove_user))
+        .route("/system/diagnostics", get(diagnostics::show_diagnostics))
+        .route("/system/support-bundle", get(support::download_bundle))
+        .route("/system/update", get(update::show_update))
+        .route("/system/ytdlp", get(pinning::show_builds))
+        .route("/system/ytdlp/default", post(pinning::set_default))
+        .route("/system/ytdlp/pins", post(pinning::pin_site))
+        .route("/system/ytdlp/pins/:site/delete", post(pinning::unpin_site))
+        .route("/system/ytdlp/rollback", post(pinning::roll_back))
+        .route("/system/ytdlp/rollback/off", post(pinning::undo_rollback))
+        .route("/system/update/install", post(update::install_update))
+        .route("/system/processes", get(procs::show_processes))
+        .route("/system/processes/kill-all", post(procs::kill_all))
         .nest_service("/assets", ServeDir::new("assets"))
-        .nest_service("/content", ServeDir::new("downloads"));
+        .nest_service("/content", get(library::serve_content).with_state(state.clone()))
+        .fallback(not_found)
+        .layer(DefaultBodyLimit::max(validate::MAX_FORM_BYTES))
+        .layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit_layer))
+        .layer(middleware::from_fn_with_state(state.clone(), csrf::csrf_layer))
+        .layer(middleware::from_fn_with_state(state.clone(), library::owner_layer))
+        .layer(middleware::from_fn_with_state(state.clone(), auth::auth_layer))
+        .layer(middleware::from_fn(error::error_layer))
+        .layer(middleware::from_fn_with_state(state.clone(), page::context_layer))
+        .layer(middleware::from_fn_with_state(state.clone(), session::session_layer))
+        .layer(middleware::from_fn(logging::request_layer))
+        .with_state(state.clone());
+    // Around the router rather than in it: layers of a router only run once a route is picked
+    let app = Router::new().fallback_service(middleware::from_fn_with_state(state.clone(), prefix::prefix_layer).layer(app));
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    match listener.local_addr() {
+        // Every interface, so this machine's own name for it works
+        Ok(addr) if addr.ip().is_unspecified() => {
+            tracing::info!("Server running on {}://localhost:{}{}/", scheme, addr.port(), state.config.base_path)
+        }
+        Ok(addr) => tracing::info!("Server running on {}://{}{}/", scheme, addr, state.config.base_path),
+        Err(_) => tracing::info!("Server running"),
+    }
+    let server = server::serve(listener, app, tls);
+    tokio::pin!(server);
+    let stopped = |served: std::io::Result<()>| {
+        if let Err(e) = served {
+            tracing::error!("Server stopped: {}", e);
+            std::process::exit(1);
+        }
+    };
+    tokio::select! {
+        served = &mut server => return stopped(served),
+        _ = platform::shutdown_signal() => {}
+    }
+    // Pages still answer while the running jobs finish
+    tokio::select! {
+        served = &mut server => return stopped(served),
+        _ = drain(&state) => {}
+    }
+
+    // What's still running is saved as running and starts over next time
+    state.jobs.shut_down();
+    // Children would otherwise outlive us, half-way through a download
+    let stopped = stop_children(&state).await;
+    tracing::info!("Shutting down, stopped {} child process(es)", stopped);
+    if let Some(socket) = &state.config.control_socket {
+        let _ = fs::remove_file(socket);
+    }
+    // Leaves a single database file behind for whoever copies it next
+    if state.config.storage.wal {
+        if let Err(e) = state.db.checkpoint().await {
+            tracing::warn!("Final WAL checkpoint failed: {}", e);
+        }
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+}
+
+// How long children get to exit on SIGTERM before they're killed
+const CHILD_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Lets running jobs finish, for up to BSDL_SHUTDOWN_TIMEOUT or until a
+// second Ctrl-C or SIGTERM, with nothing new started or accepted meanwhile
+async fn drain(state: &AppState) {
+    state.jobs.drain();
+    let running = state.jobs.summary().running;
+    let timeout = state.config.shutdown_timeout;
+    if running == 0 || timeout == 0 {
+        return;
+    }
+    tracing::info!("Shutting down once {} running job(s) finish, waiting up to {}s (stop again to not wait)", running, timeout);
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(timeout);
+    let finished = async {
+        while state.jobs.summary().running > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    };
+    tokio::select! {
+        _ = finished => tracing::info!("Running jobs finished"),
+        _ = tokio::time::sleep_until(deadline) => {
+            tracing::warn!("{} job(s) still running after {}s; they start over next time", state.jobs.summary().running, timeout);
+        }
+        _ = platform::shutdown_signal() => tracing::info!("Not waiting any longer; running jobs start over next time"),
+    }
+}
 
-    println!("Server running on http://localhost:3000");
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+// Asks the children to stop, then kills those that haven't after CHILD_GRACE.
+// Returns how many there were.
+async fn stop_children(state: &AppState) -> usize {
+    let running = state.procs.count();
+    if running == 0 {
+        return 0;
+    }
+    if state.procs.terminate_all().await > 0 {
+        let deadline = tokio::time::Instant::now() + CHILD_GRACE;
+        while state.procs.count() > 0 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+    let killed = state.procs.kill_all();
+    if killed > 0 {
+        tracing::warn!("Killed {} child process(es) that didn't stop on SIGTERM", killed);
+    }
+    running
 }
 
 // --- Handlers ---
 
-async fn show_index() -> impl IntoResponse {
-    IndexTemplate { error: None }
+async fn not_found() -> AppError {
+    AppError::NotFound("Page".to_string())
 }
 
-async fn analyze_url(Form(input): Form<AnalyzeRequest>) -> impl IntoResponse {
-    let output = Command::new("./yt-dlp_linux")
-        .arg("--dump-json")
-        .arg(&input.url)
-        .output();
+// Step 1: choose source
+async fn show_index(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    // Going back to step 1 keeps the last URL in the box
+    let url = state.sessions.get(&sid).wizard.map(|w| w.url).unwrap_or_default();
+    render(IndexTemplate { error: None, url })
+}
 
-    match output {
-        Ok(o) => {
-            if !o.status.success() {
-                let err_msg = String::from_utf8_lossy(&o.stderr).to_string();
-                return IndexTemplate { error: Some(format!("yt-dlp error: {}", err_msg)) }.into_response();
+async fn analyze_url(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Extension(gone): Extension<ClientGone>,
+    Form(input): Form<AnalyzeRequest>,
+) -> Response {
+    if let Err(e) = gate::check_proxy(&state.config, input.proxy) {
+        return e.into_response();
+    }
+    let workaround = gate::Workaround::from_form(&input.browser, &input.client, &input.country, input.proxy);
+    analyze(&state, &sid, &gone, input.url.trim().to_string(), workaround, input.item).await
+}
+
+// Step 1 proper, shared with the guide's cookie upload
+async fn analyze(
+    state: &AppState,
+    sid: &SessionId,
+    gone: &ClientGone,
+    url: String,
+    workaround: Option<gate::Workaround>,
+    item: Option<usize>,
+) -> Response {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        let page = IndexTemplate { error: Some("Please enter an http:// or https:// URL".to_string()), url };
+        return render_status(StatusCode::UNPROCESSABLE_ENTITY, page);
+    }
+
+    let viewer = auth::viewer(state, sid);
+    let meta = match cached_analysis(state, &url, workaround.as_ref(), item).await {
+        Some(meta) => meta,
+        None => match run_analysis(state, viewer.as_deref(), &url, gone, workaround.as_ref(), item).await {
+            Ok(mut entries) if entries.len() == 1 && !entries[0].flat() => entries.remove(0),
+            Ok(entries) => {
+                history::analyzed(&state.db, viewer.as_deref(), &url, &format!("{} videos", entries.len()), "Done", None).await;
+                return entries::page(state, sid, entries::Post::new(&url, entries, workaround));
             }
+            Err(res) => return res,
+        },
+    };
+    history::analyzed(&state.db, viewer.as_deref(), &url, &sanitize::line(&meta.title), "Done", None).await;
 
-            let json_str = String::from_utf8_lossy(&o.stdout);
-            let meta: YtDlpOutput = match serde_json::from_str(&json_str) {
-                Ok(m) => m,
-                Err(_) => return IndexTemplate { error: Some("Failed to parse JSON from yt-dlp".to_string()) }.into_response(),
-            };
+    let channel = meta.channel_name();
+    let related = analysis::related(&url, &meta, channel.as_deref());
 
-            let mut display_formats = Vec::new();
-            let mut languages = Vec::new();
-
-            for f in meta.formats {
-                let is_audio = f.acodec.as_deref().unwrap_or("none") != "none";
-                let is_video = f.vcodec.as_deref().unwrap_or("none") != "none";
-
-                let type_label = if is_audio && is_video {
-                    "Video+Audio"
-                } else if is_video {
-                    "Video Only"
-                } else {
-                    "Audio Only"
-                };
-
-                let size = f.filesize.or(f.filesize_approx).unwrap_or(0);
-                let size_str = if size > 0 {
-                    format!("{:.2} MB", size as f64 / 1024.0 / 1024.0)
-                } else {
-                    "Unknown".to_string()
-                };
-
-                let res = if let (Some(w), Some(h)) = (f.width, f.height) {
-                    format!("{}x{}", w, h)
-                } else {
-                    "Audio".to_string()
-                };
-
-                let lang = f.language.clone().unwrap_or_else(|| "Unknown".to_string());
-                if lang != "Unknown" && !languages.contains(&lang) {
-                    languages.push(lang.clone());
-                }
+    // Nothing to download before a premiere starts; offer to wait for it
+    if meta.upcoming() {
+        return premieres::offer(state, &url, &meta).await;
+    }
+    // Photo posts skip the format table; there's only the picture to take
+    if let Some(choice) = meta.photo() {
+        let selection = Selection { workaround: workaround.clone(), item, ..Selection::photo(choice) };
+        state.sessions.update(sid, |s| {
+            s.post = None;
+            s.wizard = Some(Wizard {
+                url,
+                title: sanitize::line(&meta.title),
+                channel,
+                related,
+                unlocked: None,
+                workaround,
+                item,
+                formats: Vec::new(),
+                source_formats: meta.formats,
+                languages: Vec::new(),
+                selection: Some(selection),
+            });
+        });
+        return Redirect::to("/confirm").into_response();
+    }
+    let source_formats = meta.formats.clone();
+    let (display_formats, languages) = format_table(&url, meta.formats, meta.unlocked.as_ref());
+
+    state.sessions.update(sid, |s| {
+        s.post = None;
+        s.wizard = Some(Wizard {
+            url,
+            title: sanitize::line(&meta.title),
+            channel,
+            related,
+            unlocked: meta.unlocked,
+            workaround,
+            item,
+            formats: display_formats,
+            source_formats,
+            languages,
+            selection: None,
+        });
+    });
+    Redirect::to("/options").into_response()
+}
+
+// A recent answer from the analysis cache, which only has whole URLs asked without a workaround
+async fn cached_analysis(state: &AppState, url: &str, workaround: Option<&gate::Workaround>, item: Option<usize>) -> Option<YtDlpOutput> {
+    if workaround.is_some() || item.is_some() {
+        return None;
+    }
+    analysis::cached(&state.db, url, state.config.analysis_cache_minutes).await.unwrap_or_else(|e| {
+        tracing::warn!("Analysis cache lookup for {} failed: {}", url, e);
+        None
+    })
+}
+
+// The rows of the format table, and the languages to filter them by
+fn format_table(url: &str, formats: Vec<YtDlpFormat>, unlocked: Option<&analysis::Unlocked>) -> (Vec<DisplayFormat>, Vec<String>) {
+    let mut display_formats = Vec::new();
+    let mut languages = Vec::new();
 
-                display_formats.push(DisplayFormat {
-                    id: f.format_id,
-                    ext: f.ext.unwrap_or_default(),
-                    resolution: res,
-                    filesize: size_str,
-                    codecs: format!("{}/{}", f.vcodec.unwrap_or("none".into()), f.acodec.unwrap_or("none".into())),
-                    language: lang,
-                    type_label: type_label.to_string(),
-                    raw_height: f.height.unwrap_or(0),
-                });
+    for f in formats {
+        // Never offered, so never passed to yt-dlp
+        if validate::format_id("format_id", &f.format_id).is_err() {
+            tracing::warn!("Skipping format with unusable id {:?} for {}", f.format_id, url);
+            continue;
+        }
+        let is_audio = f.acodec.as_deref().unwrap_or("none") != "none";
+        let is_video = f.vcodec.as_deref().unwrap_or("none") != "none";
+
+        let type_label = if is_audio && is_video {
+            "Video+Audio"
+        } else if is_video {
+            "Video Only"
+        } else {
+            "Audio Only"
+        };
+
+        let size = f.filesize.or(f.filesize_approx).unwrap_or(0);
+        let size_str = if size > 0 {
+            format!("{:.2} MB", size as f64 / 1024.0 / 1024.0)
+        } else {
+            sizes::UNKNOWN.to_string()
+        };
+
+        let res = if let (Some(w), Some(h)) = (f.width, f.height) {
+            format!("{}x{}", w, h)
+        } else {
+            "Audio".to_string()
+        };
+
+        // yt-dlp notes YouTube's Premium bitrates as such; anything else the cookies got is just "Account"
+        let unlock = unlocked.filter(|u| u.contains(&f.format_id)).map(|_| {
+            match f.format_note.as_deref() {
+                Some(note) if note.to_lowercase().contains("premium") => "Premium",
+                _ => "Account",
             }
+        });
+        let geo = geo::noted(f.format_note.as_deref());
+        let lang = sanitize::line_opt(f.language.clone()).unwrap_or_else(|| "Unknown".to_string());
+        if lang != "Unknown" && !languages.contains(&lang) {
+            languages.push(lang.clone());
+        }
 
-            languages.sort();
+        // Resolution, frame rate, dynamic range, codec family, container and
+        // language; the CDN and protocol don't count
+        let family = |codec: &Option<String>| codec.as_deref().unwrap_or("none").split('.').next().unwrap_or_default().to_string();
+        let group = format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}|{}",
+            type_label,
+            res,
+            f.fps.map(|fps| fps.round() as u32).unwrap_or(0),
+            f.dynamic_range.as_deref().unwrap_or("SDR"),
+            family(&f.vcodec),
+            family(&f.acodec),
+            f.ext.as_deref().unwrap_or_default(),
+            lang,
+            unlock.unwrap_or_default()
+        );
+        let bitrate = match (is_video, is_audio) {
+            (true, false) => f.vbr.or(f.tbr),
+            (false, true) => f.abr.or(f.tbr),
+            _ => f.tbr,
+        }
+        .filter(|br| *br > 0.0);
+        let fps = f.fps.filter(|fps| *fps > 0.0).map(|fps| fps.round() as u32);
+        display_formats.push(DisplayFormat {
+            id: f.format_id,
+            ext: sanitize::line_opt(f.ext).unwrap_or_default(),
+            resolution: res,
+            filesize: size_str,
+            codecs: sanitize::line(&format!("{}/{}", f.vcodec.unwrap_or("none".into()), f.acodec.unwrap_or("none".into()))),
+            language: lang,
+            fps: fps.map(|fps| fps.to_string()).unwrap_or_default(),
+            bitrate: bitrate.map(|br| format!("{:.0}k", br)).unwrap_or_default(),
+            channels: f.audio_channels.filter(|_| is_audio).map(library::channel_layout).unwrap_or_default(),
+            dynamic_range: if is_video { sanitize::line_opt(f.dynamic_range).unwrap_or_default() } else { String::new() },
+            type_label: type_label.to_string(),
+            raw_height: f.height.unwrap_or(0),
+            raw_fps: fps.unwrap_or(0),
+            raw_bitrate: bitrate.map(|br| br.round() as u32).unwrap_or(0),
+            unlock,
+            geo,
+            protocol: sanitize::line_opt(f.protocol).unwrap_or_default(),
+            group,
+        });
+    }
+
+    languages.sort();
+    (display_formats, languages)
+}
 
-            Html(AnalyzeTemplate { 
-                url: input.url, 
-                title: meta.title, 
-                formats: display_formats,
-                languages 
-            }.render().unwrap()).into_response()
+// Asks yt-dlp about `url` and remembers the answer for the options step
+async fn run_analysis(
+    state: &AppState,
+    owner: Option<&str>,
+    url: &str,
+    gone: &ClientGone,
+    workaround: Option<&gate::Workaround>,
+    item: Option<usize>,
+) -> Result<Vec<YtDlpOutput>, Response> {
+    let asked = ask_ytdlp(state, url, gone, workaround, item).await;
+    if let Err(e) = &asked {
+        let status = if gone.is_cancelled() { "Abandoned" } else { "Failed" };
+        history::analyzed(&state.db, owner, url, "", status, e.error()).await;
+    }
+    match asked {
+        Ok(entries) => Ok(entries),
+        Err(Unanswered::Abandoned) => Err(Redirect::to(start::ANALYZER).into_response()),
+        Err(Unanswered::Gated(g, detail)) => Err(gate::page(state, url, g, detail, workaround).await),
+        Err(Unanswered::Failed(msg)) => {
+            Err(render_status(StatusCode::BAD_GATEWAY, IndexTemplate { error: Some(msg), url: url.to_string() }))
         }
-        Err(e) => IndexTemplate { error: Some(e.to_string()) }.into_response(),
+        Err(Unanswered::App(e)) => Err(e.into_response()),
     }
 }
 
-async fn download_format(Form(req): Form<DownloadRequest>) -> impl IntoResponse {
-    let mut cmd = Command::new("./yt-dlp_linux");
-    
-    // Logic: If Audio Only, convert to MP3. If Video, merge to MP4.
-    if req.file_type == "Audio Only" {
-        cmd.arg("-f")
-           .arg(&req.format_id)
-           .arg("-x")                  // Extract audio
-           .arg("--audio-format")      // Convert to...
-           .arg("mp3")                 // ...mp3
-           .arg("-o")
-           .arg("downloads/%(title)s.%(ext)s")
-           .arg(&req.url);
-    } else {
-        // Video logic
-        cmd.arg("-f")
-           .arg(&req.format_id)
-           .arg("--merge-output-format")
-           .arg("mp4")
-           .arg("-o")
-           .arg("downloads/%(title)s.%(ext)s")
-           .arg(&req.url);
+// Why yt-dlp had no answer about a URL
+enum Unanswered {
+    /// The client went away, and yt-dlp with it
+    Abandoned,
+    /// An age gate or login wall, with what yt-dlp said
+    Gated(gate::Gate, String),
+    Failed(String),
+    App(AppError),
+}
+
+impl Unanswered {
+    /// What the history keeps of it
+    fn error(&self) -> Option<String> {
+        match self {
+            Unanswered::Abandoned => None,
+            Unanswered::Gated(g, detail) => Some(format!("{}: {}", g.title(), detail)),
+            Unanswered::Failed(msg) => Some(msg.clone()),
+            Unanswered::App(e) => Some(e.to_string()),
+        }
     }
+}
 
-    let status = cmd.status();
+impl From<AppError> for Unanswered {
+    fn from(e: AppError) -> Self {
+        Unanswered::App(e)
+    }
+}
 
-    match status {
-        Ok(s) if s.success() => Redirect::to("/files").into_response(),
-        _ => Html("<h1>Download Failed</h1><a href='/'>Go Back</a>".to_string()).into_response(),
+// `run_analysis` without the pages, for the JSON API too
+async fn ask_ytdlp(
+    state: &AppState,
+    url: &str,
+    gone: &ClientGone,
+    workaround: Option<&gate::Workaround>,
+    item: Option<usize>,
+) -> Result<Vec<YtDlpOutput>, Unanswered> {
+    // The child is tied to this request: if the browser goes away we drop the
+    // output future and kill_on_drop takes yt-dlp down with it.
+    let mut cmd = Command::new(pinning::ytdlp(state, url));
+    cmd.arg("--dump-json").args(scope(item));
+    let stored = match workaround {
+        Some(w) if w.replaces_cookies() => None,
+        _ => state.secrets.cookie_accounts().await?.into_iter().next(),
+    };
+    let _cookies = match &stored {
+        Some(name) => state.secrets.attach_account(&mut cmd, name).await?,
+        None => None,
+    };
+    // Who to credit for formats only the cookies get
+    let account = match workaround {
+        Some(w) if w.replaces_cookies() => Some(w.label()),
+        _ => stored.map(|name| match name.strip_prefix(secrets::ACCOUNT_PREFIX) {
+            Some(account) => format!("the {} cookies account", account),
+            None => "the stored cookies".to_string(),
+        }),
+    };
+    if let Some(w) = workaround {
+        cmd.args(w.ytdlp_args(&state.config));
     }
+    // Nothing after this is read as an option
+    cmd.arg("--").arg(url);
+
+    // The same question without cookies, side by side, tells what they added
+    let anonymous = (account.is_some() && state.config.compare_without_cookies).then(|| {
+        let mut cmd = Command::new(pinning::ytdlp(state, url));
+        cmd.arg("--dump-json").args(scope(item));
+        if let Some(w) = workaround.filter(|w| !w.replaces_cookies()) {
+            cmd.args(w.ytdlp_args(&state.config));
+        }
+        cmd.arg("--").arg(url);
+        cmd
+    });
+    let run = async {
+        match anonymous {
+            Some(anonymous) => {
+                let (with, without) =
+                    tokio::join!(state.procs.output("analyze", cmd), state.procs.output("analyze", anonymous));
+                (with, Some(without))
+            }
+            None => (state.procs.output("analyze", cmd).await, None),
+        }
+    };
+
+    let (output, without) = tokio::select! {
+        out = run => out,
+        _ = gone.cancelled() => {
+            tracing::info!("Analyze of {} abandoned by client, yt-dlp killed", url);
+            return Err(Unanswered::Abandoned);
+        }
+    };
+
+    let o = output.map_err(|e| Unanswered::Failed(e.to_string()))?;
+    if !o.status.success() {
+        let err_msg = sanitize::text(&String::from_utf8_lossy(&o.stderr));
+        if let Some(g) = gate::Gate::detect(&err_msg) {
+            return Err(Unanswered::Gated(g, err_msg));
+        }
+        return Err(Unanswered::Failed(format!("yt-dlp error: {}", err_msg)));
+    }
+    let mut entries =
+        parse_entries(&o.stdout).map_err(|_| Unanswered::Failed("Failed to parse JSON from yt-dlp".to_string()))?;
+    if entries.is_empty() {
+        return Err(Unanswered::Failed("yt-dlp found no video at this URL".to_string()));
+    }
+    let listed = entries.len() > 1 || entries[0].flat();
+    if !listed && entries[0].formats.is_empty() && entries[0].photo().is_none() && !entries[0].upcoming() {
+        // --ignore-no-formats-error turns what would have failed into warnings
+        let warnings = sanitize::text(&String::from_utf8_lossy(&o.stderr));
+        if let Some(g) = gate::Gate::detect(&warnings) {
+            return Err(Unanswered::Gated(g, warnings));
+        }
+        return Err(Unanswered::Failed(format!("yt-dlp found nothing to download: {}", warnings)));
+    }
+    if listed {
+        // Posts with several videos and playlists are picked from before anything is compared or cached
+        return Ok(entries);
+    }
+    let mut meta = entries.remove(0);
+    if let (Some(account), Some(without)) = (account, without) {
+        let without = without
+            .ok()
+            .filter(|o| o.status.success())
+            .and_then(|o| parse_entries(&o.stdout).ok())
+            .filter(|e| e.len() == 1)
+            .and_then(|mut e| e.pop());
+        meta.unlocked = analysis::unlocked(account, &meta.formats, without.as_ref().map(|m| m.formats.as_slice()));
+    }
+
+    // The cache keeps formats, not thumbnails, so photos taken from thumbnails aren't cached;
+    // nor are premieres, which change once they start
+    if state.config.analysis_cache_minutes > 0 && item.is_none() && !meta.formats.is_empty() && !meta.upcoming() {
+        if let Err(e) = analysis::store(&state.db, url, &meta, state.config.analysis_cache_minutes).await {
+            tracing::warn!("Could not cache the analysis of {}: {}", url, e);
+        }
+    }
+    Ok(vec![meta])
 }
 
-async fn show_files() -> impl IntoResponse {
-    let mut files = Vec::new();
-    if let Ok(entries) = fs::read_dir("downloads") {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Ok(name) = entry.file_name().into_string() {
-                    if !name.starts_with(".") {
-                        let mime = mime_guess::from_path(&path).first_or_octet_stream();
-                        let mime_str = mime.to_string();
-                        
-                        let media_type = if mime.type_() == "video" {
-                            MediaType::Video
-                        } else if mime.type_() == "audio" {
-                            MediaType::Audio
-                        } else {
-                            MediaType::Other
-                        };
-
-                        let metadata = fs::metadata(&path).ok();
-                        let size = metadata.map(|m| m.len()).unwrap_or(0);
-                        let size_mb = format!("{:.2} MB", size as f64 / 1024.0 / 1024.0);
-
-                        files.push(FileInfo {
-                            name,
-                            media_type,
-                            mime_type: mime_str,
-                            size_mb
-                        });
-                    }
+// A watch URL inside a playlist would otherwise dump every video in it; the
+// playlist is offered as a related link instead. A playlist's own URL lists
+// its entries flat, without looking at each one, and each is analyzed by its
+// own URL once picked. One video of a post is picked by its position.
+// Posts without a video still describe their pictures with --ignore-no-formats-error.
+fn scope(item: Option<usize>) -> Vec<String> {
+    let mut args = vec!["--ignore-no-formats-error".to_string()];
+    match item {
+        Some(i) => args.extend(["--playlist-items".to_string(), i.to_string()]),
+        None => args.extend(["--no-playlist".to_string(), "--flat-playlist".to_string()]),
+    }
+    args
+}
+
+// --dump-json prints one object per video
+fn parse_entries(stdout: &[u8]) -> Result<Vec<YtDlpOutput>, serde_json::Error> {
+    stdout
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .map(serde_json::from_slice)
+        .collect()
+}
+
+// Step 2: choose quality/options
+async fn show_options(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    headers: HeaderMap,
+    Query(query): Query<OptionsQuery>,
+) -> Result<Response, AppError> {
+    let session = state.sessions.get(&sid);
+    match session.wizard {
+        Some(w) => options_page(&state, &headers, w, session.sizes, session.geo, None, query.all.is_some()).await,
+        None => Ok(Redirect::to(start::ANALYZER).into_response()),
+    }
+}
+
+async fn choose_options(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    headers: HeaderMap,
+    Form(req): Form<OptionsRequest>,
+) -> Result<Response, AppError> {
+    let Some(mut wizard) = state.sessions.get(&sid).wizard else {
+        return Ok(Redirect::to(start::ANALYZER).into_response());
+    };
+
+    // The pick has the right shape by now; it also has to be something this URL offered
+    let choice = match req.pick.split_once(':') {
+        Some(("f", id)) => match wizard.formats.iter().find(|f| f.id == id) {
+            Some(f) => {
+                let mut f = f.clone();
+                // Or the size looked up since
+                if let Some(size) = state.sessions.get(&sid).sizes.filter(|p| p.url == wizard.url).and_then(|p| p.found.get(id).cloned()) {
+                    f.filesize = size;
                 }
+                Some(Choice::Format(Box::new(f)))
             }
+            None => {
+                return Err(AppError::Invalid {
+                    field: "pick",
+                    reason: format!("format {} was not offered for this URL", id),
+                })
+            }
+        },
+        Some(("p", id)) => match profiles::get(&state.db, id.parse().unwrap_or_default()).await? {
+            Some(p) => Some(Choice::Profile(p)),
+            None => return Err(AppError::NotFound("Device profile".to_string())),
+        },
+        _ => None,
+    };
+    let Some(choice) = choice else {
+        let session = state.sessions.get(&sid);
+        let error = Some("Pick a device profile or one of the listed formats".to_string());
+        return options_page(&state, &headers, wizard, session.sizes, session.geo, error, false).await;
+    };
+
+    // A profile brings its own container
+    let container = match &choice {
+        Choice::Profile(p) => p.container.clone(),
+        Choice::Format(_) | Choice::BestVideo | Choice::BestAudio | Choice::Image { .. } => req.container,
+    };
+    wizard.selection = Some(Selection {
+        choice,
+        container,
+        audio_format: req.audio_format,
+        compat: req.compat.is_some(),
+        workaround: wizard.workaround.clone(),
+        item: wizard.item,
+        date_after: None,
+        playlist: false,
+    });
+    state.sessions.update(&sid, |s| s.wizard = Some(wizard));
+    Ok(Redirect::to("/confirm").into_response())
+}
+
+// `all` shows every format even in lite mode
+async fn options_page(
+    state: &AppState,
+    headers: &HeaderMap,
+    w: Wizard,
+    sizes: Option<sizes::Probe>,
+    checked: Option<geo::Check>,
+    error: Option<String>,
+    all: bool,
+) -> Result<Response, AppError> {
+    // A photo has no formats to pick from
+    if w.selection.as_ref().is_some_and(Selection::is_photo) {
+        return Ok(Redirect::to("/confirm").into_response());
+    }
+    // Re-entering the step keeps what was picked last time
+    let compat = w.selection.as_ref().is_some_and(|s| s.compat);
+    let (selected, selected_profile, container, audio_format) = match &w.selection {
+        Some(sel) => {
+            let (format, profile) = match &sel.choice {
+                Choice::Format(f) => (f.id.clone(), 0),
+                Choice::Profile(p) => (String::new(), p.id),
+                // The wizard itself never picks these
+                Choice::BestVideo | Choice::BestAudio | Choice::Image { .. } => (String::new(), 0),
+            };
+            (format, profile, sel.container.clone(), sel.audio_format.clone())
+        }
+        None => (String::new(), 0, CONTAINERS[0].to_string(), AUDIO_FORMATS[0].to_string()),
+    };
+
+    let history = match &w.channel {
+        Some(c) => library::channel_stats(&state.db, c).await?,
+        None => None,
+    };
+
+    // Sizes looked up since, if they were for this analysis
+    let probe = sizes.filter(|p| p.url == w.url);
+    let checked = checked.filter(|c| c.of(&w));
+    let mut formats = w.formats;
+    if let Some(p) = &probe {
+        for f in formats.iter_mut() {
+            if let Some(size) = p.found.get(&f.id) {
+                f.filesize = size.clone();
+            }
+        }
+    }
+    let probing = probe.as_ref().is_some_and(|p| p.running);
+    let can_probe = probe.is_none() && state.config.size_probes > 0 && formats.iter().any(|f| f.filesize == sizes::UNKNOWN);
+
+    // And the formats the check found turned away
+    if let Some(c) = &checked {
+        for f in formats.iter_mut() {
+            f.geo |= c.blocked.contains(&f.id);
+        }
+    }
+    let geo_ids: Vec<String> = formats.iter().filter(|f| f.geo).map(|f| f.id.clone()).collect();
+
+    let lite = page::lite();
+    let mut groups = analysis::group(formats);
+    let offered = groups.len();
+    if lite.on && !all {
+        groups = analysis::recommended(groups, lite::RECOMMENDED, &selected);
+    }
+
+    Ok(render(AnalyzeTemplate {
+        title: w.title,
+        profiles: profiles::list(&state.db).await?,
+        hidden: offered - groups.len(),
+        groups,
+        languages: w.languages,
+        error,
+        selected,
+        selected_profile,
+        compat,
+        container,
+        audio_format,
+        containers: CONTAINERS,
+        audio_formats: AUDIO_FORMATS,
+        channel: w.channel,
+        history,
+        related: w.related,
+        unlocked: w.unlocked,
+        accounts: state.secrets.cookie_accounts().await?.len(),
+        columns: columns::columns_for(headers),
+        choices: columns::COLUMNS,
+        lite,
+        probing,
+        can_probe,
+        url: w.url,
+        item: w.item,
+        geo_ids,
+        geo_checked: checked.is_some(),
+        countries: geo::COUNTRIES,
+        has_proxy: state.config.geo_proxy.is_some(),
+        tried: w.workaround.as_ref().map(gate::Workaround::label),
+    }))
+}
+
+// Step 3: confirm
+async fn show_confirm(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let Some(wizard) = state.sessions.get(&sid).wizard else {
+        return Ok(Redirect::to(start::ANALYZER).into_response());
+    };
+    let Some(selection) = wizard.selection else {
+        return Ok(Redirect::to("/options").into_response());
+    };
+
+    let direct = match &selection.choice {
+        Choice::Format(f) if analysis::fragmented(&f.protocol).is_some() => wizard
+            .formats
+            .iter()
+            .find(|o| o.group == f.group && analysis::fragmented(&o.protocol).is_none())
+            .map(|o| o.id.clone()),
+        _ => None,
+    };
+    Ok(render(ConfirmTemplate {
+        url: wizard.url,
+        title: wizard.title,
+        selection,
+        direct,
+    }))
+}
+
+// The download, and the compatibility copy when the selection asks for one
+fn download_steps(url: String, selection: Selection, polite: bool) -> Vec<NewJob> {
+    // The compatibility copy waits on the download and works from its file,
+    // which a whole playlist doesn't have just one of
+    let compat = selection.compat && !selection.audio_only() && !selection.playlist;
+    let template = if selection.playlist { jobs::PLAYLIST_TEMPLATE } else { jobs::DEFAULT_TEMPLATE }.to_string();
+    let download = Step::Download { url, selection: Box::new(selection), template, polite };
+    let mut steps = vec![NewJob { step: download, after: vec![] }];
+    if compat {
+        steps.push(NewJob { step: Step::Transcode, after: vec![0] });
+    }
+    steps
+}
+
+async fn download_format(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let Some(wizard) = state.sessions.get(&sid).wizard else {
+        return Ok(Redirect::to(start::ANALYZER).into_response());
+    };
+    let Some(selection) = wizard.selection else {
+        return Ok(Redirect::to("/options").into_response());
+    };
+
+    let steps = download_steps(wizard.url, selection, false);
+    state.jobs.submit_as(&wizard.title, auth::viewer(&state, &sid).as_deref(), steps)?;
+
+    state.sessions.update(&sid, |s| s.wizard = None);
+    page::flash(&state, &sid, FlashKind::Info, format!("Queued {}.", wizard.title));
+    Ok(Redirect::to("/jobs").into_response())
+}
+
+fn file_details(m: &library::MediaMeta, columns: &columns::Columns) -> Vec<String> {
+    [
+        (columns.codec, m.codec_label()),
+        (columns.language, m.language.clone()),
+        (columns.fps, m.fps_label()),
+        (columns.bitrate, m.bitrate_label()),
+        (columns.channels, m.audio_channels.map(library::channel_layout)),
+        (columns.hdr, m.dynamic_range.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(shown, value)| value.filter(|_| shown))
+    .collect()
+}
+
+// The library's files as the list shows them, with their metadata: no hidden
+// files or sidecars, only `viewer`'s folder for a signed-in user, and only
+// `channel`'s when it's given
+fn shown_files(dir: &str, names: &[String], viewer: Option<&str>, channel: Option<&str>) -> Vec<(String, Option<library::MediaMeta>)> {
+    names
+        .iter()
+        .filter(|name| !name.starts_with('.') && !library::is_sidecar(name, names) && library::visible_to(viewer, name))
+        .map(|name| (name.clone(), library::load_meta(dir, name)))
+        .filter(|(_, info)| {
+            let from = info.as_ref().and_then(|m| m.channel.as_deref().or(m.uploader.as_deref()));
+            channel.is_none() || from == channel
+        })
+        .collect()
+}
+
+// `/files/Music/Jazz` for a folder, each part encoded on its own
+fn folder_href(folder: &str) -> String {
+    if folder.is_empty() {
+        return "/files".to_string();
+    }
+    let parts: Vec<String> = folder.split('/').map(sanitize::url_path).collect();
+    format!("/files/{}", parts.join("/"))
+}
+
+async fn show_files(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    headers: HeaderMap,
+    Query(query): Query<FilesQuery>,
+) -> Result<Response, AppError> {
+    files_page(&state, &sid, &headers, query, "").await
+}
+
+/// `/files/<folder>`, which can be bookmarked like the top one.
+async fn show_folder(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    headers: HeaderMap,
+    axum::extract::Path(folder): axum::extract::Path<String>,
+    Query(query): Query<FilesQuery>,
+) -> Result<Response, AppError> {
+    files_page(&state, &sid, &headers, query, folder.trim_matches('/')).await
+}
+
+async fn files_page(
+    state: &AppState,
+    sid: &SessionId,
+    headers: &HeaderMap,
+    query: FilesQuery,
+    folder: &str,
+) -> Result<Response, AppError> {
+    if !folder.is_empty() && !library::plain_name(folder) {
+        return Err(AppError::NotFound(format!("Folder \"{}\"", folder)));
+    }
+    let channel = query.channel.filter(|c| !c.is_empty());
+    let columns = columns::columns_for(headers);
+    let viewer = auth::viewer(state, sid);
+    // A signed-in user's own folder is the top of the library for them
+    let root = viewer.as_ref().map(|user| format!("{}/", user)).unwrap_or_default();
+    let prefix = if folder.is_empty() { root } else { format!("{}{}/", root, folder) };
+    let dir = &state.config.downloads_dir;
+    let names: Vec<String> = library::list_names(dir)?.into_iter().filter(|n| n.starts_with(&prefix)).collect();
+    if !folder.is_empty() && names.is_empty() {
+        return Err(AppError::NotFound(format!("Folder \"{}\"", folder)));
+    }
+    let mut files = Vec::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut played = library::played(&state.db).await?;
+    for (name, info) in shown_files(dir, &names, viewer.as_deref(), channel.as_deref()) {
+        let label = name[prefix.len()..].to_string();
+        // One channel's files show from every folder below; otherwise a subfolder's are behind its card
+        if let (None, Some((sub, _))) = (&channel, label.split_once('/')) {
+            *counts.entry(sub.to_string()).or_default() += 1;
+            continue;
         }
+        let path = std::path::Path::new(dir).join(&name);
+        let mime = mime_guess::from_path(&path).first_or_octet_stream();
+
+        // A file vanishing between read_dir and here just shows as 0 MB
+        let meta = fs::metadata(&path).ok();
+        let size = meta.as_ref().map(|m| m.len()).unwrap_or(0);
+        let size_mb = format!("{:.2} MB", size as f64 / 1024.0 / 1024.0);
+
+        files.push(FileInfo {
+            thumbnail: library::thumbnail_for(&name, &names),
+            played: played.remove(&name),
+            name,
+            label,
+            media_type: library::media_type_of(&mime),
+            mime_type: mime.to_string(),
+            size_mb,
+            title: info.as_ref().and_then(|m| m.title.clone()),
+            added: meta.as_ref().and_then(clock::Stamp::modified),
+            details: info.as_ref().map(|m| file_details(m, &columns)).unwrap_or_default(),
+        });
     }
-    files.sort_by(|a, b| a.name.cmp(&b.name));
-    Html(FileListTemplate { files }.render().unwrap()).into_response()
-}
\ No newline at end of file
+    let below = |sub: &str| if folder.is_empty() { sub.to_string() } else { format!("{}/{}", folder, sub) };
+    let mut folders: Vec<FolderInfo> =
+        counts.into_iter().map(|(name, files)| FolderInfo { href: folder_href(&below(&name)), name, files }).collect();
+    let mut crumbs = Vec::new();
+    if !folder.is_empty() {
+        crumbs.push(Crumb { label: "Library".to_string(), href: folder_href("") });
+        let parts: Vec<&str> = folder.split('/').collect();
+        for (i, part) in parts.iter().enumerate() {
+            crumbs.push(Crumb { label: part.to_string(), href: folder_href(&parts[..=i].join("/")) });
+        }
+    }
+    // Sorted by what the card shows, which is the title when there is one
+    let locale = collate::locale_for(headers, &state.config.sort_locale);
+    collate::sort_by(&mut files, &locale, |f| f.title.as_deref().unwrap_or(&f.label));
+    collate::sort_by(&mut folders, &locale, |f| &f.name);
+    let lite = page::lite();
+    Ok(render(FileListTemplate {
+        files,
+        folders,
+        smart: smart::list(&state.db).await?,
+        crumbs,
+        here: folder_href(folder),
+        locale,
+        locales: collate::LOCALES,
+        channel,
+        columns,
+        choices: columns::COLUMNS,
+        lite,
+    }))
+}
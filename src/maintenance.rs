@@ -0,0 +1,95 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use serde::Deserialize;
+
+use crate::error::{render, AppError};
+use crate::jobs::Maintenance;
+use crate::notify;
+use crate::page::{self, FlashKind};
+use crate::sanitize;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+// Shown in the site-wide banner, so kept short
+const MAX_REASON_LEN: usize = 200;
+
+// --- Switching ---
+
+/// Turns maintenance mode on, or updates what it's for. Says whether it was off.
+pub async fn turn_on(state: &AppState, reason: &str) -> bool {
+    let was_on = state.jobs.maintenance().is_some();
+    let reason = sanitize::line(reason.trim());
+    let why = if reason.is_empty() { String::new() } else { format!(" ({})", reason) };
+    state.jobs.set_maintenance(Some(reason));
+    if !was_on {
+        notify::send(state, &format!("Maintenance mode on{}: queue paused, new submissions refused", why)).await;
+    }
+    !was_on
+}
+
+/// Turns maintenance mode off. Says whether it was on.
+pub async fn turn_off(state: &AppState) -> bool {
+    if state.jobs.maintenance().is_none() {
+        return false;
+    }
+    state.jobs.set_maintenance(None);
+    notify::send(state, "Maintenance mode off: the queue is running again").await;
+    true
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "maintenance.html")]
+struct MaintenanceTemplate {
+    maintenance: Option<Maintenance>,
+    running: usize,
+    queued: usize,
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceForm {
+    #[serde(default)]
+    reason: String,
+}
+
+impl Validate for MaintenanceForm {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::max_len("reason", &self.reason, MAX_REASON_LEN)
+    }
+}
+
+pub async fn show_maintenance(State(state): State<AppState>) -> Response {
+    let queue = state.jobs.summary();
+    render(MaintenanceTemplate { maintenance: state.jobs.maintenance(), running: queue.running, queued: queue.queued })
+}
+
+/// Turns maintenance mode on, or updates what it's for.
+pub async fn start(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<MaintenanceForm>,
+) -> Response {
+    if turn_on(&state, &form.reason).await {
+        let running = state.jobs.summary().running;
+        let msg = if running == 0 {
+            "Maintenance mode is on. Nothing is running, so it's safe to go ahead.".to_string()
+        } else {
+            format!("Maintenance mode is on. Waiting for {} running job(s) to finish.", running)
+        };
+        page::flash(&state, &sid, FlashKind::Info, msg);
+    }
+    Redirect::to("/system/maintenance").into_response()
+}
+
+pub async fn stop(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    if turn_off(&state).await {
+        page::flash(&state, &sid, FlashKind::Info, "Maintenance mode is off; queued jobs carry on.");
+    }
+    Redirect::to("/system/maintenance").into_response()
+}
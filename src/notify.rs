@@ -0,0 +1,33 @@
+use tokio::process::Command;
+
+use crate::logging;
+use crate::AppState;
+
+// --- Notifications ---
+
+/// Logs `message` and, when BSDL_NOTIFY_CMD is set, hands it to that command
+/// as its last argument (e.g. `notify-send bplus`). Sent while handling a
+/// request or job, the message ends with its ID, which the command also gets
+/// as BSDL_REQUEST_ID for a webhook's payload. A failing command is only logged.
+pub async fn send(state: &AppState, message: &str) {
+    tracing::info!("Notice: {}", message);
+    let trace = logging::trace_id();
+    let Some(command_line) = &state.config.notify_cmd else {
+        return;
+    };
+    let mut parts = command_line.split_whitespace();
+    let Some(program) = parts.next() else {
+        return;
+    };
+    let mut cmd = Command::new(program);
+    cmd.args(parts);
+    match &trace {
+        Some(id) => cmd.arg(format!("{} (ID {})", message, id)).env("BSDL_REQUEST_ID", id),
+        None => cmd.arg(message),
+    };
+    match state.procs.output("notify", cmd).await {
+        Ok(out) if out.status.success() => {}
+        Ok(out) => tracing::warn!("Notify command failed ({}): {}", out.status, String::from_utf8_lossy(&out.stderr).trim()),
+        Err(e) => tracing::warn!("Notify command could not start: {}", e),
+    }
+}
@@ -0,0 +1,338 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{Json, Response},
+};
+use serde_json::{json, Value};
+
+use crate::api::BEST;
+use crate::error::render;
+use crate::history::KINDS;
+use crate::{AppState, AUDIO_FORMATS, CONTAINERS};
+
+// Written by hand next to api.rs, health.rs, history.rs and jobs::status; a
+// field added to one of their JSON structs belongs here too
+
+fn text() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": [kind, "null"] })
+}
+
+fn one_of(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn described(mut value: Value, description: &str) -> Value {
+    value["description"] = json!(description);
+    value
+}
+
+// A JSON body of `name`
+fn body(name: &str, description: &str) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema(name) } } })
+}
+
+fn error(description: &str) -> Value {
+    body("Error", description)
+}
+
+fn schemas() -> Value {
+    json!({
+        "Error": {
+            "type": "object",
+            "required": ["error", "message", "status"],
+            "properties": {
+                "error": described(text(), "What went wrong, e.g. invalid, not_found, yt_dlp_failed or maintenance"),
+                "message": text(),
+                "status": integer(),
+                "field": described(text(), "The request field that was wrong, for invalid"),
+                "request_id": described(text(), "What the server logged the request with, as in the X-Request-Id header; quote it when reporting"),
+            },
+        },
+        "AnalyzeRequest": {
+            "type": "object",
+            "required": ["url"],
+            "additionalProperties": false,
+            "properties": {
+                "url": described(text(), "An http:// or https:// URL"),
+                "browser": described(text(), "Read cookies from this browser, past an age gate or login"),
+                "client": described(text(), "Ask as this YouTube player client"),
+                "country": described(text(), "Ask as if from this country, a two-letter code; for geo-blocked formats"),
+                "proxy": described(boolean(), "Ask through BSDL_GEO_PROXY; for geo-blocked formats"),
+                "item": described(integer(), "One video of a post that holds several, from 1"),
+            },
+        },
+        "Format": {
+            "type": "object",
+            "description": "One row of the format table",
+            "properties": {
+                "id": text(),
+                "ext": text(),
+                "resolution": described(text(), "\"1920x1080\", or \"Audio\""),
+                "height": integer(),
+                "filesize": described(text(), "\"12.34 MB\", \"~12.34 MB\" estimated, or \"Unknown\""),
+                "type": one_of(&["Video+Audio", "Video Only", "Audio Only"]),
+                "codecs": text(),
+                "language": text(),
+                "fps": text(),
+                "bitrate": described(text(), "The audio's or video's own when the format has only one, e.g. \"128k\""),
+                "channels": described(text(), "\"Stereo\", \"5.1\"..."),
+                "dynamic_range": text(),
+                "protocol": text(),
+                "unlock": described(
+                    json!({ "type": ["string", "null"], "enum": ["Premium", "Account", null] }),
+                    "Set when only the stored cookies got this format"
+                ),
+            },
+        },
+        "Video": {
+            "type": "object",
+            "description": "One video of a post that holds several; analyze it again with its item, or a playlist entry by its own url",
+            "properties": { "item": integer(), "title": text(), "url": nullable("string") },
+        },
+        "Analysis": {
+            "type": "object",
+            "properties": {
+                "url": text(),
+                "title": text(),
+                "channel": nullable("string"),
+                "duration": described(nullable("number"), "Seconds"),
+                "upcoming": described(boolean(), "A premiere or live stream that hasn't started, with nothing to download yet"),
+                "photo": described(boolean(), "A photo post, which has no formats to pick"),
+                "languages": { "type": "array", "items": text() },
+                "formats": { "type": "array", "items": schema("Format") },
+                "videos": { "type": "array", "items": schema("Video") },
+            },
+        },
+        "DownloadRequest": {
+            "type": "object",
+            "description": "Name one of format, profile or best; with none, the best video",
+            "required": ["url"],
+            "additionalProperties": false,
+            "properties": {
+                "url": described(text(), "An http:// or https:// URL"),
+                "format": described(text(), "A format id the analysis listed"),
+                "profile": described(integer(), "A device profile's id"),
+                "best": one_of(BEST),
+                "container": one_of(CONTAINERS),
+                "audio_format": one_of(AUDIO_FORMATS),
+                "compat": described(boolean(), "Also make an H.264 1080p copy"),
+                "polite": described(boolean(), "Random sleeps between items, an hourly cap and a pause whenever the site answers 429"),
+                "browser": text(),
+                "client": text(),
+                "country": text(),
+                "proxy": boolean(),
+                "item": integer(),
+                "playlist": described(boolean(), "Every video of the playlist or channel the URL is, as one job, with a profile or best"),
+            },
+        },
+        "Queued": {
+            "type": "object",
+            "properties": {
+                "job": described(integer(), "The chain's id on /jobs"),
+                "title": text(),
+                "progress": described(text(), "Server-sent events with its progress"),
+            },
+        },
+        "File": {
+            "type": "object",
+            "properties": {
+                "name": text(),
+                "media_type": one_of(&["video", "audio", "image", "other"]),
+                "mime_type": text(),
+                "size": described(integer(), "Bytes"),
+                "modified": described(nullable("integer"), "Unix seconds"),
+                "title": nullable("string"),
+                "channel": nullable("string"),
+                "duration": described(nullable("number"), "Seconds"),
+                "source": described(nullable("string"), "The page it was downloaded from"),
+                "url": described(text(), "Where to fetch the file itself"),
+            },
+        },
+        "Progress": {
+            "type": "object",
+            "properties": {
+                "percent": text(),
+                "speed": text(),
+                "eta": text(),
+                "item": described(text(), "\"3 of 10\" while a whole playlist downloads"),
+            },
+        },
+        "QueueSummary": {
+            "type": "object",
+            "properties": {
+                "running": integer(),
+                "queued": integer(),
+                "title": described(nullable("string"), "Chain title of the download that's running, if any"),
+                "progress": { "oneOf": [schema("Progress"), { "type": "null" }] },
+                "maintenance": boolean(),
+            },
+        },
+        "Health": {
+            "type": "object",
+            "required": ["status", "checks"],
+            "properties": {
+                "status": one_of(&["ok", "fail"]),
+                "checks": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": described(text(), "downloads, yt-dlp, yt-dlp nightly, ffmpeg, database or queue"),
+                            "ok": boolean(),
+                            "detail": text(),
+                        },
+                    },
+                },
+            },
+        },
+        "HistoryEntry": {
+            "type": "object",
+            "properties": {
+                "id": integer(),
+                "kind": one_of(KINDS),
+                "url": text(),
+                "title": text(),
+                "format": described(nullable("string"), "What was asked for: a format id or profile, and the container"),
+                "file_path": nullable("string"),
+                "status": one_of(&["Running", "Done", "Failed", "Cancelled", "Paused", "Queued", "Abandoned", "Interrupted"]),
+                "error": nullable("string"),
+                "started": described(integer(), "Unix seconds"),
+                "finished": described(nullable("integer"), "Unix seconds"),
+                "request_id": described(nullable("string"), "What the request or job was logged with"),
+            },
+        },
+    })
+}
+
+fn paths() -> Value {
+    json!({
+        "/api/v1/analyze": {
+            "post": {
+                "summary": "The format table of a URL, from the same analysis the wizard runs",
+                "requestBody": { "required": true, "content": { "application/json": { "schema": schema("AnalyzeRequest") } } },
+                "responses": {
+                    "200": body("Analysis", "The formats, or a post's videos to ask again with item"),
+                    "422": error("A field was wrong"),
+                    "502": error("yt-dlp failed, or the site wants cookies"),
+                },
+            },
+        },
+        "/api/v1/download": {
+            "post": {
+                "summary": "Queues a download like the wizard's last step does",
+                "requestBody": { "required": true, "content": { "application/json": { "schema": schema("DownloadRequest") } } },
+                "responses": {
+                    "202": body("Queued", "Queued"),
+                    "404": error("No device profile with that id"),
+                    "422": error("A field was wrong, or the format was not offered for this URL"),
+                    "502": error("yt-dlp failed"),
+                    "503": error("The queue is in maintenance mode"),
+                },
+            },
+        },
+        "/api/v1/files": {
+            "get": {
+                "summary": "The library's files",
+                "parameters": [{ "name": "channel", "in": "query", "description": "Only this channel's", "schema": text() }],
+                "responses": {
+                    "200": {
+                        "description": "The files",
+                        "content": { "application/json": { "schema": { "type": "array", "items": schema("File") } } },
+                    },
+                },
+            },
+        },
+        "/api/status": {
+            "get": {
+                "summary": "Counts of running and queued jobs",
+                "responses": { "200": body("QueueSummary", "The queue") },
+            },
+        },
+        "/healthz": {
+            "get": {
+                "summary": "Whether the downloads folder is writable and yt-dlp and ffmpeg are executable; needs no token",
+                "responses": { "200": body("Health", "Every check passed"), "503": body("Health", "A check failed") },
+            },
+        },
+        "/readyz": {
+            "get": {
+                "summary": "The checks of /healthz, whether the database answers and whether jobs are taken (not while shutting down); needs no token",
+                "responses": { "200": body("Health", "Every check passed"), "503": body("Health", "A check failed") },
+            },
+        },
+        "/api/history": {
+            "get": {
+                "summary": "Analyses and downloads, newest first",
+                "parameters": [{ "name": "kind", "in": "query", "schema": one_of(KINDS) }],
+                "responses": {
+                    "200": {
+                        "description": "The history",
+                        "content": { "application/json": { "schema": { "type": "array", "items": schema("HistoryEntry") } } },
+                    },
+                    "422": error("An unknown kind"),
+                },
+            },
+        },
+        "/jobs/{id}/progress": {
+            "get": {
+                "summary": "A queued chain's progress as server-sent events, each a JSON message",
+                "parameters": [{ "name": "id", "in": "path", "required": true, "schema": integer() }],
+                "responses": {
+                    "200": { "description": "The event stream", "content": { "text/event-stream": { "schema": text() } } },
+                    "404": error("No chain with that id"),
+                },
+            },
+        },
+    })
+}
+
+/// The OpenAPI description of the JSON API, served under `base_path`.
+pub fn document(base_path: &str) -> Value {
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "bplus streamdlrs-gui",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Analyze URLs, queue downloads and list the library. Errors come back as an Error with the field that was wrong. Once the app has API tokens, POSTs (and with BSDL_AUTH_READS or users every route) need one as a bearer token, or get a 401.",
+        },
+        "servers": [{ "url": Some(base_path).filter(|b| !b.is_empty()).unwrap_or("/") }],
+        // Only asked for once there is a token, so going without one is listed too
+        "security": [{}, { "bearer": [] }],
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": { "bearer": { "type": "http", "scheme": "bearer", "description": "A token from /system/tokens or BSDL_API_TOKENS_FILE" } },
+        },
+    })
+}
+
+// --- Handlers ---
+
+pub async fn openapi_json(State(state): State<AppState>) -> Json<Value> {
+    Json(document(&state.config.base_path))
+}
+
+#[derive(Template)]
+#[template(path = "api_docs.html")]
+struct DocsTemplate;
+
+/// Swagger UI over /api/openapi.json.
+pub async fn show_docs() -> Response {
+    render(DocsTemplate)
+}
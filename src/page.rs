@@ -0,0 +1,147 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use chrono_tz::Tz;
+
+use crate::auth;
+use crate::clock;
+use crate::jobs::{Maintenance, QueueSummary};
+use crate::lite::{self, Lite};
+use crate::session::SessionId;
+use crate::AppState;
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlashKind {
+    Info,
+    Error,
+}
+
+impl FlashKind {
+    pub fn css(&self) -> &'static str {
+        match self {
+            FlashKind::Info => "info",
+            FlashKind::Error => "error",
+        }
+    }
+}
+
+/// One-shot message shown on the next page the browser renders.
+#[derive(Debug, Clone)]
+pub struct Flash {
+    pub kind: FlashKind,
+    pub message: String,
+}
+
+/// What every page can show without its handler passing it in. Templates get
+/// it through `templates/_context.html`, which calls `current()`.
+#[derive(Debug, Clone, Default)]
+pub struct PageContext {
+    pub version: &'static str,
+    /// Who this browser signed in as: a user, or an API token
+    pub user: Option<String>,
+    pub queue: QueueSummary,
+    pub maintenance: Option<Maintenance>,
+    pub flash: Vec<Flash>,
+    /// IANA name of the zone times are shown in
+    pub timezone: String,
+    pub lite: Lite,
+}
+
+// --- Context ---
+
+struct Scope {
+    state: AppState,
+    sid: SessionId,
+    tz: Tz,
+    lite: Lite,
+}
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+/// Context for the page being rendered. Flash messages are taken here, so only
+/// a rendered page consumes them, not asset or media requests.
+pub fn current() -> PageContext {
+    SCOPE
+        .try_with(|scope| {
+            PageContext {
+                version: env!("CARGO_PKG_VERSION"),
+                user: auth::signed_in(&scope.state, &scope.sid).map(|who| who.to_string()),
+                queue: scope.state.jobs.summary_for(auth::viewer(&scope.state, &scope.sid).as_deref()),
+                maintenance: scope.state.jobs.maintenance(),
+                flash: scope.state.sessions.take_flash(&scope.sid),
+                timezone: scope.tz.name().to_string(),
+                lite: scope.lite,
+            }
+        })
+        .unwrap_or_else(|_| PageContext { version: env!("CARGO_PKG_VERSION"), ..PageContext::default() })
+}
+
+/// `<title>` text: the page name, led by the number of active jobs and the
+/// running download's percentage so a background tab shows what's happening.
+/// `templates/_context.html` keeps it current without a reload.
+pub fn title(name: &str) -> String {
+    let Ok(queue) = SCOPE.try_with(|scope| scope.state.jobs.summary_for(auth::viewer(&scope.state, &scope.sid).as_deref())) else {
+        return name.to_string();
+    };
+    title_for(&queue, name)
+}
+
+fn title_for(queue: &QueueSummary, name: &str) -> String {
+    let active = queue.running + queue.queued;
+    if active == 0 {
+        return name.to_string();
+    }
+    match queue.progress.as_ref().filter(|p| !p.percent.is_empty()) {
+        Some(p) => format!("({}) {} {}", active, p.percent, name),
+        None => format!("({}) {}", active, name),
+    }
+}
+
+/// Zone the current request's viewer wants times in; UTC outside a request.
+pub fn timezone() -> Tz {
+    SCOPE.try_with(|scope| scope.tz).unwrap_or(Tz::UTC)
+}
+
+/// Whether the current request gets lite pages; full pages outside a request.
+pub fn lite() -> Lite {
+    SCOPE.try_with(|scope| scope.lite).unwrap_or_default()
+}
+
+/// BSDL_BASE_PATH, for the URLs a page's scripts build; empty outside a request.
+pub fn base_path() -> String {
+    SCOPE.try_with(|scope| scope.state.config.base_path.clone()).unwrap_or_default()
+}
+
+/// The token `templates/_csrf.html` puts in the current page's forms; empty
+/// outside a request.
+pub fn csrf_token() -> String {
+    SCOPE.try_with(|scope| scope.state.sessions.csrf(&scope.sid)).unwrap_or_default()
+}
+
+/// Queues a message for the next page this session renders, usually right after a redirect.
+pub fn flash(state: &AppState, sid: &SessionId, kind: FlashKind, message: impl Into<String>) {
+    let message = message.into();
+    state.sessions.update(sid, |s| s.flash.push(Flash { kind, message }));
+}
+
+// --- Middleware ---
+
+/// Makes the request's state and session reachable from `current()` while the handler runs.
+pub async fn context_layer(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(sid) = req.extensions().get::<SessionId>().cloned() else {
+        return next.run(req).await;
+    };
+    let tz = clock::zone_for(req.headers(), state.config.timezone);
+    let lite = lite::lite_for(req.headers());
+    let mut res = SCOPE.scope(Scope { state, sid, tz, lite }, next.run(req)).await;
+    res.headers_mut().insert(HeaderName::from_static("accept-ch"), HeaderValue::from_static(lite::ACCEPT_CH));
+    res
+}
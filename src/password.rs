@@ -0,0 +1,51 @@
+use pbkdf2::pbkdf2_hmac_array;
+use rand::RngCore;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+// PBKDF2-HMAC-SHA256 (one 32-byte block), stored as "pbkdf2-sha256$<iterations>$<salt hex>$<hash hex>".
+// Python makes the same with hashlib.pbkdf2_hmac("sha256", password, salt, iterations).
+
+const SCHEME: &str = "pbkdf2-sha256";
+// New hashes take this many rounds; stored ones keep the count they were made with
+const ITERATIONS: u32 = 200_000;
+const SALT_LEN: usize = 16;
+// Fewer rounds than this are refused rather than trusted
+const MIN_ITERATIONS: u32 = 10_000;
+
+// --- Hashes ---
+
+/// A new hash of `password` with a random salt, ready to store. Slow on
+/// purpose, like `verify`; call it off the async threads.
+pub fn hash(password: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), &salt, ITERATIONS);
+    format!("{}${}${}${}", SCHEME, ITERATIONS, hex::encode(salt), hex::encode(key))
+}
+
+fn parse(stored: &str) -> Option<(u32, Vec<u8>, Vec<u8>)> {
+    let mut fields = stored.trim().split('$');
+    if fields.next()? != SCHEME {
+        return None;
+    }
+    let iterations = fields.next()?.parse::<u32>().ok().filter(|n| *n >= MIN_ITERATIONS)?;
+    let salt = hex::decode(fields.next()?).ok().filter(|s| !s.is_empty())?;
+    let key = hex::decode(fields.next()?).ok().filter(|k| k.len() == 32)?;
+    fields.next().is_none().then_some((iterations, salt, key))
+}
+
+/// Whether `stored` reads as a hash `verify` can check.
+pub fn well_formed(stored: &str) -> bool {
+    parse(stored).is_some()
+}
+
+/// Whether `password` is the one `stored` was made from.
+pub fn verify(password: &str, stored: &str) -> bool {
+    let Some((iterations, salt, key)) = parse(stored) else {
+        return false;
+    };
+    let got = pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), &salt, iterations);
+    // Every byte, so the time taken says nothing about how much matched
+    got.ct_eq(&key[..]).into()
+}
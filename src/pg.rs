@@ -0,0 +1,273 @@
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::path::Path;
+use tokio::runtime::Handle;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+use tokio_postgres::{Client, Config, NoTls, Statement};
+
+use crate::config::StoragePolicy;
+use crate::db::{self, Error, Row, Storage, ToValue, Value, ADDED_COLUMNS, SCHEMA};
+
+// PostgreSQL, for several instances sharing one database (BSDL_DB_URL). The
+// tables are SCHEMA's with PostgreSQL's types, and each statement's `?1`
+// placeholders become `$1` on the way out. Statements run on the blocking
+// pool like SQLite's and wait there on the client; the connection itself is
+// a task on the runtime, opened again when the server drops it.
+
+type BoxError = Box<dyn StdError + Sync + Send>;
+
+// SQLite's instr(), which smart folders match words with, and the rowid
+// history_events are ordered by, which PostgreSQL tables don't have
+const COMPAT: &str = "
+CREATE OR REPLACE FUNCTION instr(text, text) RETURNS integer
+    LANGUAGE sql IMMUTABLE AS 'SELECT strpos($1, $2)';
+ALTER TABLE history_events ADD COLUMN IF NOT EXISTS rowid BIGINT GENERATED BY DEFAULT AS IDENTITY;
+";
+
+pub struct Postgres {
+    client: Client,
+    config: Config,
+    lock_timeout_ms: u64,
+    // Prepared once per connection
+    statements: HashMap<String, Statement>,
+    runtime: Handle,
+}
+
+impl Postgres {
+    pub async fn connect(url: &str, policy: &StoragePolicy) -> db::Result<Postgres> {
+        let config: Config = url.parse()?;
+        let client = open(&config, policy.busy_timeout_ms).await?;
+        client.batch_execute(&types(SCHEMA)).await?;
+        for (table, column, decl) in ADDED_COLUMNS {
+            let sql = format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}", table, column, types(decl));
+            client.batch_execute(&sql).await?;
+        }
+        client.batch_execute(COMPAT).await?;
+        Ok(Postgres {
+            client,
+            config,
+            lock_timeout_ms: policy.busy_timeout_ms,
+            statements: HashMap::new(),
+            runtime: Handle::current(),
+        })
+    }
+
+    // The client and `sql` prepared on it, connecting again first if the
+    // server went away
+    fn prepare(&mut self, sql: &str) -> db::Result<(&Client, Statement)> {
+        if self.client.is_closed() {
+            tracing::warn!("PostgreSQL connection lost, reconnecting");
+            self.client = self.runtime.block_on(open(&self.config, self.lock_timeout_ms))?;
+            self.statements.clear();
+        }
+        let statement = match self.statements.get(sql) {
+            Some(s) => s.clone(),
+            None => {
+                let s = self.runtime.block_on(self.client.prepare(&placeholders(sql)))?;
+                self.statements.insert(sql.to_string(), s.clone());
+                s
+            }
+        };
+        Ok((&self.client, statement))
+    }
+}
+
+async fn open(config: &Config, lock_timeout_ms: u64) -> Result<Client, tokio_postgres::Error> {
+    let (client, connection) = config.connect(NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::warn!("PostgreSQL connection closed: {}", e);
+        }
+    });
+    // SQLite's busy timeout: how long a statement waits on a lock held elsewhere.
+    // The notices are "already exists, skipping" from the schema on every start.
+    client.batch_execute(&format!("SET lock_timeout = {}; SET client_min_messages = warning", lock_timeout_ms)).await?;
+    Ok(client)
+}
+
+impl Storage for Postgres {
+    fn execute(&mut self, sql: &str, params: &[&dyn ToValue]) -> db::Result<usize> {
+        let values = db::values(params);
+        let refs: Vec<&(dyn ToSql + Sync)> = values.iter().map(|v| v as &(dyn ToSql + Sync)).collect();
+        let runtime = self.runtime.clone();
+        let (client, statement) = self.prepare(sql)?;
+        Ok(runtime.block_on(client.execute(&statement, &refs))? as usize)
+    }
+
+    fn query(&mut self, sql: &str, params: &[&dyn ToValue]) -> db::Result<Vec<Row>> {
+        let values = db::values(params);
+        let refs: Vec<&(dyn ToSql + Sync)> = values.iter().map(|v| v as &(dyn ToSql + Sync)).collect();
+        let runtime = self.runtime.clone();
+        let (client, statement) = self.prepare(sql)?;
+        let rows = runtime.block_on(client.query(&statement, &refs))?;
+        rows.iter()
+            .map(|row| Ok(Row::new((0..row.len()).map(|i| row.try_get(i)).collect::<Result<_, _>>()?)))
+            .collect()
+    }
+
+    fn execute_batch(&mut self, sql: &str) -> db::Result<()> {
+        let runtime = self.runtime.clone();
+        Ok(runtime.block_on(self.client.batch_execute(sql))?)
+    }
+
+    // PostgreSQL always writes ahead
+    fn journal_mode(&mut self) -> db::Result<String> {
+        Ok("wal".to_string())
+    }
+
+    // The server checkpoints by itself, and CHECKPOINT needs a superuser
+    fn checkpoint(&mut self) -> db::Result<bool> {
+        Ok(true)
+    }
+
+    // The server checks its own pages; this only shows the tables can be read
+    fn integrity(&mut self) -> db::Result<Vec<String>> {
+        self.query("SELECT COUNT(*) FROM items", &[])?;
+        Ok(vec!["ok".to_string()])
+    }
+
+    fn snapshot(&mut self, _to: &Path) -> db::Result<()> {
+        Err(Error::Unsupported("backups only cover SQLite; back PostgreSQL up with pg_dump".to_string()))
+    }
+
+    fn restore(&mut self, _from: &Path) -> db::Result<()> {
+        Err(Error::Unsupported("backups only cover SQLite; restore PostgreSQL with pg_restore".to_string()))
+    }
+
+    fn engine(&self) -> &'static str {
+        "PostgreSQL"
+    }
+}
+
+// --- SQL ---
+
+// SQLite's column types as PostgreSQL names them; INTEGER PRIMARY KEY is
+// SQLite's auto-numbered rowid
+fn types(sql: &str) -> String {
+    let sql = sql.replace("INTEGER PRIMARY KEY", "BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY");
+    let mut out = String::with_capacity(sql.len());
+    let mut word = String::new();
+    for c in sql.chars().chain(['\n']) {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        out.push_str(match word.as_str() {
+            "INTEGER" => "BIGINT",
+            "REAL" => "DOUBLE PRECISION",
+            "BLOB" => "BYTEA",
+            w => w,
+        });
+        word.clear();
+        out.push(c);
+    }
+    out.pop();
+    out
+}
+
+// `?1` as PostgreSQL numbers parameters, `$1`; a bare `?` takes the next number
+fn placeholders(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut quoted = false;
+    let mut next = 0;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            quoted = !quoted;
+        } else if c == '?' && !quoted {
+            let mut number = String::new();
+            while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                number.push(d);
+            }
+            if number.is_empty() {
+                next += 1;
+                number = next.to_string();
+            }
+            out.push('$');
+            out.push_str(&number);
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+// --- Values ---
+
+fn is_text(ty: &Type) -> bool {
+    [Type::TEXT, Type::VARCHAR, Type::BPCHAR, Type::NAME, Type::UNKNOWN].contains(ty)
+}
+
+// The columns are typed where SQLite's aren't, so each value is sent as
+// whatever the statement's parameter turned out to be
+impl ToSql for Value {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        match self {
+            Value::Null => Ok(IsNull::Yes),
+            Value::Integer(i) if *ty == Type::BOOL => (*i != 0).to_sql(ty, out),
+            Value::Integer(i) if *ty == Type::INT2 => i16::try_from(*i)?.to_sql(ty, out),
+            Value::Integer(i) if *ty == Type::INT4 => i32::try_from(*i)?.to_sql(ty, out),
+            Value::Integer(i) if *ty == Type::INT8 => i.to_sql(ty, out),
+            Value::Integer(i) if *ty == Type::FLOAT8 => (*i as f64).to_sql(ty, out),
+            Value::Integer(i) if *ty == Type::FLOAT4 => (*i as f32).to_sql(ty, out),
+            Value::Integer(i) if is_text(ty) => i.to_string().to_sql(ty, out),
+            Value::Real(f) if *ty == Type::FLOAT8 => f.to_sql(ty, out),
+            Value::Real(f) if *ty == Type::FLOAT4 => (*f as f32).to_sql(ty, out),
+            Value::Real(f) if is_text(ty) => f.to_string().to_sql(ty, out),
+            Value::Text(s) if is_text(ty) => s.to_sql(ty, out),
+            Value::Blob(b) if *ty == Type::BYTEA => b.to_sql(ty, out),
+            other => Err(format!("can't send {} as {}", other.kind(), ty).into()),
+        }
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Value {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Value, BoxError> {
+        Ok(match ty {
+            t if *t == Type::BOOL => Value::Integer(bool::from_sql(ty, raw)? as i64),
+            t if *t == Type::INT2 => Value::Integer(i16::from_sql(ty, raw)?.into()),
+            t if *t == Type::INT4 => Value::Integer(i32::from_sql(ty, raw)?.into()),
+            t if *t == Type::INT8 => Value::Integer(i64::from_sql(ty, raw)?),
+            t if *t == Type::FLOAT4 => Value::Real(f32::from_sql(ty, raw)?.into()),
+            t if *t == Type::FLOAT8 => Value::Real(f64::from_sql(ty, raw)?),
+            t if *t == Type::NUMERIC => numeric(raw)?,
+            t if *t == Type::BYTEA => Value::Blob(Vec::<u8>::from_sql(ty, raw)?),
+            t if is_text(t) => Value::Text(String::from_sql(ty, raw)?),
+            t => return Err(format!("can't read a {} column", t).into()),
+        })
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Value, BoxError> {
+        Ok(Value::Null)
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+// NUMERIC, which SUM() and AVG() return: a header of four 16-bit words
+// (digit count, weight of the first digit, sign, scale), then base-10000 digits
+fn numeric(raw: &[u8]) -> Result<Value, BoxError> {
+    let word = |i: usize| raw.get(i * 2..i * 2 + 2).map(|b| i16::from_be_bytes([b[0], b[1]])).ok_or("NUMERIC cut short");
+    let (digits, weight, sign, scale) = (word(0)?, word(1)?, word(2)? as u16, word(3)?);
+    if sign == 0xC000 {
+        return Ok(Value::Real(f64::NAN));
+    }
+    let mut value = 0.0;
+    for i in 0..digits.max(0) as usize {
+        value += word(4 + i)? as f64 * 10000f64.powi(weight as i32 - i as i32);
+    }
+    if sign == 0x4000 {
+        value = -value;
+    }
+    // Exact as an f64 up to 2^53
+    Ok(if scale == 0 && value.abs() < 9e15 { Value::Integer(value as i64) } else { Value::Real(value) })
+}
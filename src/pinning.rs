@@ -0,0 +1,302 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use serde::Deserialize;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+
+use crate::clock::Stamp;
+use crate::config::Config;
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::{render, AppError};
+use crate::history;
+use crate::page::{self, FlashKind};
+use crate::platform;
+use crate::sanitize::filters;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+// Two yt-dlp builds side by side, because a new release now and then breaks
+// one site's extractor halfway through archiving it: the stable build
+// platform::ytdlp finds and a nightly (BSDL_YTDLP_NIGHTLY, or yt-dlp_nightly
+// in the app folder). Sites can be pinned to either and the rest follow a
+// default. Rolling back sends everything to stable at once and keeps the pins
+// for when the rollback is undone.
+
+// A host name, which is all a pin matches on
+const MAX_SITE_LEN: usize = 253;
+
+#[cfg(windows)]
+const NIGHTLY_NAME: &str = "yt-dlp_nightly.exe";
+#[cfg(not(windows))]
+const NIGHTLY_NAME: &str = "yt-dlp_nightly";
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Build {
+    #[default]
+    Stable,
+    Nightly,
+}
+
+impl Build {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Build::Stable => "stable",
+            Build::Nightly => "nightly",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Build> {
+        match s {
+            "stable" => Some(Build::Stable),
+            "nightly" => Some(Build::Nightly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pin {
+    pub site: String,
+    pub build: Build,
+    pub pinned_at: Stamp,
+}
+
+/// What runs where, as stored.
+#[derive(Debug, Clone, Default)]
+pub struct Choices {
+    /// For sites without a pin of their own
+    pub default: Build,
+    pub pins: Vec<Pin>,
+    pub rolled_back: Option<Stamp>,
+}
+
+impl Choices {
+    /// The build chosen for a URL: its site's pin, or a pin of a domain it's
+    /// under (`youtube.com` covers `m.youtube.com`), or the default.
+    pub fn build_for(&self, url: &str) -> Build {
+        if self.rolled_back.is_some() {
+            return Build::Stable;
+        }
+        let site = history::site(url);
+        self.pins
+            .iter()
+            .filter(|p| site == p.site || site.strip_suffix(&p.site).is_some_and(|rest| rest.ends_with('.')))
+            .max_by_key(|p| p.site.len())
+            .map_or(self.default, |p| p.build)
+    }
+}
+
+/// The stored choices, kept in memory since every yt-dlp run asks.
+#[derive(Clone, Default)]
+pub struct Pins {
+    inner: Arc<Mutex<Choices>>,
+}
+
+impl Pins {
+    pub async fn load(db: &Db) -> Result<Pins, AppError> {
+        let pins = Pins::default();
+        pins.reload(db).await?;
+        Ok(pins)
+    }
+
+    async fn reload(&self, db: &Db) -> Result<(), AppError> {
+        let choices = db
+            .call(|conn| {
+                let rows = conn.query_map("SELECT site, build, pinned_at FROM ytdlp_pins ORDER BY site", params![], |r| {
+                    Ok((r.get::<String>(0)?, r.get::<String>(1)?, Stamp(r.get(2)?)))
+                })?;
+                let mut choices = Choices::default();
+                for (site, build, pinned_at) in rows {
+                    // Written by this module only, but a hand-edited row shouldn't pick nightly
+                    let build = Build::parse(&build).unwrap_or_default();
+                    match site.as_str() {
+                        "" => choices.default = build,
+                        _ => choices.pins.push(Pin { site, build, pinned_at }),
+                    }
+                }
+                choices.rolled_back = conn.query_row("SELECT since FROM ytdlp_rollback", params![], |r| r.get(0)).optional()?.map(Stamp);
+                Ok(choices)
+            })
+            .await?;
+        *self.inner.lock().unwrap() = choices;
+        Ok(())
+    }
+
+    pub fn choices(&self) -> Choices {
+        self.inner.lock().unwrap().clone()
+    }
+
+    /// Pins `site` to a build; the site '' sets the default.
+    pub async fn pin(&self, db: &Db, site: String, build: Build) -> Result<(), AppError> {
+        db.call(move |conn| {
+            conn.execute(
+                "INSERT INTO ytdlp_pins (site, build, pinned_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(site) DO UPDATE SET build = excluded.build, pinned_at = excluded.pinned_at",
+                params![site, build.as_str(), db::now()],
+            )
+        })
+        .await?;
+        self.reload(db).await
+    }
+
+    /// Says whether there was a pin.
+    pub async fn unpin(&self, db: &Db, site: String) -> Result<bool, AppError> {
+        let removed = db.call(move |conn| conn.execute("DELETE FROM ytdlp_pins WHERE site = ?1 AND site != ''", params![site])).await?;
+        self.reload(db).await?;
+        Ok(removed > 0)
+    }
+
+    /// Rolls every site back to stable, or undoes that. Says whether it changed anything.
+    pub async fn set_rollback(&self, db: &Db, on: bool) -> Result<bool, AppError> {
+        let changed = db
+            .call(move |conn| match on {
+                true => conn.execute("INSERT INTO ytdlp_rollback (id, since) VALUES (1, ?1) ON CONFLICT DO NOTHING", params![db::now()]),
+                false => conn.execute("DELETE FROM ytdlp_rollback", params![]),
+            })
+            .await?;
+        self.reload(db).await?;
+        Ok(changed > 0)
+    }
+}
+
+// --- Binaries ---
+
+/// The nightly build, if there is one: BSDL_YTDLP_NIGHTLY, or yt-dlp_nightly in the app folder.
+pub fn nightly(config: &Config) -> Option<PathBuf> {
+    if let Some(path) = &config.ytdlp_nightly {
+        return Some(path.clone());
+    }
+    Some(FsPath::new(".").join(NIGHTLY_NAME)).filter(|path| path.is_file())
+}
+
+/// The yt-dlp to run for `url`. A site chosen for nightly runs stable while
+/// there's no nightly build.
+pub fn ytdlp(state: &AppState, url: &str) -> PathBuf {
+    match state.pins.choices().build_for(url) {
+        Build::Nightly => nightly(&state.config).unwrap_or_else(|| platform::ytdlp(&state.config)),
+        Build::Stable => platform::ytdlp(&state.config),
+    }
+}
+
+// `yt-dlp --version`, or what went wrong asking
+async fn version(state: &AppState, program: &FsPath) -> Result<String, String> {
+    let mut cmd = Command::new(program);
+    cmd.arg("--version");
+    match state.procs.output("yt-dlp builds", cmd).await {
+        Ok(out) if out.status.success() => Ok(String::from_utf8_lossy(&out.stdout).trim().to_string()),
+        Ok(out) => Err(format!("exited with {}", out.status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// --- Handlers ---
+
+struct Binary {
+    build: Build,
+    path: Option<PathBuf>,
+    version: Result<String, String>,
+}
+
+#[derive(Template)]
+#[template(path = "ytdlp_builds.html")]
+struct BuildsTemplate {
+    binaries: Vec<Binary>,
+    choices: Choices,
+}
+
+#[derive(Deserialize)]
+pub struct PinForm {
+    #[serde(default)]
+    site: String,
+    build: String,
+}
+
+impl Validate for PinForm {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::url("site", &self.site)?;
+        validate::one_of("build", &self.build, &["stable", "nightly"])
+    }
+}
+
+pub async fn show_builds(State(state): State<AppState>) -> Response {
+    let stable = platform::ytdlp(&state.config);
+    let nightly = nightly(&state.config);
+    let (stable_version, nightly_version) = tokio::join!(version(&state, &stable), async {
+        match &nightly {
+            Some(path) => version(&state, path).await,
+            None => Err(format!("none found; set BSDL_YTDLP_NIGHTLY or put {} in the app folder", NIGHTLY_NAME)),
+        }
+    });
+    let binaries = vec![
+        Binary { build: Build::Stable, path: Some(stable), version: stable_version },
+        Binary { build: Build::Nightly, path: nightly, version: nightly_version },
+    ];
+    render(BuildsTemplate { binaries, choices: state.pins.choices() })
+}
+
+/// Sets the default build.
+pub async fn set_default(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<PinForm>,
+) -> Result<Response, AppError> {
+    let build = Build::parse(&form.build).unwrap_or_default();
+    state.pins.pin(&state.db, String::new(), build).await?;
+    tracing::info!("yt-dlp default build set to {}", build.as_str());
+    page::flash(&state, &sid, FlashKind::Info, format!("Sites without a pin now run the {} build.", build.as_str()));
+    Ok(Redirect::to("/system/ytdlp").into_response())
+}
+
+/// Pins a site, given as its host name or any URL on it.
+pub async fn pin_site(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<PinForm>,
+) -> Result<Response, AppError> {
+    let site = history::site(form.site.trim());
+    if site.is_empty() || site.len() > MAX_SITE_LEN {
+        return Err(AppError::Invalid { field: "site", reason: "give a host name like youtube.com, or a URL on the site".to_string() });
+    }
+    let build = Build::parse(&form.build).unwrap_or_default();
+    state.pins.pin(&state.db, site.clone(), build).await?;
+    tracing::info!("Pinned {} to the {} yt-dlp build", site, build.as_str());
+    page::flash(&state, &sid, FlashKind::Info, format!("{} now runs the {} build.", site, build.as_str()));
+    Ok(Redirect::to("/system/ytdlp").into_response())
+}
+
+pub async fn unpin_site(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(site): Path<String>,
+) -> Result<Response, AppError> {
+    if state.pins.unpin(&state.db, site.clone()).await? {
+        tracing::info!("Unpinned {} from its yt-dlp build", site);
+        page::flash(&state, &sid, FlashKind::Info, format!("{} follows the default build again.", site));
+    }
+    Ok(Redirect::to("/system/ytdlp").into_response())
+}
+
+/// Sends every site to the stable build, keeping the pins.
+pub async fn roll_back(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    if state.pins.set_rollback(&state.db, true).await? {
+        tracing::warn!("Rolled every site back to the stable yt-dlp build");
+        page::flash(&state, &sid, FlashKind::Info, "Rolled back: every site runs the stable build until this is undone.");
+    }
+    Ok(Redirect::to("/system/ytdlp").into_response())
+}
+
+pub async fn undo_rollback(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    if state.pins.set_rollback(&state.db, false).await? {
+        tracing::info!("Undid the yt-dlp rollback; pins apply again");
+        page::flash(&state, &sid, FlashKind::Info, "Rollback undone: the pins and the default apply again.");
+    }
+    Ok(Redirect::to("/system/ytdlp").into_response())
+}
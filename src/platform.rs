@@ -0,0 +1,139 @@
+use std::fs::{File, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+// yt-dlp's own release names for each platform, looked for in the app folder
+// before falling back to whatever `yt-dlp` is on the PATH
+#[cfg(target_os = "linux")]
+const YTDLP_NAMES: &[&str] = &["yt-dlp_linux", "yt-dlp"];
+#[cfg(target_os = "macos")]
+const YTDLP_NAMES: &[&str] = &["yt-dlp_macos", "yt-dlp"];
+#[cfg(windows)]
+const YTDLP_NAMES: &[&str] = &["yt-dlp.exe"];
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const YTDLP_NAMES: &[&str] = &["yt-dlp"];
+
+// --- Command line ---
+
+/// What the binary was asked to do. Everything else is configured through
+/// BSDL_* variables.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Folder holding yt-dlp, `downloads/`, `data/` and the rest
+    pub home: Option<PathBuf>,
+    pub install_service: bool,
+    pub uninstall_service: bool,
+    /// Print a hash of the password read from stdin, for BSDL_USERS_FILE
+    pub hash_password: bool,
+    /// Stand in for BSDL_HOST and BSDL_PORT
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// `ctl` and the command after it, for the running app's control socket
+    pub ctl: Option<Vec<String>>,
+}
+
+pub const USAGE: &str =
+    "usage: bplus-streamdlrs-gui [--home DIR] [--host ADDR] [--port N] [--install-service | --uninstall-service | --hash-password]\n       bplus-streamdlrs-gui [--home DIR] ctl COMMAND (ctl help lists them)";
+
+pub fn parse_args() -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut it = std::env::args_os().skip(1);
+    while let Some(arg) = it.next() {
+        match arg.to_str() {
+            Some("--home") => args.home = Some(it.next().ok_or("--home needs a folder")?.into()),
+            Some("--install-service") => args.install_service = true,
+            Some("--uninstall-service") => args.uninstall_service = true,
+            Some("--hash-password") => args.hash_password = true,
+            Some("--host") => args.host = Some(it.next().and_then(|h| h.into_string().ok()).ok_or("--host needs an address")?),
+            Some("--port") => {
+                let port = it.next().and_then(|p| p.to_str().and_then(|p| p.parse().ok()));
+                args.port = Some(port.ok_or("--port needs a number from 0 to 65535")?);
+            }
+            Some("ctl") => {
+                let words = it.by_ref().map(|w| w.into_string().map_err(|w| format!("not UTF-8: {:?}", w)));
+                args.ctl = Some(words.collect::<Result<_, _>>()?);
+            }
+            Some("-h" | "--help") => return Err(USAGE.to_string()),
+            _ => return Err(format!("unknown argument {:?}\n{}", arg, USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+/// Makes `--home` (or BSDL_HOME) the working directory, which every relative
+/// path in the app hangs off. Service managers otherwise start us in `/` or
+/// `C:\Windows\System32`.
+pub fn enter_home(args: &Args) -> io::Result<()> {
+    let home = args.home.clone().or_else(|| std::env::var_os("BSDL_HOME").map(PathBuf::from));
+    match home {
+        Some(dir) => std::env::set_current_dir(dir),
+        None => Ok(()),
+    }
+}
+
+/// Puts `--host` and `--port` where the config (and a service installed
+/// with them) reads them from.
+pub fn apply_overrides(args: &Args) {
+    if let Some(host) = &args.host {
+        std::env::set_var("BSDL_HOST", host);
+    }
+    if let Some(port) = args.port {
+        std::env::set_var("BSDL_PORT", port.to_string());
+    }
+}
+
+// --- Binaries ---
+
+/// The yt-dlp to run: BSDL_YTDLP, the platform's release binary in the app
+/// folder, or `yt-dlp` from the PATH.
+pub fn ytdlp(config: &Config) -> PathBuf {
+    if let Some(path) = &config.ytdlp {
+        return path.clone();
+    }
+    YTDLP_NAMES
+        .iter()
+        .map(|name| Path::new(".").join(name))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(if cfg!(windows) { "yt-dlp.exe" } else { "yt-dlp" }))
+}
+
+// --- Single instance ---
+
+/// Holds an exclusive lock beside the database for as long as the returned
+/// file lives, so a second copy (say, the service plus one started by hand)
+/// can't share the database and downloads folder with this one.
+pub fn lock_instance(db_path: &Path) -> io::Result<File> {
+    let path = db_path.with_extension("lock");
+    let file = File::create(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(io::Error::other(format!(
+            "{} is locked: another copy of the app is already running from this folder",
+            path.display()
+        ))),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+// --- Shutdown ---
+
+/// Resolves on Ctrl-C, or on the SIGTERM service managers stop us with.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!("Could not listen for SIGTERM: {}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
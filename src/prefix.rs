@@ -0,0 +1,99 @@
+use axum::{
+    body::{self, Body},
+    extract::{Request, State},
+    http::{header, HeaderValue, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::AppState;
+
+// Behind a reverse proxy the app can live under a path of its own, like
+// https://nas.local/ytdl/. Handlers and templates keep linking from the root;
+// the layer here takes the prefix off requests and puts it in front of what
+// goes back out: redirects, cookie paths and the links of HTML pages. Scripts
+// build their URLs from `page::base_path()` themselves.
+
+// Attributes whose root-relative URLs get the prefix; `//host/...` ones are left alone
+const ATTRIBUTES: &[&str] = &["href", "src", "action", "poster", "data-progress"];
+
+// Prefixes every `attr="/...` of ATTRIBUTES in a page
+fn rebase(html: &str, base: &str) -> String {
+    let mut out = String::with_capacity(html.len() + 4096);
+    let mut rest = html;
+    while let Some(i) = rest.find("=\"/") {
+        let (head, tail) = rest.split_at(i + 2);
+        out.push_str(head);
+        let attr = head[..i].rsplit(|c: char| c.is_whitespace()).next().unwrap_or_default();
+        if ATTRIBUTES.contains(&attr) && !tail.starts_with("//") {
+            out.push_str(base);
+        }
+        rest = tail;
+    }
+    out.push_str(rest);
+    out
+}
+
+// --- Middleware ---
+
+/// Serves the app under BSDL_BASE_PATH. Requests with the prefix have it taken
+/// off; requests without it are taken as they come, for proxies that strip it
+/// before passing them on.
+pub async fn prefix_layer(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let base = state.config.base_path.as_str();
+    if base.is_empty() {
+        return next.run(req).await;
+    }
+    let path = req.uri().path();
+    if path == base {
+        return Redirect::permanent(&format!("{}/", base)).into_response();
+    }
+    if let Some(rest) = path.strip_prefix(base).filter(|r| r.starts_with('/')) {
+        let rest = match req.uri().query() {
+            Some(q) => format!("{}?{}", rest, q),
+            None => rest.to_string(),
+        };
+        match rest.parse::<Uri>() {
+            Ok(uri) => *req.uri_mut() = uri,
+            Err(_) => return Redirect::to(&format!("{}/", base)).into_response(),
+        }
+    }
+
+    let res = next.run(req).await;
+    let (mut parts, body) = res.into_parts();
+    if let Some(to) = parts.headers.get(header::LOCATION).and_then(|l| l.to_str().ok()) {
+        if to.starts_with('/') && !to.starts_with("//") {
+            if let Ok(v) = HeaderValue::from_str(&format!("{}{}", base, to)) {
+                parts.headers.insert(header::LOCATION, v);
+            }
+        }
+    }
+    let cookies: Vec<HeaderValue> = parts
+        .headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|c| c.to_str().ok())
+        .filter_map(|c| HeaderValue::from_str(&c.replace("; Path=/;", &format!("; Path={}/;", base))).ok())
+        .collect();
+    if !cookies.is_empty() {
+        parts.headers.remove(header::SET_COOKIE);
+        for cookie in cookies {
+            parts.headers.append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    let html = parts.headers.get(header::CONTENT_TYPE).and_then(|t| t.to_str().ok()).is_some_and(|t| t.starts_with("text/html"));
+    if !html {
+        return Response::from_parts(parts, body);
+    }
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("Could not read a page to rebase: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let page = rebase(&String::from_utf8_lossy(&bytes), base);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(page))
+}
@@ -0,0 +1,314 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::clock::Stamp;
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::{render, AppError};
+use crate::notify;
+use crate::page::{self, FlashKind};
+use crate::pinning;
+use crate::recipe::{self, Recipe};
+use crate::sanitize;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::{AppState, YtDlpOutput};
+
+// Once the announced start has passed, how long until the next look
+const RETRY_MINUTES: i64 = 5;
+// With no start time known, looked at this often
+const UNSCHEDULED_MINUTES: i64 = 60;
+/// Premieres still not downloadable this long after their start are given up on
+const GIVE_UP_HOURS: i64 = 48;
+
+// --- Data Structures ---
+
+/// An upcoming video (a premiere, or a live stream that hasn't started) that
+/// is downloaded as soon as yt-dlp can get at it.
+#[derive(Debug, Clone)]
+pub struct Premiere {
+    pub id: i64,
+    pub url: String,
+    pub title: String,
+    /// The start the site announced, which can move
+    pub release: Option<Stamp>,
+    /// `None` uses BSDL_SUBSCRIPTION_PRESET
+    pub preset: Option<String>,
+    pub created: Stamp,
+    pub last_checked: Option<Stamp>,
+    pub queued: Option<Stamp>,
+    pub last_error: Option<String>,
+}
+
+impl Premiere {
+    fn deadline(&self) -> i64 {
+        self.release.unwrap_or(self.created).0 + GIVE_UP_HOURS * 3600
+    }
+
+    pub fn given_up(&self) -> bool {
+        self.queued.is_none() && db::now() > self.deadline()
+    }
+
+    // The next look would come after the deadline
+    fn last_look(&self) -> bool {
+        db::now() + RETRY_MINUTES * 60 > self.deadline()
+    }
+
+    /// Badge class and label for the list
+    pub fn status(&self) -> (&'static str, &'static str) {
+        if self.queued.is_some() {
+            ("check-pass", "Queued")
+        } else if self.given_up() {
+            ("check-fail", "Gave up")
+        } else {
+            ("check-warn", "Waiting")
+        }
+    }
+
+    /// When the watcher looks next: at the start, then every few minutes.
+    pub fn next_check(&self) -> Option<Stamp> {
+        if self.queued.is_some() || self.given_up() {
+            return None;
+        }
+        let retry = match self.release {
+            Some(_) => RETRY_MINUTES,
+            None => UNSCHEDULED_MINUTES,
+        };
+        let after_last = self.last_checked.map(|t| t.0 + retry * 60).unwrap_or(0);
+        let start = self.release.map(|t| t.0).unwrap_or(0);
+        Some(Stamp(after_last.max(start)))
+    }
+}
+
+const COLUMNS: &str = "id, url, title, release_at, preset, created_at, last_checked_at, queued_at, last_error";
+
+fn from_row(r: &db::Row) -> db::Result<Premiere> {
+    Ok(Premiere {
+        id: r.get(0)?,
+        url: r.get(1)?,
+        title: r.get(2)?,
+        release: r.get::<Option<i64>>(3)?.map(Stamp),
+        preset: r.get(4)?,
+        created: Stamp(r.get(5)?),
+        last_checked: r.get::<Option<i64>>(6)?.map(Stamp),
+        queued: r.get::<Option<i64>>(7)?.map(Stamp),
+        last_error: r.get(8)?,
+    })
+}
+
+// --- Store ---
+
+pub async fn list(db: &Db) -> Result<Vec<Premiere>, AppError> {
+    db.call(|conn| {
+        let sql = format!("SELECT {} FROM premieres ORDER BY queued_at IS NOT NULL, release_at NULLS FIRST, id", COLUMNS);
+        conn.query_map(&sql, params![], from_row)
+    })
+    .await
+}
+
+pub async fn get(db: &Db, id: i64) -> Result<Option<Premiere>, AppError> {
+    db.call(move |conn| {
+        conn.query_row(&format!("SELECT {} FROM premieres WHERE id = ?1", COLUMNS), params![id], from_row).optional()
+    })
+    .await
+}
+
+// --- Watching ---
+
+/// Asks yt-dlp about the premiere again and queues it once it has formats.
+/// Returns whether it was queued.
+pub async fn check(state: &AppState, premiere: &Premiere) -> Result<bool, AppError> {
+    state.jobs.accepting()?;
+    let id = premiere.id;
+    let result = look(state, premiere).await;
+    let (release, error) = match &result {
+        Ok(Some(release)) => (*release, None),
+        Ok(None) => (None, None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let queued = matches!(result, Ok(None));
+    state
+        .db
+        .call(move |conn| {
+            conn.execute(
+                "UPDATE premieres SET last_checked_at = ?2, last_error = ?3, release_at = COALESCE(?4, release_at), \
+                 queued_at = CASE WHEN ?5 THEN ?2 ELSE queued_at END WHERE id = ?1",
+                params![id, db::now(), error, release, queued],
+            )
+        })
+        .await?;
+
+    if queued {
+        notify::send(state, &format!("{} is live; download queued", premiere.title)).await;
+    } else if premiere.last_look() {
+        notify::send(state, &format!("Gave up waiting for {} to go live", premiere.title)).await;
+    }
+    result.map(|_| queued)
+}
+
+// Ok(None) once queued; Ok(Some(start)) while still upcoming, with the start
+// the site announces now
+async fn look(state: &AppState, premiere: &Premiere) -> Result<Option<Option<i64>>, AppError> {
+    let mut cmd = Command::new(pinning::ytdlp(state, &premiere.url));
+    cmd.arg("--dump-json").args(crate::scope(None));
+    let _cookies = state.secrets.attach_cookies(&mut cmd).await?;
+    cmd.arg("--").arg(&premiere.url);
+
+    let output = state.procs.output("premiere", cmd).await?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        let reason = err.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no details").trim().to_string();
+        return Err(AppError::YtDlpFailed(format!("checking {} exited with {}: {}", premiere.url, output.status, reason)));
+    }
+    let meta = crate::parse_entries(&output.stdout)?.into_iter().next();
+    match meta {
+        Some(meta) if !meta.upcoming() && !meta.formats.is_empty() => {
+            let recipe = Recipe { preset: premiere.preset.clone(), ..Recipe::default() };
+            let jobs = recipe.steps(state, premiere.url.clone()).await?;
+            state.jobs.submit(&premiere.title, jobs)?;
+            Ok(None)
+        }
+        Some(meta) => Ok(Some(meta.release_timestamp)),
+        None => Ok(Some(None)),
+    }
+}
+
+/// Looks at every premiere that's due, once a minute, for the life of the process.
+pub fn spawn_watcher(state: AppState) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            tick.tick().await;
+            if state.jobs.maintenance().is_some() {
+                continue;
+            }
+            let now = Stamp::now();
+            let due = match list(&state.db).await {
+                Ok(all) => all.into_iter().filter(|p| p.next_check().is_some_and(|next| next <= now)),
+                Err(e) => {
+                    tracing::warn!("Could not load premieres: {}", e);
+                    continue;
+                }
+            };
+            for premiere in due {
+                match check(&state, &premiere).await {
+                    Ok(true) => tracing::info!("Premiere {} is live, download queued", premiere.title),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("Premiere {} check failed: {}", premiere.title, e),
+                }
+            }
+        }
+    });
+}
+
+// --- Handlers ---
+
+/// The analyzed video, offered for watching.
+pub struct Upcoming {
+    pub url: String,
+    pub title: String,
+    pub release: Option<Stamp>,
+}
+
+#[derive(Template)]
+#[template(path = "premieres.html")]
+struct PremieresTemplate {
+    premieres: Vec<Premiere>,
+    upcoming: Option<Upcoming>,
+    presets: Vec<(String, String)>,
+    global_preset: String,
+}
+
+async fn page(state: &AppState, upcoming: Option<Upcoming>) -> Result<Response, AppError> {
+    Ok(render(PremieresTemplate {
+        premieres: list(&state.db).await?,
+        upcoming,
+        presets: recipe::preset_options(&state.db).await?,
+        global_preset: state.config.subscription_preset.clone(),
+    }))
+}
+
+/// Shown instead of the format table when the analyzed video hasn't started yet.
+pub async fn offer(state: &AppState, url: &str, meta: &YtDlpOutput) -> Response {
+    let upcoming =
+        Upcoming { url: url.to_string(), title: sanitize::line(&meta.title), release: meta.release_timestamp.map(Stamp) };
+    page(state, Some(upcoming)).await.unwrap_or_else(IntoResponse::into_response)
+}
+
+pub async fn show_premieres(State(state): State<AppState>) -> Result<Response, AppError> {
+    page(&state, None).await
+}
+
+#[derive(Deserialize)]
+pub struct WatchForm {
+    url: String,
+    title: String,
+    #[serde(default)]
+    release: Option<i64>,
+    #[serde(default)]
+    preset: String,
+}
+
+impl Validate for WatchForm {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::url("url", &self.url)?;
+        validate::max_len("title", &self.title, 300)?;
+        recipe::validate_fields(&self.preset, "", "")
+    }
+}
+
+pub async fn watch(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<WatchForm>,
+) -> Result<Response, AppError> {
+    let recipe = Recipe::from_form(&form.preset, "", "", false);
+    recipe.check(&state.db).await?;
+    let (url, title, release, preset) = (form.url.trim().to_string(), sanitize::line(&form.title), form.release, recipe.preset);
+    let message = format!("Watching {}; it downloads once it's live.", title);
+    state
+        .db
+        .call(move |conn| {
+            conn.execute(
+                "INSERT INTO premieres (url, title, release_at, preset, created_at) VALUES (?1, ?2, ?3, ?4, ?5) \
+                 ON CONFLICT (url) DO UPDATE SET title = ?2, release_at = ?3, preset = ?4, queued_at = NULL, last_error = NULL",
+                params![url, title, release, preset, db::now()],
+            )
+        })
+        .await?;
+    page::flash(&state, &sid, FlashKind::Info, message);
+    Ok(Redirect::to("/premieres").into_response())
+}
+
+/// Checks `premiere` now and says how that went, here or on /schedule.
+pub async fn check_and_flash(state: &AppState, sid: &SessionId, premiere: &Premiere) {
+    match check(state, premiere).await {
+        Ok(true) => page::flash(state, sid, FlashKind::Info, format!("{} is live; download queued.", premiere.title)),
+        Ok(false) => page::flash(state, sid, FlashKind::Info, format!("{} hasn't started yet.", premiere.title)),
+        Err(e) => page::flash(state, sid, FlashKind::Error, format!("{}: check failed: {}", premiere.title, e)),
+    }
+}
+
+pub async fn check_now(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let premiere = get(&state.db, id).await?.ok_or_else(|| AppError::NotFound("Premiere".to_string()))?;
+    check_and_flash(&state, &sid, &premiere).await;
+    Ok(Redirect::to("/premieres").into_response())
+}
+
+pub async fn delete_premiere(State(state): State<AppState>, Path(id): Path<i64>) -> Result<Response, AppError> {
+    let removed = state.db.call(move |conn| conn.execute("DELETE FROM premieres WHERE id = ?1", params![id])).await?;
+    if removed == 0 {
+        return Err(AppError::NotFound("Premiere".to_string()));
+    }
+    Ok(Redirect::to("/premieres").into_response())
+}
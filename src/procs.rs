@@ -0,0 +1,296 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+};
+use askama::Template;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path as FsPath;
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::oneshot;
+
+use crate::config::ProcessLimits;
+use crate::error::render;
+use crate::AppState;
+
+// Stderr lines `lines` keeps for the caller
+const STDERR_TAIL: usize = 5;
+
+// --- Data Structures ---
+
+struct Entry {
+    pid: Option<u32>,
+    label: String,
+    command: String,
+    started: Instant,
+    kill: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub id: u64,
+    pub pid: String,
+    pub label: String,
+    pub command: String,
+    pub running_for: String,
+}
+
+/// Every child the app spawns goes through here, so there's one place to see
+/// (and kill) what is running. Entries disappear once the child has been reaped.
+/// yt-dlp and ffmpeg also get the configured priority and CPU limits here.
+#[derive(Clone, Default)]
+pub struct ProcessRegistry {
+    entries: Arc<Mutex<HashMap<u64, Entry>>>,
+    next_id: Arc<AtomicU64>,
+    limits: Arc<ProcessLimits>,
+}
+
+// Removes the registry entry however the run ends (success, error or drop)
+struct Registration {
+    registry: ProcessRegistry,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.entries.lock().unwrap().remove(&self.id);
+    }
+}
+
+// The children heavy enough to be worth limiting
+fn is_heavy(cmd: &Command) -> bool {
+    let program = FsPath::new(cmd.as_std().get_program());
+    let name = program.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.starts_with("yt-dlp") || name.starts_with("ffmpeg") || name.starts_with("ffprobe")
+}
+
+impl ProcessRegistry {
+    pub fn new(limits: ProcessLimits) -> ProcessRegistry {
+        ProcessRegistry { limits: Arc::new(limits), ..ProcessRegistry::default() }
+    }
+
+    // Runs a heavy `cmd` under nice/ionice/taskset when those are configured,
+    // and says whether it was heavy
+    fn limited(&self, cmd: Command) -> (Command, bool) {
+        let prefix = self.limits.prefix();
+        if !is_heavy(&cmd) {
+            return (cmd, false);
+        }
+        if prefix.is_empty() {
+            return (cmd, true);
+        }
+        let inner = cmd.as_std();
+        let mut wrapped = Command::new(&prefix[0]);
+        wrapped.args(&prefix[1..]).arg(inner.get_program()).args(inner.get_args());
+        for (key, value) in inner.get_envs() {
+            match value {
+                Some(value) => wrapped.env(key, value),
+                None => wrapped.env_remove(key),
+            };
+        }
+        if let Some(dir) = inner.get_current_dir() {
+            wrapped.current_dir(dir);
+        }
+        (wrapped, true)
+    }
+
+    // Moves a fresh child into the configured cgroup. Failing to is only
+    // logged: the job still runs, just without the limit.
+    fn join_cgroup(&self, child: &Child) {
+        let (Some(cgroup), Some(pid)) = (&self.limits.cgroup, child.id()) else {
+            return;
+        };
+        if !cfg!(target_os = "linux") {
+            return;
+        }
+        if let Err(e) = std::fs::write(cgroup.join("cgroup.procs"), pid.to_string()) {
+            tracing::warn!("Could not move process {} into cgroup {}: {}", pid, cgroup.display(), e);
+        }
+    }
+
+    fn register(&self, label: &str, cmd: &Command, child: &Child) -> (Registration, oneshot::Receiver<()>) {
+        let std_cmd = cmd.as_std();
+        let mut command = std_cmd.get_program().to_string_lossy().to_string();
+        let mut args = std_cmd.get_args();
+        while let Some(arg) = args.next() {
+            command.push(' ');
+            command.push_str(&arg.to_string_lossy());
+            // A proxy URL may carry a password: yt-dlp's --proxy and curl's -x
+            if (arg == "--proxy" || arg == "-x") && args.next().is_some() {
+                command.push_str(" <proxy>");
+            }
+        }
+
+        tracing::info!(target: "bplus::procs", pid = child.id(), label, %command, "started");
+        let (tx, rx) = oneshot::channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.entries.lock().unwrap().insert(id, Entry {
+            pid: child.id(),
+            label: label.to_string(),
+            command,
+            started: Instant::now(),
+            kill: Some(tx),
+        });
+        (Registration { registry: self.clone(), id }, rx)
+    }
+
+    /// Runs `cmd` to completion and collects its output, like `Command::output`.
+    pub async fn output(&self, label: &str, cmd: Command) -> io::Result<Output> {
+        let (mut cmd, heavy) = self.limited(cmd);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true);
+        let mut child = cmd.spawn()?;
+        if heavy {
+            self.join_cgroup(&child);
+        }
+        let (_registration, kill_rx) = self.register(label, &cmd, &child);
+
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let read_out = async {
+            let mut buf = Vec::new();
+            stdout.read_to_end(&mut buf).await.map(|_| buf)
+        };
+        let read_err = async {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).await.map(|_| buf)
+        };
+
+        let (status, stdout, stderr) = tokio::try_join!(wait_or_kill(&mut child, kill_rx), read_out, read_err)?;
+        Ok(Output { status, stdout, stderr })
+    }
+
+    /// Runs `cmd` to completion, handing each line of its stdout to `on_line`
+    /// as it arrives. Stderr is passed through to ours, and its last few lines
+    /// come back with the exit status for error messages.
+    pub async fn lines(
+        &self,
+        label: &str,
+        cmd: Command,
+        mut on_line: impl FnMut(&str),
+    ) -> io::Result<(ExitStatus, String)> {
+        let (mut cmd, heavy) = self.limited(cmd);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true);
+        let mut child = cmd.spawn()?;
+        if heavy {
+            self.join_cgroup(&child);
+        }
+        let (_registration, kill_rx) = self.register(label, &cmd, &child);
+
+        // Split on bytes so a stray non UTF-8 line can't end the read early
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).split(b'\n');
+        let mut stderr = BufReader::new(child.stderr.take().expect("stderr is piped")).split(b'\n');
+        let read_out = async {
+            while let Some(line) = stdout.next_segment().await? {
+                on_line(String::from_utf8_lossy(&line).trim_end_matches('\r'));
+            }
+            Ok(())
+        };
+        let read_err = async {
+            let mut tail: VecDeque<String> = VecDeque::new();
+            while let Some(line) = stderr.next_segment().await? {
+                let line = String::from_utf8_lossy(&line).trim_end_matches('\r').to_string();
+                tracing::info!(target: "bplus::procs", "{}", line);
+                if tail.len() == STDERR_TAIL {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+            Ok(Vec::from(tail).join("\n"))
+        };
+        let (status, (), tail) = tokio::try_join!(wait_or_kill(&mut child, kill_rx), read_out, read_err)?;
+        Ok((status, tail))
+    }
+
+    pub fn list(&self) -> Vec<ProcessInfo> {
+        let entries = self.entries.lock().unwrap();
+        let mut list: Vec<ProcessInfo> = entries
+            .iter()
+            .map(|(id, e)| {
+                let secs = e.started.elapsed().as_secs();
+                ProcessInfo {
+                    id: *id,
+                    pid: e.pid.map(|p| p.to_string()).unwrap_or_else(|| "?".to_string()),
+                    label: e.label.clone(),
+                    command: e.command.clone(),
+                    running_for: format!("{}m {:02}s", secs / 60, secs % 60),
+                }
+            })
+            .collect();
+        list.sort_by_key(|p| p.id);
+        list
+    }
+
+    /// Asks every registered child to stop with SIGTERM, so yt-dlp and ffmpeg
+    /// can close their files. Returns how many were asked.
+    #[cfg(unix)]
+    pub async fn terminate_all(&self) -> usize {
+        let pids: Vec<String> = self.entries.lock().unwrap().values().filter_map(|e| e.pid).map(|p| p.to_string()).collect();
+        if pids.is_empty() {
+            return 0;
+        }
+        match Command::new("kill").arg("-TERM").args(&pids).status().await {
+            Ok(_) => pids.len(),
+            Err(e) => {
+                tracing::warn!("Could not run kill: {}", e);
+                0
+            }
+        }
+    }
+
+    // Windows has no SIGTERM; kill_all is all there is
+    #[cfg(not(unix))]
+    pub async fn terminate_all(&self) -> usize {
+        0
+    }
+
+    /// How many children are running.
+    pub fn count(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Signals every registered child to die. Returns how many were signalled.
+    pub fn kill_all(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .values_mut()
+            .filter_map(|e| e.kill.take())
+            .map(|tx| tx.send(()))
+            .filter(Result::is_ok)
+            .count()
+    }
+}
+
+// Waits for the child, killing it first if the registry asks us to.
+// Either way the child is awaited, so it never lingers as a zombie.
+async fn wait_or_kill(child: &mut Child, kill_rx: oneshot::Receiver<()>) -> io::Result<ExitStatus> {
+    tokio::select! {
+        status = child.wait() => status,
+        Ok(()) = kill_rx => {
+            child.kill().await?;
+            child.wait().await
+        }
+    }
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "processes.html")]
+struct ProcessesTemplate {
+    processes: Vec<ProcessInfo>,
+}
+
+pub async fn show_processes(State(state): State<AppState>) -> Response {
+    render(ProcessesTemplate { processes: state.procs.list() })
+}
+
+pub async fn kill_all(State(state): State<AppState>) -> impl IntoResponse {
+    let killed = state.procs.kill_all();
+    tracing::info!("Kill all requested, signalled {} child process(es)", killed);
+    Redirect::to("/system/processes")
+}
@@ -0,0 +1,181 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+};
+use askama::Template;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::{render, AppError};
+use crate::validate::{self, Form, Validate};
+use crate::{AppState, CONTAINERS};
+
+// Written on first start; after that the table is the admin's to edit
+const DEFAULTS: &[(&str, &str, &str, &str)] = &[
+    (
+        "Phone",
+        "720p H.264/AAC in MP4, small files that play on any phone",
+        "bv*[height<=720][vcodec^=avc1]+ba[acodec^=mp4a]/b[height<=720][vcodec^=avc1]/b[height<=720]",
+        "mp4",
+    ),
+    (
+        "TV 4K",
+        "Best quality up to 2160p with any codec, kept in MKV",
+        "bv*[height<=2160]+ba/b[height<=2160]",
+        "mkv",
+    ),
+    (
+        "Old laptop",
+        "480p H.264, easy on slow CPUs without hardware VP9/AV1 decoding",
+        "bv*[height<=480][vcodec^=avc1]+ba[acodec^=mp4a]/b[height<=480]",
+        "mp4",
+    ),
+];
+
+// --- Data Structures ---
+
+/// A named quality preset: a yt-dlp format selector plus the container it's merged into.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub selector: String,
+    pub container: String,
+}
+
+fn from_row(r: &db::Row) -> db::Result<DeviceProfile> {
+    Ok(DeviceProfile {
+        id: r.get(0)?,
+        name: r.get(1)?,
+        description: r.get(2)?,
+        selector: r.get(3)?,
+        container: r.get(4)?,
+    })
+}
+
+// --- Store ---
+
+/// Puts the built-in profiles in place if the table is empty.
+pub async fn seed(db: &Db) -> Result<(), AppError> {
+    db.call(|conn| {
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM device_profiles", params![], |r| r.get(0))?;
+        if count == 0 {
+            for (name, description, selector, container) in DEFAULTS {
+                conn.execute(
+                    "INSERT INTO device_profiles (name, description, selector, container) VALUES (?1, ?2, ?3, ?4)",
+                    params![name, description, selector, container],
+                )?;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub async fn list(db: &Db) -> Result<Vec<DeviceProfile>, AppError> {
+    db.call(|conn| {
+        conn.query_map("SELECT id, name, description, selector, container FROM device_profiles ORDER BY id", params![], from_row)
+    })
+    .await
+}
+
+pub async fn get(db: &Db, id: i64) -> Result<Option<DeviceProfile>, AppError> {
+    db.call(move |conn| {
+        conn.query_row(
+            "SELECT id, name, description, selector, container FROM device_profiles WHERE id = ?1",
+            params![id],
+            from_row,
+        )
+        .optional()
+    })
+    .await
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "profiles.html")]
+struct ProfilesTemplate {
+    profiles: Vec<DeviceProfile>,
+    containers: &'static [&'static str],
+}
+
+#[derive(Deserialize)]
+pub struct ProfileForm {
+    // Empty when adding a new profile
+    #[serde(default)]
+    id: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    selector: String,
+    container: String,
+}
+
+impl Validate for ProfileForm {
+    fn validate(&self) -> Result<(), AppError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > 60 {
+            return Err(AppError::Invalid { field: "name", reason: "profile names need 1 to 60 characters".to_string() });
+        }
+        validate::max_len("description", &self.description, 500)?;
+        let selector = self.selector.trim();
+        if selector.is_empty() || selector.contains(char::is_whitespace) || selector.starts_with('-') {
+            return Err(AppError::Invalid {
+                field: "selector",
+                reason: "can't be empty, start with '-' or contain spaces, e.g. bv*[height<=720]+ba/b".to_string(),
+            });
+        }
+        validate::max_len("selector", selector, 500)?;
+        validate::one_of("container", &self.container, CONTAINERS)?;
+        if !self.id.trim().is_empty() && self.id.trim().parse::<i64>().is_err() {
+            return Err(AppError::Invalid { field: "id", reason: "not a profile id".to_string() });
+        }
+        Ok(())
+    }
+}
+
+pub async fn show_profiles(State(state): State<AppState>) -> Result<Response, AppError> {
+    Ok(render(ProfilesTemplate { profiles: list(&state.db).await?, containers: CONTAINERS }))
+}
+
+pub async fn save_profile(State(state): State<AppState>, Form(form): Form<ProfileForm>) -> Result<Response, AppError> {
+    let name = form.name.trim().to_string();
+    let description = form.description.trim().to_string();
+    let selector = form.selector.trim().to_string();
+    let id = form.id.trim().parse::<i64>().ok();
+
+    let container = form.container;
+    let taken_name = name.clone();
+    let res = state
+        .db
+        .call(move |conn| match id {
+            Some(id) => conn.execute(
+                "UPDATE device_profiles SET name = ?2, description = ?3, selector = ?4, container = ?5 WHERE id = ?1",
+                params![id, name, description, selector, container],
+            ),
+            None => conn.execute(
+                "INSERT INTO device_profiles (name, description, selector, container) VALUES (?1, ?2, ?3, ?4)",
+                params![name, description, selector, container],
+            ),
+        })
+        .await;
+
+    match res {
+        Ok(0) => Err(AppError::NotFound("Profile".to_string())),
+        Ok(_) => Ok(Redirect::to("/settings/profiles").into_response()),
+        Err(AppError::Db(e)) if e.is_constraint() => {
+            Err(AppError::BadRequest(format!("There is already a profile called \"{}\"", taken_name)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn delete_profile(State(state): State<AppState>, Path(id): Path<i64>) -> Result<Response, AppError> {
+    let removed = state.db.call(move |conn| conn.execute("DELETE FROM device_profiles WHERE id = ?1", params![id])).await?;
+    if removed == 0 {
+        return Err(AppError::NotFound("Profile".to_string()));
+    }
+    Ok(Redirect::to("/settings/profiles").into_response())
+}
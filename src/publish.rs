@@ -0,0 +1,156 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path as FsPath, PathBuf};
+
+use crate::auth;
+use crate::db::{self, params, OptionalExtension, Storage};
+use crate::error::AppError;
+use crate::library;
+use crate::page::{self, FlashKind};
+use crate::reconcile;
+use crate::sanitize;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+// A file published into several folders is one set of contents under several
+// names, hard links the filesystem counts for us. The table remembers which
+// names belong together, so the pages can show them and deleting one name
+// only removes that link; the contents go with the last.
+
+// --- Store ---
+
+// The name the others of `name`'s set were linked from
+fn source_of(conn: &mut dyn Storage, name: &str) -> db::Result<String> {
+    conn.query_row("SELECT source FROM publications WHERE name = ?1", params![name], |r| r.get(0))
+        .optional()
+        .map(|source| source.unwrap_or_else(|| name.to_string()))
+}
+
+/// The other names `name` is published under that are still on disk.
+pub async fn published(state: &AppState, name: &str) -> Result<Vec<String>, AppError> {
+    let (dir, name) = (state.config.downloads_dir.clone(), name.to_string());
+    state.db.call(move |conn| {
+        let source = source_of(conn, &name)?;
+        let mut names = vec![source.clone()];
+        names.extend(conn.query_map(
+            "SELECT name FROM publications WHERE source = ?1 ORDER BY created_at, name",
+            params![source],
+            |r| r.get(0),
+        )?);
+        names.retain(|n| *n != name && FsPath::new(&dir).join(n).is_file());
+        Ok(names)
+    })
+    .await
+}
+
+/// Takes `name` out of its set once its file is deleted; when the others were
+/// linked from it, the oldest of them takes its place.
+pub fn release(conn: &mut dyn Storage, name: &str) -> db::Result<()> {
+    if conn.execute("DELETE FROM publications WHERE name = ?1", params![name])? > 0 {
+        return Ok(());
+    }
+    let next: Option<String> = conn
+        .query_row("SELECT name FROM publications WHERE source = ?1 ORDER BY created_at, name LIMIT 1", params![name], |r| r.get(0))
+        .optional()?;
+    if let Some(next) = next {
+        conn.execute("DELETE FROM publications WHERE name = ?1", params![&next])?;
+        conn.execute("UPDATE publications SET source = ?2 WHERE source = ?1", params![name, &next])?;
+    }
+    Ok(())
+}
+
+// --- Linking ---
+
+fn base_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+// Hard-links `name` and its sidecars into `folder`; if one fails, none stay
+fn link_into(library: &str, name: &str, folder: &str) -> std::io::Result<()> {
+    let names = library::list_names(library)?;
+    let dir = FsPath::new(library).join(folder);
+    fs::create_dir_all(&dir)?;
+    let mut made: Vec<PathBuf> = Vec::new();
+    for file in library::with_sidecars(name, &names) {
+        let to = dir.join(base_name(&file));
+        if let Err(e) = fs::hard_link(FsPath::new(library).join(&file), &to) {
+            for link in &made {
+                let _ = fs::remove_file(link);
+            }
+            return Err(e);
+        }
+        made.push(to);
+    }
+    Ok(())
+}
+
+// --- Handlers ---
+
+#[derive(Deserialize)]
+pub struct PublishRequest {
+    folder: String,
+}
+
+impl Validate for PublishRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        let folder = self.folder.trim().trim_matches('/');
+        validate::max_len("folder", folder, 200)?;
+        if folder.is_empty() || !library::plain_name(folder) {
+            return Err(AppError::Invalid { field: "folder", reason: "a folder of the library, like Playlists/Workout".to_string() });
+        }
+        Ok(())
+    }
+}
+
+/// Publishes a file into another folder as well, as a hard link that takes
+/// no more space. A signed-in user's folders are inside their own.
+pub async fn publish_file(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(name): Path<String>,
+    Form(req): Form<PublishRequest>,
+) -> Result<Response, AppError> {
+    let dir = state.config.downloads_dir.clone();
+    library::library_file(&dir, &name)?;
+    let folder = req.folder.trim().trim_matches('/');
+    let folder = match auth::viewer(&state, &sid) {
+        Some(user) => format!("{}/{}", user, folder),
+        None => folder.to_string(),
+    };
+    if folder.split('/').count() > library::MAX_DEPTH {
+        return Err(AppError::Invalid { field: "folder", reason: "nested too deep for the library to list".to_string() });
+    }
+    let target = format!("{}/{}", folder, base_name(&name));
+    if FsPath::new(&dir).join(&target).exists() {
+        return Err(AppError::BadRequest(format!("{} already has a {}", sanitize::line(&folder), sanitize::line(base_name(&name)))));
+    }
+
+    let (from, into) = (name.clone(), folder.clone());
+    tokio::task::spawn_blocking(move || link_into(&dir, &from, &into))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+    let (from, link, now) = (name.clone(), target.clone(), db::now());
+    state
+        .db
+        .call(move |conn| {
+            let source = source_of(conn, &from)?;
+            conn.execute(
+                "INSERT INTO publications (name, source, created_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (name) DO UPDATE SET source = excluded.source, created_at = excluded.created_at",
+                params![link, source, now],
+            )
+        })
+        .await?;
+    reconcile::refresh(&state.db, &state.config.downloads_dir, vec![target.clone()]).await?;
+
+    tracing::info!("Published {} as {}", name, target);
+    let msg = format!("Published {} in {}; deleting one copy keeps the other.", sanitize::line(&name), sanitize::line(&folder));
+    page::flash(&state, &sid, FlashKind::Info, msg);
+    Ok(Redirect::to(&format!("/media/{}", sanitize::url_path(&target))).into_response())
+}
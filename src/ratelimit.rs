@@ -0,0 +1,98 @@
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::AppError;
+use crate::server::ClientAddr;
+use crate::AppState;
+
+// An instance reachable from outside would otherwise run yt-dlp for whoever
+// asks, as often as they ask. Each address gets BSDL_RATE_LIMIT of the
+// requests that start yt-dlp per minute; other pages and the library aren't limited.
+
+/// The posts counted: analyzing a URL, looking up sizes and geo-blocked
+/// formats, and queueing downloads, by form or API.
+const LIMITED: &[&str] = &[
+    "/analyze",
+    "/analyze/entries",
+    "/analyze/entries/whole",
+    "/options/sizes",
+    "/options/geo",
+    "/download",
+    "/batch",
+    "/api/v1/analyze",
+    "/api/v1/download",
+];
+
+/// Pages that run yt-dlp when given a query: a search, and /batch listing a channel.
+const LIMITED_GETS: &[&str] = &["/search", "/batch"];
+
+/// Whether `req` starts yt-dlp, so it's counted here and needs signing in
+/// wherever changes do.
+pub fn spawns(req: &Request) -> bool {
+    let path = req.uri().path();
+    match *req.method() {
+        Method::POST => LIMITED.contains(&path),
+        Method::GET | Method::HEAD => LIMITED_GETS.contains(&path) && req.uri().query().is_some_and(|q| !q.is_empty()),
+        _ => false,
+    }
+}
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+// Addresses kept before the ones whose minute is over are dropped
+const TRACKED: usize = 1024;
+
+// --- Limiter ---
+
+/// Requests per address in the current minute of each.
+#[derive(Clone, Default)]
+pub struct Limiter {
+    inner: Arc<Mutex<HashMap<IpAddr, (Instant, u32)>>>,
+}
+
+impl Limiter {
+    /// Counts a request from `ip`; past `per_minute`, how long until it may try again.
+    pub fn check(&self, ip: IpAddr, per_minute: u32) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut map = self.inner.lock().unwrap();
+        if map.len() >= TRACKED {
+            map.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        }
+        let (start, count) = map.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            (*start, *count) = (now, 0);
+        }
+        if *count >= per_minute {
+            return Err(WINDOW - now.duration_since(*start));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+// --- Middleware ---
+
+/// Turns away an address's requests that `spawns` past BSDL_RATE_LIMIT a minute.
+pub async fn limit_layer(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let per_minute = state.config.rate_limit;
+    if per_minute == 0 || !spawns(&req) {
+        return next.run(req).await;
+    }
+    let Some(ip) = req.extensions().get::<ClientAddr>().map(|a| a.ip(req.headers())) else {
+        return next.run(req).await;
+    };
+    if let Err(wait) = state.limiter.check(ip, per_minute) {
+        tracing::warn!("Rate limited {} on {}", ip, req.uri().path());
+        let msg = format!("{} a minute at most from one address; try again in {}s", per_minute, wait.as_secs().max(1));
+        return AppError::TooManyRequests(msg).into_response();
+    }
+    next.run(req).await
+}
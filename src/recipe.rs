@@ -0,0 +1,152 @@
+use crate::db::Db;
+use crate::error::AppError;
+use crate::jobs::{NewJob, Step};
+use crate::profiles;
+use crate::session::{Choice, Selection};
+use crate::validate;
+use crate::{AppState, AUDIO_FORMATS};
+
+/// What the post-processing select offers; "" falls back to the global setting.
+pub const POSTPROCESS: &[(&str, &str)] = &[
+    ("", "Global default"),
+    ("none", "None"),
+    ("normalize", "Normalize loudness"),
+    ("compat", "Compatibility copy"),
+    ("normalize,compat", "Normalize, then compatibility copy"),
+    ("torrent", "Torrent for sharing"),
+    ("normalize,torrent", "Normalize, then torrent"),
+    ("translate", "Translated subtitles"),
+];
+
+// --- Data Structures ---
+
+/// How URLs queued without the wizard (subscriptions, batches) are downloaded.
+/// `None` means "use the global BSDL_SUBSCRIPTION_* setting".
+#[derive(Debug, Clone, Default)]
+pub struct Recipe {
+    /// `best`, `audio:<format>` or `profile:<id or name>`
+    pub preset: Option<String>,
+    pub output_template: Option<String>,
+    /// Comma separated steps; empty means none at all
+    pub postprocess: Option<String>,
+    /// Subject to the polite-mode sleeps and limits
+    pub polite: bool,
+}
+
+impl Recipe {
+    /// From the preset, template and post-processing fields of a form that
+    /// passed `validate_fields`.
+    pub fn from_form(preset: &str, output_template: &str, postprocess: &str, polite: bool) -> Recipe {
+        Recipe {
+            preset: Some(preset.trim().to_string()).filter(|p| !p.is_empty()),
+            output_template: Some(output_template.trim().to_string()).filter(|t| !t.is_empty()),
+            postprocess: match postprocess {
+                "" => None,
+                "none" => Some(String::new()),
+                steps => Some(steps.to_string()),
+            },
+            polite,
+        }
+    }
+
+    /// Catches unknown presets and deleted profiles before anything is queued.
+    pub async fn check(&self, db: &Db) -> Result<(), AppError> {
+        if let Some(preset) = &self.preset {
+            selection(db, preset, false).await?;
+        }
+        Ok(())
+    }
+
+    /// The download and post-processing steps for one URL.
+    pub async fn steps(&self, state: &AppState, url: String) -> Result<Vec<NewJob>, AppError> {
+        let config = &state.config;
+        let steps: Vec<String> = match &self.postprocess {
+            Some(list) => list.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+            None => config.subscription_postprocess.clone(),
+        };
+        let normalize = steps.iter().any(|s| s == "normalize");
+        let compat = steps.iter().any(|s| s == "compat");
+        let torrent = steps.iter().any(|s| s == "torrent");
+        let translate = steps.iter().any(|s| s == "translate");
+
+        let preset = self.preset.as_deref().unwrap_or(&config.subscription_preset);
+        let selection = selection(&state.db, preset, compat).await?;
+        let audio = selection.audio_only();
+        let template = self.output_template.clone().unwrap_or_else(|| config.subscription_template.clone());
+
+        // Each step works from the file the one before it produced
+        let download = Step::Download { url, selection: Box::new(selection), template, polite: self.polite };
+        let mut jobs = vec![NewJob { step: download, after: vec![] }];
+        if normalize {
+            jobs.push(NewJob { step: Step::Normalize, after: vec![jobs.len() - 1] });
+        }
+        // Embedding changes the file, so it goes before anything made from it
+        if translate && !audio {
+            jobs.push(NewJob { step: Step::Translate, after: vec![jobs.len() - 1] });
+        }
+        // The last step to touch the file as kept
+        let kept = jobs.len() - 1;
+        if compat && !audio {
+            jobs.push(NewJob { step: Step::Transcode, after: vec![kept] });
+        }
+        if torrent {
+            // Of the file as kept, not of a compatibility copy
+            jobs.push(NewJob { step: Step::Torrent, after: vec![kept] });
+        }
+        Ok(jobs)
+    }
+}
+
+// --- Presets ---
+
+// Turns a preset into what the download step needs
+async fn selection(db: &Db, preset: &str, compat: bool) -> Result<Selection, AppError> {
+    let invalid = |reason: String| AppError::Invalid { field: "preset", reason };
+    let (choice, container, audio_format) = match preset.split_once(':') {
+        None if preset == "best" => (Choice::BestVideo, "mkv".to_string(), AUDIO_FORMATS[0].to_string()),
+        Some(("audio", format)) if AUDIO_FORMATS.contains(&format) => {
+            (Choice::BestAudio, "mkv".to_string(), format.to_string())
+        }
+        Some(("profile", key)) => {
+            let profile = profiles::list(db)
+                .await?
+                .into_iter()
+                .find(|p| p.id.to_string() == key || p.name == key)
+                .ok_or_else(|| invalid(format!("there is no device profile {}", key)))?;
+            let container = profile.container.clone();
+            (Choice::Profile(profile), container, AUDIO_FORMATS[0].to_string())
+        }
+        _ => {
+            return Err(invalid(format!(
+                "{:?} isn't a preset; use best, audio:<{}> or profile:<name>",
+                preset,
+                AUDIO_FORMATS.join("|")
+            )))
+        }
+    };
+    Ok(Selection { choice, container, audio_format, compat, workaround: None, item: None, date_after: None, playlist: false })
+}
+
+/// (value, label) for a preset select, not counting "Global default".
+pub async fn preset_options(db: &Db) -> Result<Vec<(String, String)>, AppError> {
+    let mut presets = vec![("best".to_string(), "Best video".to_string())];
+    for format in AUDIO_FORMATS {
+        presets.push((format!("audio:{}", format), format!("Audio only ({})", format)));
+    }
+    for p in profiles::list(db).await? {
+        presets.push((format!("profile:{}", p.id), format!("Profile: {}", p.name)));
+    }
+    Ok(presets)
+}
+
+/// Shape checks for the preset, template and post-processing fields; whether
+/// the preset exists is `Recipe::check`'s job.
+pub fn validate_fields(preset: &str, output_template: &str, postprocess: &str) -> Result<(), AppError> {
+    let template = output_template.trim();
+    if !template.is_empty() {
+        validate::output_template("output_template", template)?;
+    }
+    let allowed: Vec<&str> = POSTPROCESS.iter().map(|(v, _)| *v).collect();
+    validate::one_of("postprocess", postprocess, &allowed)?;
+    validate::max_len("preset", preset, 100)
+}
@@ -0,0 +1,472 @@
+use axum::{
+    extract::{Path as UrlPath, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::auth;
+use crate::clock::Stamp;
+use crate::db::{self, params, Db, OptionalExtension, Storage};
+use crate::error::{render, AppError};
+use crate::library::{self, Staged};
+use crate::page::{self, FlashKind};
+use crate::publish;
+use crate::sanitize::{self, filters};
+use crate::schedule::{Timer, Timers};
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::{AppState, MediaType};
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    pub finished_at: Stamp,
+    pub trigger: String,
+    pub scanned: i64,
+    pub indexed: i64,
+    pub missing: i64,
+    pub restored: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MissingItem {
+    pub file_name: String,
+    pub title: String,
+    pub missing_since: Option<Stamp>,
+}
+
+/// A history record someone removed; the file may or may not still be there.
+#[derive(Debug, Clone)]
+pub struct RemovedItem {
+    pub file_name: String,
+    pub title: String,
+    pub removed_at: Stamp,
+}
+
+// One file on disk, as the index wants to see it
+struct DiskFile {
+    name: String,
+    title: Option<String>,
+    source_url: Option<String>,
+    channel: Option<String>,
+    media_type: &'static str,
+    size: i64,
+    duration: Option<f64>,
+    tags: Option<String>,
+}
+
+pub fn media_type_label(t: MediaType) -> &'static str {
+    match t {
+        MediaType::Video => "video",
+        MediaType::Audio => "audio",
+        MediaType::Image => "image",
+        MediaType::Other => "other",
+    }
+}
+
+fn disk_file(dir: &str, name: &str) -> DiskFile {
+    let path = Path::new(dir).join(name);
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    let meta = library::load_meta(dir, name);
+    DiskFile {
+        name: name.to_string(),
+        title: meta.as_ref().and_then(|m| m.title.clone()),
+        channel: meta.as_ref().and_then(|m| m.channel.clone().or_else(|| m.uploader.clone())),
+        duration: meta.as_ref().and_then(|m| m.duration),
+        tags: meta.as_ref().map(|m| m.tag_list().join(",")).filter(|t| !t.is_empty()),
+        source_url: meta.and_then(|m| m.webpage_url),
+        media_type: media_type_label(library::media_type_of(&mime)),
+        size: std::fs::metadata(&path).map(|m| m.len() as i64).unwrap_or(0),
+    }
+}
+
+fn scan_disk(dir: &str) -> std::io::Result<Vec<DiskFile>> {
+    let names = library::list_names(dir)?;
+    Ok(names
+        .iter()
+        .filter(|name| !name.starts_with('.') && !library::is_sidecar(name, &names))
+        .map(|name| disk_file(dir, name))
+        .collect())
+}
+
+// The ones of `names` the library would list, looking only at their folders
+fn scan_names(dir: &str, names: &[String]) -> std::io::Result<Vec<DiskFile>> {
+    let mut files = Vec::new();
+    for name in names {
+        let siblings = library::siblings(dir, name)?;
+        if !name.starts_with('.') && siblings.contains(name) && !library::is_sidecar(name, &siblings) {
+            files.push(disk_file(dir, name));
+        }
+    }
+    Ok(files)
+}
+
+// What a pass did with a file it found
+enum Seen {
+    Indexed,
+    Restored,
+    Refreshed,
+    Removed,
+}
+
+// Indexes `file`, or refreshes its record, whose status was `known`
+fn index_file(conn: &mut dyn Storage, file: &DiskFile, known: Option<&str>, now: i64) -> db::Result<Seen> {
+    match known {
+        None => {
+            conn.execute(
+                "INSERT INTO items (file_name, title, source_url, media_type, size_bytes, status, added_at, last_seen_at, channel, duration, tags)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 'present', ?6, ?6, ?7, ?8, ?9)",
+                params![file.name, file.title, file.source_url, file.media_type, file.size, now, file.channel, file.duration, file.tags],
+            )?;
+            Ok(Seen::Indexed)
+        }
+        // Removed from the history on purpose: stays out even if the file is still here
+        Some("deleted") => Ok(Seen::Removed),
+        Some(status) => {
+            // Metadata may have been imported since the last pass, and a
+            // file that changed size has to be hashed again
+            conn.execute(
+                "UPDATE items SET status = 'present', missing_since = NULL, last_seen_at = ?2, size_bytes = ?3,
+                     content_hash = CASE WHEN size_bytes = ?3 THEN content_hash END,
+                     title = COALESCE(?4, title), source_url = COALESCE(?5, source_url),
+                     channel = COALESCE(?6, channel), duration = COALESCE(?7, duration), tags = COALESCE(?8, tags)
+                 WHERE file_name = ?1",
+                params![file.name, now, file.size, file.title, file.source_url, file.channel, file.duration, file.tags],
+            )?;
+            Ok(if status == "missing" { Seen::Restored } else { Seen::Refreshed })
+        }
+    }
+}
+
+fn mark_missing(conn: &mut dyn Storage, name: &str, now: i64) -> db::Result<usize> {
+    conn.execute("UPDATE items SET status = 'missing', missing_since = ?2 WHERE file_name = ?1", params![name, now])
+}
+
+// --- Reconciliation ---
+
+/// Brings the index in line with the downloads directory: new files get indexed,
+/// vanished ones are marked missing, and files that came back are marked present again.
+pub async fn run(db: &Db, dir: &str, trigger: &'static str) -> Result<RunSummary, AppError> {
+    let started = db::now();
+    let dir = dir.to_string();
+    let disk = tokio::task::spawn_blocking(move || scan_disk(&dir))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+
+    db.call(move |conn| {
+        let mut tx = conn.transaction()?;
+        let now = db::now();
+
+        let mut known: HashMap<String, String> = tx
+            .query_map("SELECT file_name, status FROM items", params![], |r| Ok((r.get(0)?, r.get(1)?)))?
+            .into_iter()
+            .collect();
+
+        let (mut indexed, mut restored, mut missing) = (0, 0, 0);
+        for file in &disk {
+            match index_file(&mut *tx, file, known.remove(&file.name).as_deref(), now)? {
+                Seen::Indexed => indexed += 1,
+                Seen::Restored => restored += 1,
+                Seen::Refreshed | Seen::Removed => {}
+            }
+        }
+
+        // Whatever is left in `known` is no longer on disk
+        for (name, status) in known {
+            if status == "present" {
+                mark_missing(&mut *tx, &name, now)?;
+                missing += 1;
+            }
+        }
+
+        tx.execute(
+            "INSERT INTO reconcile_runs (started_at, finished_at, trigger, scanned, indexed, missing, restored)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![started, now, trigger, disk.len() as i64, indexed, missing, restored],
+        )?;
+        tx.commit()?;
+
+        Ok(RunSummary {
+            finished_at: Stamp(now),
+            trigger: trigger.to_string(),
+            scanned: disk.len() as i64,
+            indexed,
+            missing,
+            restored,
+        })
+    })
+    .await
+}
+
+/// Brings the index in line with the downloads directory for `names` alone,
+/// after a change that touched only those: each is indexed, refreshed or
+/// marked missing as a full pass would, without reading the rest of the
+/// library. Full passes are left to the schedule and "run now".
+pub async fn refresh(db: &Db, dir: &str, names: Vec<String>) -> Result<(), AppError> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let dir = dir.to_string();
+    let (disk, names) = tokio::task::spawn_blocking(move || scan_names(&dir, &names).map(|disk| (disk, names)))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+
+    db.call(move |conn| {
+        let mut tx = conn.transaction()?;
+        let now = db::now();
+        for name in &names {
+            let known: Option<String> =
+                tx.query_row("SELECT status FROM items WHERE file_name = ?1", params![name], |r| r.get(0)).optional()?;
+            match disk.iter().find(|f| f.name == *name) {
+                Some(file) => {
+                    index_file(&mut *tx, file, known.as_deref(), now)?;
+                }
+                None if known.as_deref() == Some("present") => {
+                    mark_missing(&mut *tx, name, now)?;
+                }
+                None => {}
+            }
+        }
+        tx.commit()
+    })
+    .await
+}
+
+/// Rescans every `minutes` in the background. 0 turns the schedule off.
+pub fn spawn_schedule(db: Db, dir: String, minutes: u64, timers: Timers) {
+    if minutes == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(minutes * 60));
+        loop {
+            // The first tick fires right away, which doubles as the startup scan
+            tick.tick().await;
+            timers.fired(Timer::Rescan, minutes * 60);
+            match run(&db, &dir, "scheduled").await {
+                Ok(s) if s.indexed + s.missing + s.restored > 0 => tracing::info!(
+                    "Library rescan: {} indexed, {} missing, {} restored",
+                    s.indexed, s.missing, s.restored
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Library rescan failed: {}", e),
+            }
+        }
+    });
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "reconcile.html")]
+struct ReconcileTemplate {
+    runs: Vec<RunSummary>,
+    missing: Vec<MissingItem>,
+    removed: Vec<RemovedItem>,
+    interval_minutes: u64,
+}
+
+// How many removed records the report lists
+const REMOVED_SHOWN: usize = 50;
+
+/// The rescans, and the records missing or removed in the viewer's folder.
+pub async fn show_report(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let viewer = auth::viewer(&state, &sid);
+    let (runs, mut missing, mut removed) = state
+        .db
+        .call(|conn| {
+            let runs = conn.query_map(
+                "SELECT finished_at, trigger, scanned, indexed, missing, restored
+                 FROM reconcile_runs ORDER BY id DESC LIMIT 20",
+                params![],
+                |r| {
+                    Ok(RunSummary {
+                        finished_at: Stamp(r.get(0)?),
+                        trigger: r.get(1)?,
+                        scanned: r.get(2)?,
+                        indexed: r.get(3)?,
+                        missing: r.get(4)?,
+                        restored: r.get(5)?,
+                    })
+                },
+            )?;
+
+            let missing = conn.query_map(
+                "SELECT file_name, COALESCE(title, file_name), missing_since
+                 FROM items WHERE status = 'missing' ORDER BY missing_since DESC",
+                params![],
+                |r| {
+                    Ok(MissingItem {
+                        file_name: r.get(0)?,
+                        title: r.get(1)?,
+                        missing_since: r.get::<Option<i64>>(2)?.map(Stamp),
+                    })
+                },
+            )?;
+
+            let removed = conn.query_map(
+                "SELECT file_name, COALESCE(title, file_name), deleted_at
+                 FROM items WHERE status = 'deleted' ORDER BY deleted_at DESC",
+                params![],
+                |r| {
+                    Ok(RemovedItem {
+                        file_name: r.get(0)?,
+                        title: r.get(1)?,
+                        removed_at: Stamp(r.get::<Option<i64>>(2)?.unwrap_or_default()),
+                    })
+                },
+            )?;
+            Ok((runs, missing, removed))
+        })
+        .await?;
+    missing.retain(|m| library::visible_to(viewer.as_deref(), &m.file_name));
+    removed.retain(|r| library::visible_to(viewer.as_deref(), &r.file_name));
+    removed.truncate(REMOVED_SHOWN);
+
+    Ok(render(ReconcileTemplate { runs, missing, removed, interval_minutes: state.config.rescan_minutes }))
+}
+
+/// When the last rescan, of whatever trigger, started.
+pub async fn last_run(db: &Db) -> Result<Option<Stamp>, AppError> {
+    db.call(|conn| {
+        conn.query_row("SELECT MAX(started_at) FROM reconcile_runs", params![], |r| r.get::<Option<i64>>(0))
+            .map(|at| at.map(Stamp))
+    })
+    .await
+}
+
+pub async fn run_now(State(state): State<AppState>) -> Result<Response, AppError> {
+    run(&state.db, &state.config.downloads_dir, "manual").await?;
+    Ok(Redirect::to("/library/reconcile").into_response())
+}
+
+// --- History records ---
+
+/// Whether `name` has a history record that hasn't been removed.
+pub async fn in_history(db: &Db, name: &str) -> Result<bool, AppError> {
+    let name = name.to_string();
+    let status = db
+        .call(move |conn| {
+            conn.query_row("SELECT status FROM items WHERE file_name = ?1", params![name], |r| r.get::<String>(0))
+                .optional()
+        })
+        .await?;
+    Ok(status.is_some_and(|s| s != "deleted"))
+}
+
+#[derive(Deserialize)]
+pub struct RemoveRequest {
+    mode: String,
+}
+
+impl Validate for RemoveRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        validate::one_of("mode", &self.mode, &["record", "file"])
+    }
+}
+
+/// Marks `name` removed from the history and, with `with_file`, deletes the
+/// file and its sidecars. The files are only moved aside until the record
+/// change is committed, so the database and the downloads directory never end
+/// up telling different stories. `None` when there's no such record, otherwise
+/// how many files went.
+pub fn remove_record(conn: &mut dyn Storage, dir: &str, name: &str, with_file: bool) -> db::Result<std::io::Result<Option<usize>>> {
+    let mut tx = conn.transaction()?;
+    let marked = tx.execute(
+        "UPDATE items SET status = 'deleted', deleted_at = ?2 WHERE file_name = ?1",
+        params![name, db::now()],
+    )?;
+    if marked == 0 {
+        return Ok(Ok(None));
+    }
+    if with_file {
+        publish::release(&mut *tx, name)?;
+    }
+    let staged = match with_file.then(|| Staged::remove(dir, name)).transpose() {
+        Ok(staged) => staged.unwrap_or_default(),
+        // Dropping the transaction rolls the record back
+        Err(e) => return Ok(Err(e)),
+    };
+    if let Err(e) = tx.commit() {
+        staged.restore();
+        return Err(e);
+    }
+    Ok(Ok(Some(staged.finish())))
+}
+
+/// Removes a history record, and with `mode=file` the file and its sidecars too.
+pub async fn remove_item(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    UrlPath(name): UrlPath<String>,
+    Form(req): Form<RemoveRequest>,
+) -> Result<Response, AppError> {
+    if !library::plain_name(&name) {
+        return Err(AppError::BadRequest("Invalid file name".to_string()));
+    }
+    if !library::visible_to(auth::viewer(&state, &sid).as_deref(), &name) {
+        return Err(AppError::NotFound(format!("History entry for \"{}\"", sanitize::line(&name))));
+    }
+    let with_file = req.mode == "file";
+    let kept = if with_file { publish::published(&state, &name).await? } else { Vec::new() };
+    let (dir, key) = (state.config.downloads_dir.clone(), name.clone());
+    let outcome = state.db.call(move |conn| remove_record(conn, &dir, &key, with_file)).await?;
+
+    let shown = sanitize::line(&name);
+    match outcome? {
+        None => Err(AppError::NotFound(format!("History entry for \"{}\"", shown))),
+        // Only this name's links went; the contents are still there under the others
+        Some(files) if with_file && !kept.is_empty() => {
+            let msg = format!("Removed {} and deleted {} file(s); it's still published as {}.", shown, files, sanitize::line(&kept.join(", ")));
+            page::flash(&state, &sid, FlashKind::Info, msg);
+            Ok(Redirect::to(&format!("/media/{}", sanitize::url_path(&kept[0]))).into_response())
+        }
+        Some(files) if with_file => {
+            page::flash(&state, &sid, FlashKind::Info, format!("Removed {} from the history and deleted {} file(s).", shown, files));
+            Ok(Redirect::to("/files").into_response())
+        }
+        Some(_) => {
+            page::flash(&state, &sid, FlashKind::Info, format!("Removed {} from the history; the file was kept.", shown));
+            let back = if std::path::Path::new(&state.config.downloads_dir).join(&name).is_file() {
+                format!("/media/{}", sanitize::url_path(&name))
+            } else {
+                "/library/reconcile".to_string()
+            };
+            Ok(Redirect::to(&back).into_response())
+        }
+    }
+}
+
+/// Brings a removed record back, present or missing as its file is.
+pub async fn restore_item(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<Response, AppError> {
+    if !library::visible_to(auth::viewer(&state, &sid).as_deref(), &name) {
+        return Err(AppError::NotFound(format!("Removed history entry for \"{}\"", sanitize::line(&name))));
+    }
+    let key = name.clone();
+    let restored = state
+        .db
+        .call(move |conn| {
+            conn.execute(
+                "UPDATE items SET status = 'present', deleted_at = NULL, missing_since = NULL
+                 WHERE file_name = ?1 AND status = 'deleted'",
+                params![key],
+            )
+        })
+        .await?;
+    if restored == 0 {
+        return Err(AppError::NotFound(format!("Removed history entry for \"{}\"", sanitize::line(&name))));
+    }
+    refresh(&state.db, &state.config.downloads_dir, vec![name.clone()]).await?;
+    page::flash(&state, &sid, FlashKind::Info, format!("{} is back in the history.", sanitize::line(&name)));
+    Ok(Redirect::to("/library/reconcile").into_response())
+}
@@ -0,0 +1,252 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+
+use crate::config::FilenameProfile;
+
+// Askama HTML-escapes every `{{ }}` already. What's handled here is what escaping
+// can't fix: invisible or direction-flipping characters in titles pulled from
+// remote sites, names dropped into URLs, and links that aren't really web links.
+
+// Unreserved characters are the only ones a query value keeps as-is
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+// Characters a single URL path segment can't carry as-is
+const PATH_SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+
+// Bidi overrides/isolates and zero-width characters. They render as nothing but
+// can make "exe.mp4" read as "4pm.exe", or hide text inside a title.
+fn is_invisible(c: char) -> bool {
+    matches!(c,
+        '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{2069}' | '\u{FEFF}' | '\u{FFF9}'..='\u{FFFB}')
+}
+
+/// Multi-line text from outside (descriptions, yt-dlp stderr): control and
+/// invisible characters dropped, newlines and tabs kept.
+pub fn text(s: &str) -> String {
+    s.chars()
+        .filter(|&c| c == '\n' || c == '\t' || !(c.is_control() || is_invisible(c)))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// A one-line label (titles, codecs, uploader names): like `text`, with runs
+/// of whitespace collapsed to single spaces.
+pub fn line(s: &str) -> String {
+    text(s).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn line_opt(s: Option<String>) -> Option<String> {
+    s.map(|s| line(&s)).filter(|s| !s.is_empty())
+}
+
+// --- File Names ---
+
+// Device names Windows won't create a file for, whatever the extension
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Applies the same rules yt-dlp uses for the profile's flags to a name the app
+/// is choosing itself (uploads, duplicate suffixes).
+pub fn file_name(name: &str, profile: &FilenameProfile) -> String {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    let clean = |part: &str| {
+        let mut part = line(part);
+        if profile.restrict {
+            part = restricted(&part);
+        }
+        if profile.windows {
+            part = part
+                .chars()
+                .map(|c| if matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') { '_' } else { c })
+                .collect();
+        }
+        part
+    };
+
+    let mut stem = clean(stem);
+    if profile.trim > 0 && stem.chars().count() > profile.trim {
+        stem = stem.chars().take(profile.trim).collect();
+    }
+    if profile.windows {
+        // Trailing dots and spaces are silently dropped by Windows, which breaks lookups
+        stem = stem.trim_end_matches(['.', ' ']).to_string();
+        if WINDOWS_RESERVED.contains(&stem.to_ascii_uppercase().as_str()) {
+            stem.insert(0, '_');
+        }
+    }
+    if stem.is_empty() {
+        stem = "file".to_string();
+    }
+
+    match ext.map(clean) {
+        Some(ext) if !ext.is_empty() => format!("{}.{}", stem, ext),
+        _ => stem,
+    }
+}
+
+// Anything outside [A-Za-z0-9._-] becomes '_', without doubling up
+fn restricted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        let c = if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' };
+        if !(c == '_' && out.ends_with('_')) {
+            out.push(c);
+        }
+    }
+    out.trim_matches('_').to_string()
+}
+
+/// Percent-encodes any text as one query string (or RFC 5987 header) value.
+pub fn query_value(s: &str) -> String {
+    utf8_percent_encode(s, QUERY_VALUE).to_string()
+}
+
+/// Percent-encodes `name` for use in a redirect or link path.
+pub fn url_path(name: &str) -> String {
+    utf8_percent_encode(name, PATH_SEGMENT).to_string()
+}
+
+/// Percent-encodes each part of a library name such as `alice/Music/a b.mp3`,
+/// keeping the slashes between them.
+pub fn url_parts(name: &str) -> String {
+    name.split('/').map(url_path).collect::<Vec<_>>().join("/")
+}
+
+// --- Template Filters ---
+
+// Template modules pull these in with `use crate::sanitize::filters;`
+pub mod filters {
+    /// `/content/{{ name|urlpath }}`: a file name as one URL path segment.
+    pub fn urlpath<T: std::fmt::Display>(s: T) -> ::askama::Result<String> {
+        Ok(super::url_path(&s.to_string()))
+    }
+
+    /// `href="{{ name|urlparts }}"`: a library name as a relative path, its
+    /// folders still folders, for pages opened without the app.
+    pub fn urlparts<T: std::fmt::Display>(s: T) -> ::askama::Result<String> {
+        Ok(super::url_parts(&s.to_string()))
+    }
+
+    /// `?channel={{ name|query }}`: any text as one query string value.
+    pub fn query<T: std::fmt::Display>(s: T) -> ::askama::Result<String> {
+        Ok(super::query_value(&s.to_string()))
+    }
+
+    /// `href="{{ url|href }}"`: only http(s) links survive; `javascript:` and
+    /// friends become a dead "#".
+    pub fn href<T: std::fmt::Display>(s: T) -> ::askama::Result<String> {
+        let url = s.to_string();
+        let url = url.trim();
+        let lower = url.to_ascii_lowercase();
+        if lower.starts_with("http://") || lower.starts_with("https://") {
+            Ok(url.to_string())
+        } else {
+            Ok("#".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(restrict: bool, windows: bool, trim: usize) -> FilenameProfile {
+        FilenameProfile { restrict, windows, trim }
+    }
+
+    #[test]
+    fn text_drops_control_and_invisible_characters() {
+        assert_eq!(text("  a\u{0}b\u{7}c\u{1b}[31m  "), "abc[31m");
+        assert_eq!(text("line one\nline\ttwo\r\n"), "line one\nline\ttwo");
+        // "exe.mp4" written backwards behind a right-to-left override
+        assert_eq!(text("clip\u{202E}4pm.exe"), "clip4pm.exe");
+        assert_eq!(text("zero\u{200B}width\u{FEFF}"), "zerowidth");
+    }
+
+    #[test]
+    fn line_collapses_whitespace() {
+        assert_eq!(line("  A\n  title\twith \u{2066}gaps\u{2069}  "), "A title with gaps");
+        assert_eq!(line("\u{0}\u{200B}"), "");
+        assert_eq!(line_opt(Some(" \u{200F} ".to_string())), None);
+        assert_eq!(line_opt(Some(" Name ".to_string())), Some("Name".to_string()));
+    }
+
+    #[test]
+    fn file_name_keeps_plain_names() {
+        assert_eq!(file_name("My Video.mp4", &FilenameProfile::default()), "My Video.mp4");
+        assert_eq!(file_name("a\u{202E}4pm.exe", &FilenameProfile::default()), "a4pm.exe");
+        assert_eq!(file_name("new\nline.mkv", &FilenameProfile::default()), "new line.mkv");
+        assert_eq!(file_name("\u{200B}.mp4", &FilenameProfile::default()), "file.mp4");
+    }
+
+    #[test]
+    fn file_name_restricted_leaves_no_separators() {
+        let p = profile(true, false, 0);
+        for name in ["../../etc/passwd", "..\\..\\boot.ini", "/abs/path.mp4"] {
+            let clean = file_name(name, &p);
+            assert!(!clean.contains(['/', '\\']), "{:?} became {:?}", name, clean);
+        }
+        assert_eq!(file_name("Café à la carte!.mp4", &p), "Caf_la_carte.mp4");
+    }
+
+    #[test]
+    fn file_name_windows() {
+        let p = profile(false, true, 0);
+        assert_eq!(file_name("a/b\\c:d*e?.mp4", &p), "a_b_c_d_e_.mp4");
+        assert_eq!(file_name("con.mp4", &p), "_con.mp4");
+        assert_eq!(file_name("trailing. .mp4", &p), "trailing.mp4");
+    }
+
+    #[test]
+    fn file_name_trims_the_stem_only() {
+        assert_eq!(file_name("abcdefghij.webm", &profile(false, false, 4)), "abcd.webm");
+        assert_eq!(file_name("ééééé.mp3", &profile(false, false, 2)), "éé.mp3");
+    }
+
+    #[test]
+    fn restricted_collapses_and_trims_underscores() {
+        assert_eq!(restricted("  a  b  "), "a_b");
+        assert_eq!(restricted("x/../y"), "x_.._y");
+        assert_eq!(restricted("\u{0}\u{1f}"), "");
+        assert_eq!(restricted("keep.me-it_s"), "keep.me-it_s");
+    }
+
+    #[test]
+    fn url_path_is_one_segment() {
+        assert_eq!(url_path("My Video.mp4"), "My%20Video.mp4");
+        assert_eq!(url_path("../../etc/passwd"), "..%2F..%2Fetc%2Fpasswd");
+        assert_eq!(url_path("a?b#c%d"), "a%3Fb%23c%25d");
+        assert_eq!(url_path("\u{0}\n"), "%00%0A");
+        assert_eq!(filters::urlpath("x y").unwrap(), "x%20y");
+    }
+
+    #[test]
+    fn url_parts_keeps_the_folders() {
+        assert_eq!(url_parts("alice/Music/a b.mp3"), "alice/Music/a%20b.mp3");
+        assert_eq!(url_parts("plain.mp4"), "plain.mp4");
+        assert_eq!(url_parts("a?b/c#d"), "a%3Fb/c%23d");
+        assert_eq!(filters::urlparts("x y/z").unwrap(), "x%20y/z");
+    }
+
+    #[test]
+    fn query_value_escapes_separators() {
+        assert_eq!(query_value("a&b=c d/é"), "a%26b%3Dc%20d%2F%C3%A9");
+        assert_eq!(filters::query("/jobs?x=1").unwrap(), "%2Fjobs%3Fx%3D1");
+    }
+
+    #[test]
+    fn href_only_lets_web_links_through() {
+        assert_eq!(filters::href(" https://example.org/a?b ").unwrap(), "https://example.org/a?b");
+        assert_eq!(filters::href("HTTP://EXAMPLE.ORG").unwrap(), "HTTP://EXAMPLE.ORG");
+        assert_eq!(filters::href("javascript:alert(1)").unwrap(), "#");
+        assert_eq!(filters::href(" JavaScript:alert(1)").unwrap(), "#");
+        assert_eq!(filters::href("data:text/html,<b>").unwrap(), "#");
+        assert_eq!(filters::href("//evil.example").unwrap(), "#");
+        assert_eq!(filters::href("/local/path").unwrap(), "#");
+    }
+}
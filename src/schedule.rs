@@ -0,0 +1,297 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::backup;
+use crate::clock::Stamp;
+use crate::error::{render, AppError};
+use crate::page::{self, FlashKind};
+use crate::premieres;
+use crate::reconcile;
+use crate::session::SessionId;
+use crate::subscriptions;
+use crate::validate::{Form, Validate};
+use crate::AppState;
+
+// What the app does on its own, laid out ahead: subscription checks,
+// premieres waiting to go live, library rescans, WAL checkpoints and backups.
+// Subscriptions and premieres keep their next check in the database; the
+// timers on a fixed interval note theirs in Timers each time they fire. Any
+// of them can be run now from /schedule instead of waiting its turn.
+
+/// How far ahead the timeline reaches.
+pub const SPAN_HOURS: i64 = 24;
+
+// --- Timers ---
+
+/// Background work on a fixed interval from the start of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Timer {
+    Backup,
+    Rescan,
+    Checkpoint,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Times {
+    last: Option<Stamp>,
+    next: Stamp,
+}
+
+/// When each timer last fired and fires next.
+#[derive(Clone, Default)]
+pub struct Timers {
+    inner: Arc<Mutex<HashMap<Timer, Times>>>,
+}
+
+impl Timers {
+    /// Notes that `timer` was set going, to fire `every` seconds from now.
+    pub fn started(&self, timer: Timer, every: u64) {
+        let next = Stamp(Stamp::now().0 + every as i64);
+        self.inner.lock().unwrap().insert(timer, Times { last: None, next });
+    }
+
+    /// Notes that `timer` just fired, and fires again `every` seconds from now.
+    pub fn fired(&self, timer: Timer, every: u64) {
+        let now = Stamp::now();
+        self.inner.lock().unwrap().insert(timer, Times { last: Some(now), next: Stamp(now.0 + every as i64) });
+    }
+
+    fn get(&self, timer: Timer) -> Option<Times> {
+        self.inner.lock().unwrap().get(&timer).copied()
+    }
+}
+
+// --- Data Structures ---
+
+/// Something the app will do by itself.
+#[derive(Debug, Clone)]
+pub struct Planned {
+    /// What the run-now form sends, see `Task::parse`
+    pub task: String,
+    pub kind: &'static str,
+    pub name: String,
+    /// The page it's set up on
+    pub page: String,
+    /// "every 6 hours", or what keeps it from running
+    pub every: String,
+    pub last: Option<Stamp>,
+    /// None when it won't run by itself
+    pub next: Option<Stamp>,
+}
+
+impl Planned {
+    /// Its place on the timeline, as a percentage; what's overdue sits at the start.
+    pub fn offset(&self) -> Option<String> {
+        let ahead = self.next?.0 - Stamp::now().0;
+        (ahead <= SPAN_HOURS * 3600).then(|| format!("{:.2}", ahead.max(0) as f64 * 100.0 / (SPAN_HOURS * 3600) as f64))
+    }
+
+    pub fn due(&self) -> bool {
+        self.next.is_some_and(|t| t <= Stamp::now())
+    }
+}
+
+fn each(n: u64, unit: &str) -> String {
+    match n {
+        1 => format!("every {}", unit),
+        n => format!("every {} {}s", n, unit),
+    }
+}
+
+fn timer(timers: &Timers, timer: Timer, last: Option<Stamp>, off: &str, interval: String) -> (String, Option<Stamp>, Option<Stamp>) {
+    match timers.get(timer) {
+        Some(times) => (interval, times.last.or(last), Some(times.next)),
+        None => (off.to_string(), last, None),
+    }
+}
+
+/// Everything planned, soonest first; what won't run by itself comes last.
+pub async fn plan(state: &AppState) -> Result<Vec<Planned>, AppError> {
+    let config = &state.config;
+    let mut planned = Vec::new();
+
+    let hours = config.subscription_hours;
+    for sub in subscriptions::list(&state.db).await? {
+        let every = match (hours, sub.failures) {
+            (0, _) => "off (BSDL_SUBSCRIPTION_HOURS=0)".to_string(),
+            (hours, 0) => each(hours, "hour"),
+            (hours, n) => format!("{}, backing off after {} failed check{}", each(hours, "hour"), n, if n == 1 { "" } else { "s" }),
+        };
+        planned.push(Planned {
+            task: format!("subscription:{}", sub.id),
+            kind: "Subscription check",
+            name: sub.name.clone(),
+            page: "/subscriptions".to_string(),
+            every,
+            last: sub.last_checked,
+            next: sub.next_check(hours),
+        });
+    }
+
+    for premiere in premieres::list(&state.db).await? {
+        let Some(next) = premiere.next_check() else { continue };
+        planned.push(Planned {
+            task: format!("premiere:{}", premiere.id),
+            kind: "Premiere download",
+            name: premiere.title.clone(),
+            page: "/premieres".to_string(),
+            every: "from its start, every few minutes until it's live".to_string(),
+            last: premiere.last_checked,
+            next: Some(next),
+        });
+    }
+
+    let (every, last, next) = timer(
+        &state.timers,
+        Timer::Rescan,
+        reconcile::last_run(&state.db).await?,
+        "off (BSDL_RESCAN_MINUTES=0)",
+        each(config.rescan_minutes, "minute"),
+    );
+    planned.push(Planned {
+        task: "rescan".to_string(),
+        kind: "Library rescan",
+        name: "Downloads folder".to_string(),
+        page: "/library/reconcile".to_string(),
+        every,
+        last,
+        next,
+    });
+
+    let off = if config.storage.wal { "off (BSDL_DB_CHECKPOINT_MINUTES=0)" } else { "off, the database doesn't use a WAL" };
+    let (every, last, next) =
+        timer(&state.timers, Timer::Checkpoint, None, off, each(config.storage.checkpoint_minutes, "minute"));
+    planned.push(Planned {
+        task: "checkpoint".to_string(),
+        kind: "WAL checkpoint",
+        name: "Database".to_string(),
+        page: "/system/diagnostics".to_string(),
+        every,
+        last,
+        next,
+    });
+
+    let newest = backup::list().first().map(|b| b.created);
+    let (every, last, next) =
+        timer(&state.timers, Timer::Backup, newest, "off (BSDL_BACKUP_HOURS=0)", each(config.backup_hours, "hour"));
+    planned.push(Planned {
+        task: "backup".to_string(),
+        kind: "Backup",
+        name: format!("keeping the last {}", config.backup_keep),
+        page: "/system/backups".to_string(),
+        every,
+        last,
+        next,
+    });
+
+    planned.sort_by_key(|p| (p.next.is_none(), p.next));
+    Ok(planned)
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "schedule.html")]
+struct ScheduleTemplate {
+    planned: Vec<Planned>,
+    span_hours: i64,
+    /// Subscription checks and premieres wait while it's on
+    maintenance: bool,
+}
+
+pub async fn show_schedule(State(state): State<AppState>) -> Result<Response, AppError> {
+    Ok(render(ScheduleTemplate {
+        planned: plan(&state).await?,
+        span_hours: SPAN_HOURS,
+        maintenance: state.jobs.maintenance().is_some(),
+    }))
+}
+
+/// What /schedule can run now.
+enum Task {
+    Subscription(i64),
+    Premiere(i64),
+    Rescan,
+    Checkpoint,
+    Backup,
+}
+
+impl Task {
+    // "subscription:3", "premiere:7", "rescan", "checkpoint" or "backup"
+    fn parse(value: &str) -> Option<Task> {
+        match value.split_once(':') {
+            Some(("subscription", id)) => id.parse().ok().map(Task::Subscription),
+            Some(("premiere", id)) => id.parse().ok().map(Task::Premiere),
+            Some(_) => None,
+            None => match value {
+                "rescan" => Some(Task::Rescan),
+                "checkpoint" => Some(Task::Checkpoint),
+                "backup" => Some(Task::Backup),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RunRequest {
+    task: String,
+}
+
+impl Validate for RunRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        match Task::parse(&self.task) {
+            Some(_) => Ok(()),
+            None => Err(AppError::Invalid { field: "task", reason: format!("{:?} isn't something the schedule runs", self.task) }),
+        }
+    }
+}
+
+/// Runs one planned task now, without moving when it runs next, and comes back to the schedule.
+pub async fn run_now(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(req): Form<RunRequest>,
+) -> Result<Response, AppError> {
+    let Some(task) = Task::parse(&req.task) else {
+        return Ok(Redirect::to("/schedule").into_response());
+    };
+    match task {
+        Task::Subscription(id) => {
+            let sub = subscriptions::get(&state.db, id).await?.ok_or_else(|| AppError::NotFound("Subscription".to_string()))?;
+            subscriptions::check_and_flash(&state, &sid, &sub).await;
+        }
+        Task::Premiere(id) => {
+            let premiere = premieres::get(&state.db, id).await?.ok_or_else(|| AppError::NotFound("Premiere".to_string()))?;
+            premieres::check_and_flash(&state, &sid, &premiere).await;
+        }
+        Task::Rescan => match reconcile::run(&state.db, &state.config.downloads_dir, "manual").await {
+            Ok(s) => page::flash(
+                &state,
+                &sid,
+                FlashKind::Info,
+                format!("Library rescan: {} indexed, {} missing, {} restored.", s.indexed, s.missing, s.restored),
+            ),
+            Err(e) => page::flash(&state, &sid, FlashKind::Error, format!("Library rescan failed: {}", e)),
+        },
+        Task::Checkpoint => match state.db.checkpoint().await {
+            Ok(true) => page::flash(&state, &sid, FlashKind::Info, "WAL checkpoint done."),
+            Ok(false) => page::flash(
+                &state,
+                &sid,
+                FlashKind::Error,
+                "WAL checkpoint couldn't finish: another connection is reading.",
+            ),
+            Err(e) => page::flash(&state, &sid, FlashKind::Error, format!("WAL checkpoint failed: {}", e)),
+        },
+        Task::Backup => backup::create_and_flash(&state, &sid).await,
+    }
+    Ok(Redirect::to("/schedule").into_response())
+}
@@ -0,0 +1,129 @@
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Extension,
+};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::error::{render, render_status, AppError};
+use crate::library::length_label;
+use crate::lite::{self, Lite};
+use crate::pinning;
+use crate::sanitize;
+use crate::server::ClientGone;
+use crate::validate;
+use crate::AppState;
+
+// Searching YouTube from the analyzer, for when there's no URL to paste.
+// yt-dlp's ytsearch lists the first RESULTS matches flat, without looking at
+// each one: title, length, channel and thumbnail. Picking one analyzes its
+// page just as if its URL had been typed in.
+
+/// How many matches a search lists.
+pub const RESULTS: usize = 10;
+const MAX_QUERY_LEN: usize = 200;
+// Which yt-dlp build searches, by the site's pin
+const SITE: &str = "https://www.youtube.com/";
+
+// --- Data Structures ---
+
+/// One match of a search.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    /// Its page, which picking it analyzes
+    pub url: String,
+    pub title: String,
+    pub channel: Option<String>,
+    /// "12:34", or "Unknown" for a live stream
+    pub length: String,
+    pub thumbnail: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+// A query is passed to yt-dlp after "ytsearch10:", so only its length and
+// characters matter
+fn validate_query(query: &str) -> Result<(), AppError> {
+    validate::max_len("q", query, MAX_QUERY_LEN)?;
+    if query.chars().any(|c| c.is_control()) {
+        return Err(AppError::Invalid { field: "q", reason: "can't contain control characters".to_string() });
+    }
+    Ok(())
+}
+
+// --- Searching ---
+
+/// The first RESULTS matches for `query`.
+pub async fn search(state: &AppState, gone: &ClientGone, query: &str) -> Result<Vec<Hit>, AppError> {
+    let mut cmd = Command::new(pinning::ytdlp(state, SITE));
+    cmd.arg("--dump-json").arg("--flat-playlist");
+    // Nothing after this is read as an option
+    cmd.arg("--").arg(format!("ytsearch{}:{}", RESULTS, query));
+
+    let output = tokio::select! {
+        out = state.procs.output("search", cmd) => out?,
+        _ = gone.cancelled() => {
+            tracing::info!("Search for {:?} abandoned by client, yt-dlp killed", query);
+            return Ok(Vec::new());
+        }
+    };
+    if !output.status.success() {
+        let err = sanitize::text(&String::from_utf8_lossy(&output.stderr));
+        let reason = err.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no details").trim().to_string();
+        return Err(AppError::YtDlpFailed(format!("searching for {:?} exited with {}: {}", query, output.status, reason)));
+    }
+    let hits = crate::parse_entries(&output.stdout)?
+        .into_iter()
+        .filter_map(|meta| {
+            let url = meta.entry_url()?;
+            let title = match sanitize::line(&meta.title) {
+                t if t.is_empty() => url.clone(),
+                t => t,
+            };
+            // yt-dlp lists the thumbnails smallest first
+            let thumbnail = meta.thumbnails.iter().rev().map(|t| t.url.clone()).find(|u| u.starts_with("https://"));
+            Some(Hit { title, channel: meta.channel_name(), length: length_label(meta.duration), thumbnail, url })
+        })
+        .collect();
+    Ok(hits)
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchTemplate {
+    query: String,
+    hits: Vec<Hit>,
+    error: Option<String>,
+    lite: Lite,
+}
+
+/// The search form, and the matches when there's a query.
+pub async fn show_search(
+    State(state): State<AppState>,
+    Extension(gone): Extension<ClientGone>,
+    headers: HeaderMap,
+    Query(query): Query<SearchQuery>,
+) -> Result<Response, AppError> {
+    let lite = lite::lite_for(&headers);
+    let q = query.q.trim().to_string();
+    if q.is_empty() {
+        return Ok(render(SearchTemplate { query: q, hits: Vec::new(), error: None, lite }));
+    }
+    validate_query(&q)?;
+    match search(&state, &gone, &q).await {
+        Ok(hits) => Ok(render(SearchTemplate { query: q, hits, error: None, lite })),
+        Err(e) => {
+            let page = SearchTemplate { query: q, hits: Vec::new(), error: Some(e.to_string()), lite };
+            Ok(render_status(StatusCode::BAD_GATEWAY, page))
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use tokio::process::Command;
+
+use crate::auth;
+use crate::clock::Stamp;
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::{render, AppError};
+use crate::page::{self, FlashKind};
+use crate::sanitize::filters;
+use crate::session::SessionId;
+use crate::validate::{Form, Validate};
+use crate::AppState;
+
+/// Secret holding a Netscape-format cookies.txt handed to yt-dlp with `--cookies`.
+pub const COOKIES: &str = "cookies";
+/// Further accounts are stored as `cookies-<account>`; downloads rotate between all of them.
+pub const ACCOUNT_PREFIX: &str = "cookies-";
+
+/// Cookie exports run large, so the save form gets more room than other forms.
+pub const MAX_SECRET_BYTES: usize = 2 * 1024 * 1024;
+
+// Names the page offers a hint for; anything else is free-form
+const KNOWN: [(&str, &str); 1] = [(COOKIES, "Netscape cookies.txt passed to yt-dlp for sites that need a login")];
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone)]
+pub struct SecretInfo {
+    pub name: String,
+    pub updated: Stamp,
+}
+
+/// Encrypted name/value store kept in the database. Values are sealed with
+/// XChaCha20-Poly1305 under a key that never goes into the database or backups.
+#[derive(Clone)]
+pub struct Secrets {
+    db: Db,
+    cipher: Arc<XChaCha20Poly1305>,
+}
+
+/// A secret written out to a private temp file for a child process. Removed on drop.
+pub struct SecretFile {
+    path: PathBuf,
+}
+
+impl SecretFile {
+    pub fn path(&self) -> &FsPath {
+        &self.path
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// --- Key ---
+
+// BSDL_SECRET_KEY (64 hex chars) wins; otherwise the key file is used, and
+// created on first run.
+fn load_key(key_file: &FsPath) -> io::Result<Key> {
+    let invalid = |what: String| io::Error::new(io::ErrorKind::InvalidData, what);
+
+    let (raw, source) = match env::var("BSDL_SECRET_KEY") {
+        Ok(raw) => (raw, "BSDL_SECRET_KEY".to_string()),
+        Err(_) if key_file.exists() => (fs::read_to_string(key_file)?, key_file.display().to_string()),
+        Err(_) => {
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            write_private(key_file, hex::encode(key).as_bytes())?;
+            tracing::info!("Generated a new secret key at {}; keep a copy, backups don't include it", key_file.display());
+            return Ok(key);
+        }
+    };
+
+    let bytes = hex::decode(raw.trim()).map_err(|e| invalid(format!("{} is not hex: {}", source, e)))?;
+    if bytes.len() != 32 {
+        return Err(invalid(format!("{} must be 32 bytes (64 hex characters)", source)));
+    }
+    Ok(*Key::from_slice(&bytes))
+}
+
+// Creates a file only the current user can read
+fn write_private(path: &FsPath, contents: &[u8]) -> io::Result<()> {
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    io::Write::write_all(&mut opts.open(path)?, contents)
+}
+
+// --- Store ---
+
+impl Secrets {
+    pub fn open(db: Db, key_file: &FsPath) -> io::Result<Secrets> {
+        let key = load_key(key_file)?;
+        Ok(Secrets { db, cipher: Arc::new(XChaCha20Poly1305::new(&key)) })
+    }
+
+    pub async fn set(&self, name: &str, value: &str) -> Result<(), AppError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        // The name is bound in as associated data so values can't be swapped between rows
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: value.as_bytes(), aad: name.as_bytes() })
+            .map_err(|_| AppError::Secret(format!("could not encrypt {}", name)))?;
+
+        let name = name.to_string();
+        let nonce = nonce.to_vec();
+        self.db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO secrets (name, nonce, ciphertext, updated_at) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT(name) DO UPDATE SET nonce = ?2, ciphertext = ?3, updated_at = ?4",
+                    params![name, nonce, sealed, db::now()],
+                )
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, name: &str) -> Result<Option<String>, AppError> {
+        let key = name.to_string();
+        let row = self
+            .db
+            .call(move |conn| {
+                conn.query_row("SELECT nonce, ciphertext FROM secrets WHERE name = ?1", params![key], |r| {
+                    Ok((r.get::<Vec<u8>>(0)?, r.get::<Vec<u8>>(1)?))
+                })
+                .optional()
+            })
+            .await?;
+        let Some((nonce, sealed)) = row else {
+            return Ok(None);
+        };
+        if nonce.len() != 24 {
+            return Err(AppError::Secret(format!("{} is corrupt", name)));
+        }
+
+        // A wrong key shows up here rather than at startup
+        let plain = self
+            .cipher
+            .decrypt(XNonce::from_slice(&nonce), Payload { msg: &sealed, aad: name.as_bytes() })
+            .map_err(|_| AppError::Secret(format!("{} could not be decrypted; was the secret key changed?", name)))?;
+        String::from_utf8(plain)
+            .map(Some)
+            .map_err(|_| AppError::Secret(format!("{} is not valid UTF-8", name)))
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<bool, AppError> {
+        let name = name.to_string();
+        let removed = self.db.call(move |conn| conn.execute("DELETE FROM secrets WHERE name = ?1", params![name])).await?;
+        Ok(removed > 0)
+    }
+
+    /// Names and timestamps only; values never leave the store this way.
+    pub async fn list(&self) -> Result<Vec<SecretInfo>, AppError> {
+        self.db
+            .call(|conn| {
+                conn.query_map("SELECT name, updated_at FROM secrets ORDER BY name", params![], |r| {
+                    Ok(SecretInfo { name: r.get(0)?, updated: Stamp(r.get(1)?) })
+                })
+            })
+            .await
+    }
+
+    /// Writes `name` to a private temp file, if it is set.
+    pub async fn to_file(&self, name: &str) -> Result<Option<SecretFile>, AppError> {
+        let Some(value) = self.get(name).await? else {
+            return Ok(None);
+        };
+        let tag: String = rand::thread_rng().sample_iter(&Alphanumeric).take(12).map(char::from).collect();
+        let path = env::temp_dir().join(format!("bplus-{}-{}", name, tag));
+        write_private(&path, value.as_bytes())?;
+        Ok(Some(SecretFile { path }))
+    }
+
+    /// Names of the stored cookies files, `cookies` first.
+    pub async fn cookie_accounts(&self) -> Result<Vec<String>, AppError> {
+        let mut names: Vec<String> = self
+            .list()
+            .await?
+            .into_iter()
+            .map(|s| s.name)
+            .filter(|n| n == COOKIES || n.starts_with(ACCOUNT_PREFIX))
+            .collect();
+        names.sort_by_key(|n| n != COOKIES);
+        Ok(names)
+    }
+
+    /// Adds `--cookies` to a yt-dlp command when a cookies file is stored, the
+    /// main one if there is one. Keep the returned guard alive until the command
+    /// has finished.
+    pub async fn attach_cookies(&self, cmd: &mut Command) -> Result<Option<SecretFile>, AppError> {
+        match self.cookie_accounts().await?.first() {
+            Some(account) => self.attach_account(cmd, account).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Like `attach_cookies`, with one particular account's cookies.
+    pub async fn attach_account(&self, cmd: &mut Command, account: &str) -> Result<Option<SecretFile>, AppError> {
+        let file = self.to_file(account).await?;
+        if let Some(f) = &file {
+            cmd.arg("--cookies").arg(f.path());
+        }
+        Ok(file)
+    }
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "secrets.html")]
+struct SecretsTemplate {
+    secrets: Vec<SecretInfo>,
+    known: Vec<(&'static str, &'static str)>,
+    cookie_per_hour: usize,
+}
+
+#[derive(Deserialize)]
+pub struct SecretForm {
+    name: String,
+    value: String,
+}
+
+impl Validate for SecretForm {
+    fn validate(&self) -> Result<(), AppError> {
+        if !valid_name(self.name.trim()) {
+            return Err(AppError::Invalid {
+                field: "name",
+                reason: "secret names are up to 64 lowercase letters, digits, '-' or '_'".to_string(),
+            });
+        }
+        if self.name.trim().starts_with(auth::TOKEN_PREFIX) {
+            return Err(AppError::Invalid { field: "name", reason: "API tokens are made on /system/tokens".to_string() });
+        }
+        if self.value.is_empty() {
+            return Err(AppError::Invalid { field: "value", reason: "no value given".to_string() });
+        }
+        Ok(())
+    }
+}
+
+pub async fn show_secrets(State(state): State<AppState>) -> Result<Response, AppError> {
+    Ok(render(SecretsTemplate {
+        // API tokens are managed on their own page
+        secrets: state.secrets.list().await?.into_iter().filter(|s| !s.name.starts_with(auth::TOKEN_PREFIX)).collect(),
+        known: KNOWN.to_vec(),
+        cookie_per_hour: state.config.cookie_per_hour,
+    }))
+}
+
+pub async fn save_secret(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<SecretForm>,
+) -> Result<Response, AppError> {
+    let name = form.name.trim();
+    state.secrets.set(name, &form.value).await?;
+    page::flash(&state, &sid, FlashKind::Info, format!("Saved {}.", name));
+    Ok(Redirect::to("/system/secrets").into_response())
+}
+
+pub async fn delete_secret(State(state): State<AppState>, Path(name): Path<String>) -> Result<Response, AppError> {
+    if name.starts_with(auth::TOKEN_PREFIX) || !state.secrets.delete(&name).await? {
+        return Err(AppError::NotFound(format!("Secret \"{}\"", name)));
+    }
+    Ok(Redirect::to("/system/secrets").into_response())
+}
@@ -0,0 +1,168 @@
+use axum::{body::Body, http::HeaderMap, Router};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{crypto::ring, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+
+use crate::config::TlsFiles;
+
+// A client that hasn't finished the TLS handshake by then is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// --- Connection tracking ---
+
+/// Cancelled once the client's side of the connection is gone.
+///
+/// hyper keeps polling a handler after its client disconnects, so slow handlers
+/// select on this to abandon work nobody is waiting for.
+#[derive(Clone)]
+pub struct ClientGone(CancellationToken);
+
+impl ClientGone {
+    pub async fn cancelled(&self) {
+        self.0.cancelled().await
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+/// The address a request came from.
+#[derive(Clone, Copy)]
+pub struct ClientAddr(SocketAddr);
+
+impl ClientAddr {
+    /// The client's IP. A proxy on this machine passes everyone's requests on,
+    /// so for those the address it says it forwarded for counts instead.
+    pub fn ip(&self, headers: &HeaderMap) -> IpAddr {
+        let peer = self.0.ip();
+        if !peer.is_loopback() {
+            return peer;
+        }
+        headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+            .unwrap_or(peer)
+    }
+}
+
+// Socket wrapper that trips the token on EOF or I/O errors
+struct WatchedStream {
+    inner: TcpStream,
+    gone: CancellationToken,
+}
+
+impl AsyncRead for WatchedStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        match &res {
+            Poll::Ready(Ok(())) if buf.filled().len() == before && buf.remaining() > 0 => self.gone.cancel(),
+            Poll::Ready(Err(_)) => self.gone.cancel(),
+            _ => {}
+        }
+        res
+    }
+}
+
+impl AsyncWrite for WatchedStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, data);
+        if let Poll::Ready(Err(_)) = &res {
+            self.gone.cancel();
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// --- HTTPS ---
+
+/// Reads the certificate chain and key HTTPS is served with. Both are read
+/// once, so a renewed certificate takes a restart.
+pub fn tls_acceptor(files: &TlsFiles) -> Result<TlsAcceptor, String> {
+    let certs = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Could not read the certificate {}: {}", files.cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{} holds no certificate", files.cert.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(&files.key)
+        .map_err(|e| format!("Could not read the private key {}: {}", files.key.display(), e))?;
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("{} and {} don't make a usable pair: {}", files.cert.display(), files.key.display(), e))?;
+    // Browsers still open a WebSocket over HTTP/1.1 when they speak h2 to the rest
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// --- Serve loop ---
+
+/// Same job as `axum::serve`, but every request gets `ClientGone` and `ClientAddr`
+/// extensions, and with `tls` each connection speaks HTTPS.
+pub async fn serve(listener: TcpListener, app: Router, tls: Option<TlsAcceptor>) -> io::Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Usually EMFILE or a reset during the handshake; keep accepting
+                tracing::warn!("accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let gone = CancellationToken::new();
+        // Under TLS, so a client going away still trips the token
+        let watched = WatchedStream { inner: stream, gone: gone.clone() };
+        let app = app.clone();
+        let tls = tls.clone();
+
+        let service = hyper::service::service_fn(move |mut req: hyper::Request<Incoming>| {
+            req.extensions_mut().insert(ClientGone(gone.clone()));
+            req.extensions_mut().insert(ClientAddr(peer));
+            app.clone().oneshot(req.map(Body::new))
+        });
+
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let Some(acceptor) = tls else {
+                let _ = builder.serve_connection_with_upgrades(TokioIo::new(watched), service).await;
+                return;
+            };
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(watched)).await {
+                Ok(Ok(stream)) => {
+                    let _ = builder.serve_connection_with_upgrades(TokioIo::new(stream), service).await;
+                }
+                // Plain HTTP sent to the HTTPS port ends up here too
+                Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", peer, e),
+                Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+            }
+        });
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// launchd label, systemd unit and scheduled task name
+const NAME: &str = "bplus-streamdlrs-gui";
+#[cfg(target_os = "macos")]
+const LAUNCHD_LABEL: &str = "io.github.mrhappynice.bplus-streamdlrs-gui";
+
+// Stays out of service definitions, which aren't private the way the key file is
+const SECRET_VARS: &[&str] = &["BSDL_SECRET_KEY"];
+
+// --- Service registration ---
+
+// These run from the command line before the server starts, so unlike
+// everything else their commands don't go through the process registry.
+
+/// Registers the app to start with the user session: a systemd user unit on
+/// Linux, a launchd agent on macOS, a scheduled task that runs at logon on
+/// Windows. The current folder becomes its home and the BSDL_* settings in
+/// effect are carried over where the platform allows it.
+pub fn install() -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let home = std::env::current_dir()?;
+    let settings = settings();
+    register(&exe, &home, &settings)
+}
+
+pub fn uninstall() -> io::Result<()> {
+    unregister()
+}
+
+// BSDL_* variables worth keeping, minus the ones that shouldn't sit in a plain file
+fn settings() -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = std::env::vars()
+        .filter(|(k, _)| k.starts_with("BSDL_") && k != "BSDL_HOME")
+        .filter(|(k, _)| {
+            let secret = SECRET_VARS.contains(&k.as_str());
+            if secret {
+                println!("Not copying {} into the service; save it in the key file (BSDL_SECRET_KEY_FILE) for the service to read", k);
+            }
+            !secret
+        })
+        .collect();
+    vars.sort();
+    vars
+}
+
+fn run(cmd: &mut Command) -> io::Result<()> {
+    let status = cmd.status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{:?} exited with {}", cmd, status)));
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn user_home() -> io::Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::other("no home directory to put the service definition in"))
+}
+
+// --- Linux (systemd) ---
+
+#[cfg(target_os = "linux")]
+fn unit_path() -> io::Result<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => user_home()?.join(".config"),
+    };
+    Ok(config.join("systemd/user").join(format!("{}.service", NAME)))
+}
+
+// Double-quoted systemd word; % starts a specifier, so it's doubled too.
+// WorkingDirectory= takes a bare path and only gets the doubling.
+#[cfg(target_os = "linux")]
+fn systemd_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%"))
+}
+
+#[cfg(target_os = "linux")]
+fn register(exe: &Path, home: &Path, settings: &[(String, String)]) -> io::Result<()> {
+    let (exe, home) = (exe.to_string_lossy(), home.to_string_lossy());
+    let mut unit = format!(
+        "[Unit]\nDescription=bplus streamdlrs-gui\nAfter=network-online.target\n\n[Service]\n\
+         ExecStart={} --home {}\nWorkingDirectory={}\nRestart=always\nRestartSec=5\n",
+        systemd_quote(&exe),
+        systemd_quote(&home),
+        home.replace('%', "%%")
+    );
+    for (key, value) in settings {
+        unit.push_str(&format!("Environment={}\n", systemd_quote(&format!("{}={}", key, value))));
+    }
+    unit.push_str("\n[Install]\nWantedBy=default.target\n");
+
+    let path = unit_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, unit)?;
+    println!("Wrote {}", path.display());
+    run(Command::new("systemctl").args(["--user", "daemon-reload"]))?;
+    run(Command::new("systemctl").args(["--user", "enable", "--now", NAME]))?;
+    println!("Started {}. To keep it running while you're logged out: loginctl enable-linger", NAME);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn unregister() -> io::Result<()> {
+    let path = unit_path()?;
+    if let Err(e) = run(Command::new("systemctl").args(["--user", "disable", "--now", NAME])) {
+        eprintln!("{}", e);
+    }
+    fs::remove_file(&path)?;
+    run(Command::new("systemctl").args(["--user", "daemon-reload"]))?;
+    println!("Removed {}", path.display());
+    Ok(())
+}
+
+// --- macOS (launchd) ---
+
+#[cfg(target_os = "macos")]
+fn plist_path() -> io::Result<PathBuf> {
+    Ok(user_home()?.join("Library/LaunchAgents").join(format!("{}.plist", LAUNCHD_LABEL)))
+}
+
+#[cfg(target_os = "macos")]
+fn xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(target_os = "macos")]
+fn register(exe: &Path, home: &Path, settings: &[(String, String)]) -> io::Result<()> {
+    let (exe, home) = (xml(&exe.to_string_lossy()), xml(&home.to_string_lossy()));
+    let mut env = String::new();
+    for (key, value) in settings {
+        env.push_str(&format!("        <key>{}</key><string>{}</string>\n", xml(key), xml(value)));
+    }
+    let log = format!("{}/data/{}.log", home, NAME);
+    let plist = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n\
+         \x20   <key>Label</key><string>{}</string>\n\
+         \x20   <key>ProgramArguments</key>\n    <array><string>{}</string><string>--home</string><string>{}</string></array>\n\
+         \x20   <key>WorkingDirectory</key><string>{}</string>\n\
+         \x20   <key>EnvironmentVariables</key>\n    <dict>\n{}    </dict>\n\
+         \x20   <key>RunAtLoad</key><true/>\n\
+         \x20   <key>KeepAlive</key><true/>\n\
+         \x20   <key>StandardOutPath</key><string>{}</string>\n\
+         \x20   <key>StandardErrorPath</key><string>{}</string>\n\
+         </dict>\n</plist>\n",
+        LAUNCHD_LABEL, exe, home, home, env, log, log
+    );
+
+    let path = plist_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, plist)?;
+    println!("Wrote {}", path.display());
+    run(Command::new("launchctl").arg("load").arg("-w").arg(&path))?;
+    println!("Started {}; output goes to {}", LAUNCHD_LABEL, log);
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn unregister() -> io::Result<()> {
+    let path = plist_path()?;
+    if let Err(e) = run(Command::new("launchctl").arg("unload").arg("-w").arg(&path)) {
+        eprintln!("{}", e);
+    }
+    fs::remove_file(&path)?;
+    println!("Removed {}", path.display());
+    Ok(())
+}
+
+// --- Windows (Task Scheduler) ---
+
+// A real Windows service has to answer the service control manager, which
+// needs the service API; a task that starts at logon needs nothing of the
+// sort and runs just the same.
+#[cfg(windows)]
+fn register(exe: &Path, home: &Path, settings: &[(String, String)]) -> io::Result<()> {
+    let action = format!("\"{}\" --home \"{}\"", exe.display(), home.display());
+    run(Command::new("schtasks").args(["/Create", "/F", "/TN", NAME, "/SC", "ONLOGON", "/RL", "LIMITED", "/TR", &action]))?;
+    run(Command::new("schtasks").args(["/Run", "/TN", NAME]))?;
+    println!("Registered and started the {} task", NAME);
+    if !settings.is_empty() {
+        println!("Scheduled tasks don't carry environment variables; set these for your user account instead (setx):");
+        for (key, _) in settings {
+            println!("  {}", key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn unregister() -> io::Result<()> {
+    if let Err(e) = run(Command::new("schtasks").args(["/End", "/TN", NAME])) {
+        eprintln!("{}", e);
+    }
+    run(Command::new("schtasks").args(["/Delete", "/F", "/TN", NAME]))?;
+    println!("Removed the {} task", NAME);
+    Ok(())
+}
+
+// --- Elsewhere ---
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn register(_exe: &Path, _home: &Path, _settings: &[(String, String)]) -> io::Result<()> {
+    Err(io::Error::other("--install-service only knows systemd, launchd and the Windows Task Scheduler"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn unregister() -> io::Result<()> {
+    Err(io::Error::other("--uninstall-service only knows systemd, launchd and the Windows Task Scheduler"))
+}
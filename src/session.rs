@@ -0,0 +1,264 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::analysis::{Related, Unlocked};
+use crate::auth::SignedIn;
+use crate::entries::Post;
+use crate::gate::Workaround;
+use crate::geo::Check;
+use crate::page::Flash;
+use crate::profiles::DeviceProfile;
+use crate::sizes::Probe;
+use crate::{AppState, DisplayFormat, YtDlpFormat};
+
+pub const SESSION_COOKIE: &str = "bsdl_session";
+
+// yt-dlp's own sort order with the protocol moved up ahead of size and bitrate
+const PREFER_DIRECT: &str = "lang,quality,res,fps,hdr:12,vcodec,channels,acodec,proto";
+
+// Idle sessions are dropped after this long
+const SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+// --- Data Structures ---
+
+/// Everything the wizard has collected so far. Each step fills in more of it.
+#[derive(Debug, Clone)]
+pub struct Wizard {
+    pub url: String,
+    pub title: String,
+    /// Channel (or uploader) yt-dlp named, for the "already have" hint
+    pub channel: Option<String>,
+    /// Playlist and channel the video came from
+    pub related: Vec<Related>,
+    /// Formats only the cookies got
+    pub unlocked: Option<Unlocked>,
+    /// How the analysis got past an age gate or login, reused for the download
+    pub workaround: Option<Workaround>,
+    /// Which video of a post with several
+    pub item: Option<usize>,
+    pub formats: Vec<DisplayFormat>,
+    /// Every format as yt-dlp listed it, for the export
+    pub source_formats: Vec<YtDlpFormat>,
+    pub languages: Vec<String>,
+    pub selection: Option<Selection>,
+}
+
+/// Either an exact row from the format table or a device profile's selector.
+/// Subscriptions, which have no format table to pick from, can also ask for
+/// the best video or the best audio outright.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Choice {
+    Format(Box<DisplayFormat>),
+    Profile(DeviceProfile),
+    BestVideo,
+    BestAudio,
+    /// A photo post's picture; `thumbnail` when the site only lists it as one
+    Image { thumbnail: bool },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Selection {
+    pub choice: Choice,
+    pub container: String,
+    pub audio_format: String,
+    /// Also make an H.264 1080p copy next to the original
+    pub compat: bool,
+    pub workaround: Option<Workaround>,
+    pub item: Option<usize>,
+    /// YYYYMMDD; older uploads are skipped rather than downloaded
+    pub date_after: Option<String>,
+    /// Every video of the playlist or channel the URL is, in one yt-dlp run
+    #[serde(default)]
+    pub playlist: bool,
+}
+
+impl Selection {
+    /// Photos are saved as the site sends them, nothing to merge or convert
+    pub fn photo(choice: Choice) -> Selection {
+        Selection { choice, container: String::new(), audio_format: String::new(), compat: false, workaround: None, item: None, date_after: None, playlist: false }
+    }
+
+    pub fn is_photo(&self) -> bool {
+        matches!(self.choice, Choice::Image { .. })
+    }
+
+    /// What goes to yt-dlp's `-f`.
+    pub fn selector(&self) -> &str {
+        match &self.choice {
+            Choice::Format(f) => &f.id,
+            Choice::Profile(p) => &p.selector,
+            Choice::BestVideo => "bv*+ba/b",
+            Choice::BestAudio => "ba/b",
+            Choice::Image { .. } => "b",
+        }
+    }
+
+    /// yt-dlp's `-S` for the choices it resolves itself: of two streams that
+    /// look the same, the direct download wins over HLS or DASH fragments,
+    /// even when the fragments report a little more bitrate.
+    pub fn sort_args(&self) -> Vec<&'static str> {
+        match self.choice {
+            Choice::Profile(_) | Choice::BestVideo | Choice::BestAudio => vec!["-S", PREFER_DIRECT],
+            Choice::Format(_) | Choice::Image { .. } => Vec::new(),
+        }
+    }
+
+    pub fn audio_only(&self) -> bool {
+        match &self.choice {
+            Choice::Format(f) => f.type_label == "Audio Only",
+            Choice::BestAudio => true,
+            Choice::Profile(_) | Choice::BestVideo | Choice::Image { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub wizard: Option<Wizard>,
+    /// A post with several videos, until one of them is picked
+    pub post: Option<Post>,
+    /// Messages waiting for the next rendered page
+    pub flash: Vec<Flash>,
+    /// Format sizes looked up for the wizard's analysis
+    pub sizes: Option<Probe>,
+    /// Which of its formats the site turned away
+    pub geo: Option<Check>,
+    /// Who this browser signed in as at /login
+    pub signed_in: Option<SignedIn>,
+    /// What this browser's forms carry back, see csrf.rs
+    pub csrf: String,
+    last_seen: Instant,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            wizard: None,
+            post: None,
+            flash: Vec::new(),
+            sizes: None,
+            geo: None,
+            signed_in: None,
+            csrf: new_id(),
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+/// Session id of the current request, inserted by `session_layer`.
+#[derive(Debug, Clone)]
+pub struct SessionId(pub String);
+
+#[derive(Clone, Default)]
+pub struct SessionStore {
+    inner: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl SessionStore {
+    pub fn get(&self, id: &SessionId) -> Session {
+        let mut map = self.inner.lock().unwrap();
+        let session = map.entry(id.0.clone()).or_default();
+        session.last_seen = Instant::now();
+        session.clone()
+    }
+
+    pub fn update<F: FnOnce(&mut Session)>(&self, id: &SessionId, f: F) {
+        let mut map = self.inner.lock().unwrap();
+        let session = map.entry(id.0.clone()).or_default();
+        session.last_seen = Instant::now();
+        f(session);
+    }
+
+    /// Moves the session to a new id, for signing in. Use the returned one from now on.
+    pub fn renew(&self, id: &SessionId) -> SessionId {
+        let mut map = self.inner.lock().unwrap();
+        let session = map.remove(&id.0).unwrap_or_default();
+        let renewed = new_id();
+        map.insert(renewed.clone(), session);
+        SessionId(renewed)
+    }
+
+    /// A new session already signed in as `who`, for one request that brought
+    /// its own credentials, such as an API token.
+    pub fn issue(&self, who: SignedIn) -> SessionId {
+        let id = new_id();
+        self.inner.lock().unwrap().insert(id.clone(), Session { signed_in: Some(who), ..Session::default() });
+        SessionId(id)
+    }
+
+    /// The session's CSRF token, without copying the rest of it.
+    pub fn csrf(&self, id: &SessionId) -> String {
+        let mut map = self.inner.lock().unwrap();
+        map.entry(id.0.clone()).or_default().csrf.clone()
+    }
+
+    pub fn take_flash(&self, id: &SessionId) -> Vec<Flash> {
+        let mut map = self.inner.lock().unwrap();
+        map.get_mut(&id.0).map(|s| std::mem::take(&mut s.flash)).unwrap_or_default()
+    }
+
+    fn prune(&self) {
+        let mut map = self.inner.lock().unwrap();
+        map.retain(|_, s| s.last_seen.elapsed() < SESSION_TTL);
+    }
+}
+
+// --- Middleware ---
+
+/// Value of the named cookie, if the browser sent one.
+pub fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string())
+}
+
+fn new_id() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect()
+}
+
+/// The Set-Cookie value that hands the browser session `id`; served over
+/// HTTPS, it's never sent back over plain HTTP.
+pub fn set_cookie(state: &AppState, id: &SessionId) -> String {
+    let secure = if state.config.tls.is_some() { "; Secure" } else { "" };
+    format!("{}={}; Path=/; HttpOnly; SameSite=Lax{}", SESSION_COOKIE, id.0, secure)
+}
+
+/// Makes sure every request carries a session id, issuing a cookie when it's missing.
+pub async fn session_layer(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let existing = cookie(req.headers(), SESSION_COOKIE);
+
+    let (id, is_new) = match existing {
+        Some(id) => (id, false),
+        None => {
+            state.sessions.prune();
+            (new_id(), true)
+        }
+    };
+
+    let id = SessionId(id);
+    req.extensions_mut().insert(id.clone());
+    let mut res = next.run(req).await;
+
+    // Unless signing in already handed out a new one
+    let renewed = res.headers().get_all(header::SET_COOKIE).iter().any(|v| v.as_bytes().starts_with(SESSION_COOKIE.as_bytes()));
+    if is_new && !renewed {
+        if let Ok(value) = set_cookie(&state, &id).parse() {
+            res.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    res
+}
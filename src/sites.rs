@@ -0,0 +1,276 @@
+use axum::{extract::State, response::Response};
+use askama::Template;
+use std::collections::HashMap;
+
+use crate::clock::Stamp;
+use crate::db::{self, params, Db};
+use crate::error::{render, AppError};
+use crate::gate::Gate;
+use crate::history;
+use crate::AppState;
+
+// How each site's analyses and downloads have been going, from the history.
+// A site whose failures jump in the last day gets told what most likely
+// fixes it, going by what yt-dlp said: cookies for a login wall, a newer
+// yt-dlp when the site changed under it, slowing down when it answers 429.
+
+const DAY: i64 = 24 * 60 * 60;
+/// Days the board goes back.
+pub const DAYS: i64 = 30;
+
+// A spike is at least this many failures in the last day, half or more of
+// the attempts, and well above the site's usual rate
+const SPIKE_FAILURES: usize = 3;
+const SPIKE_RATE: f64 = 0.5;
+const SPIKE_OVER_USUAL: f64 = 0.25;
+// Fewer attempts than this before the last day don't make a usual rate
+const USUAL_ATTEMPTS: usize = 5;
+
+// Lower-cased pieces of error messages, checked after Gate::detect
+const RATE_SIGNS: &[&str] = &["429", "too many requests", "rate limit", "rate-limit"];
+const UNAVAILABLE_SIGNS: &[&str] = &[
+    "video unavailable",
+    "this video is not available",
+    "private video",
+    "has been removed",
+    "no longer available",
+    "not available in your country",
+    "found no video",
+];
+const EXTRACTOR_SIGNS: &[&str] = &[
+    "unable to extract",
+    "please report this issue",
+    "unsupported url",
+    "signature extraction failed",
+    "nsig extraction failed",
+    "requested format is not available",
+    "no video formats found",
+    "http error 403",
+    "failed to parse json",
+];
+
+// --- Data Structures ---
+
+/// What a failure most likely came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cause {
+    Login,
+    RateLimited,
+    Unavailable,
+    Extractor,
+    Other,
+}
+
+impl Cause {
+    fn of(error: &str) -> Cause {
+        let lower = error.to_lowercase();
+        if Gate::detect(error).is_some() || lower.contains("needs a login") {
+            Cause::Login
+        } else if RATE_SIGNS.iter().any(|s| lower.contains(s)) {
+            Cause::RateLimited
+        } else if UNAVAILABLE_SIGNS.iter().any(|s| lower.contains(s)) {
+            Cause::Unavailable
+        } else if EXTRACTOR_SIGNS.iter().any(|s| lower.contains(s)) {
+            Cause::Extractor
+        } else {
+            Cause::Other
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Cause::Login => "login or age check",
+            Cause::RateLimited => "rate limited",
+            Cause::Unavailable => "video unavailable",
+            Cause::Extractor => "yt-dlp couldn't read the site",
+            Cause::Other => "other errors",
+        }
+    }
+
+    /// What to do about it: a button's text and where it goes, and why.
+    pub fn fix(&self) -> Option<(&'static str, &'static str, &'static str)> {
+        match self {
+            Cause::Login => Some((
+                "Add cookies",
+                "/system/secrets",
+                "The site wants a login or an age check. Store cookies from a browser signed in to it and downloads get past it.",
+            )),
+            Cause::RateLimited => Some((
+                "Slow down",
+                "/batch",
+                "The site is turning requests away. Queue through polite mode, or give it an hour.",
+            )),
+            Cause::Extractor => Some((
+                "Update yt-dlp",
+                "/system/ytdlp",
+                "yt-dlp can't read the site's pages any more, which mostly means the site changed. Get a newer yt-dlp (get-dlp.sh), or pin the site to the nightly build.",
+            )),
+            Cause::Unavailable => None,
+            Cause::Other => Some((
+                "Update yt-dlp",
+                "/system/ytdlp",
+                "The errors don't say why. A newer yt-dlp is what fixes most sudden failures on one site.",
+            )),
+        }
+    }
+}
+
+/// Attempts and failures in a stretch of time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rate {
+    pub attempts: usize,
+    pub failed: usize,
+}
+
+impl Rate {
+    fn add(&mut self, failed: bool) {
+        self.attempts += 1;
+        self.failed += failed as usize;
+    }
+
+    fn ratio(&self) -> f64 {
+        if self.attempts == 0 { 0.0 } else { self.failed as f64 / self.attempts as f64 }
+    }
+
+    pub fn label(&self) -> String {
+        match self.attempts {
+            0 => "-".to_string(),
+            n => format!("{} of {} failed ({:.0}%)", self.failed, n, self.ratio() * 100.0),
+        }
+    }
+
+    pub fn css(&self) -> &'static str {
+        match self.ratio() {
+            _ if self.attempts == 0 => "",
+            r if r >= SPIKE_RATE => "check-fail",
+            r if r > 0.0 => "check-warn",
+            _ => "check-pass",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SiteHealth {
+    pub site: String,
+    /// yt-dlp's name for the site's extractor, from its last error
+    pub extractor: Option<String>,
+    pub day: Rate,
+    pub week: Rate,
+    pub month: Rate,
+    // Before the last day, for the usual rate
+    before: Rate,
+    // Failures by cause, over the month and the last day
+    month_causes: HashMap<Cause, usize>,
+    day_causes: HashMap<Cause, usize>,
+    pub last_success: Option<Stamp>,
+    pub last_failure: Option<Stamp>,
+    pub last_error: Option<String>,
+    /// Failures over the month by cause, commonest first
+    pub causes: Vec<(Cause, usize)>,
+    /// Set when the last day's failures spiked, with their commonest cause
+    pub spike: Option<Cause>,
+}
+
+impl SiteHealth {
+    pub fn causes_label(&self) -> String {
+        self.causes.iter().map(|(c, n)| format!("{} {}", n, c.label())).collect::<Vec<_>>().join(", ")
+    }
+}
+
+// "youtube" from "ERROR: [youtube] abc: ..."
+fn extractor(error: &str) -> Option<String> {
+    let rest = &error[error.find("ERROR: [")? + 8..];
+    let name = &rest[..rest.find(']')?];
+    (!name.is_empty() && name.len() <= 40 && name.chars().all(|c| c.is_ascii_alphanumeric() || ":_-".contains(c)))
+        .then(|| name.to_string())
+}
+
+fn ranked(counts: HashMap<Cause, usize>) -> Vec<(Cause, usize)> {
+    let mut causes: Vec<(Cause, usize)> = counts.into_iter().collect();
+    causes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.label().cmp(b.0.label())));
+    causes
+}
+
+// --- Data ---
+
+/// Every site with an analysis or download that finished in the last DAYS
+/// days, those spiking first, then by failures.
+pub async fn board(db: &Db) -> Result<Vec<SiteHealth>, AppError> {
+    let now = db::now();
+    let since = now - DAYS * DAY;
+    let rows: Vec<(String, String, Option<String>, i64)> = db
+        .call(move |conn| {
+            conn.query_map(
+                "SELECT url, status, error, COALESCE(finished_at, started_at) FROM history
+                 WHERE status IN ('Done', 'Failed') AND COALESCE(finished_at, started_at) >= ?1
+                 ORDER BY COALESCE(finished_at, started_at), id",
+                params![since],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+            )
+        })
+        .await?;
+
+    let mut sites: HashMap<String, SiteHealth> = HashMap::new();
+    for (url, status, error, at) in rows {
+        let site = history::site(&url);
+        let health = sites.entry(site.clone()).or_insert_with(|| SiteHealth { site, ..SiteHealth::default() });
+        let failed = status == "Failed";
+        let age = now - at;
+        health.month.add(failed);
+        if age < 7 * DAY {
+            health.week.add(failed);
+        }
+        if age < DAY {
+            health.day.add(failed);
+        } else {
+            health.before.add(failed);
+        }
+        if !failed {
+            health.last_success = Some(Stamp(at));
+            continue;
+        }
+        let error = error.unwrap_or_default();
+        let cause = Cause::of(&error);
+        *health.month_causes.entry(cause).or_default() += 1;
+        if age < DAY {
+            *health.day_causes.entry(cause).or_default() += 1;
+        }
+        health.extractor = extractor(&error).or(health.extractor.take());
+        health.last_failure = Some(Stamp(at));
+        health.last_error = Some(error).filter(|e| !e.is_empty());
+    }
+
+    let mut board: Vec<SiteHealth> = sites
+        .into_values()
+        .map(|mut health| {
+            let usual = if health.before.attempts >= USUAL_ATTEMPTS { health.before.ratio() } else { 0.0 };
+            let spiking = health.day.failed >= SPIKE_FAILURES
+                && health.day.ratio() >= SPIKE_RATE
+                && health.day.ratio() - usual >= SPIKE_OVER_USUAL;
+            if spiking {
+                health.spike = ranked(std::mem::take(&mut health.day_causes)).first().map(|(cause, _)| *cause);
+            }
+            health.causes = ranked(std::mem::take(&mut health.month_causes));
+            health
+        })
+        .collect();
+    board.sort_by(|a, b| {
+        (b.spike.is_some(), b.month.failed, b.month.attempts)
+            .cmp(&(a.spike.is_some(), a.month.failed, a.month.attempts))
+            .then_with(|| a.site.cmp(&b.site))
+    });
+    Ok(board)
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "site_health.html")]
+struct BoardTemplate {
+    sites: Vec<SiteHealth>,
+    days: i64,
+}
+
+pub async fn show_board(State(state): State<AppState>) -> Result<Response, AppError> {
+    Ok(render(BoardTemplate { sites: board(&state.db).await?, days: DAYS }))
+}
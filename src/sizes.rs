@@ -0,0 +1,206 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use futures_util::StreamExt;
+use std::collections::BTreeMap;
+use tokio::process::Command;
+
+use crate::error::{render, AppError};
+use crate::pinning;
+use crate::secrets::SecretFile;
+use crate::session::{SessionId, Wizard};
+use crate::AppState;
+
+/// What the format table says when yt-dlp didn't report a size.
+pub const UNKNOWN: &str = "Unknown";
+
+/// Sizes looked up for the session's analysis, after yt-dlp left them out.
+#[derive(Debug, Clone, Default)]
+pub struct Probe {
+    /// The analysis they belong to
+    pub url: String,
+    pub running: bool,
+    /// Format id to size, as the table shows it
+    pub found: BTreeMap<String, String>,
+}
+
+pub fn label(bytes: u64, approx: bool) -> String {
+    format!("{}{:.2} MB", if approx { "~" } else { "" }, bytes as f64 / 1024.0 / 1024.0)
+}
+
+/// What yt-dlp resolved a format to.
+pub struct Located {
+    pub id: String,
+    size: Option<u64>,
+    pub url: String,
+    pub user_agent: String,
+    pub referer: String,
+    /// The address yt-dlp pretends to be at, with a country workaround
+    pub forwarded_for: String,
+}
+
+// format_id, size, URL and the headers to fetch it with, tab-separated
+const PRINT: &str = "%(format_id)s\t%(filesize,filesize_approx|)s\t%(url|)s\t%(http_headers.User-Agent|)s\t%(http_headers.Referer|)s\t%(http_headers.X-Forwarded-For|)s";
+
+pub fn parse_located(line: &str) -> Option<Located> {
+    let mut fields = line.split('\t');
+    let id = fields.next()?.trim().to_string();
+    if id.is_empty() {
+        return None;
+    }
+    let size = fields.next()?.trim().parse::<f64>().ok().filter(|s| *s > 0.0).map(|s| s as u64);
+    let url = fields.next().unwrap_or_default().trim().to_string();
+    let user_agent = fields.next().unwrap_or_default().trim().to_string();
+    let referer = fields.next().unwrap_or_default().trim().to_string();
+    let forwarded_for = fields.next().unwrap_or_default().trim().to_string();
+    Some(Located { id, size, url, user_agent, referer, forwarded_for })
+}
+
+/// yt-dlp asked to resolve `ids` of the wizard's analysis to URLs, one
+/// `Located` per line, the way the analysis got them. Keep the cookies file
+/// until it's done.
+pub async fn locate(state: &AppState, w: &Wizard, ids: &[String]) -> (Command, Option<SecretFile>) {
+    let mut cmd = Command::new(pinning::ytdlp(state, &w.url));
+    cmd.arg("--skip-download").args(crate::scope(w.item)).arg("-f").arg(ids.join(",")).arg("--print").arg(PRINT);
+    let cookies = match &w.workaround {
+        Some(wa) if wa.replaces_cookies() => None,
+        _ => state.secrets.attach_cookies(&mut cmd).await.unwrap_or_else(|e| {
+            tracing::warn!("Formats of {} are resolved without cookies: {}", w.url, e);
+            None
+        }),
+    };
+    if let Some(wa) = &w.workaround {
+        cmd.args(wa.ytdlp_args(&state.config));
+    }
+    cmd.arg("--").arg(&w.url);
+    (cmd, cookies)
+}
+
+// --- Probing ---
+
+fn record(state: &AppState, sid: &SessionId, url: &str, id: &str, size: String) {
+    state.sessions.update(sid, |s| {
+        if let Some(probe) = s.sizes.as_mut().filter(|p| p.url == url) {
+            probe.found.insert(id.to_string(), size);
+        }
+    });
+}
+
+// Asks the server itself: the Content-Length of a plain file, otherwise the
+// stream's bitrate over its duration (an estimate)
+async fn ffprobe(state: &AppState, located: &Located) -> Option<(u64, bool)> {
+    let mut cmd = Command::new("ffprobe");
+    cmd.args(["-v", "error", "-show_entries", "format=size,duration,bit_rate", "-of", "default=noprint_wrappers=1"]);
+    if !located.user_agent.is_empty() {
+        cmd.arg("-user_agent").arg(&located.user_agent);
+    }
+    if !located.referer.is_empty() {
+        cmd.arg("-headers").arg(format!("Referer: {}\r\n", located.referer));
+    }
+    cmd.arg("-i").arg(&located.url);
+    let output = match state.procs.output("sizes", cmd).await {
+        Ok(o) if o.status.success() => o,
+        Ok(o) => {
+            tracing::warn!("ffprobe of format {} exited with {}", located.id, o.status);
+            return None;
+        }
+        Err(e) => {
+            tracing::warn!("Could not run ffprobe for format {}: {}", located.id, e);
+            return None;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| {
+        stdout.lines().find_map(|l| l.strip_prefix(name)?.strip_prefix('=')?.trim().parse::<f64>().ok()).filter(|v| *v > 0.0)
+    };
+    match (field("size"), field("duration"), field("bit_rate")) {
+        (Some(size), _, _) => Some((size as u64, false)),
+        (None, Some(duration), Some(bit_rate)) => Some(((duration * bit_rate / 8.0) as u64, true)),
+        _ => None,
+    }
+}
+
+async fn probe(state: AppState, sid: SessionId, w: Wizard, ids: Vec<String>) {
+    let (cmd, _cookies) = locate(&state, &w, &ids).await;
+
+    let mut sizeless = Vec::new();
+    let listed = state
+        .procs
+        .lines("sizes", cmd, |line| {
+            let Some(located) = parse_located(line).filter(|l| ids.contains(&l.id)) else {
+                return;
+            };
+            match located.size {
+                Some(size) => record(&state, &sid, &w.url, &located.id, label(size, false)),
+                None if located.url.starts_with("http") => sizeless.push(located),
+                None => {}
+            }
+        })
+        .await;
+    match listed {
+        Ok((status, _)) if !status.success() => tracing::warn!("Looking up sizes of {}: yt-dlp exited with {}", w.url, status),
+        Err(e) => tracing::warn!("Looking up sizes of {}: {}", w.url, e),
+        Ok(_) => {}
+    }
+
+    let mut probes = futures_util::stream::iter(sizeless)
+        .map(|located| {
+            let state = &state;
+            async move { (ffprobe(state, &located).await, located.id) }
+        })
+        .buffer_unordered(state.config.size_probes.max(1));
+    while let Some((size, id)) = probes.next().await {
+        if let Some((bytes, approx)) = size {
+            record(&state, &sid, &w.url, &id, label(bytes, approx));
+        }
+    }
+
+    state.sessions.update(&sid, |s| {
+        if let Some(probe) = s.sizes.as_mut().filter(|p| p.url == w.url) {
+            probe.running = false;
+        }
+    });
+}
+
+// --- Handlers ---
+
+/// Starts looking up the sizes the format table is missing and goes back to it.
+pub async fn probe_sizes(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Result<Response, AppError> {
+    let session = state.sessions.get(&sid);
+    let Some(w) = session.wizard else {
+        return Ok(Redirect::to(crate::start::ANALYZER).into_response());
+    };
+    if state.config.size_probes == 0 {
+        return Err(AppError::BadRequest("Looking up sizes is turned off (BSDL_SIZE_PROBES=0)".to_string()));
+    }
+    // Once per analysis, and not while it's still going
+    if session.sizes.as_ref().is_some_and(|p| p.url == w.url) {
+        return Ok(Redirect::to("/options").into_response());
+    }
+    let ids: Vec<String> = w.formats.iter().filter(|f| f.filesize == UNKNOWN).map(|f| f.id.clone()).collect();
+    if ids.is_empty() {
+        return Ok(Redirect::to("/options").into_response());
+    }
+    let url = w.url.clone();
+    state.sessions.update(&sid, |s| s.sizes = Some(Probe { url, running: true, found: BTreeMap::new() }));
+    tracing::info!("Looking up {} missing format size(s) of {}", ids.len(), w.url);
+    tokio::spawn(probe(state.clone(), sid, w, ids));
+    Ok(Redirect::to("/options").into_response())
+}
+
+#[derive(Template)]
+#[template(path = "_sizes.html")]
+struct SizesTemplate {
+    running: bool,
+    found: BTreeMap<String, String>,
+}
+
+/// The sizes found so far, as an HTML fragment the format table fills itself in from.
+pub async fn sizes_fragment(State(state): State<AppState>, Extension(sid): Extension<SessionId>) -> Response {
+    let session = state.sessions.get(&sid);
+    let probe = session.sizes.filter(|p| session.wizard.as_ref().is_some_and(|w| w.url == p.url)).unwrap_or_default();
+    render(SizesTemplate { running: probe.running, found: probe.found })
+}
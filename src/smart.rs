@@ -0,0 +1,276 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+
+use crate::auth;
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::error::{render, AppError};
+use crate::library;
+use crate::page::{self, FlashKind};
+use crate::sanitize::filters;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+const MEDIA_TYPES: &[&str] = &["video", "audio", "image", "other"];
+
+// --- Data Structures ---
+
+/// A saved search of the library index, e.g. audio over 30 minutes tagged
+/// "lectures". What it holds is worked out again on every visit.
+#[derive(Debug, Clone)]
+pub struct SmartFolder {
+    pub id: i64,
+    pub name: String,
+    pub media_type: Option<String>,
+    pub min_minutes: Option<u32>,
+    pub max_minutes: Option<u32>,
+    pub tag: Option<String>,
+    /// Words the title or channel contains
+    pub words: Option<String>,
+}
+
+impl SmartFolder {
+    /// "audio, over 30 min, tagged lectures".
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(t) = &self.media_type {
+            parts.push(t.clone());
+        }
+        match (self.min_minutes, self.max_minutes) {
+            (Some(min), Some(max)) => parts.push(format!("{} to {} min", min, max)),
+            (Some(min), None) => parts.push(format!("over {} min", min)),
+            (None, Some(max)) => parts.push(format!("under {} min", max)),
+            (None, None) => {}
+        }
+        if let Some(tag) = &self.tag {
+            parts.push(format!("tagged {}", tag));
+        }
+        if let Some(words) = &self.words {
+            parts.push(format!("\"{}\"", words));
+        }
+        parts.join(", ")
+    }
+}
+
+/// One indexed file a smart folder matched.
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub file_name: String,
+    pub title: String,
+    pub channel: Option<String>,
+    pub media_type: String,
+    pub size_mb: String,
+    pub length: String,
+}
+
+fn from_row(r: &db::Row) -> db::Result<SmartFolder> {
+    Ok(SmartFolder {
+        id: r.get(0)?,
+        name: r.get(1)?,
+        media_type: r.get(2)?,
+        min_minutes: r.get(3)?,
+        max_minutes: r.get(4)?,
+        tag: r.get(5)?,
+        words: r.get(6)?,
+    })
+}
+
+// --- Store ---
+
+pub async fn list(db: &Db) -> Result<Vec<SmartFolder>, AppError> {
+    db.call(|conn| {
+        conn.query_map(
+            "SELECT id, name, media_type, min_minutes, max_minutes, tag, words FROM smart_folders ORDER BY lower(name)",
+            params![],
+            from_row,
+        )
+    })
+    .await
+}
+
+async fn get(db: &Db, id: i64) -> Result<Option<SmartFolder>, AppError> {
+    db.call(move |conn| {
+        conn.query_row(
+            "SELECT id, name, media_type, min_minutes, max_minutes, tag, words FROM smart_folders WHERE id = ?1",
+            params![id],
+            from_row,
+        )
+        .optional()
+    })
+    .await
+}
+
+/// The present files of the index `folder` matches, newest first.
+async fn matches(db: &Db, folder: &SmartFolder) -> Result<Vec<Match>, AppError> {
+    let f = folder.clone();
+    db.call(move |conn| {
+        // Tags are stored lowercased between commas, so a whole tag is ",tag,".
+        // The casts give PostgreSQL a type for parameters it only sees tested for NULL.
+        conn.query_map(
+            "SELECT file_name, COALESCE(title, file_name), channel, media_type, size_bytes, duration FROM items
+             WHERE status = 'present'
+               AND (CAST(?1 AS TEXT) IS NULL OR media_type = ?1)
+               AND (CAST(?2 AS INTEGER) IS NULL OR duration >= ?2 * 60)
+               AND (CAST(?3 AS INTEGER) IS NULL OR duration <= ?3 * 60)
+               AND (CAST(?4 AS TEXT) IS NULL OR instr(',' || tags || ',', ',' || lower(?4) || ',') > 0)
+               AND (CAST(?5 AS TEXT) IS NULL OR instr(lower(COALESCE(title, file_name) || ' ' || COALESCE(channel, '')), lower(?5)) > 0)
+             ORDER BY added_at DESC",
+            params![f.media_type, f.min_minutes, f.max_minutes, f.tag, f.words],
+            |r| {
+                let size: i64 = r.get(4)?;
+                Ok(Match {
+                    file_name: r.get(0)?,
+                    title: r.get(1)?,
+                    channel: r.get(2)?,
+                    media_type: r.get(3)?,
+                    size_mb: format!("{:.2} MB", size as f64 / 1024.0 / 1024.0),
+                    length: library::length_label(r.get(5)?),
+                })
+            },
+        )
+    })
+    .await
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "smart_folders.html")]
+struct SmartFoldersTemplate {
+    folders: Vec<SmartFolder>,
+    media_types: &'static [&'static str],
+}
+
+pub async fn show_smart_folders(State(state): State<AppState>) -> Result<Response, AppError> {
+    Ok(render(SmartFoldersTemplate { folders: list(&state.db).await?, media_types: MEDIA_TYPES }))
+}
+
+#[derive(Template)]
+#[template(path = "smart_folder.html")]
+struct SmartFolderTemplate {
+    folder: SmartFolder,
+    items: Vec<Match>,
+}
+
+pub async fn show_smart_folder(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let folder = get(&state.db, id).await?.ok_or_else(|| AppError::NotFound("Smart folder".to_string()))?;
+    let viewer = auth::viewer(&state, &sid);
+    let mut items = matches(&state.db, &folder).await?;
+    items.retain(|m| library::visible_to(viewer.as_deref(), &m.file_name));
+    Ok(render(SmartFolderTemplate { folder, items }))
+}
+
+#[derive(Deserialize)]
+pub struct SmartFolderForm {
+    name: String,
+    #[serde(default)]
+    media_type: String,
+    #[serde(default)]
+    min_minutes: String,
+    #[serde(default)]
+    max_minutes: String,
+    #[serde(default)]
+    tag: String,
+    #[serde(default)]
+    words: String,
+}
+
+// Blank is no bound
+fn minutes(field: &'static str, value: &str) -> Result<Option<u32>, AppError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|m| *m <= 100_000)
+        .map(Some)
+        .ok_or_else(|| AppError::Invalid { field, reason: "a whole number of minutes".to_string() })
+}
+
+fn given(value: &str) -> Option<String> {
+    Some(value.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+impl Validate for SmartFolderForm {
+    fn validate(&self) -> Result<(), AppError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > 60 {
+            return Err(AppError::Invalid { field: "name", reason: "smart folder names need 1 to 60 characters".to_string() });
+        }
+        if !self.media_type.is_empty() {
+            validate::one_of("media_type", &self.media_type, MEDIA_TYPES)?;
+        }
+        let (min, max) = (minutes("min_minutes", &self.min_minutes)?, minutes("max_minutes", &self.max_minutes)?);
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                return Err(AppError::Invalid { field: "max_minutes", reason: "less than the minimum".to_string() });
+            }
+        }
+        validate::max_len("tag", self.tag.trim(), 100)?;
+        if self.tag.contains(',') {
+            return Err(AppError::Invalid { field: "tag", reason: "one tag, without commas".to_string() });
+        }
+        validate::max_len("words", self.words.trim(), 200)?;
+        if self.media_type.is_empty() && min.is_none() && max.is_none() && given(&self.tag).is_none() && given(&self.words).is_none() {
+            return Err(AppError::Invalid { field: "name", reason: "pick at least one thing to match".to_string() });
+        }
+        Ok(())
+    }
+}
+
+pub async fn save_smart_folder(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<SmartFolderForm>,
+) -> Result<Response, AppError> {
+    let name = form.name.trim().to_string();
+    let media_type = given(&form.media_type);
+    let (min, max) = (minutes("min_minutes", &form.min_minutes)?, minutes("max_minutes", &form.max_minutes)?);
+    let (tag, words) = (given(&form.tag).map(|t| t.to_lowercase()), given(&form.words));
+    let (stored, now) = (name.clone(), db::now());
+    let res = state
+        .db
+        .call(move |conn| {
+            conn.query_row(
+                "INSERT INTO smart_folders (name, media_type, min_minutes, max_minutes, tag, words, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) RETURNING id",
+                params![stored, media_type, min, max, tag, words, now],
+                |r| r.get::<i64>(0),
+            )
+        })
+        .await;
+    match res {
+        Ok(id) => {
+            page::flash(&state, &sid, FlashKind::Info, format!("Saved the smart folder {}.", name));
+            Ok(Redirect::to(&format!("/library/smart/{}", id)).into_response())
+        }
+        Err(AppError::Db(e)) if e.is_constraint() => {
+            Err(AppError::BadRequest(format!("There is already a smart folder called \"{}\"", name)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn delete_smart_folder(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let removed = state.db.call(move |conn| conn.execute("DELETE FROM smart_folders WHERE id = ?1", params![id])).await?;
+    if removed == 0 {
+        return Err(AppError::NotFound("Smart folder".to_string()));
+    }
+    page::flash(&state, &sid, FlashKind::Info, "Removed the smart folder; the files in it are still there.");
+    Ok(Redirect::to("/library/smart").into_response())
+}
@@ -0,0 +1,85 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+
+use crate::error::{render, AppError};
+use crate::page::{self, FlashKind};
+use crate::session::{self, SessionId};
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+/// Remembers for a year which page `/` opens on in this browser.
+pub const START_COOKIE: &str = "bsdl_start";
+
+/// Where the URL form is, whichever page `/` opens on.
+pub const ANALYZER: &str = "/analyze";
+
+/// A page `/` can open on.
+pub struct StartPage {
+    pub key: &'static str,
+    pub path: &'static str,
+    pub label: &'static str,
+}
+
+/// The first is the default.
+pub const START_PAGES: &[StartPage] = &[
+    StartPage { key: "analyzer", path: ANALYZER, label: "URL form" },
+    StartPage { key: "jobs", path: "/jobs", label: "Jobs dashboard" },
+    StartPage { key: "files", path: "/files", label: "Downloaded files" },
+    StartPage { key: "subscriptions", path: "/subscriptions", label: "Subscriptions" },
+];
+
+/// The page this browser picked, otherwise the URL form.
+pub fn start_for(headers: &HeaderMap) -> &'static StartPage {
+    let picked = session::cookie(headers, START_COOKIE);
+    START_PAGES.iter().find(|p| picked.as_deref() == Some(p.key)).unwrap_or(&START_PAGES[0])
+}
+
+// --- Handlers ---
+
+/// `/`: the URL form, or a redirect to the start page picked on /settings.
+pub async fn show_start(state: State<AppState>, sid: Extension<SessionId>, headers: HeaderMap) -> Response {
+    match start_for(&headers) {
+        p if p.path == ANALYZER => crate::show_index(state, sid).await,
+        p => Redirect::to(p.path).into_response(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+struct SettingsTemplate {
+    pages: &'static [StartPage],
+    start: &'static str,
+}
+
+pub async fn show_settings(headers: HeaderMap) -> Response {
+    render(SettingsTemplate { pages: START_PAGES, start: start_for(&headers).key })
+}
+
+#[derive(Deserialize)]
+pub struct StartRequest {
+    start: String,
+}
+
+impl Validate for StartRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        let keys: Vec<&str> = START_PAGES.iter().map(|p| p.key).collect();
+        validate::one_of("start", &self.start, &keys)
+    }
+}
+
+pub async fn set_start(State(state): State<AppState>, Extension(sid): Extension<SessionId>, Form(req): Form<StartRequest>) -> Response {
+    let picked = START_PAGES.iter().find(|p| p.key == req.start).unwrap_or(&START_PAGES[0]);
+    page::flash(&state, &sid, FlashKind::Info, format!("This browser now starts on {}.", picked.label));
+    let mut res = Redirect::to("/settings").into_response();
+    let cookie = format!("{}={}; Path=/; Max-Age=31536000; SameSite=Lax", START_COOKIE, req.start);
+    if let Ok(value) = cookie.parse() {
+        res.headers_mut().append(header::SET_COOKIE, value);
+    }
+    res
+}
@@ -0,0 +1,630 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::clock::Stamp;
+use crate::db::{self, params, Db, OptionalExtension};
+use crate::dedupe;
+use crate::error::{render, AppError};
+use crate::jobs::Step;
+use crate::notify;
+use crate::page::{self, FlashKind};
+use crate::pinning;
+use crate::recipe::{self, Recipe, POSTPROCESS};
+use crate::reconcile;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+// Newest uploads looked at on each check
+const CHECK_DEPTH: usize = 30;
+/// Failed checks in a row after which a subscription counts as unhealthy
+const UNHEALTHY_AFTER: i64 = 3;
+// Each failure doubles the wait before the next check, up to a week
+const MAX_BACKOFF_HOURS: u64 = 7 * 24;
+
+// --- Data Structures ---
+
+/// A channel or playlist that is checked for new uploads, each of which is
+/// downloaded with the subscription's own settings. `None` means "use the
+/// global BSDL_SUBSCRIPTION_* setting".
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub id: i64,
+    pub name: String,
+    pub url: String,
+    /// `best`, `audio:<format>` or `profile:<id>`
+    pub preset: Option<String>,
+    pub output_template: Option<String>,
+    /// Comma separated steps; empty means none at all
+    pub postprocess: Option<String>,
+    pub created: Stamp,
+    pub last_checked: Option<Stamp>,
+    /// Checks that failed since the last one that worked
+    pub failures: i64,
+    pub last_error: Option<String>,
+    pub last_success: Option<Stamp>,
+    /// Only the newest this many uploads are downloaded, and older files are deleted
+    pub keep_latest: Option<usize>,
+    /// YYYY-MM-DD; older uploads aren't downloaded
+    pub date_after: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    Healthy,
+    /// Failed recently, but not often enough to worry about yet
+    Failing,
+    Unhealthy,
+}
+
+impl Health {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Health::Healthy => "Healthy",
+            Health::Failing => "Failing",
+            Health::Unhealthy => "Unhealthy",
+        }
+    }
+
+    // Same badges as the diagnostics page
+    pub fn css(&self) -> &'static str {
+        match self {
+            Health::Healthy => "check-pass",
+            Health::Failing => "check-warn",
+            Health::Unhealthy => "check-fail",
+        }
+    }
+}
+
+fn health_of(failures: i64) -> Health {
+    match failures {
+        0 => Health::Healthy,
+        n if n < UNHEALTHY_AFTER => Health::Failing,
+        _ => Health::Unhealthy,
+    }
+}
+
+impl Subscription {
+    pub fn preset_value(&self) -> &str {
+        self.preset.as_deref().unwrap_or("")
+    }
+
+    pub fn template_value(&self) -> &str {
+        self.output_template.as_deref().unwrap_or("")
+    }
+
+    pub fn keep_value(&self) -> String {
+        self.keep_latest.map(|n| n.to_string()).unwrap_or_default()
+    }
+
+    pub fn date_value(&self) -> &str {
+        self.date_after.as_deref().unwrap_or("")
+    }
+
+    // The upload at `position` in the listing (0 is the newest) is one to download
+    fn in_window(&self, position: usize, entry: &Entry) -> bool {
+        let recent = self.keep_latest.is_none_or(|n| position < n);
+        // Flat listings often leave the date out; --dateafter on the download catches those
+        let new_enough = match (self.ytdlp_date(), &entry.date) {
+            (Some(after), Some(date)) => *date >= after,
+            _ => true,
+        };
+        recent && new_enough
+    }
+
+    // What --dateafter takes
+    fn ytdlp_date(&self) -> Option<String> {
+        self.date_after.as_ref().map(|d| d.replace('-', ""))
+    }
+
+    pub fn health(&self) -> Health {
+        health_of(self.failures)
+    }
+
+    /// When the scheduler will next look, `hours` apart and further once checks keep failing.
+    pub fn next_check(&self, hours: u64) -> Option<Stamp> {
+        if hours == 0 {
+            return None;
+        }
+        let Some(last) = self.last_checked else {
+            return Some(Stamp::now());
+        };
+        let backoff = 1u64 << self.failures.clamp(0, 16);
+        let wait = hours.saturating_mul(backoff).min(MAX_BACKOFF_HOURS.max(hours));
+        Some(Stamp(last.0 + (wait * 3600) as i64))
+    }
+
+    /// How its uploads are downloaded. Subscriptions run unattended, so always politely.
+    pub fn recipe(&self) -> Recipe {
+        Recipe {
+            preset: self.preset.clone(),
+            output_template: self.output_template.clone(),
+            postprocess: self.postprocess.clone(),
+            polite: true,
+        }
+    }
+
+    pub fn postprocess_value(&self) -> &str {
+        match self.postprocess.as_deref() {
+            None => "",
+            Some("") => "none",
+            Some(steps) => steps,
+        }
+    }
+}
+
+const COLUMNS: &str = "id, name, url, preset, output_template, postprocess, created_at, last_checked_at, \
+                       failures, last_error, last_success_at, keep_latest, date_after";
+
+fn from_row(r: &db::Row) -> db::Result<Subscription> {
+    Ok(Subscription {
+        id: r.get(0)?,
+        name: r.get(1)?,
+        url: r.get(2)?,
+        preset: r.get(3)?,
+        output_template: r.get(4)?,
+        postprocess: r.get(5)?,
+        created: Stamp(r.get(6)?),
+        last_checked: r.get::<Option<i64>>(7)?.map(Stamp),
+        failures: r.get(8)?,
+        last_error: r.get(9)?,
+        last_success: r.get::<Option<i64>>(10)?.map(Stamp),
+        keep_latest: r.get::<Option<i64>>(11)?.map(|n| n.max(0) as usize),
+        date_after: r.get(12)?,
+    })
+}
+
+// --- Store ---
+
+pub async fn list(db: &Db) -> Result<Vec<Subscription>, AppError> {
+    db.call(|conn| {
+        conn.query_map(&format!("SELECT {} FROM subscriptions ORDER BY lower(name)", COLUMNS), params![], from_row)
+    })
+    .await
+}
+
+pub async fn get(db: &Db, id: i64) -> Result<Option<Subscription>, AppError> {
+    db.call(move |conn| {
+        conn.query_row(&format!("SELECT {} FROM subscriptions WHERE id = ?1", COLUMNS), params![id], from_row).optional()
+    })
+    .await
+}
+
+// Never checked, or their (backed off) interval has passed
+async fn due(db: &Db, hours: u64) -> Result<Vec<Subscription>, AppError> {
+    let now = Stamp::now();
+    let mut subs = list(db).await?;
+    subs.retain(|s| s.next_check(hours).is_some_and(|next| next <= now));
+    Ok(subs)
+}
+
+// Keeps the failure count and last error, and says so when a subscription
+// turns unhealthy or recovers
+async fn record(state: &AppState, sub: &Subscription, result: &Result<usize, AppError>) -> Result<(), AppError> {
+    let id = sub.id;
+    let (failures, error) = match result {
+        Ok(_) => (0, None),
+        Err(e) => (sub.failures + 1, Some(e.to_string())),
+    };
+    let last_error = error.clone();
+    state
+        .db
+        .call(move |conn| match last_error {
+            None => conn.execute(
+                "UPDATE subscriptions SET failures = 0, last_error = NULL, last_success_at = ?2 WHERE id = ?1",
+                params![id, db::now()],
+            ),
+            Some(e) => conn.execute(
+                "UPDATE subscriptions SET failures = ?2, last_error = ?3 WHERE id = ?1",
+                params![id, failures, e],
+            ),
+        })
+        .await?;
+
+    let (before, after) = (sub.health(), health_of(failures));
+    if before != after && (before == Health::Unhealthy || after == Health::Unhealthy) {
+        let message = match error {
+            Some(e) => format!("Subscription {} is unhealthy after {} failed checks: {}", sub.name, failures, e),
+            None => format!("Subscription {} is working again", sub.name),
+        };
+        notify::send(state, &message).await;
+    }
+    Ok(())
+}
+
+// --- Checking ---
+
+/// One video in a channel or playlist listing.
+pub struct Entry {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    /// Upload date as YYYYMMDD, when the listing has it
+    pub date: Option<String>,
+}
+
+// yt-dlp prints "NA" for fields a flat listing doesn't have
+fn parse_entries(stdout: &str) -> Vec<Entry> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(4, '\t');
+            let id = parts.next()?.trim();
+            let url = parts.next()?.trim();
+            let date = parts.next().unwrap_or("").trim();
+            let title = parts.next().unwrap_or("").trim();
+            if id.is_empty() || id == "NA" || !(url.starts_with("http://") || url.starts_with("https://")) {
+                return None;
+            }
+            let title = if title.is_empty() || title == "NA" { id } else { title };
+            let date = (date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit())).then(|| date.to_string());
+            Some(Entry { id: id.to_string(), url: url.to_string(), title: title.to_string(), date })
+        })
+        .collect()
+}
+
+/// The newest `depth` videos of a channel or playlist, listed without looking
+/// at each one.
+pub async fn list_entries(state: &AppState, url: &str, depth: usize) -> Result<Vec<Entry>, AppError> {
+    let mut cmd = Command::new(pinning::ytdlp(state, url));
+    cmd.arg("--flat-playlist")
+        .arg("--playlist-end")
+        .arg(depth.to_string())
+        .arg("--print")
+        .arg("%(id)s\t%(url)s\t%(upload_date)s\t%(title)s");
+    let _cookies = state.secrets.attach_cookies(&mut cmd).await?;
+    cmd.arg("--").arg(url);
+
+    let output = state.procs.output("listing", cmd).await?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        let reason = err.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no details").trim().to_string();
+        return Err(AppError::YtDlpFailed(format!("listing {} exited with {}: {}", url, output.status, reason)));
+    }
+    Ok(parse_entries(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Lists the subscription's newest uploads and queues the ones it hasn't seen.
+/// The very first check only takes note of what's already there. Returns how
+/// many uploads were queued.
+pub async fn check(state: &AppState, sub: &Subscription) -> Result<usize, AppError> {
+    // Not a failure of the subscription, so nothing is recorded
+    state.jobs.accepting()?;
+    let result = fetch(state, sub).await;
+    record(state, sub, &result).await?;
+    result
+}
+
+async fn fetch(state: &AppState, sub: &Subscription) -> Result<usize, AppError> {
+    let id = sub.id;
+    // Recorded up front so a failing source isn't retried every minute
+    state
+        .db
+        .call(move |conn| conn.execute("UPDATE subscriptions SET last_checked_at = ?2 WHERE id = ?1", params![id, db::now()]))
+        .await?;
+
+    // Past the window, the listing only finds what to prune
+    let depth = sub.keep_latest.map_or(CHECK_DEPTH, |n| n + CHECK_DEPTH);
+    let entries = list_entries(state, &sub.url, depth).await?;
+
+    let ids: Vec<String> = entries.iter().map(|e| e.id.clone()).collect();
+    let (first, fresh) = state
+        .db
+        .call(move |conn| {
+            let mut tx = conn.transaction()?;
+            let seen: i64 =
+                tx.query_row("SELECT COUNT(*) FROM subscription_items WHERE subscription_id = ?1", params![id], |r| r.get(0))?;
+            let mut fresh = Vec::new();
+            for video in ids {
+                let added = tx.execute(
+                    "INSERT INTO subscription_items (subscription_id, video_id, seen_at) VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING",
+                    params![id, video, db::now()],
+                )?;
+                if added > 0 {
+                    fresh.push(video);
+                }
+            }
+            tx.commit()?;
+            Ok((seen == 0, fresh))
+        })
+        .await?;
+    if first {
+        return Ok(0);
+    }
+    // Downloaded before, outside the app
+    let had = dedupe::archived(&state.db, fresh.clone()).await?;
+    if !had.is_empty() {
+        tracing::info!("Subscription {}: {} new upload(s) are in the download archive, not queued", sub.name, had.len());
+    }
+
+    // Listings are newest first; queue the oldest first
+    let recipe = sub.recipe();
+    let mut queued = 0;
+    let window = entries.iter().enumerate().filter(|(i, e)| sub.in_window(*i, e)).map(|(_, e)| e);
+    for entry in window.rev().filter(|e| fresh.contains(&e.id) && !had.contains(&e.id)) {
+        let mut jobs = recipe.steps(state, entry.url.clone()).await?;
+        for job in &mut jobs {
+            if let Step::Download { selection, .. } = &mut job.step {
+                selection.date_after = sub.ytdlp_date();
+            }
+        }
+        state.jobs.submit(&format!("{}: {}", sub.name, entry.title), jobs)?;
+        queued += 1;
+    }
+
+    if let Some(keep) = sub.keep_latest {
+        let old: Vec<String> = entries.iter().skip(keep).map(|e| e.url.clone()).collect();
+        // Anything played lately stays, whatever the window says
+        let days = state.config.keep_played_days as i64;
+        let played_since = if days == 0 { i64::MAX } else { db::now() - days * 24 * 3600 };
+        match prune(state, old, played_since).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Subscription {}: deleted {} file(s) of uploads past the newest {}", sub.name, n, keep),
+            Err(e) => tracing::warn!("Subscription {}: pruning failed: {}", sub.name, e),
+        }
+    }
+    Ok(queued)
+}
+
+// Deletes the downloaded files of `urls`, the uploads that fell out of a
+// subscription's window, the same way the history page does, except those
+// played since `played_since`. Returns how many files went.
+async fn prune(state: &AppState, urls: Vec<String>, played_since: i64) -> Result<usize, AppError> {
+    let dir = state.config.downloads_dir.clone();
+    state.db.call(move |conn| {
+        let mut names = Vec::new();
+        for url in &urls {
+            names.extend(conn.query_map(
+                "SELECT file_name FROM items WHERE status = 'present' AND source_url = ?1
+                   AND (last_played_at IS NULL OR last_played_at < ?2)",
+                params![url, played_since],
+                |r| r.get::<String>(0),
+            )?);
+        }
+        let mut files = 0;
+        for name in names {
+            match reconcile::remove_record(conn, &dir, &name, true)? {
+                Ok(n) => files += n.unwrap_or(0),
+                Err(e) => tracing::warn!("Could not delete {}: {}", name, e),
+            }
+        }
+        Ok(files)
+    })
+    .await
+}
+
+/// Checks every subscription that's due, once a minute, for the life of the process.
+pub fn spawn_schedule(state: AppState, hours: u64) {
+    if hours == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            tick.tick().await;
+            // Due checks wait until maintenance is over
+            if state.jobs.maintenance().is_some() {
+                continue;
+            }
+            let subs = match due(&state.db, hours).await {
+                Ok(subs) => subs,
+                Err(e) => {
+                    tracing::warn!("Could not load subscriptions: {}", e);
+                    continue;
+                }
+            };
+            for sub in subs {
+                match check(&state, &sub).await {
+                    Ok(0) => {}
+                    Ok(n) => tracing::info!("Subscription {}: queued {} new upload(s)", sub.name, n),
+                    Err(e) => tracing::warn!("Subscription {} check failed: {}", sub.name, e),
+                }
+            }
+        }
+    });
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "subscriptions.html")]
+struct SubscriptionsTemplate {
+    /// Each with when it's next due
+    subscriptions: Vec<(Subscription, Option<Stamp>)>,
+    /// (value, label) for the preset select, after the global default
+    presets: Vec<(String, String)>,
+    postprocess: &'static [(&'static str, &'static str)],
+    global_preset: String,
+    global_template: String,
+    global_postprocess: String,
+    hours: u64,
+    keep_played_days: u64,
+    unhealthy: usize,
+    /// Filled into the new subscription row, e.g. from the analyze page
+    new_name: String,
+    new_url: String,
+}
+
+/// `?name=&url=` for starting a new subscription
+#[derive(Deserialize)]
+pub struct NewSubscription {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    url: String,
+}
+
+#[derive(Deserialize)]
+pub struct SubscriptionForm {
+    // Empty when adding a new subscription
+    #[serde(default)]
+    id: String,
+    name: String,
+    url: String,
+    #[serde(default)]
+    preset: String,
+    #[serde(default)]
+    output_template: String,
+    #[serde(default)]
+    postprocess: String,
+    #[serde(default)]
+    keep_latest: String,
+    #[serde(default)]
+    date_after: String,
+}
+
+impl SubscriptionForm {
+    fn keep_latest(&self) -> Option<i64> {
+        self.keep_latest.trim().parse().ok()
+    }
+
+    fn date_after(&self) -> Option<String> {
+        Some(self.date_after.trim().to_string()).filter(|d| !d.is_empty())
+    }
+}
+
+impl Validate for SubscriptionForm {
+    fn validate(&self) -> Result<(), AppError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > 80 {
+            return Err(AppError::Invalid { field: "name", reason: "names need 1 to 80 characters".to_string() });
+        }
+        let url = self.url.trim();
+        validate::url("url", url)?;
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(AppError::Invalid { field: "url", reason: "must be an http:// or https:// URL".to_string() });
+        }
+        recipe::validate_fields(&self.preset, &self.output_template, &self.postprocess)?;
+        let keep = self.keep_latest.trim();
+        if !keep.is_empty() && !keep.parse::<i64>().is_ok_and(|n| (1..=1000).contains(&n)) {
+            return Err(AppError::Invalid { field: "keep_latest", reason: "must be from 1 to 1000, or empty to keep everything".to_string() });
+        }
+        if let Some(date) = self.date_after() {
+            validate::date("date_after", &date)?;
+        }
+        if !self.id.trim().is_empty() && self.id.trim().parse::<i64>().is_err() {
+            return Err(AppError::Invalid { field: "id", reason: "not a subscription id".to_string() });
+        }
+        Ok(())
+    }
+}
+
+pub async fn show_subscriptions(
+    State(state): State<AppState>,
+    Query(new): Query<NewSubscription>,
+) -> Result<Response, AppError> {
+    let presets = recipe::preset_options(&state.db).await?;
+    let config = &state.config;
+    let global_postprocess = match config.subscription_postprocess.join(", ") {
+        steps if steps.is_empty() => "none".to_string(),
+        steps => steps,
+    };
+    let subscriptions: Vec<_> = list(&state.db)
+        .await?
+        .into_iter()
+        .map(|s| {
+            let next = s.next_check(config.subscription_hours);
+            (s, next)
+        })
+        .collect();
+    let unhealthy = subscriptions.iter().filter(|(s, _)| s.health() == Health::Unhealthy).count();
+    Ok(render(SubscriptionsTemplate {
+        subscriptions,
+        presets,
+        postprocess: POSTPROCESS,
+        global_preset: config.subscription_preset.clone(),
+        global_template: config.subscription_template.clone(),
+        global_postprocess,
+        hours: config.subscription_hours,
+        keep_played_days: config.keep_played_days,
+        unhealthy,
+        new_name: new.name,
+        new_url: new.url,
+    }))
+}
+
+pub async fn save_subscription(
+    State(state): State<AppState>,
+    Form(form): Form<SubscriptionForm>,
+) -> Result<Response, AppError> {
+    let name = form.name.trim().to_string();
+    let url = form.url.trim().to_string();
+    let id = form.id.trim().parse::<i64>().ok();
+    let recipe = Recipe::from_form(&form.preset, &form.output_template, &form.postprocess, true);
+    // Rather now than at the next check
+    recipe.check(&state.db).await?;
+    let Recipe { preset, output_template: template, postprocess, .. } = recipe;
+    let (keep, after) = (form.keep_latest(), form.date_after());
+
+    let taken_url = url.clone();
+    let res = state
+        .db
+        .call(move |conn| match id {
+            Some(id) => conn.execute(
+                "UPDATE subscriptions SET name = ?2, url = ?3, preset = ?4, output_template = ?5, postprocess = ?6, \
+                 keep_latest = ?7, date_after = ?8 WHERE id = ?1",
+                params![id, name, url, preset, template, postprocess, keep, after],
+            ),
+            None => conn.execute(
+                "INSERT INTO subscriptions (name, url, preset, output_template, postprocess, keep_latest, date_after, created_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![name, url, preset, template, postprocess, keep, after, db::now()],
+            ),
+        })
+        .await;
+
+    match res {
+        Ok(0) => Err(AppError::NotFound("Subscription".to_string())),
+        Ok(_) => Ok(Redirect::to("/subscriptions").into_response()),
+        Err(AppError::Db(e)) if e.is_constraint() => {
+            Err(AppError::BadRequest(format!("{} is already subscribed", taken_url)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn delete_subscription(State(state): State<AppState>, Path(id): Path<i64>) -> Result<Response, AppError> {
+    let removed = state
+        .db
+        .call(move |conn| {
+            let mut tx = conn.transaction()?;
+            tx.execute("DELETE FROM subscription_items WHERE subscription_id = ?1", params![id])?;
+            let removed = tx.execute("DELETE FROM subscriptions WHERE id = ?1", params![id])?;
+            tx.commit()?;
+            Ok(removed)
+        })
+        .await?;
+    if removed == 0 {
+        return Err(AppError::NotFound("Subscription".to_string()));
+    }
+    Ok(Redirect::to("/subscriptions").into_response())
+}
+
+/// Checks `sub` now and says how that went, here or on /schedule.
+pub async fn check_and_flash(state: &AppState, sid: &SessionId, sub: &Subscription) {
+    match check(state, sub).await {
+        Ok(0) if sub.last_checked.is_none() => {
+            page::flash(state, sid, FlashKind::Info, format!("{}: noted the current uploads; new ones will be fetched.", sub.name))
+        }
+        Ok(0) => page::flash(state, sid, FlashKind::Info, format!("{}: nothing new.", sub.name)),
+        Ok(n) => page::flash(state, sid, FlashKind::Info, format!("{}: queued {} new upload(s).", sub.name, n)),
+        Err(e) => page::flash(state, sid, FlashKind::Error, format!("{}: check failed: {}", sub.name, e)),
+    }
+}
+
+pub async fn check_now(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let sub = get(&state.db, id).await?.ok_or_else(|| AppError::NotFound("Subscription".to_string()))?;
+    check_and_flash(&state, &sid, &sub).await;
+    Ok(Redirect::to("/subscriptions").into_response())
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::config::Translation;
+use crate::error::AppError;
+use crate::http::{self, Part};
+use crate::library;
+use crate::pinning;
+use crate::transcode;
+use crate::AppState;
+
+/// Subtitles sit next to their file as `<stem>.<lang>.srt`, as yt-dlp names them.
+pub const SUBTITLE_EXT: &str = "srt";
+
+pub fn subtitle_name(original: &str, lang: &str) -> String {
+    format!("{}.{}.{}", library::stem(original), lang, SUBTITLE_EXT)
+}
+
+// Cues sent to LibreTranslate per request
+const BATCH: usize = 50;
+
+// --- SRT ---
+
+struct Cue {
+    timing: String,
+    text: String,
+}
+
+// Blocks are split by blank lines: a counter, the timing, then the text
+fn parse(srt: &str) -> Vec<Cue> {
+    srt.replace("\r\n", "\n")
+        .trim_start_matches('\u{feff}')
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut lines = block.trim().lines();
+            let first = lines.next()?;
+            let timing = if first.contains("-->") { first } else { lines.next()? };
+            if !timing.contains("-->") {
+                return None;
+            }
+            Some(Cue { timing: timing.trim().to_string(), text: lines.collect::<Vec<_>>().join("\n") })
+        })
+        .collect()
+}
+
+fn write(cues: &[Cue]) -> String {
+    cues.iter().enumerate().map(|(i, cue)| format!("{}\n{}\n{}\n\n", i + 1, cue.timing, cue.text.trim())).collect()
+}
+
+// --- LibreTranslate ---
+
+#[derive(Serialize)]
+struct Request<'a> {
+    q: &'a [String],
+    source: &'a str,
+    target: &'a str,
+    format: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Translated {
+    #[serde(rename = "translatedText")]
+    translated_text: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Refused {
+    error: String,
+}
+
+async fn translate(t: &Translation, texts: &[String]) -> Result<Vec<String>, AppError> {
+    let url = format!("{}/translate", t.url.trim_end_matches('/'));
+    // yt-dlp's en-US is LibreTranslate's en
+    let source = t.from.split(['-', '_']).next().unwrap_or(&t.from);
+    let mut out = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(BATCH) {
+        let request = Request { q: batch, source, target: &t.to, format: "text", api_key: t.api_key.as_deref() };
+        let body = serde_json::to_vec(&request)?;
+        let reply = http::post(&url, "application/json", &[Part::Bytes(&body)])
+            .await
+            .map_err(|e| AppError::Translate(format!("could not reach {}: {}", t.url, e)))?;
+        if !reply.ok() {
+            let text = reply.text();
+            let reason = serde_json::from_str::<Refused>(&text).map(|r| r.error).unwrap_or(text);
+            return Err(AppError::Translate(format!("{} answered {}: {}", t.url, reply.status, reason)));
+        }
+        let translated: Translated = serde_json::from_slice(&reply.body)
+            .map_err(|e| AppError::Translate(format!("unexpected answer from {}: {}", t.url, e)))?;
+        if translated.translated_text.len() != batch.len() {
+            return Err(AppError::Translate(format!(
+                "{} sent back {} lines for {}",
+                t.url,
+                translated.translated_text.len(),
+                batch.len()
+            )));
+        }
+        out.extend(translated.translated_text);
+    }
+    Ok(out)
+}
+
+// --- Pipeline ---
+
+// Subtitles in the source language: already there, or fetched from where the
+// file came from, the site's own over automatic captions
+async fn source_subtitles(state: &AppState, t: &Translation, name: &str) -> Result<Option<PathBuf>, AppError> {
+    let prefix = format!("{}.{}", library::stem(name), t.from);
+    let target = subtitle_name(name, &t.to);
+    let dir = &state.config.downloads_dir;
+    let find = || -> std::io::Result<Option<PathBuf>> {
+        Ok(library::list_names(dir)?
+            .into_iter()
+            .find(|n| n.starts_with(&prefix) && n.ends_with(".srt") && *n != target)
+            .map(|n| Path::new(dir).join(n)))
+    };
+    if let Some(found) = find()? {
+        return Ok(Some(found));
+    }
+
+    let url = library::load_meta(dir, name).and_then(|m| m.webpage_url).ok_or_else(|| {
+        AppError::BadRequest(format!("{} has no source URL to fetch subtitles from; import its metadata first", name))
+    })?;
+    // '%' is yt-dlp's template character and has to be doubled
+    let template = format!("{}/{}.%(ext)s", dir, library::stem(name).replace('%', "%%"));
+    let mut cmd = Command::new(pinning::ytdlp(state, &url));
+    cmd.args(["--skip-download", "--write-subs", "--write-auto-subs", "--no-playlist"])
+        .arg("--sub-langs")
+        .arg(&t.from)
+        .args(["--sub-format", "srt/best", "--convert-subs", "srt"])
+        .arg("-o")
+        .arg(&template);
+    let _cookies = state.secrets.attach_cookies(&mut cmd).await?;
+    cmd.arg("--").arg(&url);
+
+    let output = state.procs.output("subtitles", cmd).await?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        let reason = err.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no details").trim().to_string();
+        return Err(AppError::YtDlpFailed(format!("fetching subtitles exited with {}: {}", output.status, reason)));
+    }
+    Ok(find()?)
+}
+
+/// Machine-translates `media`'s BSDL_TRANSLATE_FROM subtitles into
+/// `<stem>.<BSDL_TRANSLATE_TO>.srt` next to it, and adds that to the file
+/// itself with BSDL_TRANSLATE_EMBED. `None` when there were no subtitles to translate.
+pub async fn translate_file(state: &AppState, media: &Path) -> Result<Option<PathBuf>, AppError> {
+    let t = state.config.translation.as_ref().ok_or_else(|| {
+        AppError::BadRequest("Subtitle translation needs BSDL_TRANSLATE_URL and BSDL_TRANSLATE_TO".to_string())
+    })?;
+    let name = media
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| AppError::BadRequest(format!("{} has no usable file name", media.display())))?;
+    let Some(source) = source_subtitles(state, t, name).await? else {
+        tracing::info!("No {} subtitles for {}, nothing to translate", t.from, name);
+        return Ok(None);
+    };
+
+    let cues = parse(&String::from_utf8_lossy(&tokio::fs::read(&source).await?));
+    let texts: Vec<String> = cues.iter().map(|c| c.text.clone()).collect();
+    let translated = translate(t, &texts).await?;
+    let cues: Vec<Cue> = cues.into_iter().zip(translated).map(|(cue, text)| Cue { timing: cue.timing, text }).collect();
+
+    let dir = media.parent().unwrap_or(Path::new(&state.config.downloads_dir));
+    let target = dir.join(subtitle_name(name, &t.to));
+    let partial = dir.join(format!(".{}.part", subtitle_name(name, &t.to)));
+    tokio::fs::write(&partial, write(&cues)).await?;
+    tokio::fs::rename(&partial, &target).await?;
+    tracing::info!("Translated {} cues of {} into {}", cues.len(), name, target.display());
+
+    if t.embed {
+        transcode::embed_subtitles(state, media, &target, &t.to).await?;
+    }
+    Ok(Some(target))
+}
@@ -0,0 +1,267 @@
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{Datelike, Timelike};
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::diagnostics;
+use crate::error::AppError;
+use crate::jobs::JobStatus;
+use crate::logging;
+use crate::AppState;
+
+// Only the end of a log file is worth attaching
+const LOG_TAIL_LINES: usize = 500;
+
+// --- Redaction ---
+
+// Keeps the program and drops its arguments, which tend to hold tokens and
+// topic URLs (`curl -s https://ntfy.sh/private-topic -d`)
+fn redact_cmd(command_line: &mut String) {
+    let mut parts = command_line.split_whitespace();
+    let program = parts.next().unwrap_or_default().to_string();
+    let dropped = parts.count();
+    *command_line = if dropped == 0 { program } else { format!("{} <{} argument(s) redacted>", program, dropped) };
+}
+
+/// Cuts query strings and user info out of every URL in `text`; signed media
+/// URLs in yt-dlp errors carry tokens there.
+fn scrub(text: &str) -> String {
+    text.split_inclusive(char::is_whitespace)
+        .map(|word| {
+            let trimmed = word.trim_end();
+            if !(trimmed.starts_with("http://") || trimmed.starts_with("https://")) {
+                return word.to_string();
+            }
+            let (scheme, rest) = trimmed.split_once("://").unwrap_or(("", trimmed));
+            let rest = rest.split_once('@').map(|(_, host)| host).unwrap_or(rest);
+            let clean = match rest.split_once('?') {
+                Some((path, _)) => format!("{}://{}?<redacted>", scheme, path),
+                None => format!("{}://{}", scheme, rest),
+            };
+            format!("{}{}", clean, &word[trimmed.len()..])
+        })
+        .collect()
+}
+
+// --- Sections ---
+
+fn about() -> String {
+    format!(
+        "bplus-streamdlrs-gui {}\n{} {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+// What the bundle says of the config, setting by setting. Only what's listed
+// here is printed, so a new setting stays out until someone decides it's safe:
+// commands lose their arguments, URLs their query strings and user info, and
+// keys and tracker passkeys only show whether they're set. BSDL_SECRET_KEY
+// never makes it into Config; stored secrets aren't read at all.
+fn config(state: &AppState) -> String {
+    let c = &*state.config;
+    let cmd = |cmd: &Option<String>| match cmd {
+        Some(cmd) => {
+            let mut cmd = cmd.clone();
+            redact_cmd(&mut cmd);
+            cmd
+        }
+        None => "none".to_string(),
+    };
+    let url = |url: Option<&str>| url.map_or_else(|| "none".to_string(), scrub);
+    let path = |path: Option<&Path>| path.map_or_else(|| "none".to_string(), |p| p.display().to_string());
+    let set = |set: bool| if set { "set" } else { "not set" }.to_string();
+    let translation = c.translation.as_ref();
+    let settings: Vec<(&str, String)> = vec![
+        ("BSDL_HOST", c.host.clone()),
+        ("BSDL_PORT", c.port.to_string()),
+        ("BSDL_TLS_CERT", path(c.tls.as_ref().map(|t| t.cert.as_path()))),
+        ("BSDL_TLS_KEY", path(c.tls.as_ref().map(|t| t.key.as_path()))),
+        ("BSDL_MAX_UPLOAD_MB", c.max_upload_mb.to_string()),
+        ("BSDL_DB_PATH", c.db_path.display().to_string()),
+        ("BSDL_DB_URL", set(c.storage.url.is_some())),
+        ("BSDL_CONTROL_SOCKET", path(c.control_socket.as_deref())),
+        ("BSDL_DB_JOURNAL", if c.storage.wal { "wal" } else { "delete" }.to_string()),
+        ("BSDL_DB_BUSY_TIMEOUT_MS", c.storage.busy_timeout_ms.to_string()),
+        ("BSDL_DB_CHECKPOINT_MINUTES", c.storage.checkpoint_minutes.to_string()),
+        ("BSDL_LANDING", format!("{:?}", c.landing.mode).to_lowercase()),
+        ("BSDL_LANDING_DIR", c.landing.dir.display().to_string()),
+        ("BSDL_RESCAN_MINUTES", c.rescan_minutes.to_string()),
+        ("BSDL_BACKUP_HOURS", c.backup_hours.to_string()),
+        ("BSDL_BACKUP_KEEP", c.backup_keep.to_string()),
+        ("BSDL_BACKUP_UPLOAD_CMD", cmd(&c.backup_upload_cmd)),
+        ("BSDL_SECRET_KEY_FILE", c.secret_key_file.display().to_string()),
+        ("BSDL_RESTRICT_FILENAMES", c.filenames.restrict.to_string()),
+        ("BSDL_WINDOWS_FILENAMES", c.filenames.windows.to_string()),
+        ("BSDL_TRIM_FILENAMES", c.filenames.trim.to_string()),
+        ("BSDL_POLITE_SLEEP_MIN", c.polite.sleep_min.to_string()),
+        ("BSDL_POLITE_SLEEP_MAX", c.polite.sleep_max.to_string()),
+        ("BSDL_POLITE_PER_HOUR", c.polite.per_hour.to_string()),
+        ("BSDL_POLITE_PAUSE_MINUTES", c.polite.pause_minutes.to_string()),
+        ("BSDL_COOKIE_PER_HOUR", c.cookie_per_hour.to_string()),
+        ("BSDL_MAX_DOWNLOADS", c.max_downloads.to_string()),
+        ("BSDL_RATE_LIMIT", c.rate_limit.to_string()),
+        ("BSDL_SHUTDOWN_TIMEOUT", c.shutdown_timeout.to_string()),
+        ("BSDL_SIZE_PROBES", c.size_probes.to_string()),
+        ("BSDL_MONTHLY_BUDGET_GB", (c.budget.monthly_bytes / (1024 * 1024 * 1024)).to_string()),
+        ("BSDL_BUDGET_PAUSE_PERCENT", c.budget.pause_percent.to_string()),
+        ("BSDL_BUDGET_RESET_DAY", c.budget.reset_day.to_string()),
+        ("BSDL_BUDGET_CMD", cmd(&c.budget.check_cmd)),
+        ("BSDL_NICE", c.limits.nice.to_string()),
+        ("BSDL_IONICE", c.limits.ionice.clone().unwrap_or_else(|| "none".to_string())),
+        ("BSDL_CPU_AFFINITY", c.limits.cpu_affinity.clone().unwrap_or_else(|| "none".to_string())),
+        ("BSDL_CGROUP", path(c.limits.cgroup.as_deref())),
+        ("BSDL_LOW_MEMORY", c.memory.low.to_string()),
+        ("BSDL_WORKER_THREADS", c.memory.worker_threads.map_or_else(|| "default".to_string(), |n| n.to_string())),
+        ("BSDL_FFMPEG_THREADS", c.memory.ffmpeg_threads.to_string()),
+        ("BSDL_KEEP_FINISHED_JOBS", c.memory.keep_finished_jobs.to_string()),
+        ("BSDL_SORT_LOCALE", c.sort_locale.clone()),
+        ("BSDL_TIMEZONE", c.timezone.name().to_string()),
+        ("BSDL_SELF_UPDATE", c.self_update.to_string()),
+        ("BSDL_UPDATE_REPO", c.update_repo.clone()),
+        ("BSDL_UPDATE_ASSET", c.update_asset.clone()),
+        ("BSDL_CONTENT_EXTENSIONS", c.content_extensions.join(",")),
+        ("BSDL_ANALYSIS_CACHE_MINUTES", c.analysis_cache_minutes.to_string()),
+        ("BSDL_COMPARE_WITHOUT_COOKIES", c.compare_without_cookies.to_string()),
+        ("BSDL_SUBSCRIPTION_HOURS", c.subscription_hours.to_string()),
+        ("BSDL_SUBSCRIPTION_PRESET", c.subscription_preset.clone()),
+        ("BSDL_SUBSCRIPTION_TEMPLATE", c.subscription_template.clone()),
+        ("BSDL_SUBSCRIPTION_POSTPROCESS", c.subscription_postprocess.join(",")),
+        ("BSDL_KEEP_PLAYED_DAYS", c.keep_played_days.to_string()),
+        ("BSDL_NOTIFY_CMD", cmd(&c.notify_cmd)),
+        ("BSDL_TORRENT_TRACKERS", format!("{} tracker(s)", c.torrent_trackers.len())),
+        ("BSDL_TORRENT_WEBSEED", url(c.torrent_webseed.as_deref())),
+        ("BSDL_IPFS_API", url(c.ipfs_api.as_deref())),
+        ("BSDL_API_TOKENS_FILE", path(c.api_tokens_file.as_deref())),
+        ("BSDL_AUTH_READS", c.auth_reads.to_string()),
+        ("BSDL_USERS_FILE", path(c.users_file.as_deref())),
+        ("BSDL_ADMINS", c.admins.join(", ")),
+        ("BSDL_TRANSLATE_URL", url(translation.map(|t| t.url.as_str()))),
+        ("BSDL_TRANSLATE_API_KEY", set(translation.is_some_and(|t| t.api_key.is_some()))),
+        ("BSDL_TRANSLATE_FROM", translation.map_or_else(|| "none".to_string(), |t| t.from.clone())),
+        ("BSDL_TRANSLATE_TO", translation.map_or_else(|| "none".to_string(), |t| t.to.clone())),
+        ("BSDL_TRANSLATE_EMBED", translation.is_some_and(|t| t.embed).to_string()),
+    ];
+    settings.into_iter().map(|(name, value)| format!("{} = {}\n", name, value)).collect()
+}
+
+async fn checks(state: &AppState) -> String {
+    let mut out = String::new();
+    for (heading, checks) in [("Live checks", diagnostics::run(state).await), ("At startup", state.startup.to_vec())] {
+        out.push_str(&format!("{}\n", heading));
+        for c in checks {
+            out.push_str(&format!("  [{}] {}: {}\n", c.status.label(), c.name, scrub(&c.detail)));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn failed_jobs(state: &AppState) -> String {
+    let mut out = String::new();
+    for chain in state.jobs.list() {
+        let failed: Vec<_> = chain.jobs.iter().filter(|j| j.status == JobStatus::Failed).collect();
+        if failed.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("{} (queued {}, ID {})\n", scrub(&chain.title), chain.created, chain.trace));
+        for job in failed {
+            out.push_str(&format!("  {}: {}\n", job.label, scrub(job.error.as_deref().unwrap_or("no error recorded"))));
+        }
+        out.push('\n');
+    }
+    if out.is_empty() {
+        out.push_str("No failed jobs since the app started.\n");
+    }
+    out
+}
+
+// What this run logged lately, kept in memory, then the log a launchd agent
+// writes next to the database, or whatever else ends in .log there, which
+// also covers earlier runs. systemd and a terminal keep theirs elsewhere.
+fn logs(state: &AppState) -> Vec<(String, String)> {
+    let mut logs = Vec::new();
+    let recent = logging::recent();
+    if !recent.is_empty() {
+        logs.push(("logs/recent.log".to_string(), scrub(&recent.join("\n")) + "\n"));
+    }
+    let dir = state.config.db_path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return logs;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("log") {
+            continue;
+        }
+        let (Some(name), Ok(text)) = (path.file_name().and_then(|n| n.to_str()), fs::read_to_string(&path)) else {
+            continue;
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        let tail = lines[lines.len().saturating_sub(LOG_TAIL_LINES)..].join("\n");
+        logs.push((format!("logs/{}", name), scrub(&tail) + "\n"));
+    }
+    logs
+}
+
+const README: &str = "Support bundle for a bug report.\n\n\
+    Commands from the config are cut down to their program name, keys only say\n\
+    whether they're set and URL query strings are removed, but read the files\n\
+    through before attaching them.\n\
+    logs/recent.log holds this run's latest lines; for anything older, add the\n\
+    output of `journalctl --user -u bplus-streamdlrs-gui` or the terminal the\n\
+    app runs in. A request ID from an error page or the\n\
+    jobs page finds its lines in the logs: every line logged for it says\n\
+    request{id=...} or job{... id=...}.\n";
+
+// --- Handlers ---
+
+/// A .zip of the version, redacted config, diagnostics, failed jobs and
+/// log tails, ready to attach to a GitHub issue.
+pub async fn download_bundle(State(state): State<AppState>) -> Result<Response, AppError> {
+    let mut files = vec![
+        ("README.txt".to_string(), README.to_string()),
+        ("version.txt".to_string(), about()),
+        ("config.txt".to_string(), config(&state)),
+        ("diagnostics.txt".to_string(), checks(&state).await),
+        ("failed-jobs.txt".to_string(), failed_jobs(&state)),
+    ];
+    files.extend(logs(&state));
+
+    let now = chrono::Utc::now();
+    let folder = format!("bplus-support-{}", now.format("%Y%m%d-%H%M%S"));
+    // Zip times carry no zone; these are UTC, like the folder name
+    let modified = zip::DateTime::from_date_and_time(
+        now.year() as u16,
+        now.month() as u8,
+        now.day() as u8,
+        now.hour() as u8,
+        now.minute() as u8,
+        now.second() as u8,
+    )
+    .unwrap_or_default();
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(0o644)
+        .last_modified_time(modified);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, body) in &files {
+        zip.start_file(format!("{}/{}", folder, name), options).map_err(std::io::Error::from)?;
+        zip.write_all(body.as_bytes())?;
+    }
+    let archive = zip.finish().map_err(std::io::Error::from)?.into_inner();
+
+    let disposition = format!("attachment; filename=\"{}.zip\"", folder);
+    Ok((
+        [(header::CONTENT_TYPE, "application/zip".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        archive,
+    )
+        .into_response())
+}
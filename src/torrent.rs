@@ -0,0 +1,149 @@
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+use crate::library;
+use crate::sanitize;
+use crate::AppState;
+
+/// Torrents sit next to their file as `<stem>.torrent`.
+pub const TORRENT_EXT: &str = "torrent";
+
+pub fn torrent_name(original: &str) -> String {
+    format!("{}.{}", library::stem(original), TORRENT_EXT)
+}
+
+// Aim for about this many pieces, within sane piece sizes
+const TARGET_PIECES: u64 = 1500;
+const MIN_PIECE: u64 = 256 * 1024;
+const MAX_PIECE: u64 = 16 * 1024 * 1024;
+
+// Powers of two, as clients expect
+fn piece_length(size: u64) -> u64 {
+    let mut piece = MIN_PIECE;
+    while piece < MAX_PIECE && size / piece > TARGET_PIECES {
+        piece *= 2;
+    }
+    piece
+}
+
+// --- Bencoding ---
+
+fn bytes(out: &mut Vec<u8>, b: &[u8]) {
+    out.extend_from_slice(b.len().to_string().as_bytes());
+    out.push(b':');
+    out.extend_from_slice(b);
+}
+
+fn int(out: &mut Vec<u8>, n: i64) {
+    out.extend_from_slice(format!("i{}e", n).as_bytes());
+}
+
+fn list(out: &mut Vec<u8>, items: &[String]) {
+    out.push(b'l');
+    for item in items {
+        bytes(out, item.as_bytes());
+    }
+    out.push(b'e');
+}
+
+// --- Creation ---
+
+// Every piece's hash, read through once
+fn piece_hashes(path: &Path, piece: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; piece as usize];
+    let mut pieces = Vec::new();
+    loop {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match file.read(&mut buf[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        pieces.extend_from_slice(&Sha1::digest(&buf[..filled]));
+        if filled < buf.len() {
+            break;
+        }
+    }
+    Ok(pieces)
+}
+
+// `webseed` is the URL of the file's folder under /content, ending in /
+fn build(path: &Path, name: &str, trackers: &[String], webseed: Option<&str>) -> std::io::Result<Vec<u8>> {
+    let size = std::fs::metadata(path)?.len();
+    let piece = piece_length(size);
+    let pieces = piece_hashes(path, piece)?;
+
+    // Keys in sorted order, as bencoding requires
+    let mut out = vec![b'd'];
+    if let Some(first) = trackers.first() {
+        bytes(&mut out, b"announce");
+        bytes(&mut out, first.as_bytes());
+    }
+    if trackers.len() > 1 {
+        // One tier per tracker, tried in order
+        bytes(&mut out, b"announce-list");
+        out.push(b'l');
+        for tracker in trackers {
+            list(&mut out, std::slice::from_ref(tracker));
+        }
+        out.push(b'e');
+    }
+    bytes(&mut out, b"created by");
+    bytes(&mut out, format!("bplus {}", env!("CARGO_PKG_VERSION")).as_bytes());
+    bytes(&mut out, b"creation date");
+    int(&mut out, crate::db::now());
+    bytes(&mut out, b"info");
+    out.push(b'd');
+    bytes(&mut out, b"length");
+    int(&mut out, size as i64);
+    bytes(&mut out, b"name");
+    bytes(&mut out, name.as_bytes());
+    bytes(&mut out, b"piece length");
+    int(&mut out, piece as i64);
+    bytes(&mut out, b"pieces");
+    bytes(&mut out, &pieces);
+    out.push(b'e');
+    if let Some(folder) = webseed {
+        // A URL ending in / gets the file name appended by the client
+        bytes(&mut out, b"url-list");
+        list(&mut out, &[folder.to_string()]);
+    }
+    out.push(b'e');
+    Ok(out)
+}
+
+/// Writes a single-file .torrent for `original` next to it, announcing to
+/// BSDL_TORRENT_TRACKERS and web-seeded from this app's /content when
+/// BSDL_TORRENT_WEBSEED is set. The file itself is left alone.
+pub async fn create(state: &AppState, original: &Path) -> Result<PathBuf, AppError> {
+    let name = original
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| AppError::BadRequest(format!("{} has no usable file name", original.display())))?
+        .to_string();
+    let dir = original.parent().unwrap_or(Path::new(&state.config.downloads_dir)).to_path_buf();
+    let target = dir.join(torrent_name(&name));
+    let partial = dir.join(format!(".{}.part", torrent_name(&name)));
+
+    // The folder as /content has it: downloads/alice/Music/a.mp3 is /content/alice/Music/a.mp3
+    let folder = library::name_of(&state.config.downloads_dir, original)
+        .and_then(|n| n.rsplit_once('/').map(|(folder, _)| format!("{}/", sanitize::url_parts(folder))))
+        .unwrap_or_default();
+    let webseed = state.config.torrent_webseed.as_ref().map(|base| format!("{}/content/{}", base.trim_end_matches('/'), folder));
+    let (source, trackers) = (original.to_path_buf(), state.config.torrent_trackers.clone());
+    let torrent = tokio::task::spawn_blocking(move || build(&source, &name, &trackers, webseed.as_deref()))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+    tokio::fs::write(&partial, torrent).await?;
+    tokio::fs::rename(&partial, &target).await?;
+    tracing::info!("Torrent written to {}", target.display());
+    Ok(target)
+}
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::error::AppError;
+use crate::library;
+use crate::AppState;
+
+/// Compatibility copies sit next to the original as `<stem>.compat.mp4`.
+pub const COMPAT_SUFFIX: &str = ".compat.mp4";
+
+pub fn compat_name(original: &str) -> String {
+    format!("{}{}", library::stem(original), COMPAT_SUFFIX)
+}
+
+// --- Transcoding ---
+
+/// Makes an H.264/AAC MP4 of `original`, at most 1080p tall, for players that
+/// can't handle the source codec or container. The original is left alone.
+pub async fn compat_copy(state: &AppState, original: &Path) -> Result<PathBuf, AppError> {
+    let name = original
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| AppError::BadRequest(format!("{} has no usable file name", original.display())))?;
+    let dir = original.parent().unwrap_or(Path::new(&state.config.downloads_dir));
+    let target = dir.join(compat_name(name));
+    // Hidden while ffmpeg is writing so the library never lists a half-made copy
+    let partial = dir.join(format!(".{}.part", compat_name(name)));
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-hide_banner")
+        .arg("-nostdin")
+        .arg("-y")
+        .arg("-i")
+        .arg(original)
+        .args(["-map", "0:v:0", "-map", "0:a:0?"])
+        .args(["-c:v", "libx264", "-preset", "medium", "-crf", "23", "-pix_fmt", "yuv420p"])
+        .args(["-vf", "scale=-2:'min(1080,ih)'"])
+        .args(["-c:a", "aac", "-b:a", "160k"])
+        .args(["-movflags", "+faststart", "-f", "mp4"])
+        .args(state.config.memory.ffmpeg_args())
+        .arg(&partial);
+
+    run_ffmpeg(state, cmd, &partial, &format!("compatibility copy of {}", name)).await?;
+    tokio::fs::rename(&partial, &target).await?;
+    tracing::info!("Compatibility copy written to {}", target.display());
+    Ok(target)
+}
+
+// Audio encoder ffmpeg should use for a container, picked by file extension
+fn audio_codec(ext: &str) -> Option<&'static [&'static str]> {
+    Some(match ext {
+        "mp3" => &["libmp3lame", "-q:a", "2"],
+        "m4a" | "mp4" | "m4v" | "mov" | "mkv" | "aac" => &["aac", "-b:a", "192k"],
+        "opus" | "ogg" | "oga" | "webm" => &["libopus", "-b:a", "128k"],
+        "flac" => &["flac"],
+        "wav" => &["pcm_s16le"],
+        _ => return None,
+    })
+}
+
+/// Evens out `file`'s loudness (EBU R128, −16 LUFS) and replaces it in place.
+/// Video and other streams are copied untouched.
+pub async fn normalize(state: &AppState, file: &Path) -> Result<(), AppError> {
+    let name = file
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| AppError::BadRequest(format!("{} has no usable file name", file.display())))?;
+    let ext = name.rsplit_once('.').map(|(_, e)| e.to_lowercase()).unwrap_or_default();
+    let codec = audio_codec(&ext)
+        .ok_or_else(|| AppError::BadRequest(format!("Don't know how to re-encode the audio of .{} files", ext)))?;
+    let dir = file.parent().unwrap_or(Path::new(&state.config.downloads_dir));
+    // Keeps the extension so ffmpeg picks the same container
+    let partial = dir.join(format!(".{}.normalizing.{}", library::stem(name), ext));
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-hide_banner")
+        .arg("-nostdin")
+        .arg("-y")
+        .arg("-i")
+        .arg(file)
+        .args(["-map", "0", "-c", "copy"])
+        .args(["-af", "loudnorm=I=-16:TP=-1.5:LRA=11"])
+        .arg("-c:a")
+        .args(codec)
+        .args(state.config.memory.ffmpeg_args())
+        .arg(&partial);
+
+    run_ffmpeg(state, cmd, &partial, &format!("normalizing {}", name)).await?;
+    tokio::fs::rename(&partial, file).await?;
+    tracing::info!("Normalized loudness of {}", file.display());
+    Ok(())
+}
+
+// Subtitle codec a container takes, picked by file extension
+fn subtitle_codec(ext: &str) -> Option<&'static str> {
+    Some(match ext {
+        "mp4" | "m4v" | "mov" => "mov_text",
+        "mkv" => "srt",
+        "webm" => "webvtt",
+        _ => return None,
+    })
+}
+
+// How many subtitle tracks `file` has already
+async fn subtitle_tracks(state: &AppState, file: &Path) -> Result<usize, AppError> {
+    let mut cmd = Command::new("ffprobe");
+    cmd.args(["-v", "error", "-select_streams", "s", "-show_entries", "stream=index", "-of", "csv=p=0"]).arg(file);
+    let output = state.procs.output("transcode", cmd).await?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::FfmpegFailed(format!("ffprobe of {} failed ({}): {}", file.display(), output.status, err.trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().filter(|l| !l.trim().is_empty()).count())
+}
+
+/// Adds `subtitles` to `file` as one more subtitle track tagged `lang`, and
+/// replaces it in place. Every other stream is copied untouched.
+pub async fn embed_subtitles(state: &AppState, file: &Path, subtitles: &Path, lang: &str) -> Result<(), AppError> {
+    let name = file
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| AppError::BadRequest(format!("{} has no usable file name", file.display())))?;
+    let ext = name.rsplit_once('.').map(|(_, e)| e.to_lowercase()).unwrap_or_default();
+    let codec = subtitle_codec(&ext)
+        .ok_or_else(|| AppError::BadRequest(format!("Can't add subtitle tracks to .{} files", ext)))?;
+    let dir = file.parent().unwrap_or(Path::new(&state.config.downloads_dir));
+    let partial = dir.join(format!(".{}.subtitling.{}", library::stem(name), ext));
+    // The new track comes after the ones already there
+    let track = subtitle_tracks(state, file).await?;
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-hide_banner")
+        .arg("-nostdin")
+        .arg("-y")
+        .arg("-i")
+        .arg(file)
+        .arg("-i")
+        .arg(subtitles)
+        .args(["-map", "0", "-map", "1:0", "-c", "copy"])
+        .arg(format!("-c:s:{}", track))
+        .arg(codec)
+        .arg(format!("-metadata:s:s:{}", track))
+        .arg(format!("language={}", lang))
+        .arg(&partial);
+
+    run_ffmpeg(state, cmd, &partial, &format!("adding subtitles to {}", name)).await?;
+    tokio::fs::rename(&partial, file).await?;
+    tracing::info!("Added {} subtitles to {}", lang, file.display());
+    Ok(())
+}
+
+// Runs ffmpeg writing to `partial`, which is removed again if it fails
+async fn run_ffmpeg(state: &AppState, cmd: Command, partial: &Path, what: &str) -> Result<(), AppError> {
+    let output = state.procs.output("transcode", cmd).await?;
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(partial).await;
+        let err = String::from_utf8_lossy(&output.stderr);
+        // ffmpeg puts the actual reason at the end
+        let tail: Vec<&str> = err.lines().rev().take(3).collect();
+        return Err(AppError::FfmpegFailed(format!(
+            "{} failed ({}): {}",
+            what,
+            output.status,
+            tail.into_iter().rev().collect::<Vec<_>>().join(" / ")
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::dedupe;
+use crate::error::{render, AppError};
+use crate::page::{self, FlashKind};
+use crate::sanitize::filters;
+use crate::session::SessionId;
+use crate::AppState;
+
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// A release-wide checksum listing, for releases without `<asset>.sha256`
+const SUMS_ASSET: &str = "SHA256SUMS";
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub html_url: String,
+    #[serde(default)]
+    pub published_at: Option<String>,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+impl Release {
+    pub fn is_newer(&self) -> bool {
+        match (version_parts(&self.tag_name), version_parts(CURRENT_VERSION)) {
+            (Some(latest), Some(current)) => latest > current,
+            _ => false,
+        }
+    }
+
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+
+    /// Where the SHA-256 of asset `name` is published: `<name>.sha256`, or SHA256SUMS.
+    pub fn checksum_asset(&self, name: &str) -> Option<&Asset> {
+        self.asset(&format!("{}.sha256", name)).or_else(|| self.asset(SUMS_ASSET))
+    }
+}
+
+// The hash given for `name` in sha256sum's output ("<hex>  <name>" lines),
+// or a file holding just the hash
+fn expected_sum(listing: &str, name: &str) -> Option<String> {
+    listing.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let hex = fields.next()?;
+        // sha256sum marks files read in binary mode with a *
+        let file = fields.next().map(|f| f.trim_start_matches('*'));
+        let found = hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()) && file.is_none_or(|f| f == name);
+        found.then(|| hex.to_ascii_lowercase())
+    })
+}
+
+// "v1.2.3" and "1.2.3-rc1" both become [1, 2, 3]
+fn version_parts(tag: &str) -> Option<Vec<u64>> {
+    let core = tag.trim().trim_start_matches('v').split(['-', '+']).next()?;
+    core.split('.').map(|p| p.parse().ok()).collect()
+}
+
+// --- GitHub ---
+
+/// Asks GitHub for the newest published release of the configured repository.
+pub async fn latest(state: &AppState) -> Result<Release, AppError> {
+    let url = format!("https://api.github.com/repos/{}/releases/latest", state.config.update_repo);
+    let mut cmd = Command::new("curl");
+    cmd.args(["-sSfL", "-m", "20", "-H", "Accept: application/vnd.github+json"])
+        .args(["-A", concat!("bplus-streamdlrs-gui/", env!("CARGO_PKG_VERSION"))])
+        .arg(&url);
+
+    let output = state.procs.output("update check", cmd).await?;
+    if !output.status.success() {
+        return Err(AppError::Update(format!(
+            "could not reach {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+// The SHA-256 the release publishes for `asset`
+async fn published_sum(state: &AppState, asset: &Asset, sums: &Asset) -> Result<String, AppError> {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-sSfL", "-m", "20"]).arg(&sums.browser_download_url);
+    let output = state.procs.output("update checksum", cmd).await?;
+    if !output.status.success() {
+        return Err(AppError::Update(format!(
+            "downloading {} failed: {}",
+            sums.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    expected_sum(&String::from_utf8_lossy(&output.stdout), &asset.name)
+        .ok_or_else(|| AppError::Update(format!("{} has no SHA-256 for {}", sums.name, asset.name)))
+}
+
+// Downloads next to the running binary so the final rename stays on one
+// filesystem, and keeps it only if its SHA-256 is the published one
+async fn fetch(state: &AppState, asset: &Asset, expected: &str, exe: &Path) -> Result<PathBuf, AppError> {
+    let target = exe.with_extension("new");
+    let mut cmd = Command::new("curl");
+    cmd.args(["-sSfL", "-m", "600", "-o"]).arg(&target).arg(&asset.browser_download_url);
+
+    let output = state.procs.output("update download", cmd).await?;
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(&target).await;
+        return Err(AppError::Update(format!(
+            "downloading {} failed: {}",
+            asset.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let len = tokio::fs::metadata(&target).await?.len();
+    if len == 0 || (asset.size > 0 && len != asset.size) {
+        let _ = tokio::fs::remove_file(&target).await;
+        return Err(AppError::Update(format!(
+            "{} arrived with {} bytes, expected {}",
+            asset.name, len, asset.size
+        )));
+    }
+
+    let hashed = target.clone();
+    let sum = tokio::task::spawn_blocking(move || dedupe::hash_file(&hashed))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+    if sum != expected {
+        let _ = tokio::fs::remove_file(&target).await;
+        return Err(AppError::Update(format!("{} has SHA-256 {}, but the release says {}", asset.name, sum, expected)));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o755)).await?;
+    }
+    Ok(target)
+}
+
+/// Swaps the running binary for the release asset, once its SHA-256 matches
+/// the one published with it; without one nothing is installed. The old one
+/// is kept as `<binary>.old` so a bad release can be rolled back by hand.
+pub async fn install(state: &AppState, release: &Release) -> Result<PathBuf, AppError> {
+    let asset = release.asset(&state.config.update_asset).ok_or_else(|| {
+        AppError::Update(format!("release {} has no asset named {}", release.tag_name, state.config.update_asset))
+    })?;
+    let sums = release.checksum_asset(&asset.name).ok_or_else(|| {
+        AppError::Update(format!("release {} publishes no SHA-256 for {}, so it isn't installed", release.tag_name, asset.name))
+    })?;
+    let expected = published_sum(state, asset, sums).await?;
+    let exe = std::env::current_exe()?;
+    let fresh = fetch(state, asset, &expected, &exe).await?;
+
+    let old = exe.with_extension("old");
+    tokio::fs::rename(&exe, &old).await?;
+    if let Err(e) = tokio::fs::rename(&fresh, &exe).await {
+        let _ = tokio::fs::rename(&old, &exe).await;
+        return Err(e.into());
+    }
+    tracing::info!("Installed {} over {} (previous binary kept at {})", release.tag_name, exe.display(), old.display());
+    Ok(exe)
+}
+
+// Replaces this process with the new binary, keeping the pid so service
+// managers don't notice. Where exec isn't available the app just exits and
+// relies on whatever started it to bring it back.
+fn restart(exe: PathBuf) {
+    tokio::spawn(async move {
+        // Give the redirect time to reach the browser
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tracing::info!("Restarting into {}", exe.display());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            let err = std::process::Command::new(&exe).args(std::env::args_os().skip(1)).exec();
+            tracing::warn!("Restart failed: {}; exiting instead", err);
+        }
+        std::process::exit(0);
+    });
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "update.html")]
+struct UpdateTemplate {
+    current: &'static str,
+    release: Option<Release>,
+    error: Option<String>,
+    asset: String,
+    install_enabled: bool,
+    busy: bool,
+    /// The release publishes a SHA-256 for the asset
+    checksummed: bool,
+}
+
+pub async fn show_update(State(state): State<AppState>) -> Response {
+    let (release, error) = match latest(&state).await {
+        Ok(r) => (Some(r), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let running = state.jobs.summary().running;
+    let checksummed = release.as_ref().is_some_and(|r| r.checksum_asset(&state.config.update_asset).is_some());
+    render(UpdateTemplate {
+        current: CURRENT_VERSION,
+        release,
+        error,
+        asset: state.config.update_asset.clone(),
+        install_enabled: state.config.self_update,
+        busy: running > 0,
+        checksummed,
+    })
+}
+
+pub async fn install_update(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+) -> Result<Response, AppError> {
+    if !state.config.self_update {
+        return Err(AppError::BadRequest("Self-update is turned off; set BSDL_SELF_UPDATE=true to allow it".to_string()));
+    }
+    if state.jobs.summary().running > 0 {
+        return Err(AppError::BadRequest("Wait for the running jobs to finish before updating".to_string()));
+    }
+
+    let release = latest(&state).await?;
+    if !release.is_newer() {
+        page::flash(&state, &sid, FlashKind::Info, format!("Already on the latest release ({}).", CURRENT_VERSION));
+        return Ok(Redirect::to("/system/update").into_response());
+    }
+    let exe = install(&state, &release).await?;
+    // Sessions don't survive the restart, so there's no point flashing here
+    restart(exe);
+    Ok(Redirect::to("/system/update").into_response())
+}
@@ -0,0 +1,164 @@
+use axum::{
+    extract::{Multipart, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use askama::Template;
+use rand::{distributions::Alphanumeric, Rng};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::auth;
+use crate::config::FilenameProfile;
+use crate::error::{render, AppError};
+use crate::library;
+use crate::reconcile;
+use crate::sanitize;
+use crate::session::SessionId;
+use crate::AppState;
+
+// --- Templates ---
+
+#[derive(Template)]
+#[template(path = "upload.html")]
+struct UploadTemplate {
+    max_upload_mb: u64,
+}
+
+// --- Handlers ---
+
+pub async fn show_upload(State(state): State<AppState>) -> Response {
+    render(UploadTemplate { max_upload_mb: state.config.max_upload_mb })
+}
+
+/// Saves the uploaded files into the library, or a signed-in user's folder of it.
+pub async fn upload_files(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    mut multipart: Multipart,
+) -> Result<Response, AppError> {
+    let limit = state.config.max_upload_mb * 1024 * 1024;
+    let mut saved = Vec::new();
+    // Where downloads of theirs go too, see jobs::owned_by
+    let dir = match auth::viewer(&state, &sid) {
+        Some(user) => Path::new(&state.config.downloads_dir).join(user),
+        None => PathBuf::from(&state.config.downloads_dir),
+    };
+    fs::create_dir_all(&dir).await?;
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Upload was malformed: {}", e)))?
+    {
+        let Some(raw_name) = field.file_name().map(str::to_string) else {
+            continue;
+        };
+        if raw_name.is_empty() {
+            continue;
+        }
+
+        let name = safe_file_name(&raw_name, &state.config.filenames)
+            .ok_or_else(|| AppError::BadRequest(format!("\"{}\" is not a usable file name", raw_name)))?;
+        check_media_type(&name, field.content_type())?;
+
+        // Stream into a hidden temp file so a half-finished upload never shows up in the library
+        let tmp: String = rand::thread_rng().sample_iter(&Alphanumeric).take(12).map(char::from).collect();
+        let tmp_path = dir.join(format!(".upload-{}.part", tmp));
+        let mut file = fs::File::create(&tmp_path).await?;
+        let mut written: u64 = 0;
+
+        loop {
+            let chunk = match field.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = fs::remove_file(&tmp_path).await;
+                    return Err(AppError::BadRequest(format!("Upload of {} was interrupted: {}", name, e)));
+                }
+            };
+            written += chunk.len() as u64;
+            if written > limit {
+                let _ = fs::remove_file(&tmp_path).await;
+                return Err(AppError::TooLarge(format!(
+                    "{} is larger than the {} MB upload limit",
+                    name, state.config.max_upload_mb
+                )));
+            }
+            if let Err(e) = file.write_all(&chunk).await {
+                let _ = fs::remove_file(&tmp_path).await;
+                return Err(e.into());
+            }
+        }
+        file.flush().await?;
+        drop(file);
+
+        let target = free_path(&dir, &name, &state.config.filenames).await;
+        fs::rename(&tmp_path, &target).await?;
+        tracing::info!("Uploaded {} ({} bytes)", target.display(), written);
+        saved.extend(library::name_of(&state.config.downloads_dir, &target));
+    }
+
+    if saved.is_empty() {
+        return Err(AppError::BadRequest("No files were selected".to_string()));
+    }
+    reconcile::refresh(&state.db, &state.config.downloads_dir, saved).await?;
+    Ok(Redirect::to("/files").into_response())
+}
+
+// --- Helpers ---
+
+// Keeps only the final path component, so "../../etc/x.mp4" becomes "x.mp4",
+// drops invisible characters that could disguise the extension, and applies
+// the configured filename profile
+fn safe_file_name(raw: &str, profile: &FilenameProfile) -> Option<String> {
+    let name = sanitize::file_name(raw.rsplit(['/', '\\']).next()?, profile);
+    if name.is_empty() || name.starts_with('.') {
+        return None;
+    }
+    Some(name)
+}
+
+// Only audio and video belong in the library; both the extension and the
+// browser-declared type have to agree on that
+fn check_media_type(name: &str, declared: Option<&str>) -> Result<(), AppError> {
+    let guessed = mime_guess::from_path(name).first_or_octet_stream();
+    if guessed.type_() != "video" && guessed.type_() != "audio" {
+        return Err(AppError::BadRequest(format!("{} is not an audio or video file", name)));
+    }
+
+    if let Some(declared) = declared {
+        let is_media = declared.starts_with("video/") || declared.starts_with("audio/");
+        if !is_media && declared != "application/octet-stream" {
+            return Err(AppError::BadRequest(format!("{} was sent as {}, not audio or video", name, declared)));
+        }
+    }
+    Ok(())
+}
+
+// "name.mp4" if it's free, otherwise "name (1).mp4", "name (2).mp4", ...
+// ("name_1.mp4" with restricted file names)
+async fn free_path(dir: &Path, name: &str, profile: &FilenameProfile) -> PathBuf {
+    let candidate = dir.join(name);
+    if fs::metadata(&candidate).await.is_err() {
+        return candidate;
+    }
+
+    let path = Path::new(name);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut n = 1;
+    loop {
+        // `name` is already cleaned; the suffix goes on after any trimming so it can't be cut off
+        let candidate = if profile.restrict {
+            dir.join(format!("{}_{}{}", stem, n, ext))
+        } else {
+            dir.join(format!("{} ({}){}", stem, n, ext))
+        };
+        if fs::metadata(&candidate).await.is_err() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
@@ -0,0 +1,262 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use serde::Deserialize;
+use std::path::Path as FsPath;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::auth::{self, SignedIn};
+use crate::clock::Stamp;
+use crate::config::Config;
+use crate::db::{self, params, Db};
+use crate::error::{render, AppError};
+use crate::page::{self, FlashKind};
+use crate::password;
+use crate::sanitize::filters;
+use crate::session::SessionId;
+use crate::validate::{self, Form, Validate};
+use crate::AppState;
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 256;
+
+// --- Data Structures ---
+
+#[derive(Debug, Clone)]
+struct User {
+    name: String,
+    /// As password::hash makes it
+    hash: String,
+    /// Read from BSDL_USERS_FILE rather than added on the users page
+    from_file: bool,
+    updated: Option<Stamp>,
+    /// May use the /system pages
+    admin: bool,
+}
+
+/// A user as the users page lists them.
+#[derive(Debug, Clone)]
+pub struct UserInfo {
+    pub name: String,
+    pub from_file: bool,
+    pub updated: Option<Stamp>,
+    pub admin: bool,
+}
+
+/// Who may sign in with a password. Once there is one, every page wants a
+/// signed-in browser (or an API token).
+#[derive(Clone, Default)]
+pub struct Users {
+    inner: Arc<Mutex<Vec<User>>>,
+}
+
+/// Lowercase letters, digits, '.', '-' and '_', up to 32, starting with a
+/// letter or digit. Also the name of the user's folder in the library.
+pub fn valid_name(name: &str) -> bool {
+    name.len() <= 32
+        && name.bytes().next().is_some_and(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"._-".contains(&b))
+}
+
+// "name hash" per line; blank lines and # comments are skipped
+fn parse_file(path: &FsPath, text: &str) -> Vec<User> {
+    let mut users = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, hash) = line.split_once(char::is_whitespace).map_or((line, ""), |(n, h)| (n, h.trim()));
+        if !valid_name(name) || !password::well_formed(hash) {
+            tracing::warn!("Ignoring line {} of {}: not a user name and a hash from --hash-password", i + 1, path.display());
+            continue;
+        }
+        users.push(User { name: name.to_string(), hash: hash.to_string(), from_file: true, updated: None, admin: false });
+    }
+    users
+}
+
+// Checked against when the name is unknown, so a wrong name takes as long as a wrong password
+fn stand_in() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| password::hash(""))
+}
+
+impl Users {
+    /// The configured file's users and the stored ones.
+    pub async fn load(config: &Config, db: &Db) -> Result<Users, AppError> {
+        let users = Users::default();
+        users.reload(config, db).await?;
+        Ok(users)
+    }
+
+    async fn reload(&self, config: &Config, db: &Db) -> Result<(), AppError> {
+        let mut loaded = match &config.users_file {
+            Some(path) => parse_file(path, &std::fs::read_to_string(path)?),
+            None => Vec::new(),
+        };
+        let stored = db
+            .call(|conn| {
+                conn.query_map("SELECT name, password, updated_at, admin FROM users ORDER BY name", params![], |r| {
+                    Ok(User { name: r.get(0)?, hash: r.get(1)?, from_file: false, updated: Some(Stamp(r.get(2)?)), admin: r.get(3)? })
+                })
+            })
+            .await?;
+        // The file wins over a stored user of the same name
+        for user in stored {
+            if !loaded.iter().any(|u| u.name == user.name) {
+                loaded.push(user);
+            }
+        }
+        for user in &mut loaded {
+            user.admin |= config.admins.contains(&user.name);
+        }
+        // Someone has to be able to add the rest
+        if !loaded.iter().any(|u| u.admin) {
+            if let Some(first) = loaded.first_mut() {
+                first.admin = true;
+            }
+        }
+        *self.inner.lock().unwrap() = loaded;
+        Ok(())
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.inner.lock().unwrap().is_empty()
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    pub fn knows(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().iter().any(|u| u.name == name)
+    }
+
+    /// Whether `name` may use the /system pages.
+    pub fn is_admin(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().iter().any(|u| u.name == name && u.admin)
+    }
+
+    fn in_file(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().iter().any(|u| u.name == name && u.from_file)
+    }
+
+    /// Whether `password` is `name`'s.
+    pub async fn check(&self, name: &str, password: &str) -> bool {
+        let hash = self.inner.lock().unwrap().iter().find(|u| u.name == name).map(|u| u.hash.clone());
+        let known = hash.is_some();
+        let hash = hash.unwrap_or_else(|| stand_in().to_string());
+        let password = password.to_string();
+        let matched = tokio::task::spawn_blocking(move || password::verify(&password, &hash)).await.unwrap_or(false);
+        known && matched
+    }
+
+    pub fn list(&self) -> Vec<UserInfo> {
+        let users = self.inner.lock().unwrap();
+        users.iter().map(|u| UserInfo { name: u.name.clone(), from_file: u.from_file, updated: u.updated, admin: u.admin }).collect()
+    }
+}
+
+// --- Handlers ---
+
+#[derive(Template)]
+#[template(path = "users.html")]
+struct UsersTemplate {
+    users: Vec<UserInfo>,
+    file: Option<String>,
+}
+
+pub async fn show_users(State(state): State<AppState>) -> Response {
+    render(UsersTemplate {
+        users: state.users.list(),
+        file: state.config.users_file.as_ref().map(|p| p.display().to_string()),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct UserForm {
+    name: String,
+    password: String,
+    /// The checkbox, "on" when ticked
+    admin: Option<String>,
+}
+
+impl Validate for UserForm {
+    fn validate(&self) -> Result<(), AppError> {
+        if !valid_name(self.name.trim()) {
+            return Err(AppError::Invalid {
+                field: "name",
+                reason: "user names are up to 32 lowercase letters, digits, '.', '-' or '_', starting with a letter or digit".to_string(),
+            });
+        }
+        validate::max_len("password", &self.password, MAX_PASSWORD_LEN)?;
+        if self.password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(AppError::Invalid {
+                field: "password",
+                reason: format!("passwords are at least {} characters", MIN_PASSWORD_LEN),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Adds a user, or sets a new password for one. The first user is an admin
+/// and signs in the browser that added them, so turning sign-in on from here
+/// doesn't lock it out.
+pub async fn save_user(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Form(form): Form<UserForm>,
+) -> Result<Response, AppError> {
+    let name = form.name.trim().to_string();
+    if state.users.in_file(&name) {
+        return Err(AppError::Invalid { field: "name", reason: format!("{} is in the users file; change it there", name) });
+    }
+    let existed = state.users.knows(&name);
+    let admin = form.admin.is_some() || !state.users.enabled();
+    let password = form.password;
+    let hash = tokio::task::spawn_blocking(move || password::hash(&password))
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))?;
+    let (stored, now) = (name.clone(), db::now());
+    state
+        .db
+        .call(move |conn| {
+            conn.execute(
+                "INSERT INTO users (name, password, updated_at, admin) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (name) DO UPDATE SET password = excluded.password, updated_at = excluded.updated_at, admin = excluded.admin",
+                params![stored, hash, now, admin],
+            )
+        })
+        .await?;
+    state.users.reload(&state.config, &state.db).await?;
+    tracing::info!("{} user {}", if existed { "Changed the password of" } else { "Added" }, name);
+    let msg = if existed { format!("Changed {}'s password.", name) } else { format!("Added {}; they can sign in at /login.", name) };
+    page::flash(&state, &sid, FlashKind::Info, msg);
+    if auth::signed_in(&state, &sid).is_none() {
+        return Ok(auth::sign_in(&state, &sid, SignedIn::User(name), "/system/users"));
+    }
+    Ok(Redirect::to("/system/users").into_response())
+}
+
+pub async fn remove_user(
+    State(state): State<AppState>,
+    Extension(sid): Extension<SessionId>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let stored = name.clone();
+    let removed = state.db.call(move |conn| conn.execute("DELETE FROM users WHERE name = ?1", params![stored])).await?;
+    if removed == 0 {
+        return Err(AppError::NotFound(format!("User \"{}\"", name)));
+    }
+    state.users.reload(&state.config, &state.db).await?;
+    tracing::info!("Removed user {}", name);
+    page::flash(&state, &sid, FlashKind::Info, format!("Removed {}; they can't sign in any more.", name));
+    // Removing the user this browser signed in as signs it out
+    let back = if auth::signed_in(&state, &sid).is_some() || !auth::enabled(&state) { "/system/users" } else { "/login" };
+    Ok(Redirect::to(back).into_response())
+}
@@ -0,0 +1,151 @@
+use axum::{
+    async_trait,
+    extract::{
+        rejection::{FormRejection, JsonRejection},
+        FromRequest, Request,
+    },
+    http::StatusCode,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::AppError;
+
+/// Largest body any form may send; /upload sets its own, larger limit.
+pub const MAX_FORM_BYTES: usize = 64 * 1024;
+/// Longest URL accepted for analysis or metadata import
+pub const MAX_URL_LEN: usize = 2048;
+/// Longest yt-dlp output template a subscription may set
+pub const MAX_TEMPLATE_LEN: usize = 200;
+// yt-dlp format ids look like "137", "hls-720p" or "dash-video=3000000"
+const MAX_FORMAT_ID_LEN: usize = 64;
+
+// --- Extractor ---
+
+/// Form fields checked before a handler ever sees them.
+pub trait Validate {
+    fn validate(&self) -> Result<(), AppError>;
+}
+
+/// Drop-in for `axum::Form` that runs `Validate` and turns every rejection,
+/// including axum's own, into an `AppError` so it gets the usual error page.
+pub struct Form<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Form<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Form(value) = axum::Form::<T>::from_request(req, state).await.map_err(rejected)?;
+        value.validate()?;
+        Ok(Form(value))
+    }
+}
+
+/// The same for JSON bodies, as the API takes them.
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::<T>::from_request(req, state).await.map_err(rejected_json)?;
+        value.validate()?;
+        Ok(Json(value))
+    }
+}
+
+fn rejected_json(e: JsonRejection) -> AppError {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => AppError::TooLarge("The request body is larger than the API accepts".to_string()),
+        // Well-formed JSON with missing or mistyped fields
+        StatusCode::UNPROCESSABLE_ENTITY => AppError::Invalid { field: "body", reason: e.body_text() },
+        _ => AppError::BadRequest(e.body_text()),
+    }
+}
+
+fn rejected(e: FormRejection) -> AppError {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => AppError::TooLarge("The form is larger than this page accepts".to_string()),
+        // Missing or malformed fields
+        StatusCode::UNPROCESSABLE_ENTITY => AppError::Invalid { field: "form", reason: e.body_text() },
+        _ => AppError::BadRequest(e.body_text()),
+    }
+}
+
+// --- Field checks ---
+
+pub fn max_len(field: &'static str, value: &str, max: usize) -> Result<(), AppError> {
+    if value.chars().count() > max {
+        return Err(AppError::Invalid { field, reason: format!("longer than {} characters", max) });
+    }
+    Ok(())
+}
+
+/// Length and characters only; whether it's http(s) is up to the handler, which
+/// can explain that inline.
+pub fn url(field: &'static str, value: &str) -> Result<(), AppError> {
+    max_len(field, value, MAX_URL_LEN)?;
+    if value.trim().chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(AppError::Invalid { field, reason: "URLs can't contain spaces or control characters".to_string() });
+    }
+    Ok(())
+}
+
+pub fn one_of(field: &'static str, value: &str, allowed: &[&str]) -> Result<(), AppError> {
+    if !allowed.contains(&value) {
+        return Err(AppError::Invalid { field, reason: format!("must be one of {}", allowed.join(", ")) });
+    }
+    Ok(())
+}
+
+pub fn format_id(field: &'static str, value: &str) -> Result<(), AppError> {
+    let ok = !value.is_empty()
+        && value.len() <= MAX_FORMAT_ID_LEN
+        && !value.starts_with('-')
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+' | '='));
+    if !ok {
+        return Err(AppError::Invalid { field, reason: "not a yt-dlp format id".to_string() });
+    }
+    Ok(())
+}
+
+/// A calendar date as a date input sends it, YYYY-MM-DD.
+pub fn date(field: &'static str, value: &str) -> Result<(), AppError> {
+    let parts: Vec<&str> = value.split('-').collect();
+    let ok = matches!(parts.as_slice(), [y, m, d]
+        if y.len() == 4 && m.len() == 2 && d.len() == 2
+            && parts.iter().all(|p| p.bytes().all(|b| b.is_ascii_digit()))
+            && (1..=12).contains(&m.parse::<u32>().unwrap_or(0))
+            && (1..=31).contains(&d.parse::<u32>().unwrap_or(0)));
+    if !ok {
+        return Err(AppError::Invalid { field, reason: "must be a date like 2024-01-31".to_string() });
+    }
+    Ok(())
+}
+
+/// A yt-dlp output template naming one file in the (flat) library,
+/// e.g. `%(uploader)s - %(title)s.%(ext)s`.
+pub fn output_template(field: &'static str, value: &str) -> Result<(), AppError> {
+    max_len(field, value, MAX_TEMPLATE_LEN)?;
+    let reason = if value.contains(['/', '\\']) {
+        "can't contain folders; everything lands directly in downloads/"
+    } else if value.starts_with(['.', '-']) {
+        "can't start with '.' or '-'"
+    } else if value.chars().any(char::is_control) {
+        "can't contain control characters"
+    } else if !value.ends_with(".%(ext)s") {
+        "must end in .%(ext)s so the file keeps its real extension"
+    } else {
+        return Ok(());
+    };
+    Err(AppError::Invalid { field, reason: reason.to_string() })
+}
@@ -0,0 +1,94 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    Extension,
+};
+use std::collections::HashSet;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::auth;
+use crate::jobs::JobEvent;
+use crate::session::SessionId;
+use crate::AppState;
+
+// Clients only send pings and the close; anything bigger ends the socket
+const MAX_CLIENT_MESSAGE: usize = 64 * 1024;
+
+/// GET /ws: upgrades to a WebSocket that sends every chain as it stands, then
+/// each state change, progress tick and yt-dlp log line as a JSON text message.
+/// A signed-in user only hears about the chains they queued.
+pub async fn socket(State(state): State<AppState>, Extension(sid): Extension<SessionId>, upgrade: WebSocketUpgrade) -> Response {
+    let viewer = auth::viewer(&state, &sid);
+    upgrade
+        .max_message_size(MAX_CLIENT_MESSAGE)
+        .max_frame_size(MAX_CLIENT_MESSAGE)
+        .on_failed_upgrade(|e| tracing::warn!("WebSocket upgrade failed: {}", e))
+        // Ends with an error whenever the client just goes away
+        .on_upgrade(move |socket| async move {
+            let _ = stream(&state, viewer.as_deref(), socket).await;
+        })
+}
+
+// --- Streaming ---
+
+async fn send(socket: &mut WebSocket, event: &JobEvent) -> Result<(), axum::Error> {
+    let json = serde_json::to_string(event).map_err(axum::Error::new)?;
+    socket.send(Message::Text(json)).await
+}
+
+// The chains a socket has been told about, so the rest of what happens
+// to them goes to it too
+type Shown = HashSet<u64>;
+
+// Every chain `viewer` sees, oldest first, so a client can draw the whole queue
+async fn snapshot(state: &AppState, viewer: Option<&str>, shown: &mut Shown, socket: &mut WebSocket) -> Result<(), axum::Error> {
+    shown.clear();
+    for chain in state.jobs.list().into_iter().rev().filter(|c| c.visible_to(viewer)) {
+        shown.insert(chain.id);
+        send(socket, &JobEvent::from(chain)).await?;
+    }
+    Ok(())
+}
+
+// Whether the event is about a chain `viewer` sees
+fn hears(state: &AppState, viewer: Option<&str>, shown: &mut Shown, event: &JobEvent) -> bool {
+    match *event {
+        JobEvent::Chain { id, .. } => {
+            let visible = state.jobs.visible_to(id, viewer);
+            if visible {
+                shown.insert(id);
+            }
+            visible
+        }
+        JobEvent::Progress { chain, .. } | JobEvent::Log { chain, .. } => shown.contains(&chain),
+        JobEvent::Removed { id } => shown.remove(&id),
+    }
+}
+
+async fn stream(state: &AppState, viewer: Option<&str>, mut socket: WebSocket) -> Result<(), axum::Error> {
+    // Subscribed before the snapshot so nothing falls between the two
+    let mut events = state.jobs.subscribe();
+    let mut shown = Shown::new();
+    snapshot(state, viewer, &mut shown, &mut socket).await?;
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if hears(state, viewer, &mut shown, &event) => send(&mut socket, &event).await?,
+                Ok(_) => {}
+                // Fell behind: start over from where things stand
+                Err(RecvError::Lagged(_)) => snapshot(state, viewer, &mut shown, &mut socket).await?,
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            // Pings are answered by the socket itself, and nothing else the
+            // client says means anything here
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}